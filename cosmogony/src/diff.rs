@@ -0,0 +1,218 @@
+// Comparison of two builds of a cosmogony, to detect the unexpected changes
+// (like a vandalized boundary or a mapping change breaking the hierarchy) between
+// two builds of the same area.
+//
+// The zones are matched by their osm id (their ids are not stable between two builds).
+
+use crate::zone::{Zone, ZoneIndex, ZoneType};
+use geo_types::{LineString, MultiPolygon};
+use serde_derive::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The main attributes of an added or removed zone
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ZoneSummary {
+    pub osm_id: String,
+    pub name: String,
+    pub zone_type: Option<ZoneType>,
+    pub country_code: Option<String>,
+}
+
+/// A zone whose type has changed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Retyped {
+    pub zone: ZoneSummary,
+    pub previous_type: Option<ZoneType>,
+}
+
+/// A zone whose parent has changed, the parents being identified by their osm id
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reparented {
+    pub zone: ZoneSummary,
+    pub previous_parent: Option<String>,
+    pub parent: Option<String>,
+}
+
+/// A zone whose area has changed more than the threshold
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeometryChange {
+    pub zone: ZoneSummary,
+    /// the area change, as a ratio of the largest of the 2 areas
+    /// (1 if one of the 2 zones has no boundary)
+    pub ratio: f64,
+}
+
+/// The differences between two builds
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CosmogonyDiff {
+    pub added: Vec<ZoneSummary>,
+    pub removed: Vec<ZoneSummary>,
+    pub retyped: Vec<Retyped>,
+    pub reparented: Vec<Reparented>,
+    pub geometry_changes: Vec<GeometryChange>,
+}
+
+impl CosmogonyDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.retyped.is_empty()
+            && self.reparented.is_empty()
+            && self.geometry_changes.is_empty()
+    }
+}
+
+impl From<&Zone> for ZoneSummary {
+    fn from(zone: &Zone) -> Self {
+        ZoneSummary {
+            osm_id: zone.osm_id.clone(),
+            name: zone.name.clone(),
+            zone_type: zone.zone_type,
+            country_code: zone.country_code.clone(),
+        }
+    }
+}
+
+fn ring_area(ring: &LineString<f64>) -> f64 {
+    ring.0
+        .windows(2)
+        .map(|w| w[0].x * w[1].y - w[1].x * w[0].y)
+        .sum::<f64>()
+        .abs()
+        / 2.
+}
+
+/// planar area of a boundary, in square degrees
+fn planar_area(boundary: &MultiPolygon<f64>) -> f64 {
+    boundary
+        .iter()
+        .map(|p| ring_area(p.exterior()) - p.interiors().iter().map(ring_area).sum::<f64>())
+        .sum()
+}
+
+/// the area of the zone, the geodesic one if it has been computed
+fn area(zone: &Zone) -> Option<f64> {
+    let boundary = zone.boundary.as_ref()?;
+    zone.area.or_else(|| Some(planar_area(boundary)))
+}
+
+fn area_change(before: &Zone, after: &Zone) -> f64 {
+    let areas = if before.area.is_some() && after.area.is_some() {
+        (area(before), area(after))
+    } else {
+        // the areas must be of the same kind to be compared
+        let planar = |z: &Zone| z.boundary.as_ref().map(planar_area);
+        (planar(before), planar(after))
+    };
+    match areas {
+        (Some(a), Some(b)) if a.max(b) > 0. => (a - b).abs() / a.max(b),
+        (Some(_), Some(_)) | (None, None) => 0.,
+        _ => 1.,
+    }
+}
+
+/// Compare two builds, reporting the area changes above `geometry_threshold`
+/// (a ratio of the largest area, eg. 0.05 for the changes above 5%)
+pub fn diff_zones(before: &[Zone], after: &[Zone], geometry_threshold: f64) -> CosmogonyDiff {
+    let by_osm_id = |zones: &[Zone]| -> BTreeMap<String, usize> {
+        zones
+            .iter()
+            .enumerate()
+            .map(|(pos, z)| (z.osm_id.clone(), pos))
+            .collect()
+    };
+    let before_pos = by_osm_id(before);
+    let after_pos = by_osm_id(after);
+    // the ids are not the positions once the zones have been cleaned
+    let osm_ids_by_id = |zones: &[Zone]| -> BTreeMap<ZoneIndex, String> {
+        zones.iter().map(|z| (z.id, z.osm_id.clone())).collect()
+    };
+    let before_osm_ids = osm_ids_by_id(before);
+    let after_osm_ids = osm_ids_by_id(after);
+
+    let mut diff = CosmogonyDiff::default();
+    for zone in before {
+        if !after_pos.contains_key(&zone.osm_id) {
+            diff.removed.push(zone.into());
+        }
+    }
+    for zone in after {
+        let Some(previous) = before_pos.get(&zone.osm_id).map(|pos| &before[*pos]) else {
+            diff.added.push(zone.into());
+            continue;
+        };
+        if previous.zone_type != zone.zone_type {
+            diff.retyped.push(Retyped {
+                zone: zone.into(),
+                previous_type: previous.zone_type,
+            });
+        }
+        let previous_parent = previous
+            .parent
+            .and_then(|p| before_osm_ids.get(&p).cloned());
+        let parent = zone.parent.and_then(|p| after_osm_ids.get(&p).cloned());
+        if previous_parent != parent {
+            diff.reparented.push(Reparented {
+                zone: zone.into(),
+                previous_parent,
+                parent,
+            });
+        }
+        let ratio = area_change(previous, zone);
+        if ratio > geometry_threshold {
+            diff.geometry_changes.push(GeometryChange {
+                zone: zone.into(),
+                ratio,
+            });
+        }
+    }
+    diff
+}
+
+impl fmt::Display for CosmogonyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let zone_type = |z: &ZoneSummary| z.zone_type.map_or("untyped", |t| t.as_str());
+        writeln!(f, "Added zones: {}", self.added.len())?;
+        for z in &self.added {
+            writeln!(f, "  + {} {} ({})", z.osm_id, z.name, zone_type(z))?;
+        }
+        writeln!(f, "Removed zones: {}", self.removed.len())?;
+        for z in &self.removed {
+            writeln!(f, "  - {} {} ({})", z.osm_id, z.name, zone_type(z))?;
+        }
+        writeln!(f, "Retyped zones: {}", self.retyped.len())?;
+        for r in &self.retyped {
+            writeln!(
+                f,
+                "  {} {}: {} -> {}",
+                r.zone.osm_id,
+                r.zone.name,
+                r.previous_type.map_or("untyped", |t| t.as_str()),
+                zone_type(&r.zone)
+            )?;
+        }
+        writeln!(f, "Re-parented zones: {}", self.reparented.len())?;
+        for r in &self.reparented {
+            writeln!(
+                f,
+                "  {} {}: {} -> {}",
+                r.zone.osm_id,
+                r.zone.name,
+                r.previous_parent.as_deref().unwrap_or("none"),
+                r.parent.as_deref().unwrap_or("none")
+            )?;
+        }
+        writeln!(f, "Geometry changes: {}", self.geometry_changes.len())?;
+        for g in &self.geometry_changes {
+            writeln!(
+                f,
+                "  {} {}: {:.1}%",
+                g.zone.osm_id,
+                g.zone.name,
+                g.ratio * 100.
+            )?;
+        }
+        Ok(())
+    }
+}
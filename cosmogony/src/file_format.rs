@@ -7,13 +7,36 @@ pub enum OutputFormat {
     JsonGz,
     JsonStream,
     JsonStreamGz,
+    JsonZst,
+    JsonStreamZst,
+    Shapefile,
+    Csv,
+    Tsv,
+    GeoPackage,
+    Sqlite,
+    TopoJson,
+    GeoParquet,
+    Tree,
+    TreeStream,
 }
 
-static ALL_EXTENSIONS: [(&str, OutputFormat); 4] = [
+// the tree extensions are first, as they also end like the json ones
+static ALL_EXTENSIONS: [(&str, OutputFormat); 15] = [
+    (".tree.json", OutputFormat::Tree),
+    (".tree.jsonl", OutputFormat::TreeStream),
     (".json", OutputFormat::Json),
     (".jsonl", OutputFormat::JsonStream),
     (".json.gz", OutputFormat::JsonGz),
     (".jsonl.gz", OutputFormat::JsonStreamGz),
+    (".json.zst", OutputFormat::JsonZst),
+    (".jsonl.zst", OutputFormat::JsonStreamZst),
+    (".shp", OutputFormat::Shapefile),
+    (".csv", OutputFormat::Csv),
+    (".tsv", OutputFormat::Tsv),
+    (".gpkg", OutputFormat::GeoPackage),
+    (".sqlite", OutputFormat::Sqlite),
+    (".topojson", OutputFormat::TopoJson),
+    (".parquet", OutputFormat::GeoParquet),
 ];
 
 impl OutputFormat {
@@ -0,0 +1,103 @@
+// Traversal of the hierarchy of the zones of a cosmogony (the children of a zone, its
+// ancestors, the zones of a type), without rebuilding lookup maps from the flat list of zones.
+//
+// The zones are only linked by the id of their parent, the lookups are indexed once by
+// `Cosmogony::hierarchy`. The index borrows the zones, so it cannot be outdated by a change of
+// the zones.
+
+use crate::model::Cosmogony;
+use crate::zone::{Zone, ZoneIndex, ZoneType};
+use std::collections::BTreeMap;
+
+/// The hierarchy of the zones of a cosmogony, indexed by zone id and by zone type
+pub struct ZonesHierarchy<'a> {
+    /// the zones ids are not their position once the zones have been cleaned
+    zones_by_id: BTreeMap<ZoneIndex, &'a Zone>,
+    children: BTreeMap<ZoneIndex, Vec<&'a Zone>>,
+    by_type: BTreeMap<ZoneType, Vec<&'a Zone>>,
+    roots: Vec<&'a Zone>,
+}
+
+impl<'a> ZonesHierarchy<'a> {
+    pub fn new(zones: &'a [Zone]) -> Self {
+        let zones_by_id: BTreeMap<_, _> = zones.iter().map(|z| (z.id, z)).collect();
+        let mut children = BTreeMap::<ZoneIndex, Vec<&Zone>>::new();
+        let mut by_type = BTreeMap::<ZoneType, Vec<&Zone>>::new();
+        let mut roots = vec![];
+        for zone in zones {
+            match zone.parent.filter(|p| zones_by_id.contains_key(p)) {
+                Some(parent) => children.entry(parent).or_default().push(zone),
+                None => roots.push(zone),
+            }
+            if let Some(zone_type) = zone.zone_type {
+                by_type.entry(zone_type).or_default().push(zone);
+            }
+        }
+        ZonesHierarchy {
+            zones_by_id,
+            children,
+            by_type,
+            roots,
+        }
+    }
+
+    /// The zone with this id
+    pub fn zone(&self, id: ZoneIndex) -> Option<&'a Zone> {
+        self.zones_by_id.get(&id).copied()
+    }
+
+    /// The parent of a zone
+    pub fn parent_of(&self, id: ZoneIndex) -> Option<&'a Zone> {
+        self.zone(id)?.parent.and_then(|p| self.zone(p))
+    }
+
+    /// The zones whose parent is this zone, in the order of the cosmogony
+    pub fn children_of(&self, id: ZoneIndex) -> &[&'a Zone] {
+        self.children.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    /// The ancestors of a zone: its parent, the parent of its parent... up to a zone without
+    /// parent (usually its country)
+    pub fn parent_chain(&self, id: ZoneIndex) -> Vec<&'a Zone> {
+        let mut chain: Vec<&Zone> = vec![];
+        let mut ancestor = self.parent_of(id);
+        // the chain is bounded, in case of a cycle in the hierarchy
+        while let Some(zone) = ancestor.filter(|_| chain.len() < self.zones_by_id.len()) {
+            chain.push(zone);
+            ancestor = self.parent_of(zone.id);
+        }
+        chain
+    }
+
+    /// The zones of a type, in the order of the cosmogony
+    pub fn zones_of_type(&self, zone_type: ZoneType) -> &[&'a Zone] {
+        self.by_type.get(&zone_type).map_or(&[], Vec::as_slice)
+    }
+
+    /// The zones without parent (the countries and the zones not included in any other one)
+    pub fn roots(&self) -> &[&'a Zone] {
+        &self.roots
+    }
+}
+
+impl Cosmogony {
+    /// Index the hierarchy of the zones, to get the children, the ancestors or the zones of a
+    /// type
+    ///
+    /// The zones are indexed at each call, the hierarchy should be kept to traverse it many
+    /// times.
+    ///
+    /// ```no_run
+    /// # use cosmogony::{load_cosmogony_from_file, ZoneType};
+    /// let cosmogony = load_cosmogony_from_file("cosmogony.json").unwrap();
+    /// let hierarchy = cosmogony.hierarchy();
+    /// for city in hierarchy.zones_of_type(ZoneType::City) {
+    ///     let districts = hierarchy.children_of(city.id);
+    ///     let ancestors = hierarchy.parent_chain(city.id);
+    ///     println!("{}: {} districts, {} ancestors", city.name, districts.len(), ancestors.len());
+    /// }
+    /// ```
+    pub fn hierarchy(&self) -> ZonesHierarchy<'_> {
+        ZonesHierarchy::new(&self.zones)
+    }
+}
@@ -0,0 +1,104 @@
+// The parent and the children zones often share some identical rings (eg. a city equal to its
+// district, or the coast of a country and of its states), written once for each zone.
+//
+// When the rings are interned, each ring is written once, in the first zone using it, with an
+// id. The zones' boundaries are then written as the ids of their rings, so the files are smaller
+// and faster to load. The files must be read in order, as a zone can use the rings defined by
+// the previous zones.
+
+use crate::Zone;
+use anyhow::{anyhow, Error};
+use geo_types::{LineString, MultiPolygon, Polygon};
+use serde_derive::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// The boundary of a zone, as references to interned rings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct InternedBoundary {
+    /// the rings used for the first time by this zone, by id
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rings: BTreeMap<usize, Vec<(f64, f64)>>,
+    /// the polygons of the boundary, as the id of their exterior ring followed by the ids of
+    /// their holes
+    pub polygons: Vec<Vec<usize>>,
+}
+
+/// Replace the boundaries of the zones with references to interned rings
+///
+/// The zones must be written in the order they are interned.
+#[derive(Default)]
+pub struct RingInterner {
+    /// the ids of the rings already written, by coordinates
+    ids: HashMap<Vec<(u64, u64)>, usize>,
+}
+
+impl RingInterner {
+    /// The zone to write, with its boundary interned
+    pub fn intern(&mut self, zone: &Zone) -> Zone {
+        let mut interned = zone.clone();
+        let Some(boundary) = interned.boundary.take() else {
+            return interned;
+        };
+        let mut interned_boundary = InternedBoundary::default();
+        for polygon in &boundary {
+            let rings = std::iter::once(polygon.exterior()).chain(polygon.interiors());
+            let ids = rings.map(|ring| {
+                let key = ring
+                    .coords()
+                    .map(|c| (c.x.to_bits(), c.y.to_bits()))
+                    .collect();
+                let next_id = self.ids.len();
+                *self.ids.entry(key).or_insert_with(|| {
+                    let coords = ring.coords().map(|c| (c.x, c.y)).collect();
+                    interned_boundary.rings.insert(next_id, coords);
+                    next_id
+                })
+            });
+            interned_boundary.polygons.push(ids.collect());
+        }
+        interned.interned_boundary = Some(interned_boundary);
+        interned
+    }
+}
+
+/// Resolve the interned boundaries of the zones read, in the order they have been written
+#[derive(Default)]
+pub struct RingResolver {
+    rings: HashMap<usize, LineString<f64>>,
+}
+
+impl RingResolver {
+    /// Replace the interned boundary of a zone, if any, by its boundary
+    pub fn resolve(&mut self, zone: &mut Zone) -> Result<(), Error> {
+        let Some(interned) = zone.interned_boundary.take() else {
+            return Ok(());
+        };
+        for (id, coords) in interned.rings {
+            self.rings.insert(id, LineString::from(coords));
+        }
+        let ring = |id: &usize| {
+            self.rings.get(id).cloned().ok_or_else(|| {
+                anyhow!(
+                    "{}: the ring {} is used before being defined",
+                    zone.osm_id,
+                    id
+                )
+            })
+        };
+        let polygons = interned
+            .polygons
+            .iter()
+            .map(|ids| {
+                let (exterior, interiors) = ids
+                    .split_first()
+                    .ok_or_else(|| anyhow!("{}: polygon without ring", zone.osm_id))?;
+                Ok(Polygon::new(
+                    ring(exterior)?,
+                    interiors.iter().map(ring).collect::<Result<_, Error>>()?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+        zone.boundary = Some(MultiPolygon(polygons));
+        Ok(())
+    }
+}
@@ -1,9 +1,19 @@
+pub mod diff;
 pub mod file_format;
+mod hierarchy;
+pub mod interning;
 mod model;
 pub mod mutable_slice;
 mod read;
+mod tags;
 mod zone;
 
-pub use model::{Cosmogony, CosmogonyMetadata, CosmogonyStats};
-pub use read::{load_cosmogony_from_file, read_zones_from_file};
-pub use zone::{Coord, Zone, ZoneIndex, ZoneType};
+pub use hierarchy::ZonesHierarchy;
+pub use interning::InternedBoundary;
+pub use model::{
+    BuildWarning, BuildWarningKind, Cosmogony, CosmogonyMetadata, CosmogonyStats, CountDeviation,
+    CountryStats, NameCollision, PhaseDuration, SiblingOverlap, SCHEMA_VERSION,
+};
+pub use read::{load_cosmogony_from_file, read_cosmogony_versioned, read_zones_from_file};
+pub use tags::ZoneTags;
+pub use zone::{AlternateNames, Coord, OsmMetadata, Zone, ZoneIndex, ZoneOrigin, ZoneType};
@@ -1,4 +1,4 @@
-use crate::zone::Zone;
+use crate::zone::{Zone, ZoneType};
 use serde_derive::*;
 use std::collections::BTreeMap;
 use std::fmt;
@@ -9,11 +9,76 @@ pub struct Cosmogony {
     pub meta: CosmogonyMetadata,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+/// The version of the schema of the zones, incremented at each incompatible change
+///
+/// * 1: the files written before the schema was versioned
+/// * 2: the zones have an `origin`
+/// * 3: the boundaries can be written as references to interned rings (`interned_boundary`),
+///   and the zones can have the `borough` type
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// the files without a schema version were written before the versioning
+fn unversioned_schema() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CosmogonyMetadata {
+    /// the version of the schema of the zones, see [`SCHEMA_VERSION`]
+    #[serde(default = "unversioned_schema")]
+    pub schema_version: u32,
     pub osm_filename: String,
     pub stats: CosmogonyStats,
-    // errors:
+    /// the recoverable problems found during the build
+    #[serde(default)]
+    pub warnings: Vec<BuildWarning>,
+}
+
+impl Default for CosmogonyMetadata {
+    fn default() -> Self {
+        CosmogonyMetadata {
+            schema_version: SCHEMA_VERSION,
+            osm_filename: String::new(),
+            stats: CosmogonyStats::default(),
+            warnings: vec![],
+        }
+    }
+}
+
+/// The kinds of the recoverable problems found during the build
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildWarningKind {
+    /// the ways of the boundary do not form closed rings, the zone has no boundary
+    UnclosedRings,
+    /// the admin_centre (or label) member of the zone is not in the osm file
+    MissingAdminCentre,
+    /// several zones of the same type could be the parent of the zone, the smallest is chosen
+    AmbiguousParent,
+    /// the zone duplicates another zone, it has been removed
+    DroppedDuplicate,
+    /// the boundary is clipped by the extract, it has been closed along the extract's bounds
+    ClippedBoundary,
+}
+
+impl BuildWarningKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BuildWarningKind::UnclosedRings => "unclosed_rings",
+            BuildWarningKind::MissingAdminCentre => "missing_admin_centre",
+            BuildWarningKind::AmbiguousParent => "ambiguous_parent",
+            BuildWarningKind::DroppedDuplicate => "dropped_duplicate",
+            BuildWarningKind::ClippedBoundary => "clipped_boundary",
+        }
+    }
+}
+
+/// A recoverable problem of a zone, found during the build
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BuildWarning {
+    pub kind: BuildWarningKind,
+    pub osm_id: String,
+    pub message: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -24,6 +89,110 @@ pub struct CosmogonyStats {
     pub zone_with_unkwown_country_rules: BTreeMap<String, usize>,
     pub unhandled_admin_level: BTreeMap<String, BTreeMap<u32, usize>>,
     pub zone_without_country: usize,
+    /// number of zones by country code
+    #[serde(default)]
+    pub country_zone_counts: BTreeMap<String, u64>,
+    /// the main stats of the zones of each country, by country code
+    #[serde(default)]
+    pub country_stats: BTreeMap<String, CountryStats>,
+    /// number of zones whose country has been guessed from the zones around them
+    #[serde(default)]
+    pub zone_with_guessed_country: usize,
+    #[serde(default)]
+    pub disputed_zones: usize,
+    /// for each extra zone, the osm zones it overlaps
+    #[serde(default)]
+    pub extra_zones_conflicts: BTreeMap<String, Vec<String>>,
+    /// number of invalid boundaries that have been repaired
+    #[serde(default)]
+    pub repaired_geometries: usize,
+    /// number of invalid boundaries that could not be repaired (the zones are discarded)
+    #[serde(default)]
+    pub rejected_geometries: usize,
+    /// number of zones whose boundary polygon could not be built
+    /// (they are discarded unless the geometry-less zones are kept)
+    #[serde(default)]
+    pub zones_without_boundary: usize,
+    /// the zones removed as duplicates of another zone (same name, level and boundary),
+    /// with the osm id of the zone kept instead
+    #[serde(default)]
+    pub duplicate_zones: BTreeMap<String, String>,
+    /// number of zones surrounded by another zone of the same admin_level
+    #[serde(default)]
+    pub enclaves: usize,
+    /// number of enclaves that are not a hole of the boundary surrounding them
+    /// (they are attached to their own hierarchy if possible)
+    #[serde(default)]
+    pub enclaves_without_hole: usize,
+    /// the zones overlapping a sibling (same parent and same type) more than the accepted threshold
+    #[serde(default)]
+    pub sibling_overlaps: Vec<SiblingOverlap>,
+    /// the numbers of zones of a country and type deviating from the expected ones
+    #[serde(default)]
+    pub count_deviations: Vec<CountDeviation>,
+    /// the siblings (same parent) with the same name, whose labels have been disambiguated
+    #[serde(default)]
+    pub name_collisions: Vec<NameCollision>,
+    /// number of boundaries without admin_level whose level has been inferred from their parent
+    #[serde(default)]
+    pub levels_inferred: usize,
+    /// the durations of the phases of the build, in their order
+    #[serde(default)]
+    pub phase_durations: Vec<PhaseDuration>,
+}
+
+/// The duration of a phase of the build: the reading of the osm file, the computation of the
+/// zones inclusions, or a stage of the pipeline (excluding the inclusions it needed)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PhaseDuration {
+    pub phase: String,
+    /// duration of the phase, in seconds
+    pub seconds: f64,
+}
+
+/// The stats of the zones of a country
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CountryStats {
+    pub zone_type_counts: BTreeMap<String, u64>,
+    pub zones_without_boundary: u64,
+    /// number of zones (other than the countries) without a parent
+    pub zones_without_parent: u64,
+}
+
+/// A number of zones of a country and type too far from the expected one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CountDeviation {
+    pub country_code: String,
+    pub zone_type: ZoneType,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// 2 zones with the same parent and the same type, that overlap each other
+///
+/// Siblings should not overlap, it usually is an osm editing mistake.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SiblingOverlap {
+    pub osm_ids: [String; 2],
+    pub zone_type: Option<ZoneType>,
+    /// area of the overlap, in km²
+    pub area: f64,
+    /// ratio of the overlap area over the area of the smallest of the 2 zones
+    pub ratio: f64,
+}
+
+/// Zones with the same parent and the same name
+///
+/// Their labels would be identical, a suffix (their `ref`, type or admin_level) is added to the
+/// labels to tell them apart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NameCollision {
+    pub name: String,
+    /// osm_id of the parent of the zones (None for the zones without parent)
+    pub parent: Option<String>,
+    pub osm_ids: Vec<String>,
+    /// the zones have no distinct `ref`, type nor admin_level, their labels are still identical
+    pub unresolved: bool,
 }
 
 impl CosmogonyStats {
@@ -33,11 +202,33 @@ impl CosmogonyStats {
                 .zone_type
                 .map(|t| format!("{:?}", t))
                 .unwrap_or_else(|| "None".into());
+            if let Some(ref country_code) = zone.country_code {
+                let country_stats = self.country_stats.entry(country_code.clone()).or_default();
+                *country_stats
+                    .zone_type_counts
+                    .entry(type_.clone())
+                    .or_insert(0) += 1;
+                if zone.boundary.is_none() {
+                    country_stats.zones_without_boundary += 1;
+                }
+                if zone.parent.is_none() && zone.zone_type != Some(ZoneType::Country) {
+                    country_stats.zones_without_parent += 1;
+                }
+            }
             let count = self.zone_type_counts.entry(type_).or_insert(0);
             *count += 1;
             let level = zone.admin_level.unwrap_or(0);
             let count = self.level_counts.entry(level).or_insert(0);
             *count += 1;
+            if zone.disputed {
+                self.disputed_zones += 1;
+            }
+            if let Some(ref country_code) = zone.country_code {
+                *self
+                    .country_zone_counts
+                    .entry(country_code.clone())
+                    .or_insert(0) += 1;
+            }
             if zone.wikidata.is_some() {
                 let wd_count = self.wikidata_counts.entry(level).or_insert(0);
                 *wd_count += 1;
@@ -56,6 +247,103 @@ impl fmt::Display for CosmogonyStats {
         for (zone_type, count) in &self.zone_type_counts {
             writeln!(f, "{:?}: {} element(s)", zone_type, count)?;
         }
+        for (country_code, stats) in &self.country_stats {
+            let types: Vec<_> = stats
+                .zone_type_counts
+                .iter()
+                .map(|(zone_type, count)| format!("{}: {}", zone_type, count))
+                .collect();
+            writeln!(
+                f,
+                "Country {}: {} ({} without boundary, {} without parent)",
+                country_code,
+                types.join(", "),
+                stats.zones_without_boundary,
+                stats.zones_without_parent
+            )?;
+        }
+        if self.zone_with_guessed_country > 0 {
+            writeln!(
+                f,
+                "Zones with a country guessed from their neighbors: {}",
+                self.zone_with_guessed_country
+            )?;
+        }
+        if self.disputed_zones > 0 {
+            writeln!(f, "Disputed zones: {}", self.disputed_zones)?;
+        }
+        if !self.extra_zones_conflicts.is_empty() {
+            writeln!(
+                f,
+                "Extra zones overlapping osm zones: {}",
+                self.extra_zones_conflicts.len()
+            )?;
+        }
+        if self.repaired_geometries > 0 || self.rejected_geometries > 0 {
+            writeln!(
+                f,
+                "Invalid geometries: {} repaired, {} rejected",
+                self.repaired_geometries, self.rejected_geometries
+            )?;
+        }
+        if self.zones_without_boundary > 0 {
+            writeln!(f, "Zones without boundary: {}", self.zones_without_boundary)?;
+        }
+        if !self.duplicate_zones.is_empty() {
+            writeln!(f, "Duplicate zones removed: {}", self.duplicate_zones.len())?;
+        }
+        if self.levels_inferred > 0 {
+            writeln!(
+                f,
+                "Boundaries with an inferred admin_level: {}",
+                self.levels_inferred
+            )?;
+        }
+        if self.enclaves > 0 {
+            writeln!(
+                f,
+                "Enclaves: {} ({} without hole in the surrounding zone)",
+                self.enclaves, self.enclaves_without_hole
+            )?;
+        }
+        if !self.sibling_overlaps.is_empty() {
+            writeln!(f, "Overlapping siblings: {}", self.sibling_overlaps.len())?;
+            for o in &self.sibling_overlaps {
+                writeln!(
+                    f,
+                    "    {} / {}: {:.3} km² ({:.1}%)",
+                    o.osm_ids[0],
+                    o.osm_ids[1],
+                    o.area,
+                    o.ratio * 100.
+                )?;
+            }
+        }
+        if !self.count_deviations.is_empty() {
+            writeln!(
+                f,
+                "Unexpected zones counts: {}",
+                self.count_deviations.len()
+            )?;
+            for d in &self.count_deviations {
+                writeln!(
+                    f,
+                    "    {} {}: {} zones, {} expected",
+                    d.country_code,
+                    d.zone_type.as_str(),
+                    d.actual,
+                    d.expected
+                )?;
+            }
+        }
+        if !self.phase_durations.is_empty() {
+            let durations: Vec<_> = self
+                .phase_durations
+                .iter()
+                .map(|d| format!("{}: {:.2}s", d.phase, d.seconds))
+                .collect();
+            writeln!(f, "Durations: {}", durations.join(", "))?;
+        }
 
         Ok(())
     }
@@ -1,5 +1,6 @@
 use crate::file_format::OutputFormat;
-use crate::{Cosmogony, Zone};
+use crate::interning::RingResolver;
+use crate::{Cosmogony, Zone, ZoneOrigin, SCHEMA_VERSION};
 use anyhow::{anyhow, Error};
 use std::path::Path;
 
@@ -7,10 +8,29 @@ use std::path::Path;
 fn read_zones(
     reader: impl std::io::BufRead,
 ) -> impl std::iter::Iterator<Item = Result<Zone, Error>> {
+    let mut resolver = RingResolver::default();
     reader
         .lines()
         .map(|l| l.map_err(|err| err.into()))
         .map(|l| l.and_then(|l| serde_json::from_str(&l).map_err(|err| anyhow!("{}", err))))
+        .map(move |z| {
+            let mut zone = z?;
+            resolver.resolve(&mut zone)?;
+            Ok(zone)
+        })
+}
+
+/// the zones of a cosmogony with their interned boundaries resolved, if any
+///
+/// The cosmogonies of a more recent schema version are rejected, as their zones could be read
+/// incompletely (eg. without their interned boundaries for the readers of the version 2).
+fn resolve_boundaries(mut cosmogony: Cosmogony) -> Result<Cosmogony, Error> {
+    check_schema_version(cosmogony.meta.schema_version)?;
+    let mut resolver = RingResolver::default();
+    for zone in &mut cosmogony.zones {
+        resolver.resolve(zone)?;
+    }
+    Ok(cosmogony)
 }
 
 fn from_json_stream(reader: impl std::io::BufRead) -> Result<Cosmogony, Error> {
@@ -23,6 +43,8 @@ fn from_json_stream(reader: impl std::io::BufRead) -> Result<Cosmogony, Error> {
 }
 
 /// Load a cosmogony from a file
+///
+/// The json cosmogonies written with a more recent version of the schema are rejected.
 pub fn load_cosmogony_from_file(input: impl AsRef<Path>) -> Result<Cosmogony, Error> {
     let format = OutputFormat::from_filename(input.as_ref())?;
     let f = std::fs::File::open(&input)?;
@@ -30,6 +52,13 @@ pub fn load_cosmogony_from_file(input: impl AsRef<Path>) -> Result<Cosmogony, Er
     load_cosmogony(f, format)
 }
 
+impl Cosmogony {
+    /// Load a cosmogony from a file, in any of the json formats (see [`load_cosmogony_from_file`])
+    pub fn load(path: impl AsRef<Path>) -> Result<Cosmogony, Error> {
+        load_cosmogony_from_file(path)
+    }
+}
+
 /// Return an iterator on the zones
 /// if the input file is a jsonstream, the zones are streamed
 /// if the input file is a json, the whole cosmogony is loaded
@@ -40,7 +69,7 @@ pub fn read_zones_from_file(
     let f = std::fs::File::open(input.as_ref())?;
     let f = std::io::BufReader::new(f);
     match format {
-        OutputFormat::JsonGz | OutputFormat::Json => {
+        OutputFormat::JsonGz | OutputFormat::JsonZst | OutputFormat::Json => {
             let cosmo = load_cosmogony(f, format)?;
             Ok(Box::new(cosmo.zones.into_iter().map(Ok)))
         }
@@ -50,6 +79,20 @@ pub fn read_zones_from_file(
             let r = std::io::BufReader::new(r);
             Ok(Box::new(read_zones(r)))
         }
+        OutputFormat::JsonStreamZst => {
+            let r = zstd::stream::read::Decoder::with_buffer(f)?;
+            let r = std::io::BufReader::new(r);
+            Ok(Box::new(read_zones(r)))
+        }
+        OutputFormat::Shapefile => Err(anyhow!("cannot read zones from a shapefile")),
+        OutputFormat::Csv | OutputFormat::Tsv => Err(anyhow!("cannot read zones from a table")),
+        OutputFormat::GeoPackage => Err(anyhow!("cannot read zones from a geopackage")),
+        OutputFormat::Sqlite => Err(anyhow!("cannot read zones from a sqlite database")),
+        OutputFormat::TopoJson => Err(anyhow!("cannot read zones from a topojson file")),
+        OutputFormat::GeoParquet => Err(anyhow!("cannot read zones from a geoparquet file")),
+        OutputFormat::Tree | OutputFormat::TreeStream => {
+            Err(anyhow!("cannot read zones from a tree file"))
+        }
     }
 }
 
@@ -58,14 +101,124 @@ fn load_cosmogony(reader: impl std::io::BufRead, format: OutputFormat) -> Result
     match format {
         OutputFormat::JsonGz => {
             let r = flate2::read::GzDecoder::new(reader);
-            serde_json::from_reader(r).map_err(|err| anyhow!("{}", err))
+            serde_json::from_reader(r)
+                .map_err(|err| anyhow!("{}", err))
+                .and_then(resolve_boundaries)
         }
-        OutputFormat::Json => serde_json::from_reader(reader).map_err(|err| anyhow!("{}", err)),
+        OutputFormat::JsonZst => {
+            let r = zstd::stream::read::Decoder::with_buffer(reader)?;
+            serde_json::from_reader(r)
+                .map_err(|err| anyhow!("{}", err))
+                .and_then(resolve_boundaries)
+        }
+        OutputFormat::Json => serde_json::from_reader(reader)
+            .map_err(|err| anyhow!("{}", err))
+            .and_then(resolve_boundaries),
         OutputFormat::JsonStream => from_json_stream(reader),
         OutputFormat::JsonStreamGz => {
             let r = flate2::bufread::GzDecoder::new(reader);
             let r = std::io::BufReader::new(r);
             from_json_stream(r)
         }
+        OutputFormat::JsonStreamZst => {
+            let r = zstd::stream::read::Decoder::with_buffer(reader)?;
+            from_json_stream(std::io::BufReader::new(r))
+        }
+        OutputFormat::Shapefile => Err(anyhow!("cannot read a cosmogony from a shapefile")),
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            Err(anyhow!("cannot read a cosmogony from a table"))
+        }
+        OutputFormat::GeoPackage => Err(anyhow!("cannot read a cosmogony from a geopackage")),
+        OutputFormat::Sqlite => Err(anyhow!("cannot read a cosmogony from a sqlite database")),
+        OutputFormat::TopoJson => Err(anyhow!("cannot read a cosmogony from a topojson file")),
+        OutputFormat::GeoParquet => Err(anyhow!("cannot read a cosmogony from a geoparquet file")),
+        OutputFormat::Tree | OutputFormat::TreeStream => {
+            Err(anyhow!("cannot read a cosmogony from a tree file"))
+        }
+    }
+}
+
+fn check_schema_version(version: u32) -> Result<(), Error> {
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "the cosmogony has the schema version {}, this version of cosmogony can only read \
+             the versions up to {}",
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Upgrade a zone read with a previous version of the schema to the current one
+fn upgrade_zone(zone: &mut Zone, version: u32) {
+    if version < 2 && zone.origin.is_none() && zone.boundary.is_some() {
+        // the boundaries of the zones built from the place nodes were their voronoi cells
+        zone.origin = Some(if zone.osm_id.starts_with("relation:") {
+            ZoneOrigin::OsmRelation
+        } else if zone.osm_id.starts_with("way:") {
+            ZoneOrigin::OsmWay
+        } else {
+            ZoneOrigin::Voronoi
+        });
+    }
+}
+
+/// the version of the schema of a zone of a json stream, as the streams have no metadata
+fn stream_zone_schema_version(zone: &serde_json::Value) -> u32 {
+    if zone.get("interned_boundary").is_some() {
+        3
+    } else if zone.get("origin").is_some() {
+        SCHEMA_VERSION
+    } else {
+        1
+    }
+}
+
+fn from_versioned_json_stream(reader: impl std::io::BufRead) -> Result<Cosmogony, Error> {
+    let mut resolver = RingResolver::default();
+    let zones = reader
+        .lines()
+        .map(|l| {
+            let value: serde_json::Value = serde_json::from_str(&l?)?;
+            let version = stream_zone_schema_version(&value);
+            let mut zone: Zone = serde_json::from_value(value)?;
+            resolver.resolve(&mut zone)?;
+            upgrade_zone(&mut zone, version);
+            Ok(zone)
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(Cosmogony {
+        zones,
+        ..Default::default()
+    })
+}
+
+/// Load a cosmogony written with the current or a previous version of the schema (see
+/// [`SCHEMA_VERSION`]), its zones being upgraded to the current version
+///
+/// The cosmogonies written by a more recent version of the schema are rejected.
+pub fn read_cosmogony_versioned(input: impl AsRef<Path>) -> Result<Cosmogony, Error> {
+    let format = OutputFormat::from_filename(input.as_ref())?;
+    let f = std::io::BufReader::new(std::fs::File::open(&input)?);
+    match format {
+        OutputFormat::JsonStream => from_versioned_json_stream(f),
+        OutputFormat::JsonStreamGz => {
+            let r = flate2::bufread::GzDecoder::new(f);
+            from_versioned_json_stream(std::io::BufReader::new(r))
+        }
+        OutputFormat::JsonStreamZst => {
+            let r = zstd::stream::read::Decoder::with_buffer(f)?;
+            from_versioned_json_stream(std::io::BufReader::new(r))
+        }
+        _ => {
+            let mut cosmogony = load_cosmogony(f, format)?;
+            let version = cosmogony.meta.schema_version;
+            for zone in &mut cosmogony.zones {
+                upgrade_zone(zone, version);
+            }
+            cosmogony.meta.schema_version = SCHEMA_VERSION;
+            Ok(cosmogony)
+        }
     }
 }
@@ -0,0 +1,151 @@
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The number of shards of the interner, so the zones deserialized in parallel rarely wait for
+/// each other
+const SHARDS: usize = 64;
+
+/// A shard of the interner
+#[derive(Default)]
+struct Shard {
+    strings: HashSet<Arc<str>>,
+    /// the number of strings after the last removal of the unused ones
+    used: usize,
+}
+
+impl Shard {
+    fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(s) = self.strings.get(s) {
+            return s.clone();
+        }
+        // the strings only referenced by the interner are not used by any zone anymore. They are
+        // removed when the shard has doubled, so the interner does not keep the strings of the
+        // cosmogonies dropped
+        if self.strings.len() >= 2 * self.used.max(64) {
+            self.strings.retain(|s| Arc::strong_count(s) > 1);
+            self.used = self.strings.len();
+        }
+        let s: Arc<str> = s.into();
+        self.strings.insert(s.clone());
+        s
+    }
+}
+
+/// The strings used by the tags of all the zones.
+///
+/// Most of the keys and many values (`boundary`, `administrative`, `admin_level`, ...) are
+/// shared by lots of zones, so they are only stored once.
+fn intern(s: &str) -> Arc<str> {
+    static INTERNED: OnceLock<Vec<Mutex<Shard>>> = OnceLock::new();
+    let shards = INTERNED.get_or_init(|| (0..SHARDS).map(|_| Default::default()).collect());
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    shards[hasher.finish() as usize % SHARDS]
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .intern(s)
+}
+
+/// The osm tags of a zone, sorted by key
+///
+/// The keys and values are interned, which reduces a lot the memory used by the zones.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ZoneTags(Vec<(Arc<str>, Arc<str>)>);
+
+impl ZoneTags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn position(&self, key: &str) -> Result<usize, usize> {
+        self.0.binary_search_by(|(k, _)| (**k).cmp(key))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.position(key).ok().map(|idx| &*self.0[idx].1)
+    }
+
+    pub fn contains(&self, key: &str, value: &str) -> bool {
+        self.get(key) == Some(value)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_ok()
+    }
+
+    /// set the value of a tag, replacing the previous value if any
+    pub fn insert(&mut self, key: &str, value: &str) {
+        match self.position(key) {
+            Ok(idx) => self.0[idx].1 = intern(value),
+            Err(idx) => self.0.insert(idx, (intern(key), intern(value))),
+        }
+    }
+
+    /// set the value of a tag, only if the tag is not already set
+    pub fn insert_if_absent(&mut self, key: &str, value: &str) {
+        if let Err(idx) = self.position(key) {
+            self.0.insert(idx, (intern(key), intern(value)));
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Arc<str>> {
+        self.position(key).ok().map(|idx| self.0.remove(idx).1)
+    }
+
+    /// only keep the tags for which `f` returns true
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &str) -> bool) {
+        self.0.retain(|(k, v)| f(k, v))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (&**k, &**v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<K: AsRef<str>, V: AsRef<str>> FromIterator<(K, V)> for ZoneTags {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tags = ZoneTags::new();
+        for (k, v) in iter {
+            tags.insert(k.as_ref(), v.as_ref());
+        }
+        tags
+    }
+}
+
+impl From<&osmpbfreader::Tags> for ZoneTags {
+    fn from(tags: &osmpbfreader::Tags) -> Self {
+        tags.iter().collect()
+    }
+}
+
+impl fmt::Debug for ZoneTags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for ZoneTags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for ZoneTags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let tags = BTreeMap::<String, String>::deserialize(deserializer)?;
+        Ok(tags.iter().collect())
+    }
+}
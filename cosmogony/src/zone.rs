@@ -1,7 +1,8 @@
+use crate::interning::InternedBoundary;
 use crate::mutable_slice::MutableSlice;
+use crate::tags::ZoneTags;
 use geo_types::{Coordinate, Geometry, MultiPolygon, Point, Rect};
 use log::warn;
-use osmpbfreader::objects::Tags;
 use serde::Serialize;
 use serde_derive::*;
 use std::collections::BTreeMap;
@@ -14,6 +15,7 @@ pub type Coord = Point<f64>;
 pub enum ZoneType {
     Suburb,
     CityDistrict,
+    Borough,
     City,
     StateDistrict,
     State,
@@ -27,6 +29,7 @@ impl ZoneType {
         match *self {
             ZoneType::Suburb => "suburb",
             ZoneType::CityDistrict => "city_district",
+            ZoneType::Borough => "borough",
             ZoneType::City => "city",
             ZoneType::StateDistrict => "state_district",
             ZoneType::State => "state",
@@ -40,6 +43,7 @@ impl ZoneType {
         Some(match s {
             "suburb" | "quarter" | "neighbourhood" => Self::Suburb,
             "city_district" => Self::CityDistrict,
+            "borough" => Self::Borough,
             "city" | "town" | "village" => Self::City,
             "state_district" => Self::StateDistrict,
             "state" => Self::State,
@@ -51,11 +55,85 @@ impl ZoneType {
     }
 }
 
+/// Where the boundary of a zone comes from
+#[derive(Serialize, Deserialize, Copy, Debug, Clone, Eq, Hash, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneOrigin {
+    /// an osm relation
+    OsmRelation,
+    /// a closed osm way
+    OsmWay,
+    /// a voronoi cell around an osm place, an approximation of the real boundary
+    Voronoi,
+    /// the residential landuses around an osm place, an approximation of the real boundary
+    ResidentialLanduse,
+}
+
+/// The metadata of the osm object of a zone, to know how stale its boundary is
+#[derive(Serialize, Deserialize, Copy, Debug, Clone, Eq, Hash, PartialEq)]
+pub struct OsmMetadata {
+    /// version of the osm object
+    pub version: u32,
+    /// changeset of the last version of the osm object
+    pub changeset: i64,
+    /// date of the last version of the osm object, in seconds since the unix epoch
+    pub timestamp: i64,
+    /// date of the last version of the object, of one of its ways or of one of their nodes (a
+    /// relation's version does not change when its ways are moved), in seconds since the unix
+    /// epoch
+    pub last_modified: i64,
+}
+
+impl ZoneOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ZoneOrigin::OsmRelation => "osm_relation",
+            ZoneOrigin::OsmWay => "osm_way",
+            ZoneOrigin::Voronoi => "voronoi",
+            ZoneOrigin::ResidentialLanduse => "residential_landuse",
+        }
+    }
+}
+
 #[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct ZoneIndex {
     pub index: usize,
 }
 
+/// The alternate names of a zone, by language (`default` for the tag without language, like
+/// `official_name`)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AlternateNames {
+    /// the `official_name` tags
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub official: BTreeMap<String, String>,
+    /// the `short_name` tags
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub short: BTreeMap<String, String>,
+    /// the `alt_name` tags (several names can be separated by `;`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alt: BTreeMap<String, String>,
+    /// the `old_name` tags
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub old: BTreeMap<String, String>,
+}
+
+impl AlternateNames {
+    pub fn is_empty(&self) -> bool {
+        self.official.is_empty()
+            && self.short.is_empty()
+            && self.alt.is_empty()
+            && self.old.is_empty()
+    }
+
+    /// All the alternate names, of all the kinds and languages
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        [&self.official, &self.short, &self.alt, &self.old]
+            .into_iter()
+            .flat_map(|names| names.values().map(String::as_str))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Zone {
     pub id: ZoneIndex,
@@ -69,41 +147,133 @@ pub struct Zone {
     pub label: String,
     #[serde(default)]
     pub international_labels: BTreeMap<String, String>,
+    /// the label in a secondary language next to the local name (eg. "東京都, Tokyo, Japan"),
+    /// only built if asked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secondary_label: Option<String>,
     // we do not serialize the internal_names,
     // it's only used temporary to build the international_labels
     #[serde(skip)]
     pub international_names: BTreeMap<String, String>, // TODO can we store it outside the zone ?
+    /// the official, short, alternative and old names of the zone (only extracted if asked)
+    #[serde(default, skip_serializing_if = "AlternateNames::is_empty")]
+    pub alternate_names: AlternateNames,
     pub zip_codes: Vec<String>,
     #[serde(
         serialize_with = "serialize_as_geojson",
         deserialize_with = "deserialize_as_coord"
     )]
     pub center: Option<Coord>,
+    /// position of the `admin_centre` (or `label`) member node of the relation
     #[serde(
         serialize_with = "serialize_as_geojson",
-        deserialize_with = "deserialize_as_multipolygon",
-        rename = "geometry",
+        deserialize_with = "deserialize_as_coord",
         default
     )]
-    pub boundary: Option<geo_types::MultiPolygon<f64>>,
-
+    pub admin_centre: Option<Coord>,
+    /// name of the `admin_centre` (or `label`) member node of the relation
+    #[serde(default)]
+    pub admin_centre_name: Option<String>,
+    /// bbox of the boundary (or the center of the zones without boundary), as
+    /// `[min_lon, min_lat, max_lon, max_lat]`, written before the geometry so it can be read
+    /// without parsing the geometry
     #[serde(
         serialize_with = "serialize_bbox_as_geojson",
         deserialize_with = "deserialize_as_rect",
         default
     )]
     pub bbox: Option<Rect<f64>>,
+    #[serde(
+        serialize_with = "serialize_as_geojson",
+        deserialize_with = "deserialize_as_multipolygon",
+        rename = "geometry",
+        default
+    )]
+    pub boundary: Option<geo_types::MultiPolygon<f64>>,
+    /// the boundary as references to interned rings, only in the files written with the rings
+    /// interned (it is resolved into `boundary` when the file is read)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interned_boundary: Option<InternedBoundary>,
 
-    pub tags: Tags,
-    #[serde(default = "Tags::new")] //to keep the retrocompatibility with cosmogony2mimir
-    pub center_tags: Tags,
+    pub tags: ZoneTags,
+    #[serde(default)] //to keep the retrocompatibility with cosmogony2mimir
+    pub center_tags: ZoneTags,
 
     pub parent: Option<ZoneIndex>,
+    /// all the zones that could be the parent of the zone (eg. the 2 counties of a city spanning
+    /// both), `parent` first (only filled if the multi parents hierarchy is enabled)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<ZoneIndex>,
+    /// all the zones whose boundary contains the zone, whatever their type, the smallest first
+    /// (only filled if the inclusions are kept)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub included_in: Vec<ZoneIndex>,
+    /// the zones of the same type sharing a border with the zone (only computed if asked)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub neighbors: Vec<ZoneIndex>,
     pub wikidata: Option<String>,
     // pub links: Vec<ZoneIndex>
     #[serde(default)]
     pub is_generated: bool,
+    /// where the boundary of the zone comes from
+    /// (None for the zones without boundary, and in the older cosmogonies)
+    #[serde(default)]
+    pub origin: Option<ZoneOrigin>,
     pub country_code: Option<String>,
+    /// the zone is a disputed territory (tagged `boundary=disputed` or claimed by several countries)
+    #[serde(default)]
+    pub disputed: bool,
+    /// the zone is surrounded by another zone of the same admin_level (eg. Llívia or Baarle)
+    #[serde(default)]
+    pub is_enclave: bool,
+    /// the boundary of the zone is clipped by the extract, closed along the extract's bounds
+    /// (only with the partial assembly of the boundaries)
+    #[serde(default)]
+    pub clipped: bool,
+    /// the zone has no admin_level tag, its admin_level is the one of its parent + 1
+    /// (only if the boundaries without admin_level are kept)
+    #[serde(default)]
+    pub level_inferred: bool,
+    /// `start_date` tag of the zone
+    #[serde(default)]
+    pub start_date: Option<String>,
+    /// `end_date` tag of the zone
+    #[serde(default)]
+    pub end_date: Option<String>,
+    /// the zone no longer exists (its `end_date` is past, or its tags have a lifecycle prefix
+    /// like `was:`), only kept if the historic zones are kept
+    #[serde(default)]
+    pub historic: bool,
+    /// ISO3166-1:alpha2 codes of the countries claiming the zone (only set on disputed zones)
+    #[serde(default)]
+    pub claimed_by: Vec<String>,
+    /// osm_id of the zones from another dataset overlapping this zone
+    /// (only filled when the extra zones are blended keeping both zones)
+    #[serde(default)]
+    pub overlaps: Vec<String>,
+    /// population of the zone, from the osm `population` tag
+    /// (or aggregated from the sub zones if the population propagation is enabled)
+    #[serde(default)]
+    pub population: Option<u64>,
+    /// geodesic area of the boundary, in km²
+    /// (the area and the perimeter are the ones of the simplified boundary, if the boundaries are
+    /// simplified)
+    #[serde(default)]
+    pub area: Option<f64>,
+    /// geodesic perimeter of the boundary (holes included), in km
+    #[serde(default)]
+    pub perimeter: Option<f64>,
+    /// IANA timezone of the zone's center (eg. `Europe/Paris`)
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// ids of the h3 cells covering the zone (the cells whose center is in its boundary), only
+    /// computed if asked, at the asked resolution
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub h3_cells: Vec<String>,
+    /// version, changeset and dates of the osm object of the zone (only read if asked, and if
+    /// the osm file has the metadata)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osm_metadata: Option<OsmMetadata>,
 }
 
 impl Default for Zone {
@@ -119,16 +289,40 @@ impl Default for Zone {
             label: "".into(),
             international_labels: BTreeMap::default(),
             international_names: BTreeMap::default(),
+            alternate_names: AlternateNames::default(),
+            secondary_label: None,
             center: None,
+            admin_centre: None,
+            admin_centre_name: None,
             boundary: None,
+            interned_boundary: None,
             bbox: None,
             parent: None,
-            tags: Tags::new(),
-            center_tags: Tags::new(),
+            parents: vec![],
+            included_in: vec![],
+            neighbors: vec![],
+            tags: ZoneTags::new(),
+            center_tags: ZoneTags::new(),
             wikidata: None,
             zip_codes: vec![],
             is_generated: true,
+            origin: None,
             country_code: None,
+            disputed: false,
+            is_enclave: false,
+            clipped: false,
+            level_inferred: false,
+            start_date: None,
+            end_date: None,
+            historic: false,
+            claimed_by: vec![],
+            overlaps: vec![],
+            population: None,
+            area: None,
+            perimeter: None,
+            timezone: None,
+            h3_cells: vec![],
+            osm_metadata: None,
         }
     }
 }
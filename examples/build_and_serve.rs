@@ -0,0 +1,96 @@
+//! Build a cosmogony from an osm file and serve a minimal reverse geocoding http api.
+//!
+//! ```sh
+//! cargo run --release --example build_and_serve -- <osm-file.pbf> [<address>]
+//! curl 'http://127.0.0.1:8080/reverse?lon=6.13&lat=49.61'
+//! ```
+//!
+//! The response is the list of the zones containing the point, from the smallest to the largest.
+
+use anyhow::{anyhow, Context, Result};
+use cosmogony::Zone;
+use cosmogony_builder::{build_cosmogony, BuildOptions, CosmogonyIndex};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+fn parse_query(path: &str) -> Result<(f64, f64)> {
+    let query = path
+        .strip_prefix("/reverse?")
+        .ok_or_else(|| anyhow!("unknown route {}", path))?;
+    let param = |name: &str| -> Result<f64> {
+        query
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| *k == name)
+            .ok_or_else(|| anyhow!("missing parameter {}", name))?
+            .1
+            .parse()
+            .with_context(|| format!("invalid parameter {}", name))
+    };
+    Ok((param("lon")?, param("lat")?))
+}
+
+fn to_json(zone: &Zone) -> serde_json::Value {
+    json!({
+        "osm_id": zone.osm_id,
+        "name": zone.name,
+        "zone_type": zone.zone_type.map(|t| t.as_str()),
+        "label": zone.label,
+    })
+}
+
+fn handle(stream: TcpStream, index: &CosmogonyIndex<'_>) -> Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, body) = match parse_query(path) {
+        Ok((lon, lat)) => {
+            let zones: Vec<_> = index
+                .zones_containing(lon, lat)
+                .into_iter()
+                .map(to_json)
+                .collect();
+            ("200 OK", serde_json::to_string(&zones)?)
+        }
+        Err(e) => (
+            "400 Bad Request",
+            json!({ "error": e.to_string() }).to_string(),
+        ),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let pbf = args
+        .next()
+        .ok_or_else(|| anyhow!("usage: build_and_serve <osm-file.pbf> [<address>]"))?;
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:8080".into());
+
+    let cosmogony = build_cosmogony(pbf, &BuildOptions::default())?;
+    let index = CosmogonyIndex::from(&cosmogony);
+
+    let listener = TcpListener::bind(&address)?;
+    println!(
+        "{} zones served on http://{}",
+        cosmogony.zones.len(),
+        address
+    );
+    for stream in listener.incoming() {
+        if let Err(e) = stream.map_err(Into::into).and_then(|s| handle(s, &index)) {
+            eprintln!("error while handling a request: {}", e);
+        }
+    }
+    Ok(())
+}
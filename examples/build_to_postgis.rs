@@ -0,0 +1,117 @@
+//! Build a cosmogony from an osm file and load it in a PostGIS database.
+//!
+//! ```sh
+//! cargo run --release --example build_to_postgis -- <osm-file.pbf> | psql <database>
+//! ```
+//!
+//! The zones are written as a sql script creating a `cosmogony_zone` table, the zones being
+//! loaded with a `COPY` (much faster than inserts for large extracts).
+
+use anyhow::{anyhow, Result};
+use cosmogony::{Zone, ZoneIndex};
+use cosmogony_builder::{build_cosmogony, BuildOptions};
+use geo_types::{LineString, MultiPolygon};
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+
+const CREATE_TABLE: &str = "\
+CREATE TABLE IF NOT EXISTS cosmogony_zone (
+    osm_id text PRIMARY KEY,
+    parent_osm_id text,
+    name text NOT NULL,
+    label text NOT NULL,
+    zone_type text,
+    admin_level integer,
+    country_code text,
+    wikidata text,
+    boundary geometry(MultiPolygon, 4326)
+);
+";
+
+/// escape a value for the text format of `COPY`
+fn copy_value(value: Option<&str>) -> String {
+    match value {
+        None => "\\N".into(),
+        Some(v) => v
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r"),
+    }
+}
+
+fn ring_wkt(ring: &LineString<f64>) -> String {
+    let coords: Vec<_> = ring.0.iter().map(|c| format!("{} {}", c.x, c.y)).collect();
+    format!("({})", coords.join(","))
+}
+
+fn to_ewkt(mpoly: &MultiPolygon<f64>) -> String {
+    let polygons: Vec<_> = mpoly
+        .0
+        .iter()
+        .map(|p| {
+            let rings: Vec<_> = std::iter::once(p.exterior())
+                .chain(p.interiors())
+                .map(ring_wkt)
+                .collect();
+            format!("({})", rings.join(","))
+        })
+        .collect();
+    format!("SRID=4326;MULTIPOLYGON({})", polygons.join(","))
+}
+
+fn copy_row(zone: &Zone, osm_ids: &BTreeMap<ZoneIndex, &str>) -> String {
+    let parent = zone.parent.and_then(|p| osm_ids.get(&p)).copied();
+    let admin_level = zone.admin_level.map(|l| l.to_string());
+    let boundary = zone.boundary.as_ref().map(to_ewkt);
+    [
+        Some(zone.osm_id.as_str()),
+        parent,
+        Some(zone.name.as_str()),
+        Some(zone.label.as_str()),
+        zone.zone_type.map(|t| t.as_str()),
+        admin_level.as_deref(),
+        zone.country_code.as_deref(),
+        zone.wikidata.as_deref(),
+        boundary.as_deref(),
+    ]
+    .into_iter()
+    .map(copy_value)
+    .collect::<Vec<_>>()
+    .join("\t")
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let pbf = std::env::args()
+        .nth(1)
+        .ok_or_else(|| anyhow!("usage: build_to_postgis <osm-file.pbf>"))?;
+    let cosmogony = build_cosmogony(pbf, &BuildOptions::default())?;
+
+    // the zones ids are only meaningful in the cosmogony, the parents are referenced by osm id
+    let osm_ids: BTreeMap<_, _> = cosmogony
+        .zones
+        .iter()
+        .map(|z| (z.id, z.osm_id.as_str()))
+        .collect();
+
+    let mut output = BufWriter::new(std::io::stdout().lock());
+    writeln!(output, "BEGIN;")?;
+    write!(output, "{}", CREATE_TABLE)?;
+    writeln!(
+        output,
+        "COPY cosmogony_zone (osm_id, parent_osm_id, name, label, zone_type, admin_level, \
+         country_code, wikidata, boundary) FROM stdin;"
+    )?;
+    for zone in &cosmogony.zones {
+        writeln!(output, "{}", copy_row(zone, &osm_ids))?;
+    }
+    writeln!(output, "\\.")?;
+    writeln!(
+        output,
+        "CREATE INDEX IF NOT EXISTS cosmogony_zone_boundary_idx ON cosmogony_zone USING gist (boundary);"
+    )?;
+    writeln!(output, "COMMIT;")?;
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Find the zones of a batch of coordinates in an existing cosmogony file.
+//!
+//! ```sh
+//! cargo run --release --example reverse_lookup_batch -- cosmogony.jsonl [<lang>] < points.csv
+//! ```
+//!
+//! Each input line is a `lon,lat` pair, and each output line is `lon,lat,osm_id,label` where
+//! the zone is the smallest zone containing the point (the label is in the given language
+//! if it is available).
+
+use anyhow::{anyhow, Context, Result};
+use cosmogony::Zone;
+use cosmogony_builder::{canonical_lang_tag, CosmogonyIndex};
+use std::io::{BufRead, BufWriter, Write};
+
+fn label<'a>(zone: &'a Zone, lang: Option<&str>) -> &'a str {
+    lang.and_then(|l| zone.international_labels.get(l))
+        .unwrap_or(&zone.label)
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let cosmogony_file = args
+        .next()
+        .ok_or_else(|| anyhow!("usage: reverse_lookup_batch <cosmogony-file> [<lang>]"))?;
+    let lang = args
+        .next()
+        .map(|l| canonical_lang_tag(&l).ok_or_else(|| anyhow!("invalid language {}", l)))
+        .transpose()?;
+
+    let cosmogony = cosmogony::load_cosmogony_from_file(&cosmogony_file)?;
+    let index = CosmogonyIndex::from(&cosmogony);
+
+    let mut output = BufWriter::new(std::io::stdout().lock());
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let (lon, lat) = line
+            .split_once(',')
+            .ok_or_else(|| anyhow!("invalid line '{}', expected 'lon,lat'", line))?;
+        let (lon, lat): (f64, f64) = (
+            lon.trim().parse().context("invalid longitude")?,
+            lat.trim().parse().context("invalid latitude")?,
+        );
+        match index.zones_containing(lon, lat).first() {
+            Some(zone) => writeln!(
+                output,
+                "{},{},{},\"{}\"",
+                lon,
+                lat,
+                zone.osm_id,
+                label(zone, lang.as_deref()).replace('"', "\"\"")
+            )?,
+            None => writeln!(output, "{},{},,", lon, lat)?,
+        }
+    }
+    Ok(())
+}
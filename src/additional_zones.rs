@@ -1,15 +1,20 @@
 use crate::hierarchy_builder::ZonesTree;
-use crate::is_place;
+use crate::is_extracted_place;
+use crate::options::{AdditionalZonesStrategy, BuildOptions};
+use crate::osm_store::OsmObjects;
+use crate::residential_landuse::ResidentialAreas;
 use crate::zone_ext::ZoneExt;
 use anyhow::{Context, Result};
-use cosmogony::{Zone, ZoneIndex, ZoneType};
+use cosmogony::{Zone, ZoneIndex, ZoneOrigin, ZoneType};
 use geo::prelude::BoundingRect;
 use geo_types::{Coordinate, MultiPolygon, Point, Rect};
 use geos::{Geom, Geometry};
 use itertools::Itertools;
-use osmpbfreader::{OsmId, OsmObj};
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
-use std::collections::BTreeMap;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
+    IntoParallelRefMutIterator, ParallelIterator,
+};
+use std::collections::{BTreeMap, BTreeSet};
 
 fn difference<'a>(g: &geos::Geometry<'a>, other: &Zone) -> Option<geos::Geometry<'a>> {
     let zone_as_geos: Option<Geometry> = other.boundary.as_ref().and_then(|b| {
@@ -33,10 +38,16 @@ fn difference<'a>(g: &geos::Geometry<'a>, other: &Zone) -> Option<geos::Geometry
 
 pub fn compute_additional_places(
     zones: &mut Vec<Zone>,
-    parsed_pbf: &BTreeMap<OsmId, OsmObj>,
+    parsed_pbf: &dyn OsmObjects,
     zones_rtree: ZonesTree,
-) {
-    let place_zones = read_places(parsed_pbf);
+    options: &BuildOptions,
+) -> Result<()> {
+    // the cells are clipped to the parent of the places, which cannot be a country
+    let max_parent_type = options
+        .voronoi_clip_level
+        .unwrap_or(ZoneType::CountryRegion);
+    let mut place_zones = read_places(parsed_pbf, options);
+    add_boundaryless_zones(&mut place_zones, zones);
 
     info!(
         "there are {} places, we'll try to make boundaries for them",
@@ -57,12 +68,20 @@ pub fn compute_additional_places(
                             "Ignoring place with id {} and country {} as parent",
                             place.osm_id, parent.osm_id
                         );
+                    } else if parent_zone > max_parent_type {
+                        debug!(
+                            "Ignoring place with id {}, its parent {} is larger than a {}",
+                            place.osm_id,
+                            parent.osm_id,
+                            max_parent_type.as_str()
+                        );
                     }
 
                     // Ensuring zones are stricly increasing also ensures there will be no
                     // duplicates, for example by adding an admin label which is inside its
                     // boundary.
                     parent_zone > place.zone_type.unwrap_or(parent_zone)
+                        && parent_zone <= max_parent_type
                         && parent_zone < ZoneType::Country
                 })
                 .unwrap_or(false)
@@ -83,16 +102,60 @@ pub fn compute_additional_places(
         candidate_parent_zones.len()
     );
 
-    let new_cities: Vec<Zone> = {
+    let mut new_cities: Vec<(Zone, Vec<ZoneIndex>)> = {
         candidate_parent_zones
             .into_par_iter()
             .filter(|(_, places)| !places.is_empty())
-            .map(|(parent, places)| compute_voronoi(parent, &places, zones, &zones_rtree))
+            .map(|(parent, places)| {
+                options.cancellation.check()?;
+                Ok(compute_voronoi(parent, &places, zones, &zones_rtree))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
             .flatten()
             .collect()
     };
 
+    if options.additional_zones_strategy == AdditionalZonesStrategy::Landuse {
+        let landuses = ResidentialAreas::read(parsed_pbf);
+        let restricted = new_cities
+            .par_iter_mut()
+            .map(|(city, suburbs)| {
+                let suburbs: Vec<&Zone> = suburbs.iter().map(|s| &zones[s.index]).collect();
+                landuses.restrict_to_landuses(city, &suburbs)
+            })
+            .filter(|restricted| *restricted)
+            .count();
+        info!(
+            "{} places restricted to their residential landuses",
+            restricted
+        );
+    }
+
     publish_new_places(zones, new_cities);
+    Ok(())
+}
+
+/// The zones whose boundary could not be built are also given a voronoi cell,
+/// with their admin_centre as seed.
+///
+/// They replace the place node of their admin_centre, since the relation
+/// has more information (the osm id, the admin_level, ...).
+fn add_boundaryless_zones(places: &mut Vec<Zone>, zones: &[Zone]) {
+    let boundaryless: Vec<&Zone> = zones
+        .iter()
+        .filter(|z| z.boundary.is_none() && z.zone_type.is_some() && z.admin_centre.is_some())
+        .collect();
+    if boundaryless.is_empty() {
+        return;
+    }
+    let seed = |c: Point<f64>| (c.x().to_bits(), c.y().to_bits());
+    let admin_centres: BTreeSet<_> = boundaryless
+        .iter()
+        .filter_map(|z| z.admin_centre.map(seed))
+        .collect();
+    places.retain(|p| !p.center.is_some_and(|c| admin_centres.contains(&seed(c))));
+    places.extend(boundaryless.into_iter().cloned());
 }
 
 fn get_parent<'a>(place: &Zone, zones: &'a [Zone], zones_rtree: &ZonesTree) -> Option<&'a Zone> {
@@ -115,18 +178,24 @@ fn get_parent<'a>(place: &Zone, zones: &'a [Zone], zones_rtree: &ZonesTree) -> O
         .find(|z| z.contains_center(place))
 }
 
-fn read_places(parsed_pbf: &BTreeMap<OsmId, OsmObj>) -> Vec<Zone> {
+fn read_places(parsed_pbf: &dyn OsmObjects, options: &BuildOptions) -> Vec<Zone> {
+    let place_types = &options.place_types;
     parsed_pbf
-        .values()
+        .objects()
         .enumerate()
         .filter_map(|(index, obj)| {
-            if !is_place(obj) {
+            if !is_extracted_place(&obj, options) {
                 return None;
             }
 
             let node = obj.node()?;
             let next_index = ZoneIndex { index };
             let mut zone = Zone::from_osm_node(node, next_index)?;
+            // some place values (like hamlet) are not known by the zone type parser
+            zone.zone_type = node
+                .tags
+                .get("place")
+                .and_then(|p| place_types.zone_type(p));
 
             if zone.name.is_empty() {
                 return None;
@@ -152,7 +221,7 @@ fn read_places(parsed_pbf: &BTreeMap<OsmId, OsmObj>) -> Vec<Zone> {
         .collect()
 }
 
-fn convert_to_geo(geom: Geometry<'_>) -> Result<Option<MultiPolygon<f64>>> {
+pub(crate) fn convert_to_geo(geom: Geometry<'_>) -> Result<Option<MultiPolygon<f64>>> {
     let is_empty_poly = |poly: &geo::Polygon| poly.exterior().lines().next().is_none();
 
     let polys = match geom.try_into().context("failed to convert to geo")? {
@@ -241,28 +310,38 @@ fn get_places_to_subtract<'a>(
         .into_par_iter()
         .map(|z_idx| &zones[z_idx.index])
         .filter(|z| {
+            // the suburbs of the parent are kept inside the places
             z.admin_type()
-                .map(|zt| Some(zt) == zone.zone_type || z.parent == Some(*parent_id))
+                .map(|zt| {
+                    Some(zt) == zone.zone_type
+                        || (z.parent == Some(*parent_id)
+                            && !is_suburb_of(z, &zones[parent_id.index]))
+                })
                 .unwrap_or(false)
         })
         .filter(|z| zone.intersects(z))
         .collect()
 }
 
+/// Compute the boundaries of the places inside a parent zone
+///
+/// Returns the new zones, with the existing suburbs each of them contains.
 fn compute_voronoi(
     parent: &ZoneIndex,
     places: &[&Zone],
     zones: &[Zone],
     zones_rtree: &ZonesTree,
-) -> Vec<Zone> {
+) -> Vec<(Zone, Vec<ZoneIndex>)> {
     let points: Vec<(usize, Point<_>)> = places
         .iter()
         .enumerate()
-        .filter_map(|(idx, p)| p.center.map(|c| (idx, c)))
+        .filter_map(|(idx, p)| p.admin_centre.or(p.center).map(|c| (idx, c)))
         .collect();
 
     let parent_index = parent.index;
     let parent = &zones[parent_index];
+    // the synthetic places cannot split the existing suburbs
+    let suburbs = get_suburbs(parent, zones, zones_rtree);
 
     if points.len() == 1 {
         let mut place = places[0].clone();
@@ -272,7 +351,7 @@ fn compute_voronoi(
         let zones_to_subtract = get_places_to_subtract(parent, &parent.id, zones, zones_rtree);
         // If an error occurs, we can't just use the parent area so instead, we return nothing.
         if subtract_existing_zones(&mut place, &zones_to_subtract).is_ok() {
-            return vec![place];
+            return vec![(place, suburbs.iter().map(|z| z.id).collect())];
         }
         return Vec::new();
     }
@@ -357,44 +436,29 @@ fn compute_voronoi(
         })
         .collect();
 
-    voronoi_polygons
+    // Since GEOS doesn't return voronoi geometries in the same order as the given points,
+    // we associate each clipped cell with the index of its place
+    let cells: Vec<(usize, Geometry<'_>)> = voronoi_polygons
         .into_par_iter()
         .filter_map(|voronoi| {
             // WARNING: This clone should not be necessary, but segfaults occured. Thread-safety issue in geos ?
             let geos_points = geos_points.clone();
 
-            // Since GEOS doesn't return voronoi geometries in the same order as the given points...
-            let mut place = {
-                if let Some(idx) = geos_points
-                    .iter()
-                    .filter(|(_, x)| voronoi.contains(x).unwrap_or(false))
-                    .map(|(pos, _)| *pos)
-                    .next()
-                {
-                    places[idx].clone()
-                } else {
+            let idx = match geos_points
+                .iter()
+                .filter(|(_, x)| voronoi.contains(x).unwrap_or(false))
+                .map(|(pos, _)| *pos)
+                .next()
+            {
+                Some(idx) => idx,
+                None => {
                     println!("town not found for parent {}...", parent.osm_id);
                     return None;
                 }
             };
 
             match geos_parent.intersection(&voronoi) {
-                Ok(s) => {
-                    place.parent = Some(parent.id);
-
-                    place.boundary = convert_to_geo(s)
-                        .map_err(|err| warn!("failed to convert to geos: {err:?}"))
-                        .ok()
-                        .flatten();
-
-                    if let Some(ref boundary) = place.boundary {
-                        place.bbox = boundary.bounding_rect();
-                    }
-                    let zones_to_subtract =
-                        get_places_to_subtract(&place, &parent.id, zones, zones_rtree);
-                    subtract_existing_zones(&mut place, &zones_to_subtract).ok()?;
-                    Some(place)
-                }
+                Ok(cell) => Some((idx, cell)),
                 Err(e) => {
                     warn!(
                         "intersection failure: {} ({})",
@@ -408,12 +472,240 @@ fn compute_voronoi(
                 }
             }
         })
+        .collect();
+
+    let suburbs: Vec<(ZoneIndex, Geometry<'_>)> = suburbs
+        .iter()
+        .filter_map(|z| {
+            let boundary = z.boundary.as_ref()?;
+            Geometry::try_from(boundary)
+                .map_err(|e| warn!("Failed to convert suburb {} to geos: {}", z.osm_id, e))
+                .ok()
+                .map(|g| (z.id, g))
+        })
+        .collect();
+    let (cells, suburbs_by_cell) = assign_suburbs(cells, &suburbs);
+
+    cells
+        .into_par_iter()
+        .zip(suburbs_by_cell)
+        .filter_map(|((idx, cell), suburbs)| {
+            let mut place = places[idx].clone();
+            place.parent = Some(parent.id);
+
+            place.boundary = convert_to_geo(cell)
+                .map_err(|err| warn!("failed to convert to geos: {err:?}"))
+                .ok()
+                .flatten();
+
+            if let Some(ref boundary) = place.boundary {
+                place.bbox = boundary.bounding_rect();
+            }
+            let zones_to_subtract = get_places_to_subtract(&place, &parent.id, zones, zones_rtree);
+            subtract_existing_zones(&mut place, &zones_to_subtract).ok()?;
+            Some((place, suburbs))
+        })
         .collect()
 }
 
-fn publish_new_places(zones: &mut Vec<Zone>, new_cities: Vec<Zone>) {
-    for mut city in new_cities {
-        city.id = ZoneIndex { index: zones.len() };
-        zones.push(city);
+/// Check if a zone is an existing zone smaller than a city, directly inside a parent
+/// larger than a city (so inside a synthetic city once the city boundaries are computed)
+fn is_suburb_of(zone: &Zone, parent: &Zone) -> bool {
+    zone.parent == Some(parent.id)
+        && parent.zone_type > Some(ZoneType::City)
+        && zone.admin_type().is_some_and(|zt| zt < ZoneType::City)
+}
+
+fn get_suburbs<'a>(parent: &Zone, zones: &'a [Zone], zones_rtree: &ZonesTree) -> Vec<&'a Zone> {
+    zones_rtree
+        .fetch_zone_bbox(parent)
+        .into_iter()
+        .map(|z_idx| &zones[z_idx.index])
+        .filter(|z| z.boundary.is_some() && is_suburb_of(z, parent))
+        .collect()
+}
+
+/// Make sure each suburb is entirely inside one cell.
+///
+/// A suburb is given to the cell containing its centroid, and the parts of the suburb
+/// in the other cells are moved to this cell.
+/// Returns the updated cells, with the suburbs given to each of them.
+fn assign_suburbs<'a>(
+    mut cells: Vec<(usize, Geometry<'a>)>,
+    suburbs: &[(ZoneIndex, Geometry<'a>)],
+) -> (Vec<(usize, Geometry<'a>)>, Vec<Vec<ZoneIndex>>) {
+    let mut suburbs_by_cell = vec![vec![]; cells.len()];
+    for (suburb_id, suburb) in suburbs {
+        let owner = suburb.get_centroid().ok().and_then(|centroid| {
+            cells
+                .iter()
+                .position(|(_, cell)| cell.intersects(&centroid).unwrap_or(false))
+        });
+        let owner = match owner {
+            Some(owner) => owner,
+            None => {
+                debug!("no cell found for the centroid of suburb {:?}", suburb_id);
+                continue;
+            }
+        };
+        suburbs_by_cell[owner].push(*suburb_id);
+        for i in 0..cells.len() {
+            if i == owner || !cells[i].1.intersects(suburb).unwrap_or(false) {
+                continue;
+            }
+            let moved = cells[i].1.intersection(suburb).and_then(|part| {
+                let remaining = cells[i].1.difference(suburb)?;
+                let extended = cells[owner].1.union(&part)?;
+                Ok((remaining, extended))
+            });
+            match moved {
+                Ok((remaining, extended)) => {
+                    cells[i].1 = remaining;
+                    cells[owner].1 = extended;
+                }
+                Err(e) => warn!("failed to move suburb {:?} in its cell: {}", suburb_id, e),
+            }
+        }
+    }
+    (cells, suburbs_by_cell)
+}
+
+fn publish_new_places(zones: &mut Vec<Zone>, new_cities: Vec<(Zone, Vec<ZoneIndex>)>) {
+    for (mut city, suburbs) in new_cities {
+        // the zones without boundary already are in the zones, they are only updated
+        let existing = zones
+            .get(city.id.index)
+            .is_some_and(|z| z.osm_id == city.osm_id);
+        if !existing {
+            city.id = ZoneIndex { index: zones.len() };
+        }
+        city.compute_measures();
+        city.origin = (city.boundary.as_ref()).map(|_| city.origin.unwrap_or(ZoneOrigin::Voronoi));
+        // the suburbs are entirely inside the new city, it becomes their parent
+        for suburb in suburbs {
+            let suburb = &mut zones[suburb.index];
+            suburb.parent = Some(city.id);
+            if !suburb.parents.is_empty() {
+                suburb.parents = vec![city.id];
+            }
+        }
+        if existing {
+            let index = city.id.index;
+            zones[index] = city;
+        } else {
+            zones.push(city);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo_types::{LineString, Polygon};
+
+    fn rect(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Geometry<'static> {
+        let ring = LineString::from(vec![
+            (min_x, min_y),
+            (min_x, max_y),
+            (max_x, max_y),
+            (max_x, min_y),
+            (min_x, min_y),
+        ]);
+        Geometry::try_from(&Polygon::new(ring, vec![])).unwrap()
+    }
+
+    #[test]
+    fn boundaryless_zones_replace_their_admin_centre() {
+        let zone = |osm_id: &str, center: (f64, f64), admin_centre: bool| Zone {
+            osm_id: osm_id.into(),
+            zone_type: Some(ZoneType::City),
+            center: Some(center.into()),
+            admin_centre: admin_centre.then(|| center.into()),
+            ..Default::default()
+        };
+        let mut places = vec![
+            zone("node:1", (1., 1.), false),
+            zone("node:2", (2., 2.), false),
+        ];
+        let zones = vec![
+            zone("relation:3", (1., 1.), true),
+            // no admin_centre, it cannot be seeded
+            zone("relation:4", (3., 3.), false),
+        ];
+        add_boundaryless_zones(&mut places, &zones);
+
+        let osm_ids: Vec<_> = places.iter().map(|p| p.osm_id.as_str()).collect();
+        assert_eq!(osm_ids, vec!["node:2", "relation:3"]);
+    }
+
+    #[test]
+    fn voronoi_cells_are_clipped_to_the_clip_level() {
+        use crate::hierarchy_builder::find_inclusions;
+        use crate::options::CancellationToken;
+        use osmpbfreader::{Node, NodeId, OsmId, OsmObj};
+
+        let zone = |index, zone_type, max_x: f64| {
+            let ring = LineString::from(vec![(0., 0.), (max_x, 0.), (max_x, 10.), (0., 10.)]);
+            let boundary = MultiPolygon(vec![Polygon::new(ring, vec![])]);
+            Zone {
+                id: ZoneIndex { index },
+                osm_id: format!("relation:{}", index),
+                zone_type: Some(zone_type),
+                bbox: boundary.bounding_rect(),
+                boundary: Some(boundary),
+                ..Default::default()
+            }
+        };
+        let place = |id, lon: f64| {
+            let node = Node {
+                id: NodeId(id),
+                tags: [("place", "town"), ("name", "Somewhere")]
+                    .iter()
+                    .map(|(k, v)| ((*k).into(), (*v).into()))
+                    .collect(),
+                decimicro_lat: 50_000_000,
+                decimicro_lon: (lon * 1e7) as i32,
+            };
+            (OsmId::Node(node.id), OsmObj::Node(node))
+        };
+        let pbf: BTreeMap<_, _> = [place(1, 2.), place(2, 7.)].into_iter().collect();
+        let new_places = |clip_level| {
+            let mut zones = vec![
+                zone(0, ZoneType::State, 10.),
+                zone(1, ZoneType::StateDistrict, 5.),
+            ];
+            zones[1].parent = Some(zones[0].id);
+            let (_, ztree) = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+            let options = BuildOptions {
+                voronoi_clip_level: clip_level,
+                ..Default::default()
+            };
+            compute_additional_places(&mut zones, &pbf, ztree, &options).unwrap();
+            zones.split_off(2)
+        };
+
+        // the place outside the state district is only inside the state
+        let places = new_places(None);
+        assert_eq!(places.len(), 2);
+
+        let places = new_places(Some(ZoneType::StateDistrict));
+        assert_eq!(places.len(), 1);
+        assert_eq!(places[0].osm_id, "node:1");
+        assert_eq!(places[0].parent, Some(ZoneIndex { index: 1 }));
+        assert!(places[0].bbox.unwrap().max().x <= 5.);
+    }
+
+    #[test]
+    fn suburb_is_moved_in_one_cell() {
+        let cells = vec![(0, rect(0., 0., 2., 2.)), (1, rect(2., 0., 4., 2.))];
+        // the suburb is mostly in the first cell, its centroid is in the first cell
+        let suburbs = vec![(ZoneIndex { index: 42 }, rect(1., 0.5, 2.5, 1.))];
+
+        let (cells, suburbs_by_cell) = assign_suburbs(cells, &suburbs);
+
+        assert_eq!(suburbs_by_cell, vec![vec![ZoneIndex { index: 42 }], vec![]]);
+        assert!(cells[0].1.covers(&suburbs[0].1).unwrap());
+        assert!((cells[0].1.area().unwrap() - 4.25).abs() < 1e-9);
+        assert!((cells[1].1.area().unwrap() - 3.75).abs() < 1e-9);
     }
 }
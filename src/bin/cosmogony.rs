@@ -1,13 +1,27 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::error::ErrorKind;
 use clap::Parser;
-use cosmogony::{file_format::OutputFormat, Cosmogony};
-use cosmogony_builder::{build_cosmogony, merger};
+use cosmogony::interning::RingInterner;
+use cosmogony::{file_format::OutputFormat, Cosmogony, CosmogonyMetadata, ZoneType};
+use cosmogony_builder::fragments::{write_changed_countries, EmitMode};
+use cosmogony_builder::metrics_export::write_build_metrics_file;
+use cosmogony_builder::replication;
+use cosmogony_builder::shapefile_export::{write_zones_as_shapefile, ShapefileLayout};
+use cosmogony_builder::table_export::{write_zones_as_table, TableFormat};
+use cosmogony_builder::topojson_export::write_zones_as_topojson;
+use cosmogony_builder::tree_export::{write_zones_as_tree, TreeFormat};
+use cosmogony_builder::uncovered_areas::write_uncovered_areas;
+use cosmogony_builder::{
+    build_cosmogony, check_inclusion_threshold, merger, stream_cosmogony, AdditionalZonesStrategy,
+    AdminLevelRemap, BuildOptions, Config, DisputedStrategy, ExtraZonesPolicy, MemorySize, OsmIds,
+    PlaceTypes, SecondaryLabelFormat, SimplifyTolerances, WindingOrder,
+};
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use std::fs::File;
 use std::io::BufWriter;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 /// You can:
 ///
@@ -15,6 +29,10 @@ use std::path::PathBuf;
 ///
 /// * merge several cosmogonies into one (merge)
 ///
+/// * export the zones of a cosmogony as vector tiles (tiles, with the `mvt` feature)
+///
+/// * compare two cosmogonies (diff)
+///
 /// Note: for retrocompatibility, if no subcommand is provided, the default one is `generate`
 ///
 /// So `cosmogony -i <osm-file> -o output file` if the same as
@@ -26,35 +44,137 @@ enum Args {
     ///
     /// Note: for retrocompatibility this is also the default subcommand if none is provided
     #[clap(name = "generate")]
-    Generate(GenerateArgs),
+    Generate(Box<GenerateArgs>),
     /// Merge cosmogony subcommand
     ///
     /// Use it to merge several streamed cosmogony files into one.
     /// Can be useful to split the processing of a large osm file (like the planet)
-    /// into several non overlapping small ones
+    /// into several non overlapping small ones (or overlapping ones, with `--overlapping`)
     #[clap(name = "merge")]
     Merge(MergeArgs),
+    /// Vector tiles subcommand
+    ///
+    /// Use it to export the zones' boundaries of a cosmogony file as vector tiles in a MBTiles
+    /// file, to visually check the cosmogony in a vector tiles viewer.
+    #[cfg(feature = "mvt")]
+    #[clap(name = "tiles")]
+    Tiles(TilesArgs),
+    /// Diff subcommand
+    ///
+    /// Use it to compare two builds of the same area: the added, removed, retyped and
+    /// re-parented zones, and the zones whose area has changed a lot, are reported.
+    #[clap(name = "diff")]
+    Diff(DiffArgs),
+    /// Check rules subcommand
+    ///
+    /// Use it to check the typing rules (the bundled libpostal rules, or a directory of rules)
+    /// and an admin_level overrides file while editing them, before a long build.
+    #[clap(name = "check-rules")]
+    CheckRules(CheckRulesArgs),
 }
 
 #[derive(Debug, clap::Parser)]
 struct GenerateArgs {
     /// OSM PBF file.
-    #[clap(short, long)]
-    input: String,
+    #[clap(short, long, required_unless_present = "config")]
+    input: Option<String>,
     /// output file name
     #[clap(
         short,
         long,
-        default_value = "cosmogony.json",
         help = concat!(
-            "Output file name. Format will be deduced from the file extension. ",
-            "Accepted extensions are '.json', '.json.gz', '.jsonl', '.jsonl.gz'. ",
-            "'jsonl' is json stream where each line is a zone as json.",
+            "Output file name ('cosmogony.json' by default). ",
+            "Format will be deduced from the file extension. ",
+            "Accepted extensions are '.json', '.json.gz', '.json.zst', '.jsonl', '.jsonl.gz', ",
+            "'.jsonl.zst', '.shp', ",
+            "'.csv', '.tsv', '.gpkg', '.sqlite', '.topojson', '.parquet'. ",
+            "'jsonl' is json stream where each line is a zone as json, ",
+            "'.csv' and '.tsv' are tables of the zones with their hierarchy, without geometry, ",
+            "'.gpkg' is a GeoPackage with one layer by zone type (needs the 'gpkg' feature), ",
+            "'.sqlite' is a SQLite database of the zones, their names and their hierarchy ",
+            "(needs the 'sqlite' feature), ",
+            "'.topojson' is a TopoJSON topology whose zones share their borders, ",
+            "'.parquet' is a GeoParquet table of the zones (needs the 'geoparquet' feature).",
         )
     )]
-    output: String,
+    output: Option<String>,
+    #[clap(
+        help = concat!(
+            "Toml file describing the build (input, outputs and options), its keys are the names ",
+            "of the command line options, eg. 'country-code = \"FR\"' or 'output = [\"fr.jsonl\", ",
+            "\"fr.gpkg\"]'. The command line options take precedence over the config file.",
+        ),
+        long
+    )]
+    config: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "For a shapefile output, write one shapefile by zone type ",
+            "('<output>_<zone_type>.shp') instead of a single one.",
+        ),
+        long = "shapefile-per-type"
+    )]
+    shapefile_per_type: bool,
+    #[clap(
+        help = concat!(
+            "For a json output, write each distinct ring of the boundaries once, the zones ",
+            "referencing their rings by id. The output is smaller, but it can only be read ",
+            "by cosmogony (or by a reader resolving the rings).",
+        ),
+        long
+    )]
+    intern_rings: bool,
+    #[clap(
+        help = concat!(
+            "What to write. 'full' writes all the zones in the output file, ",
+            "'changed-only' uses the output as a directory with one '<country_code>.jsonl' ",
+            "fragment by country and a 'manifest.json', and only rewrites the fragments ",
+            "of the countries that changed (or whose zone ids shifted) since the previous ",
+            "build in this directory.",
+        ),
+        long,
+        default_value = "full"
+    )]
+    emit: EmitMode,
+    #[clap(
+        help = concat!(
+            "Write the zones as json lines once the build is done, each zone being flushed then ",
+            "freed once written, instead of serializing the whole cosmogony. ",
+            "Only for the '.jsonl', '.jsonl.gz' and '.jsonl.zst' outputs, or '-' to write on the ",
+            "standard output.",
+        ),
+        long,
+        conflicts_with_all = ["emit", "shapefile_per_type", "intern_rings"]
+    )]
+    stream: bool,
     #[clap(help = "Do not display the stats", long)]
     no_stats: bool,
+    #[clap(
+        help = concat!(
+            "Only build the cosmogony to compute its stats, written as json on the standard ",
+            "output (with the number of zones by country), without writing any output file.",
+        ),
+        long,
+        conflicts_with_all = ["output", "stream", "emit"]
+    )]
+    stats_only: bool,
+    #[clap(
+        help = concat!(
+            "GeoJSON file where to also write the parts of the zones not covered by their ",
+            "children (eg. the unincorporated areas of a county), one feature by zone.",
+        ),
+        long,
+        conflicts_with_all = ["stream", "stats_only"]
+    )]
+    uncovered_areas: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "File where to write the metrics of the build (durations, zones counts, warnings) ",
+            "in the Prometheus text format, eg. for the textfile collector of node_exporter.",
+        ),
+        long
+    )]
+    metrics_file: Option<PathBuf>,
     #[clap(
         help = "Country code if the pbf file does not contains any country",
         long
@@ -68,19 +188,498 @@ struct GenerateArgs {
     #[clap(
         help = concat!(
             "Only generates labels for given langs. ",
-            "Either repeat parameter or use comma-separated value.",
+            "Either repeat parameter or use comma-separated value. ",
+            "A lang can be a prefix ('zh*') or be excluded ('!ja'), ",
+            "and 'default+' also trims the names of the zones to the given langs.",
         ),
         long = "filter-langs"
     )]
     filter_langs_raw: Vec<String>,
     #[clap(
         help = concat!(
-            "Configure the max number of threads using during computations. ",
-            "This won't affect the number of threads used to parse the OSM file.",
+            "Discard the names whose language is not a valid BCP-47 language tag ",
+            "(like 'name:fr-x-old' or 'name:left').",
+        ),
+        long
+    )]
+    reject_invalid_lang_tags: bool,
+    #[clap(
+        help = concat!(
+            "Add a 'latin' name and label to the zones whose name is not in the latin script, ",
+            "from their 'int_name' tag or a transliteration of their name ",
+            "(with --filter-langs, 'latin' must be one of the kept langs).",
+        ),
+        long
+    )]
+    romanize_names: bool,
+    #[clap(
+        help = concat!(
+            "Extract the official_name, short_name, alt_name and old_name tags of the zones (and ",
+            "their 'name:<lang>' variants) in an 'alternate_names' field, by language.",
+        ),
+        long
+    )]
+    alternate_names: bool,
+    #[clap(
+        help = concat!(
+            "Also give to the zones a 'secondary_label' with their label in this language ",
+            "(eg. 'en', or 'latin' with --romanize-names) next to their local name, like ",
+            "'東京都, Tokyo, Japan'.",
+        ),
+        long
+    )]
+    secondary_label_lang: Option<String>,
+    #[clap(
+        help = concat!(
+            "Format of the secondary labels, with the {name} (the local name of the zone), ",
+            "{label} (its label in the secondary language) and {local_label} placeholders ",
+            "('{name}, {label}' by default).",
+        ),
+        long,
+        requires = "secondary_label_lang"
+    )]
+    secondary_label_format: Option<SecondaryLabelFormat>,
+    #[clap(
+        help = concat!(
+            "Yaml file with the label templates by country code, with the zone types and zip ",
+            "placeholders (eg. 'US: \"{city}, {state} {zip}, {country}\"'). The labels of the ",
+            "other countries are the names of the zone and its parents joined by commas.",
+        ),
+        long
+    )]
+    label_templates: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Configure the max number of threads using during computations, ",
+            "including the decoding of the OSM file.",
         ),
         long
     )]
     num_threads: Option<usize>,
+    #[clap(
+        help = concat!(
+            "How the disputed zones are attached to the hierarchy. ",
+            "Accepted values are 'first' or 'second' (attach to this claimant only), ",
+            "'both' (geometrical attachment, the default) and 'neutral' ",
+            "(attach to none of the claimants).",
+        ),
+        long
+    )]
+    disputed_strategy: Option<DisputedStrategy>,
+    #[clap(
+        help = concat!(
+            "Cosmogony file (json or jsonl, possibly gzipped) with additional typed zones ",
+            "to blend with the osm zones.",
+        ),
+        long
+    )]
+    extra_zones: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "What to do when an extra zone overlaps an osm zone of the same type. ",
+            "Accepted values are 'prefer-extra', 'prefer-osm' and 'keep-both' (the default).",
+        ),
+        long
+    )]
+    extra_zones_policy: Option<ExtraZonesPolicy>,
+    #[clap(
+        help = concat!(
+            "Change the admin_level of the zones of some countries before typing them, to fix ",
+            "the levels tagged inconsistently in a region, eg. 'FR:6=8,FR:7=9'.",
+        ),
+        long
+    )]
+    admin_level_remap: Option<AdminLevelRemap>,
+    #[clap(
+        help = concat!(
+            "Directory of libpostal-like typing rules files ('<country>.yaml') used instead of ",
+            "the bundled libpostal rules, to try some rules without building cosmogony again.",
+        ),
+        long
+    )]
+    rules_dir: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Yaml file overriding libpostal's admin_level mapping for some countries, ",
+            "eg. 'FR: {admin_level: {\"7\": null, \"9\": suburb}}'.",
+        ),
+        long
+    )]
+    admin_level_overrides: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Json file caching the countries detected for the zones, reused by the next builds ",
+            "of the same input file with the same options (rewritten if they differ).",
+        ),
+        long
+    )]
+    country_cache: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Yaml file forcing the parent of some zones, applied after the hierarchy is built, ",
+            "eg. 'relation:123: relation:456' ('null' to remove the parent).",
+        ),
+        long
+    )]
+    parent_overrides: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Record in a 'parents' field all the zones that could be the parent of a zone ",
+            "(eg. the 2 counties of a city spanning both), the chosen parent first.",
+        ),
+        long
+    )]
+    multi_parents: bool,
+    #[clap(
+        help = concat!(
+            "Record in an 'included_in' field all the zones containing a zone, whatever their ",
+            "type, the smallest first (to analyze the overlaps or build alternative hierarchies).",
+        ),
+        long
+    )]
+    keep_inclusions: bool,
+    #[clap(
+        help = concat!(
+            "Include a zone in the larger zones covering at least this ratio of its area (eg. ",
+            "0.5), instead of only in the zones covering it entirely, so the zones straddling a ",
+            "border get the parent containing most of them.",
+        ),
+        long,
+        value_parser = parse_inclusion_threshold
+    )]
+    inclusion_threshold: Option<f64>,
+    #[clap(
+        help = concat!(
+            "Record in the zones' 'neighbors' the zones of the same type sharing a border with ",
+            "them (for the cartographic coloring or the nearby areas). Expensive.",
+        ),
+        long
+    )]
+    neighbors: bool,
+    #[clap(
+        help = concat!(
+            "Shapefile or geojson file with the land polygons (like the split WGS84 land polygons ",
+            "of osmdata.openstreetmap.de) used to clip the zones' boundaries to the coastline.",
+        ),
+        long
+    )]
+    land_polygons: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Report in the stats the siblings (zones with the same parent and type) overlapping ",
+            "more than this ratio of the smallest zone (0.1 by default).",
+        ),
+        long
+    )]
+    sibling_overlap_threshold: Option<f64>,
+    #[clap(
+        help = concat!(
+            "Yaml file with the expected number of zones by country and zone type ",
+            "(eg. 'FR: {state: 18, city: {count: 34900, tolerance: 0.02}}', the default ",
+            "tolerance being 10%). The deviating counts are reported in the stats.",
+        ),
+        long
+    )]
+    expected_counts: Option<PathBuf>,
+    #[clap(
+        help = "Fail if some zones counts deviate from the expected ones (see --expected-counts).",
+        long
+    )]
+    fail_on_unexpected_counts: bool,
+    #[clap(
+        help = concat!(
+            "Sort the zones by osm id (and renumber their ids) so that the output is ",
+            "byte-for-byte reproducible, eg. to diff 2 builds.",
+        ),
+        long
+    )]
+    deterministic: bool,
+    #[clap(
+        help = concat!(
+            "Use a bundled low resolution countries dataset to find the country of the zones ",
+            "not included in any country of the osm file (eg. for city extracts).",
+        ),
+        long
+    )]
+    country_fallback: bool,
+    #[clap(
+        help = concat!(
+            "Give to the dependent territories (the admin_level 3 or 4 zones with an ",
+            "ISO3166-1 code, like French Guiana or Puerto Rico) their own country code instead ",
+            "of the one of the country including them. The zones of the territories without ",
+            "libpostal rules are typed with the rules of this country.",
+        ),
+        long
+    )]
+    dependent_territories: bool,
+    #[clap(
+        help = concat!(
+            "Use the land areas of the countries (the boundary=land_area relations with an ",
+            "ISO3166-1:alpha2 code) to choose the country of the zones included in several ",
+            "countries, like the islands in the maritime area of a neighboring country.",
+        ),
+        long
+    )]
+    country_land_areas: bool,
+    #[clap(
+        help = concat!(
+            "Give to the zones without country (eg. at the edges of the extract) the most ",
+            "frequent country of the zones including them or included in them.",
+        ),
+        long
+    )]
+    guess_missing_countries: bool,
+    #[clap(
+        help = concat!(
+            "Do not look for the countries of the zones (eg. for a fantasy map or a region ",
+            "without libpostal rules): the zones are typed by their admin_level only, have no ",
+            "country code and the hierarchy is only geometric.",
+        ),
+        long,
+        conflicts_with_all = [
+            "country_code",
+            "country_fallback",
+            "dependent_territories",
+            "country_land_areas",
+            "guess_missing_countries",
+        ]
+    )]
+    no_country: bool,
+    #[clap(
+        help = concat!(
+            "Only keep these osm tags in the zones (eg. 'wikidata,ref,ISO3166-2'), all the tags ",
+            "are kept if not set. A tag can be a prefix ('name:*'). ",
+            "Either repeat parameter or use comma-separated value.",
+        ),
+        long = "keep-tags",
+        value_delimiter = ','
+    )]
+    kept_tags: Vec<String>,
+    #[clap(
+        help = concat!(
+            "Give to the zones the version, changeset and dates of their osm object in an ",
+            "'osm_metadata' field, to know how stale their boundary is (the osm file must have ",
+            "the metadata).",
+        ),
+        long
+    )]
+    osm_metadata: bool,
+    #[clap(
+        help = concat!(
+            "Spill the osm objects read from the pbf to a temporary file (in TMPDIR) when they ",
+            "use more than this memory (eg. '16G'), instead of keeping them all in memory. ",
+            "Needed for the planet, at the cost of a slower build.",
+        ),
+        long
+    )]
+    max_memory: Option<MemorySize>,
+    #[clap(
+        help = concat!(
+            "osmChange file ('.osc' or '.osc.gz') applied to the osm objects of the input, eg. ",
+            "a replication diff published since the input was written. Can be repeated, the ",
+            "files are applied in order, after the ones of the config.",
+        ),
+        long = "osm-change"
+    )]
+    osm_changes: Vec<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Directory with the replication state of the input ('state.txt') and the ",
+            "replication diffs since this state ('<sequence number>.osc.gz'), applied to the ",
+            "osm objects of the input. With the 'replication' feature, the diffs published ",
+            "since are downloaded first from the --replication-server.",
+        ),
+        long
+    )]
+    replication_dir: Option<PathBuf>,
+    #[cfg(feature = "replication")]
+    #[clap(
+        help = "Replication server of the diffs downloaded in the --replication-dir",
+        long,
+        default_value = "https://planet.openstreetmap.org/replication/minute"
+    )]
+    replication_server: String,
+    #[clap(
+        help = concat!(
+            "Osm objects not to extract as zones, separated by commas (eg. 'relation:42,way:7', ",
+            "a number alone being a relation), to skip the broken or vandalized relations ",
+            "breaking the builds. Added to the ones of the config.",
+        ),
+        long
+    )]
+    exclude_osm_ids: Option<OsmIds>,
+    #[clap(
+        help = concat!(
+            "File with osm objects not to extract as zones, one by line or separated by commas ",
+            "('#' starting a comment), added to the ones of --exclude-osm-ids.",
+        ),
+        long
+    )]
+    exclude_osm_ids_file: Option<PathBuf>,
+    #[clap(
+        help = concat!(
+            "Geojson file with the timezones boundaries (like the ones of timezone-boundary-builder, ",
+            "with the timezone in a 'tzid' property) used to give a timezone to the zones.",
+        ),
+        long
+    )]
+    timezones: Option<PathBuf>,
+    #[cfg(feature = "h3")]
+    #[clap(
+        help = concat!(
+            "Resolution (0 to 15) of the h3 cells covering the zones, whose ids are given to the ",
+            "zones in their 'h3_cells' (the cells whose center is in the zone), eg. to join ",
+            "events to the zones by cell id.",
+        ),
+        long,
+        value_parser = clap::value_parser!(u8).range(0..=15)
+    )]
+    h3_resolution: Option<u8>,
+    #[clap(
+        help = "Do not try to repair the invalid boundaries before computing the inclusions",
+        long = "disable-geometry-repair"
+    )]
+    disable_geometry_repair: bool,
+    #[clap(
+        help = concat!(
+            "Keep the zones whose boundary polygon could not be built, without geometry and ",
+            "located by their admin_centre node.",
+        ),
+        long
+    )]
+    keep_boundaryless_zones: bool,
+    #[clap(
+        help = concat!(
+            "Close the boundaries clipped by the extract (whose ways outside of it are missing) ",
+            "along the extract's bounds instead of dropping the zones. These zones are flagged ",
+            "as 'clipped'.",
+        ),
+        long
+    )]
+    partial_assembly: bool,
+    #[clap(
+        help = concat!(
+            "Also extract the 'boundary=ceremonial' and 'boundary=traditional' relations ",
+            "(like the UK's ceremonial counties) as non administrative zones, attached to ",
+            "the administrative zones containing them.",
+        ),
+        long
+    )]
+    ceremonial_boundaries: bool,
+    #[clap(
+        help = concat!(
+            "Also extract the 'boundary=administrative' zones without 'admin_level' (they are ",
+            "skipped by default), with the admin_level of the administrative zone containing ",
+            "them + 1, flagged as 'level_inferred'.",
+        ),
+        long
+    )]
+    infer_missing_levels: bool,
+    #[clap(
+        help = concat!(
+            "Keep the historic zones (whose 'end_date' is past, or whose tags have a lifecycle ",
+            "prefix like 'was:' or 'disused:') flagged as 'historic', instead of skipping them.",
+        ),
+        long
+    )]
+    keep_historic: bool,
+    #[clap(
+        help = concat!(
+            "Comma-separated list of osm place values used to build additional zones ",
+            "for the places without boundary. ",
+            "Accepted values are city, town, village, hamlet, suburb, quarter and neighbourhood ",
+            "(all but hamlet by default).",
+        ),
+        long
+    )]
+    place_types: Option<PlaceTypes>,
+    #[clap(
+        help = concat!(
+            "Largest type of the zones the voronoi cells of the places are clipped to ",
+            "(eg. 'state_district' to keep the synthetic cities inside the counties), the places ",
+            "only inside larger zones get no boundary. Any type smaller than a country by default.",
+        ),
+        long,
+        value_parser = parse_zone_type
+    )]
+    voronoi_clip_level: Option<ZoneType>,
+    #[clap(
+        help = concat!(
+            "How the boundaries of the places without boundary are computed: 'voronoi' (a ",
+            "voronoi partition of their parent zone, the default) or 'landuse' (the residential ",
+            "landuses of their voronoi cell, more realistic in the sparsely mapped regions).",
+        ),
+        long
+    )]
+    additional_zones_strategy: Option<AdditionalZonesStrategy>,
+    #[clap(
+        help = concat!(
+            "Simplify the boundaries, keeping the shared borders edge-matched. ",
+            "The value is a comma-separated list of tolerances (in degrees): a default tolerance ",
+            "and tolerances by zone type, eg. '0.0001,country=0.001,state=0.0005'.",
+        ),
+        long
+    )]
+    simplify: Option<SimplifyTolerances>,
+    #[clap(
+        help = concat!(
+            "Snap together the borders of the zones closer than this tolerance (in degrees, ",
+            "eg. '0.0000001'), so the borders shared by adjacent zones (whose geometries have ",
+            "been repaired or computed) have the same points and are simplified identically.",
+        ),
+        long = "snap-borders"
+    )]
+    snap_tolerance: Option<f64>,
+    #[clap(
+        help = concat!(
+            "Round the output coordinates to this number of decimals (full precision if not set). ",
+            "6 decimals is a sub-meter precision.",
+        ),
+        long,
+        value_parser = clap::value_parser!(u32).range(0..=15)
+    )]
+    precision: Option<u32>,
+    #[clap(
+        help = concat!(
+            "Orientation of the rings of the boundaries: 'rfc7946' (counterclockwise exteriors ",
+            "and clockwise holes, the default) or 'clockwise' (the opposite, for the legacy ",
+            "consumers).",
+        ),
+        long
+    )]
+    winding_order: Option<WindingOrder>,
+    #[clap(
+        help = "Give to the zones without population the sum of their children's population",
+        long = "propagate-population"
+    )]
+    propagate_population: bool,
+    #[clap(
+        help = concat!(
+            "Do not run a stage of the pipeline (eg. 'additional_zones'). ",
+            "Either repeat parameter or use comma-separated value.",
+        ),
+        long = "skip-stage",
+        value_delimiter = ','
+    )]
+    skipped_stages: Vec<String>,
+    #[clap(
+        help = concat!(
+            "Write the zones in '<DIR>/<STAGE>.jsonl' after a stage of the pipeline (for debugging). ",
+            "The parameter can be repeated.",
+        ),
+        long = "dump-after",
+        num_args = 2,
+        value_names = ["STAGE", "DIR"]
+    )]
+    dump_after: Vec<String>,
+    #[clap(
+        help = concat!(
+            "Stop the pipeline after a stage (for debugging). ",
+            "No output file is written, use --dump-after to inspect the zones.",
+        ),
+        long = "stop-after"
+    )]
+    stop_after: Option<String>,
 }
 
 impl GenerateArgs {
@@ -90,6 +689,106 @@ impl GenerateArgs {
             .flat_map(|val| val.split(',').map(String::from))
             .collect()
     }
+
+    /// the options of the command line, on top of the options of the config file
+    fn build_options(&self, base: BuildOptions) -> BuildOptions {
+        let or_base_vec = |v: &Vec<String>, base: Vec<String>| {
+            if v.is_empty() {
+                base
+            } else {
+                v.clone()
+            }
+        };
+        let filter_langs = self.filter_langs();
+        let dump_after = self
+            .dump_after
+            .chunks(2)
+            .map(|c| (c[0].clone(), PathBuf::from(&c[1])))
+            .collect();
+        BuildOptions {
+            country_code: self.country_code.clone().or(base.country_code),
+            disable_voronoi: self.disable_voronoi || base.disable_voronoi,
+            filter_langs: or_base_vec(&filter_langs, base.filter_langs),
+            reject_invalid_lang_tags: self.reject_invalid_lang_tags
+                || base.reject_invalid_lang_tags,
+            romanize_names: self.romanize_names || base.romanize_names,
+            alternate_names: self.alternate_names || base.alternate_names,
+            secondary_label_lang: self
+                .secondary_label_lang
+                .clone()
+                .or(base.secondary_label_lang),
+            secondary_label_format: self
+                .secondary_label_format
+                .clone()
+                .unwrap_or(base.secondary_label_format),
+            label_templates: self.label_templates.clone().or(base.label_templates),
+            disputed_strategy: self.disputed_strategy.unwrap_or(base.disputed_strategy),
+            extra_zones: self.extra_zones.clone().or(base.extra_zones),
+            extra_zones_policy: self.extra_zones_policy.unwrap_or(base.extra_zones_policy),
+            disable_geometry_repair: self.disable_geometry_repair || base.disable_geometry_repair,
+            keep_boundaryless_zones: self.keep_boundaryless_zones || base.keep_boundaryless_zones,
+            partial_assembly: self.partial_assembly || base.partial_assembly,
+            ceremonial_boundaries: self.ceremonial_boundaries || base.ceremonial_boundaries,
+            infer_missing_levels: self.infer_missing_levels || base.infer_missing_levels,
+            keep_historic: self.keep_historic || base.keep_historic,
+            place_types: self.place_types.unwrap_or(base.place_types),
+            voronoi_clip_level: self.voronoi_clip_level.or(base.voronoi_clip_level),
+            additional_zones_strategy: self
+                .additional_zones_strategy
+                .unwrap_or(base.additional_zones_strategy),
+            simplify: self.simplify.clone().or(base.simplify),
+            snap_tolerance: self.snap_tolerance.or(base.snap_tolerance),
+            coordinates_precision: self.precision.or(base.coordinates_precision),
+            winding_order: self.winding_order.unwrap_or(base.winding_order),
+            propagate_population: self.propagate_population || base.propagate_population,
+            skipped_stages: or_base_vec(&self.skipped_stages, base.skipped_stages),
+            dump_after,
+            stop_after: self.stop_after.clone(),
+            admin_level_remap: self.admin_level_remap.clone().or(base.admin_level_remap),
+            rules_dir: self.rules_dir.clone().or(base.rules_dir),
+            admin_level_overrides: self
+                .admin_level_overrides
+                .clone()
+                .or(base.admin_level_overrides),
+            country_cache: self.country_cache.clone().or(base.country_cache),
+            parent_overrides: self.parent_overrides.clone().or(base.parent_overrides),
+            multi_parents: self.multi_parents || base.multi_parents,
+            keep_inclusions: self.keep_inclusions || base.keep_inclusions,
+            inclusion_threshold: self.inclusion_threshold.or(base.inclusion_threshold),
+            neighbors: self.neighbors || base.neighbors,
+            land_polygons: self.land_polygons.clone().or(base.land_polygons),
+            sibling_overlap_threshold: self
+                .sibling_overlap_threshold
+                .or(base.sibling_overlap_threshold),
+            expected_counts: self.expected_counts.clone().or(base.expected_counts),
+            fail_on_unexpected_counts: self.fail_on_unexpected_counts
+                || base.fail_on_unexpected_counts,
+            timezones: self.timezones.clone().or(base.timezones),
+            #[cfg(feature = "h3")]
+            h3_resolution: self.h3_resolution.or(base.h3_resolution),
+            deterministic: self.deterministic || base.deterministic,
+            country_fallback: self.country_fallback || base.country_fallback,
+            dependent_territories: self.dependent_territories || base.dependent_territories,
+            country_land_areas: self.country_land_areas || base.country_land_areas,
+            guess_missing_countries: self.guess_missing_countries || base.guess_missing_countries,
+            no_country: self.no_country || base.no_country,
+            kept_tags: or_base_vec(&self.kept_tags, base.kept_tags),
+            osm_metadata: self.osm_metadata || base.osm_metadata,
+            max_memory: self.max_memory.or(base.max_memory),
+            osm_changes: {
+                let mut changes = base.osm_changes;
+                changes.extend(self.osm_changes.iter().cloned());
+                changes
+            },
+            excluded_osm_ids: {
+                let mut ids = base.excluded_osm_ids;
+                ids.0.extend(self.exclude_osm_ids.iter().flat_map(|i| &i.0));
+                ids
+            },
+            osm_filter: base.osm_filter,
+            cancellation: base.cancellation,
+        }
+    }
 }
 
 #[derive(Debug, clap::Parser)]
@@ -103,16 +802,141 @@ struct MergeArgs {
         long = "output",
         default_value = "cosmogony.jsonl",
         help = r#"Output file name. Format will be deduced from the file extension.
-    Accepted extensions are '.jsonl', '.jsonl.gz' (no json or json.gz)
+    Accepted extensions are '.jsonl', '.jsonl.gz', '.jsonl.zst' (no json, json.gz or json.zst)
     'jsonl' is json stream, each line is a zone as json
     "#
     )]
     output: PathBuf,
+    /// The cosmogonies have been built from overlapping extracts.
+    ///
+    /// The zones present in several cosmogonies are merged (the largest geometry is kept)
+    /// instead of being duplicated. All the zones are loaded in memory.
+    #[clap(long = "overlapping")]
+    overlapping: bool,
+}
+
+#[cfg(feature = "mvt")]
+#[derive(Debug, clap::Parser)]
+struct TilesArgs {
+    /// Cosmogony file ('.json', '.jsonl', possibly compressed with gzip ('.gz') or zstd ('.zst'))
+    #[clap(short, long)]
+    input: PathBuf,
+    /// Output MBTiles file
+    #[clap(short, long, default_value = "cosmogony.mbtiles")]
+    output: PathBuf,
+    /// Min zoom of the tiles
+    #[clap(long, default_value = "0")]
+    min_zoom: u8,
+    /// Max zoom of the tiles (the number of tiles grows quickly with the zoom)
+    #[clap(long, default_value = "8")]
+    max_zoom: u8,
+}
+
+fn parse_zone_type(s: &str) -> Result<ZoneType> {
+    ZoneType::parse(s).ok_or_else(|| anyhow!("invalid zone type '{}'", s))
+}
+
+fn parse_inclusion_threshold(s: &str) -> Result<f64> {
+    check_inclusion_threshold(s.parse()?)
+}
+
+#[cfg(feature = "mvt")]
+fn tiles(args: TilesArgs) -> Result<()> {
+    use cosmogony_builder::mbtiles_export::{write_zones_as_mbtiles, TileOptions};
+    if args.min_zoom > args.max_zoom {
+        bail!("the min zoom cannot be greater than the max zoom");
+    }
+    log::info!("reading the zones of {}", args.input.display());
+    let zones = cosmogony::read_zones_from_file(&args.input)?.collect::<Result<Vec<_>>>()?;
+    let options = TileOptions {
+        zooms: args.min_zoom..=args.max_zoom,
+    };
+    let nb_tiles = write_zones_as_mbtiles(&zones, &args.output, &options)?;
+    log::info!("{} tiles written in {}", nb_tiles, args.output.display());
+    Ok(())
 }
 
-fn to_json_stream(mut writer: impl std::io::Write, cosmogony: &Cosmogony) -> Result<()> {
+#[derive(Debug, clap::Parser)]
+struct DiffArgs {
+    /// The previous cosmogony file ('.json', '.jsonl', possibly compressed with gzip ('.gz') or zstd ('.zst'))
+    #[clap(name = "BEFORE")]
+    before: PathBuf,
+    /// The new cosmogony file
+    #[clap(name = "AFTER")]
+    after: PathBuf,
+    /// The area changes reported, as a ratio of the largest of the 2 areas
+    #[clap(long, default_value = "0.05")]
+    geometry_threshold: f64,
+    /// Write the diff as json in this file instead of printing a summary
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Parser)]
+struct CheckRulesArgs {
+    /// Directory of libpostal-like typing rules files (the bundled libpostal rules by default)
+    #[clap(long)]
+    rules_dir: Option<PathBuf>,
+    /// Yaml file overriding libpostal's admin_level mapping for some countries
+    #[clap(long)]
+    admin_level_overrides: Option<PathBuf>,
+}
+
+fn check_rules(args: CheckRulesArgs) -> Result<()> {
+    let errors = cosmogony_builder::zone_typer::ZoneTyper::validate_rules(
+        args.rules_dir.as_deref(),
+        args.admin_level_overrides.as_deref(),
+    );
+    for e in &errors {
+        println!("{}", e);
+    }
+    if !errors.is_empty() {
+        bail!("{} problems in the typing rules", errors.len());
+    }
+    log::info!("the typing rules are valid");
+    Ok(())
+}
+
+fn diff(args: DiffArgs) -> Result<()> {
+    let read = |path: &Path| -> Result<Vec<_>> {
+        log::info!("reading the zones of {}", path.display());
+        cosmogony::read_zones_from_file(path)?.collect()
+    };
+    let before = read(&args.before)?;
+    let after = read(&args.after)?;
+    let diff = cosmogony::diff::diff_zones(&before, &after, args.geometry_threshold);
+    match args.output {
+        Some(output) => {
+            let file = BufWriter::new(File::create(&output)?);
+            serde_json::to_writer_pretty(file, &diff)?;
+        }
+        None => print!("{}", diff),
+    }
+    Ok(())
+}
+
+fn merge(args: MergeArgs) -> Result<()> {
+    if args.overlapping {
+        let stats = merger::merge_overlapping_cosmogonies(&args.files, &args.output)?;
+        log::info!("Merge statistics:\n{}", stats);
+        Ok(())
+    } else {
+        merger::merge_cosmogony(&args.files, &args.output)
+    }
+}
+
+fn to_json_stream(
+    mut writer: impl std::io::Write,
+    cosmogony: &Cosmogony,
+    intern_rings: bool,
+) -> Result<()> {
+    let mut interner = RingInterner::default();
     for z in &cosmogony.zones {
-        serde_json::to_writer(&mut writer, z)?;
+        if intern_rings {
+            serde_json::to_writer(&mut writer, &interner.intern(z))?;
+        } else {
+            serde_json::to_writer(&mut writer, z)?;
+        }
         writer.write_all(b"\n")?;
     }
 
@@ -121,36 +945,200 @@ fn to_json_stream(mut writer: impl std::io::Write, cosmogony: &Cosmogony) -> Res
     Ok(())
 }
 
+fn to_json(
+    mut writer: impl std::io::Write,
+    cosmogony: &Cosmogony,
+    intern_rings: bool,
+) -> Result<()> {
+    if !intern_rings {
+        serde_json::to_writer(writer, cosmogony)?;
+        return Ok(());
+    }
+    // the zones are interned one by one, to avoid copying the whole cosmogony
+    let mut interner = RingInterner::default();
+    writer.write_all(b"{\"zones\":[")?;
+    for (i, z) in cosmogony.zones.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut writer, &interner.intern(z))?;
+    }
+    writer.write_all(b"],\"meta\":")?;
+    serde_json::to_writer(&mut writer, &cosmogony.meta)?;
+    writer.write_all(b"}")?;
+    Ok(())
+}
+
+/// How the output files are written
+struct OutputLayout {
+    shapefile_layout: ShapefileLayout,
+    intern_rings: bool,
+}
+
 fn serialize_cosmogony(
     cosmogony: &Cosmogony,
     output_file: String,
     format: OutputFormat,
+    layout: &OutputLayout,
 ) -> Result<()> {
     log::info!("writing the output file {}", output_file);
+    if format == OutputFormat::Shapefile {
+        return write_zones_as_shapefile(
+            &cosmogony.zones,
+            Path::new(&output_file),
+            layout.shapefile_layout,
+        );
+    }
+    if format == OutputFormat::GeoPackage {
+        #[cfg(feature = "gpkg")]
+        return cosmogony_builder::gpkg_export::write_zones_as_gpkg(
+            &cosmogony.zones,
+            Path::new(&output_file),
+        );
+        #[cfg(not(feature = "gpkg"))]
+        bail!("the geopackage output needs cosmogony to be built with the 'gpkg' feature");
+    }
+    if format == OutputFormat::Sqlite {
+        #[cfg(feature = "sqlite")]
+        return cosmogony_builder::sqlite_export::write_zones_as_sqlite(
+            &cosmogony.zones,
+            Path::new(&output_file),
+        );
+        #[cfg(not(feature = "sqlite"))]
+        bail!("the sqlite output needs cosmogony to be built with the 'sqlite' feature");
+    }
+    if format == OutputFormat::GeoParquet {
+        #[cfg(feature = "geoparquet")]
+        return cosmogony_builder::parquet_export::write_zones_as_geoparquet(
+            &cosmogony.zones,
+            Path::new(&output_file),
+        );
+        #[cfg(not(feature = "geoparquet"))]
+        bail!("the geoparquet output needs cosmogony to be built with the 'geoparquet' feature");
+    }
     let file = File::create(output_file)?;
     let stream = BufWriter::new(file);
     match format {
+        OutputFormat::Csv => write_zones_as_table(&cosmogony.zones, stream, TableFormat::Csv)?,
+        OutputFormat::Tsv => write_zones_as_table(&cosmogony.zones, stream, TableFormat::Tsv)?,
+        OutputFormat::TopoJson => write_zones_as_topojson(&cosmogony.zones, stream)?,
+        OutputFormat::Tree => write_zones_as_tree(&cosmogony.zones, stream, TreeFormat::Nested)?,
+        OutputFormat::TreeStream => {
+            write_zones_as_tree(&cosmogony.zones, stream, TreeFormat::Lines)?
+        }
         OutputFormat::JsonGz => {
             let e = GzEncoder::new(stream, Compression::default());
-            serde_json::to_writer(e, cosmogony)?;
+            to_json(e, cosmogony, layout.intern_rings)?;
         }
         OutputFormat::Json => {
-            serde_json::to_writer(stream, cosmogony)?;
+            to_json(stream, cosmogony, layout.intern_rings)?;
         }
         OutputFormat::JsonStream => {
-            to_json_stream(stream, cosmogony)?;
+            to_json_stream(stream, cosmogony, layout.intern_rings)?;
         }
         OutputFormat::JsonStreamGz => {
             let e = GzEncoder::new(stream, Compression::default());
-            to_json_stream(e, cosmogony)?;
+            to_json_stream(e, cosmogony, layout.intern_rings)?;
+        }
+        OutputFormat::JsonZst => {
+            let mut e = zstd::Encoder::new(stream, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            to_json(&mut e, cosmogony, layout.intern_rings)?;
+            e.finish()?;
+        }
+        OutputFormat::JsonStreamZst => {
+            let mut e = zstd::Encoder::new(stream, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            to_json_stream(&mut e, cosmogony, layout.intern_rings)?;
+            e.finish()?;
         }
+        OutputFormat::Shapefile
+        | OutputFormat::GeoPackage
+        | OutputFormat::Sqlite
+        | OutputFormat::GeoParquet => unreachable!(),
     };
     Ok(())
 }
 
+/// build the cosmogony and write its zones to the output one by one, flushing each zone
+fn stream(input: &str, output: &str, options: &BuildOptions) -> Result<CosmogonyMetadata> {
+    if output == "-" {
+        return stream_cosmogony(input.to_string(), options, std::io::stdout().lock());
+    }
+    let format = OutputFormat::from_filename(output)?;
+    if !matches!(
+        format,
+        OutputFormat::JsonStream | OutputFormat::JsonStreamGz | OutputFormat::JsonStreamZst
+    ) {
+        bail!("only the '.jsonl', '.jsonl.gz' and '.jsonl.zst' outputs can be streamed");
+    }
+    log::info!("streaming the zones in {}", output);
+    let stream = BufWriter::new(File::create(output)?);
+    match format {
+        OutputFormat::JsonStreamGz => {
+            let mut e = GzEncoder::new(stream, Compression::default());
+            let meta = stream_cosmogony(input.to_string(), options, &mut e)?;
+            e.finish()?;
+            Ok(meta)
+        }
+        OutputFormat::JsonStreamZst => {
+            let mut e = zstd::Encoder::new(stream, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            let meta = stream_cosmogony(input.to_string(), options, &mut e)?;
+            e.finish()?;
+            Ok(meta)
+        }
+        _ => stream_cosmogony(input.to_string(), options, stream),
+    }
+}
+
 fn cosmogony(args: GenerateArgs) -> Result<()> {
-    let format = OutputFormat::from_filename(&args.output)?;
-    let filter_langs = args.filter_langs();
+    let config = match args.config {
+        Some(ref path) => {
+            log::info!("reading the config file {}", path.display());
+            Config::from_path(path)?
+        }
+        None => Config::default(),
+    };
+    let input =
+        args.input.clone().or(config.input).ok_or_else(|| {
+            anyhow!("no input file, neither in the command line nor in the config")
+        })?;
+    let outputs = match args.output {
+        Some(ref output) => vec![output.clone()],
+        None if !config.outputs.is_empty() => config.outputs,
+        None => vec!["cosmogony.json".to_string()],
+    };
+    let mut options = args.build_options(config.options);
+    if let Some(ref path) = args.exclude_osm_ids_file {
+        options.excluded_osm_ids.0.extend(OsmIds::read(path)?.0);
+    }
+    if let Some(ref dir) = args.replication_dir {
+        #[cfg(feature = "replication")]
+        let diffs = replication::download_pending_diffs(&args.replication_server, dir)?;
+        #[cfg(not(feature = "replication"))]
+        let diffs = replication::downloaded_diffs(dir)?;
+        log::info!(
+            "applying {} replication diffs of {}",
+            diffs.len(),
+            dir.display()
+        );
+        options.osm_changes.extend(diffs);
+    }
+    if args.stats_only {
+        // the boundaries are not written, there is no need to simplify or round them
+        options.simplify = None;
+        options.snap_tolerance = None;
+        options.coordinates_precision = None;
+    }
+    let streamed = args.stream && options.stop_after.is_none();
+    if outputs.len() > 1 && (streamed || args.emit != EmitMode::Full) {
+        bail!("only one output can be written when streaming or emitting the changed countries");
+    }
+    let formats = match args.emit {
+        EmitMode::Full if !streamed => outputs
+            .iter()
+            .map(|o| Ok(Some(OutputFormat::from_filename(o)?)))
+            .collect::<Result<Vec<_>>>()?,
+        _ => vec![None],
+    };
 
     if let Some(num_threads) = args.num_threads {
         rayon::ThreadPoolBuilder::new()
@@ -159,29 +1147,68 @@ fn cosmogony(args: GenerateArgs) -> Result<()> {
             .map_err(|err| anyhow!("could not init rayon's global thread pool: {err}"))?;
     }
 
-    let cosmogony = build_cosmogony(
-        args.input,
-        args.country_code,
-        args.disable_voronoi,
-        &filter_langs,
-    )?;
+    let start = Instant::now();
+    // the durations of the phases of the build, for the metrics
+    let mut durations = vec![];
+    let meta = if streamed {
+        let meta = stream(&input, &outputs[0], &options)?;
+        durations.push(("build", start.elapsed()));
+        meta
+    } else {
+        let cosmogony = build_cosmogony(input, &options)?;
+        durations.push(("build", start.elapsed()));
+        let write_start = Instant::now();
 
-    serialize_cosmogony(&cosmogony, args.output, format)?;
+        if let Some(ref stage) = options.stop_after {
+            log::info!("pipeline stopped after stage {}, no output written", stage);
+        } else if args.stats_only {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &cosmogony.meta.stats)?;
+            println!();
+        } else {
+            let layout = OutputLayout {
+                shapefile_layout: if args.shapefile_per_type {
+                    ShapefileLayout::PerZoneType
+                } else {
+                    ShapefileLayout::Combined
+                },
+                intern_rings: args.intern_rings,
+            };
+            for (output, format) in outputs.into_iter().zip(formats) {
+                if let Some(format) = format {
+                    serialize_cosmogony(&cosmogony, output, format, &layout)?;
+                } else {
+                    log::info!("writing the changed countries in {}", output);
+                    write_changed_countries(&cosmogony, Path::new(&output))?;
+                }
+            }
+            if let Some(ref path) = args.uncovered_areas {
+                log::info!("writing the uncovered areas in {}", path.display());
+                let stream = BufWriter::new(File::create(path)?);
+                write_uncovered_areas(&cosmogony.zones, options.winding_order, stream)?;
+            }
+            durations.push(("write", write_start.elapsed()));
+        }
+        cosmogony.meta
+    };
+    if let Some(ref path) = args.metrics_file {
+        log::info!("writing the build metrics in {}", path.display());
+        write_build_metrics_file(&meta, &durations, path)?;
+    }
 
     if !args.no_stats {
-        log::info!(
-            "Statistics for {}:\n{}",
-            cosmogony.meta.osm_filename,
-            cosmogony.meta.stats
-        );
+        log::info!("Statistics for {}:\n{}", meta.osm_filename, meta.stats);
     }
     Ok(())
 }
 
 fn run(args: Args) -> Result<()> {
     match args {
-        Args::Merge(merge_args) => merger::merge_cosmogony(&merge_args.files, &merge_args.output),
-        Args::Generate(gen_args) => cosmogony(gen_args),
+        Args::Merge(merge_args) => merge(merge_args),
+        Args::Generate(gen_args) => cosmogony(*gen_args),
+        #[cfg(feature = "mvt")]
+        Args::Tiles(tiles_args) => tiles(tiles_args),
+        Args::Diff(diff_args) => diff(diff_args),
+        Args::CheckRules(check_rules_args) => check_rules(check_rules_args),
     }
 }
 
@@ -199,7 +1226,7 @@ fn main() {
     // Note: for retrocompatibility, we also try to read the args without subcommand
     // to generate a cosmogony
     let args = GenerateArgs::try_parse()
-        .map(Args::Generate)
+        .map(|args| Args::Generate(Box::new(args)))
         .unwrap_or_else(|err| {
             if let ErrorKind::DisplayVersion = err.kind() {
                 // The version number has been displayed.
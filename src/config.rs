@@ -0,0 +1,351 @@
+// A toml file describing a whole build, to avoid the very long command lines.
+//
+// The keys are the names of the `generate` command line options, eg.
+//
+// ```toml
+// input = "france-latest.osm.pbf"
+// output = ["france.jsonl.gz", "france.gpkg"]
+// country-code = "FR"
+// filter-langs = ["fr", "en"]
+// simplify = "0.0001,country=0.001"
+// ```
+//
+// The relative paths are relative to the directory of the config file.
+
+use crate::options::{
+    check_inclusion_threshold, check_tolerance, AdditionalZonesStrategy, AdminLevelRemap,
+    BuildOptions, CancellationToken, DisputedStrategy, ExtraZonesPolicy, MemorySize, OsmIds,
+    PlaceTypes, SecondaryLabelFormat, SimplifyTolerances, WindingOrder,
+};
+use anyhow::{anyhow, Context, Error};
+use cosmogony::ZoneType;
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// The description of a build: its input, its outputs and its options
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// the osm pbf file
+    pub input: Option<String>,
+    /// the output files, their format is deduced from their extension
+    pub outputs: Vec<String>,
+    pub options: BuildOptions,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Tolerances {
+    Default(f64),
+    ByZoneType(String),
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawConfig {
+    input: Option<PathBuf>,
+    output: Option<OneOrMany>,
+    country_code: Option<String>,
+    #[serde(default)]
+    disable_voronoi: bool,
+    #[serde(default)]
+    filter_langs: Vec<String>,
+    #[serde(default)]
+    reject_invalid_lang_tags: bool,
+    #[serde(default)]
+    romanize_names: bool,
+    #[serde(default)]
+    alternate_names: bool,
+    secondary_label_lang: Option<String>,
+    secondary_label_format: Option<String>,
+    label_templates: Option<PathBuf>,
+    disputed_strategy: Option<String>,
+    extra_zones: Option<PathBuf>,
+    extra_zones_policy: Option<String>,
+    #[serde(default)]
+    disable_geometry_repair: bool,
+    #[serde(default)]
+    keep_boundaryless_zones: bool,
+    #[serde(default)]
+    partial_assembly: bool,
+    #[serde(default)]
+    ceremonial_boundaries: bool,
+    #[serde(default)]
+    infer_missing_levels: bool,
+    #[serde(default)]
+    keep_historic: bool,
+    place_types: Option<Vec<String>>,
+    voronoi_clip_level: Option<String>,
+    additional_zones_strategy: Option<String>,
+    simplify: Option<Tolerances>,
+    snap_borders: Option<f64>,
+    precision: Option<u32>,
+    winding_order: Option<String>,
+    #[serde(default)]
+    propagate_population: bool,
+    #[serde(default)]
+    skip_stage: Vec<String>,
+    admin_level_remap: Option<String>,
+    rules_dir: Option<PathBuf>,
+    admin_level_overrides: Option<PathBuf>,
+    country_cache: Option<PathBuf>,
+    parent_overrides: Option<PathBuf>,
+    #[serde(default)]
+    multi_parents: bool,
+    #[serde(default)]
+    keep_inclusions: bool,
+    inclusion_threshold: Option<f64>,
+    #[serde(default)]
+    neighbors: bool,
+    land_polygons: Option<PathBuf>,
+    sibling_overlap_threshold: Option<f64>,
+    expected_counts: Option<PathBuf>,
+    #[serde(default)]
+    fail_on_unexpected_counts: bool,
+    timezones: Option<PathBuf>,
+    #[cfg(feature = "h3")]
+    h3_resolution: Option<u8>,
+    #[serde(default)]
+    deterministic: bool,
+    #[serde(default)]
+    country_fallback: bool,
+    #[serde(default)]
+    dependent_territories: bool,
+    #[serde(default)]
+    country_land_areas: bool,
+    #[serde(default)]
+    guess_missing_countries: bool,
+    #[serde(default)]
+    no_country: bool,
+    #[serde(default)]
+    keep_tags: Vec<String>,
+    #[serde(default)]
+    osm_metadata: bool,
+    max_memory: Option<String>,
+    #[serde(default)]
+    osm_changes: Vec<PathBuf>,
+    exclude_osm_ids: Option<String>,
+    exclude_osm_ids_file: Option<PathBuf>,
+}
+
+impl RawConfig {
+    fn into_config(self, dir: &Path) -> Result<Config, Error> {
+        let path = |p: Option<PathBuf>| p.map(|p| dir.join(p));
+        let outputs = match self.output {
+            None => vec![],
+            Some(OneOrMany::One(o)) => vec![o],
+            Some(OneOrMany::Many(o)) => o,
+        };
+        let mut excluded_osm_ids = self
+            .exclude_osm_ids
+            .map(|ids| ids.parse::<OsmIds>())
+            .transpose()?
+            .unwrap_or_default();
+        if let Some(file) = path(self.exclude_osm_ids_file) {
+            excluded_osm_ids.0.extend(OsmIds::read(&file)?.0);
+        }
+        let simplify = match self.simplify {
+            None => None,
+            Some(Tolerances::Default(t)) => Some(SimplifyTolerances {
+                default: check_tolerance(t)?,
+                by_zone_type: vec![],
+            }),
+            Some(Tolerances::ByZoneType(t)) => Some(t.parse()?),
+        };
+        let options = BuildOptions {
+            country_code: self.country_code,
+            disable_voronoi: self.disable_voronoi,
+            filter_langs: self.filter_langs,
+            reject_invalid_lang_tags: self.reject_invalid_lang_tags,
+            romanize_names: self.romanize_names,
+            alternate_names: self.alternate_names,
+            secondary_label_lang: self.secondary_label_lang,
+            secondary_label_format: self
+                .secondary_label_format
+                .map(|f| f.parse::<SecondaryLabelFormat>())
+                .transpose()?
+                .unwrap_or_default(),
+            label_templates: path(self.label_templates),
+            disputed_strategy: self
+                .disputed_strategy
+                .map(|s| s.parse::<DisputedStrategy>())
+                .transpose()?
+                .unwrap_or_default(),
+            extra_zones: path(self.extra_zones),
+            extra_zones_policy: self
+                .extra_zones_policy
+                .map(|p| p.parse::<ExtraZonesPolicy>())
+                .transpose()?
+                .unwrap_or_default(),
+            disable_geometry_repair: self.disable_geometry_repair,
+            keep_boundaryless_zones: self.keep_boundaryless_zones,
+            partial_assembly: self.partial_assembly,
+            ceremonial_boundaries: self.ceremonial_boundaries,
+            infer_missing_levels: self.infer_missing_levels,
+            keep_historic: self.keep_historic,
+            place_types: self
+                .place_types
+                .map(|p| p.join(",").parse::<PlaceTypes>())
+                .transpose()?
+                .unwrap_or_default(),
+            voronoi_clip_level: self
+                .voronoi_clip_level
+                .map(|t| ZoneType::parse(&t).ok_or_else(|| anyhow!("invalid zone type '{}'", t)))
+                .transpose()?,
+            additional_zones_strategy: self
+                .additional_zones_strategy
+                .map(|s| s.parse::<AdditionalZonesStrategy>())
+                .transpose()?
+                .unwrap_or_default(),
+            simplify,
+            snap_tolerance: self.snap_borders,
+            coordinates_precision: self.precision,
+            winding_order: self
+                .winding_order
+                .map(|w| w.parse::<WindingOrder>())
+                .transpose()?
+                .unwrap_or_default(),
+            propagate_population: self.propagate_population,
+            skipped_stages: self.skip_stage,
+            dump_after: vec![],
+            stop_after: None,
+            admin_level_remap: self
+                .admin_level_remap
+                .map(|r| r.parse::<AdminLevelRemap>())
+                .transpose()?,
+            rules_dir: path(self.rules_dir),
+            admin_level_overrides: path(self.admin_level_overrides),
+            country_cache: path(self.country_cache),
+            parent_overrides: path(self.parent_overrides),
+            multi_parents: self.multi_parents,
+            keep_inclusions: self.keep_inclusions,
+            inclusion_threshold: self
+                .inclusion_threshold
+                .map(check_inclusion_threshold)
+                .transpose()?,
+            neighbors: self.neighbors,
+            land_polygons: path(self.land_polygons),
+            sibling_overlap_threshold: self.sibling_overlap_threshold,
+            expected_counts: path(self.expected_counts),
+            fail_on_unexpected_counts: self.fail_on_unexpected_counts,
+            timezones: path(self.timezones),
+            #[cfg(feature = "h3")]
+            h3_resolution: self.h3_resolution,
+            deterministic: self.deterministic,
+            country_fallback: self.country_fallback,
+            dependent_territories: self.dependent_territories,
+            country_land_areas: self.country_land_areas,
+            guess_missing_countries: self.guess_missing_countries,
+            no_country: self.no_country,
+            kept_tags: self.keep_tags,
+            osm_metadata: self.osm_metadata,
+            max_memory: self
+                .max_memory
+                .map(|m| m.parse::<MemorySize>())
+                .transpose()?,
+            osm_changes: self.osm_changes.iter().map(|p| dir.join(p)).collect(),
+            excluded_osm_ids,
+            osm_filter: None,
+            cancellation: CancellationToken::default(),
+        };
+        Ok(Config {
+            input: path(self.input).map(|p| p.to_string_lossy().into_owned()),
+            outputs: outputs
+                .into_iter()
+                .map(|o| match o.as_str() {
+                    // the standard output
+                    "-" => o,
+                    _ => dir.join(o).to_string_lossy().into_owned(),
+                })
+                .collect(),
+            options,
+        })
+    }
+}
+
+impl Default for Config {
+    /// the same defaults as the command line
+    fn default() -> Self {
+        RawConfig::default()
+            .into_config(Path::new(""))
+            .expect("the default config is valid")
+    }
+}
+
+impl Config {
+    /// Parse a toml config
+    ///
+    /// The relative paths of the config are relative to `dir`.
+    pub fn from_toml(toml: &str, dir: &Path) -> Result<Config, Error> {
+        let raw: RawConfig = toml::from_str(toml)?;
+        raw.into_config(dir)
+    }
+
+    /// Read a toml config file
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Config, Error> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("impossible to read {}", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_toml(&toml, dir)
+            .with_context(|| format!("invalid config file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            input = "lux.osm.pbf"
+            output = ["lux.jsonl.gz", "/tmp/lux.gpkg"]
+            country-code = "LU"
+            filter-langs = ["fr", "de"]
+            simplify = "0.0001,country=0.001"
+            place-types = ["city", "hamlet"]
+            disputed-strategy = "neutral"
+            land-polygons = "land/land_polygons.shp"
+            "#,
+            Path::new("/data"),
+        )
+        .unwrap();
+        assert_eq!(config.input.as_deref(), Some("/data/lux.osm.pbf"));
+        assert_eq!(config.outputs, vec!["/data/lux.jsonl.gz", "/tmp/lux.gpkg"]);
+
+        let options = config.options;
+        assert_eq!(options.country_code.as_deref(), Some("LU"));
+        assert_eq!(options.filter_langs, vec!["fr", "de"]);
+        let simplify = options.simplify.unwrap();
+        assert_eq!(simplify.tolerance(Some(ZoneType::Country)), 0.001);
+        assert_eq!(simplify.tolerance(Some(ZoneType::City)), 0.0001);
+        assert!(options.place_types.hamlet && !options.place_types.town);
+        assert_eq!(options.disputed_strategy, DisputedStrategy::Neutral);
+        assert_eq!(
+            options.land_polygons,
+            Some(PathBuf::from("/data/land/land_polygons.shp"))
+        );
+        assert_eq!(options.sibling_overlap_threshold, None);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let dir = Path::new("");
+        assert!(Config::from_toml("simplify = 0.001", dir).is_ok());
+        assert!(Config::from_toml("unknown-option = true", dir).is_err());
+        assert!(Config::from_toml("disputed-strategy = \"all\"", dir).is_err());
+        assert!(Config::from_toml("simplify = -0.001", dir).is_err());
+        assert!(Config::from_toml("simplify = \"0.001,country=-1\"", dir).is_err());
+        assert!(Config::from_toml("inclusion-threshold = 0.5", dir).is_ok());
+        assert!(Config::from_toml("inclusion-threshold = 0", dir).is_err());
+        assert!(Config::from_toml("inclusion-threshold = 1.5", dir).is_err());
+    }
+}
@@ -0,0 +1,461 @@
+// The detection of the countries of the zones (the country containing each zone, the country
+// whose rules type it, and the countries claiming it) is the same for the consecutive builds of
+// the same extract with the same options, when iterating on the other stages.
+//
+// The detection results can be persisted in a cache file, reloaded by the next builds instead of
+// building the countries rtree and detecting the countries again. The results are keyed by a
+// fingerprint of the input file (its size, modification date, and a hash of its first and last
+// MiB, which holds the pbf header with its replication timestamp), by the osm ids of the zones
+// (which change with the filters of the objects) and by all the options, except the ones only
+// changing the stages after the detection or the output, with a hash of the contents of the files
+// they reference. A cache with another key is ignored, and replaced at the end of the detection.
+//
+// The filters of the osm objects given by the library are functions which cannot be compared, the
+// cache is not used with them.
+
+use crate::options::{
+    AdminLevelRemap, BuildOptions, DisputedStrategy, ExtraZonesPolicy, OsmIds, PlaceTypes,
+};
+use anyhow::{Context, Error};
+use cosmogony::Zone;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// the size of the parts of the input file read to compute its fingerprint
+const FINGERPRINT_PART_SIZE: u64 = 1 << 20;
+
+/// The countries detected for a zone
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CountryDetection {
+    /// the country of the zone
+    pub country: Option<String>,
+    /// the country whose rules type the zone
+    pub rules_country: Option<String>,
+    /// the countries containing the zone (only detected for the zones without claimants tags)
+    pub claimants: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheContents {
+    key: String,
+    /// the detections by zone osm id
+    detections: BTreeMap<String, CountryDetection>,
+}
+
+/// FNV-1a hash, stable across the builds (unlike the std hasher)
+#[derive(Clone, Copy)]
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(mut self, bytes: &[u8]) -> Self {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        self
+    }
+}
+
+/// A fingerprint of an input file, cheap to compute even for the planet
+pub fn input_fingerprint(path: &Path) -> Result<String, Error> {
+    let error = || format!("impossible to read {}", path.display());
+    let mut file = File::open(path).with_context(error)?;
+    let metadata = file.metadata().with_context(error)?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let mut part = vec![];
+    (&mut file)
+        .take(FINGERPRINT_PART_SIZE)
+        .read_to_end(&mut part)
+        .with_context(error)?;
+    let mut hash = Fnv::new().write(&part);
+    if size > FINGERPRINT_PART_SIZE {
+        part.clear();
+        file.seek(SeekFrom::Start(size.saturating_sub(FINGERPRINT_PART_SIZE)))
+            .with_context(error)?;
+        file.read_to_end(&mut part).with_context(error)?;
+        hash = hash.write(&part);
+    }
+    Ok(format!("{}-{}-{:016x}", size, modified, hash.0))
+}
+
+/// A file referenced by the options, with a hash of its contents (of all the files of a
+/// directory)
+#[derive(Serialize)]
+struct FileKey<'a> {
+    path: &'a Path,
+    hash: String,
+}
+
+impl<'a> FileKey<'a> {
+    fn new(path: &'a Path) -> Result<Self, Error> {
+        let error = || format!("impossible to read {}", path.display());
+        let mut hash = Fnv::new();
+        if path.is_dir() {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+                .with_context(error)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.is_file())
+                .collect();
+            files.sort();
+            for file in files {
+                let contents = std::fs::read(&file).with_context(error)?;
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                hash = hash.write(name.as_bytes()).write(b"\0").write(&contents);
+            }
+        } else {
+            hash = hash.write(&std::fs::read(path).with_context(error)?);
+        }
+        Ok(FileKey {
+            path,
+            hash: format!("{:016x}", hash.0),
+        })
+    }
+
+    fn of(path: &'a Option<PathBuf>) -> Result<Option<Self>, Error> {
+        path.as_deref().map(FileKey::new).transpose()
+    }
+}
+
+/// The options that can change the zones before their countries are detected, or the detection
+#[derive(Serialize)]
+struct DetectionKey<'a> {
+    /// the bundled libpostal rules change with the versions
+    version: &'static str,
+    country_code: &'a Option<String>,
+    disputed_strategy: DisputedStrategy,
+    extra_zones: Option<FileKey<'a>>,
+    extra_zones_policy: ExtraZonesPolicy,
+    disable_geometry_repair: bool,
+    keep_boundaryless_zones: bool,
+    partial_assembly: bool,
+    ceremonial_boundaries: bool,
+    infer_missing_levels: bool,
+    keep_historic: bool,
+    place_types: PlaceTypes,
+    skipped_stages: &'a [String],
+    admin_level_remap: &'a Option<AdminLevelRemap>,
+    rules_dir: Option<FileKey<'a>>,
+    admin_level_overrides: Option<FileKey<'a>>,
+    inclusion_threshold: Option<f64>,
+    country_fallback: bool,
+    dependent_territories: bool,
+    country_land_areas: bool,
+    guess_missing_countries: bool,
+    no_country: bool,
+    osm_changes: Vec<FileKey<'a>>,
+    excluded_osm_ids: &'a OsmIds,
+}
+
+impl<'a> DetectionKey<'a> {
+    /// The key of the options, None if they cannot be compared (with an osm filter)
+    ///
+    /// All the options are listed, so that a new option has to be added to the key, or
+    /// explicitly ignored if it only changes the next stages or the output.
+    fn new(options: &'a BuildOptions) -> Result<Option<Self>, Error> {
+        let BuildOptions {
+            country_code,
+            disputed_strategy,
+            extra_zones,
+            extra_zones_policy,
+            disable_geometry_repair,
+            keep_boundaryless_zones,
+            partial_assembly,
+            ceremonial_boundaries,
+            infer_missing_levels,
+            keep_historic,
+            place_types,
+            skipped_stages,
+            admin_level_remap,
+            rules_dir,
+            admin_level_overrides,
+            inclusion_threshold,
+            country_fallback,
+            dependent_territories,
+            country_land_areas,
+            guess_missing_countries,
+            no_country,
+            osm_changes,
+            excluded_osm_ids,
+            osm_filter,
+            disable_voronoi: _,
+            filter_langs: _,
+            reject_invalid_lang_tags: _,
+            romanize_names: _,
+            alternate_names: _,
+            secondary_label_lang: _,
+            secondary_label_format: _,
+            label_templates: _,
+            voronoi_clip_level: _,
+            additional_zones_strategy: _,
+            simplify: _,
+            snap_tolerance: _,
+            coordinates_precision: _,
+            winding_order: _,
+            propagate_population: _,
+            dump_after: _,
+            stop_after: _,
+            country_cache: _,
+            parent_overrides: _,
+            multi_parents: _,
+            keep_inclusions: _,
+            neighbors: _,
+            land_polygons: _,
+            sibling_overlap_threshold: _,
+            expected_counts: _,
+            fail_on_unexpected_counts: _,
+            timezones: _,
+            #[cfg(feature = "h3")]
+                h3_resolution: _,
+            deterministic: _,
+            kept_tags: _,
+            osm_metadata: _,
+            max_memory: _,
+            cancellation: _,
+        } = options;
+        if osm_filter.is_some() {
+            return Ok(None);
+        }
+        Ok(Some(DetectionKey {
+            version: env!("CARGO_PKG_VERSION"),
+            country_code,
+            disputed_strategy: *disputed_strategy,
+            extra_zones: FileKey::of(extra_zones)?,
+            extra_zones_policy: *extra_zones_policy,
+            disable_geometry_repair: *disable_geometry_repair,
+            keep_boundaryless_zones: *keep_boundaryless_zones,
+            partial_assembly: *partial_assembly,
+            ceremonial_boundaries: *ceremonial_boundaries,
+            infer_missing_levels: *infer_missing_levels,
+            keep_historic: *keep_historic,
+            place_types: *place_types,
+            skipped_stages,
+            admin_level_remap,
+            rules_dir: FileKey::of(rules_dir)?,
+            admin_level_overrides: FileKey::of(admin_level_overrides)?,
+            inclusion_threshold: *inclusion_threshold,
+            country_fallback: *country_fallback,
+            dependent_territories: *dependent_territories,
+            country_land_areas: *country_land_areas,
+            guess_missing_countries: *guess_missing_countries,
+            no_country: *no_country,
+            osm_changes: osm_changes
+                .iter()
+                .map(|p| FileKey::new(p))
+                .collect::<Result<_, _>>()?,
+            excluded_osm_ids,
+        }))
+    }
+}
+
+/// The cache file of the country detections
+pub struct CountryCache {
+    path: PathBuf,
+    key: String,
+}
+
+impl CountryCache {
+    /// The cache of the country detections of some zones, read from an input file with a
+    /// fingerprint and built with some options
+    ///
+    /// None if the options cannot be compared with the ones of the cache (with an osm filter).
+    pub fn new(
+        path: &Path,
+        fingerprint: &str,
+        zones: &[Zone],
+        options: &BuildOptions,
+    ) -> Result<Option<Self>, Error> {
+        let Some(options_key) = DetectionKey::new(options)? else {
+            return Ok(None);
+        };
+        let mut hash = Fnv::new();
+        for zone in zones {
+            hash = hash.write(zone.osm_id.as_bytes()).write(b",");
+        }
+        hash = hash.write(&serde_json::to_vec(&options_key)?);
+        Ok(Some(CountryCache {
+            path: path.to_path_buf(),
+            key: format!("{}-{:016x}", fingerprint, hash.0),
+        }))
+    }
+
+    /// The cached detections of the zones, if the cache has been written with the same key
+    pub fn read(&self, zones: &[Zone]) -> Option<Vec<CountryDetection>> {
+        let file = File::open(&self.path).ok()?;
+        let contents: CacheContents = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| warn!("invalid country cache {}: {}", self.path.display(), e))
+            .ok()?;
+        if contents.key != self.key {
+            info!(
+                "the country cache {} is for another input, it is ignored",
+                self.path.display()
+            );
+            return None;
+        }
+        let mut detections = contents.detections;
+        zones.iter().map(|z| detections.remove(&z.osm_id)).collect()
+    }
+
+    /// Write the detections of the zones in the cache
+    pub fn write(&self, zones: &[Zone], detections: &[CountryDetection]) -> Result<(), Error> {
+        let contents = CacheContents {
+            key: self.key.clone(),
+            detections: zones
+                .iter()
+                .zip(detections)
+                .map(|(z, d)| (z.osm_id.clone(), d.clone()))
+                .collect(),
+        };
+        let file = File::create(&self.path)
+            .with_context(|| format!("impossible to create {}", self.path.display()))?;
+        serde_json::to_writer(BufWriter::new(file), &contents)
+            .with_context(|| format!("impossible to write {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn country_detections_are_cached() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let input = dir.join("input.osm.pbf");
+        std::fs::write(&input, b"some osm data").unwrap();
+        let fingerprint = input_fingerprint(&input).unwrap();
+        let zone = |osm_id: &str| Zone {
+            osm_id: osm_id.into(),
+            ..Default::default()
+        };
+        let zones = vec![zone("relation:1"), zone("relation:2")];
+        let detections = vec![
+            CountryDetection {
+                country: Some("LU".into()),
+                rules_country: Some("LU".into()),
+                claimants: vec!["LU".into()],
+            },
+            CountryDetection::default(),
+        ];
+        let options = BuildOptions::default();
+        let cache_path = dir.join("countries.json");
+        let cache = |zones: &[Zone], fingerprint: &str, options: &BuildOptions| {
+            CountryCache::new(&cache_path, fingerprint, zones, options)
+                .unwrap()
+                .unwrap()
+        };
+        let cache_of = |options: &BuildOptions| cache(&zones, &fingerprint, options);
+        assert_eq!(cache_of(&options).read(&zones), None);
+        cache_of(&options).write(&zones, &detections).unwrap();
+        assert_eq!(cache_of(&options).read(&zones), Some(detections.clone()));
+
+        // another input, other options or other zones do not use the cache
+        std::fs::write(&input, b"other osm data").unwrap();
+        let other_fingerprint = input_fingerprint(&input).unwrap();
+        assert_ne!(fingerprint, other_fingerprint);
+        assert_eq!(
+            cache(&zones, &other_fingerprint, &options).read(&zones),
+            None
+        );
+        let options = BuildOptions {
+            country_fallback: true,
+            ..Default::default()
+        };
+        assert_eq!(cache_of(&options).read(&zones), None);
+        assert_eq!(
+            cache(&zones[..1], &fingerprint, &options).read(&zones[..1]),
+            None
+        );
+        let rules_dir = dir.join("rules");
+        std::fs::create_dir(&rules_dir).unwrap();
+        std::fs::write(rules_dir.join("lu.yaml"), "admin_level: {}").unwrap();
+        let extra_zones = dir.join("extra_zones.geojson");
+        std::fs::write(&extra_zones, "{}").unwrap();
+        for options in [
+            BuildOptions {
+                rules_dir: Some(rules_dir.clone()),
+                ..Default::default()
+            },
+            BuildOptions {
+                partial_assembly: true,
+                ..Default::default()
+            },
+            BuildOptions {
+                extra_zones: Some(extra_zones.clone()),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(cache_of(&options).read(&zones), None);
+        }
+
+        // the files referenced by the options are keyed by their contents
+        for options in [
+            BuildOptions {
+                rules_dir: Some(rules_dir.clone()),
+                ..Default::default()
+            },
+            BuildOptions {
+                extra_zones: Some(extra_zones.clone()),
+                ..Default::default()
+            },
+        ] {
+            cache_of(&options).write(&zones, &detections).unwrap();
+            assert!(cache_of(&options).read(&zones).is_some());
+        }
+        std::fs::write(&extra_zones, r#"{"type": "FeatureCollection"}"#).unwrap();
+        std::fs::write(rules_dir.join("lu.yaml"), "admin_level: {\"8\": city}").unwrap();
+        for options in [
+            BuildOptions {
+                rules_dir: Some(rules_dir.clone()),
+                ..Default::default()
+            },
+            BuildOptions {
+                extra_zones: Some(extra_zones.clone()),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(cache_of(&options).read(&zones), None);
+        }
+        // a missing file fails
+        let options = BuildOptions {
+            admin_level_overrides: Some(dir.join("missing.yaml")),
+            ..Default::default()
+        };
+        assert!(CountryCache::new(&cache_path, &fingerprint, &zones, &options).is_err());
+
+        // the options of the next stages do not change the detections
+        let options = BuildOptions::default();
+        cache_of(&options).write(&zones, &detections).unwrap();
+        let options = BuildOptions {
+            romanize_names: true,
+            country_cache: Some(cache_path.clone()),
+            ..Default::default()
+        };
+        options.cancellation.cancel();
+        assert!(cache_of(&options).read(&zones).is_some());
+
+        // the osm filters cannot be compared
+        let options = BuildOptions {
+            osm_filter: Some(crate::options::OsmFilter::new(|_| true)),
+            ..Default::default()
+        };
+        assert!(
+            CountryCache::new(&cache_path, &fingerprint, &zones, &options)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -1,17 +1,40 @@
+use crate::named_polygons::NamedPolygons;
+use crate::osm_store::OsmObjects;
+use crate::zone_ext::ZoneExt;
 use crate::zone_typer::ZoneTyper;
+use anyhow::{Context, Error};
 use cosmogony::{Zone, ZoneIndex};
+use geo::Contains;
+use geo_types::{MultiPolygon, Point};
+use itertools::Itertools;
+use osmpbfreader::OsmObj;
 use std::collections::BTreeMap;
 
 pub const COUNTRY_CODE_TAG: &str = "ISO3166-1:alpha2";
 
+/// the code of the countries and of the dependent territories, often without the `:alpha2`
+const DEPENDENCY_CODE_TAG: &str = "ISO3166-1";
+
+/// Low resolution boundaries of the countries, with their code in a `ISO3166-1:alpha2` property
+/// (see data/README.md)
+const BUNDLED_COUNTRIES: &str = include_str!("../data/countries.geojson");
+
 // to reduce the memory footprint we only store some of the countries information
 pub struct Country {
     iso: String, // ISO3166-1:alpha2 code (eg: FR, DE, US, etc.),
     admin_level: Option<u32>,
+    /// a dependent territory without libpostal rules, whose zones are typed with the rules of
+    /// the country including it
+    without_rules: bool,
+    /// the land of the country, without its maritime area (that can include the islands of the
+    /// neighboring countries)
+    land_area: Option<MultiPolygon<f64>>,
 }
 
 pub struct CountryFinder {
     countries: BTreeMap<ZoneIndex, Country>,
+    /// countries used for the zones not included in any country of the osm file
+    fallback: Option<NamedPolygons>,
 }
 
 impl CountryFinder {
@@ -30,24 +53,330 @@ impl CountryFinder {
                                 Country {
                                     iso: country_code,
                                     admin_level: z.admin_level,
+                                    without_rules: false,
+                                    land_area: None,
                                 },
                             )
                         })
                 })
                 .collect(),
+            fallback: None,
         }
     }
 
-    pub fn find_zone_country(&self, z: &Zone, inclusion: &[ZoneIndex]) -> Option<String> {
-        inclusion
+    /// Also use the dependent territories (the admin_level 3 or 4 zones with an `ISO3166-1`
+    /// code, like French Guiana in France) as the country of their zones, even without rules
+    pub fn with_dependent_territories(mut self, zones: &[Zone], typer: &ZoneTyper) -> Self {
+        let dependencies = zones
+            .iter()
+            .filter(|z| matches!(z.admin_level, Some(3) | Some(4)))
+            .filter(|z| !self.countries.contains_key(&z.id))
+            .filter_map(|z| {
+                let code = z
+                    .tags
+                    .get(COUNTRY_CODE_TAG)
+                    .or_else(|| z.tags.get(DEPENDENCY_CODE_TAG))?
+                    .to_uppercase();
+                // the numeric and alpha3 codes are not libpostal countries
+                if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+                    return None;
+                }
+                let country = Country {
+                    without_rules: !typer.contains_rule(&code),
+                    iso: code,
+                    admin_level: z.admin_level,
+                    land_area: None,
+                };
+                Some((z.id, country))
+            })
+            .collect::<Vec<_>>();
+        info!("{} dependent territories found", dependencies.len());
+        self.countries.extend(dependencies);
+        self
+    }
+
+    /// Use the bundled low resolution countries boundaries to find the country of the zones
+    /// not included in any country of the osm file (like the zones of a city extract)
+    pub fn with_bundled_fallback(mut self, typer: &ZoneTyper) -> Result<Self, Error> {
+        let countries = NamedPolygons::from_geojson(BUNDLED_COUNTRIES.as_bytes(), COUNTRY_CODE_TAG)
+            .context("invalid bundled countries")?;
+        let nb_countries = countries.len();
+        let countries = NamedPolygons::new(
+            countries
+                .into_polygons()
+                .into_iter()
+                .filter(|(iso, _)| typer.contains_rule(iso))
+                .collect(),
+        );
+        info!(
+            "{} fallback countries loaded ({} without libpostal rules ignored)",
+            countries.len(),
+            nb_countries - countries.len()
+        );
+        self.fallback = Some(countries);
+        Ok(self)
+    }
+
+    /// Use the land areas of the countries to choose the country of the zones included in
+    /// several countries
+    pub fn with_land_areas(mut self, mut land_areas: BTreeMap<String, MultiPolygon<f64>>) -> Self {
+        for country in self.countries.values_mut() {
+            country.land_area = land_areas.remove(&country.iso);
+        }
+        let nb_land_areas = self
+            .countries
+            .values()
+            .filter(|c| c.land_area.is_some())
+            .count();
+        info!("{} countries with a land area", nb_land_areas);
+        self
+    }
+
+    /// The countries including a zone, without the ones whose land area does not contain it
+    /// when the zone is included in several countries (like an island in the maritime area of
+    /// a neighboring country)
+    fn including_countries(&self, z: &Zone, inclusion: &[ZoneIndex]) -> Vec<&Country> {
+        let countries: Vec<&Country> = inclusion
             .iter()
-            .chain(std::iter::once(&z.id)) // we also add the zone to check if it's itself a country
             .filter_map(|parent_index| self.countries.get(parent_index))
+            .collect();
+        if countries.len() < 2 || countries.iter().all(|c| c.land_area.is_none()) {
+            return countries;
+        }
+        let Some(center) = zone_center(z) else {
+            return countries;
+        };
+        let on_land: Vec<&Country> = countries
+            .iter()
+            .copied()
+            .filter(|c| c.land_area.as_ref().is_none_or(|l| l.contains(&center)))
+            .collect();
+        // a zone in none of the land areas (eg. an offshore zone) keeps all its countries
+        if on_land.is_empty() {
+            countries
+        } else {
+            on_land
+        }
+    }
+
+    pub fn find_zone_country(&self, z: &Zone, inclusion: &[ZoneIndex]) -> Option<String> {
+        self.including_countries(z, inclusion)
+            .into_iter()
+            .chain(self.countries.get(&z.id)) // we also add the zone to check if it's itself a country
+            .max_by_key(|c| c.admin_level.unwrap_or(0u32))
+            .map(|c| c.iso.clone())
+            .or_else(|| self.find_fallback_country(z))
+    }
+
+    /// The country whose rules type the zones of a country: the country itself, or the country
+    /// including it for a dependent territory without its own rules
+    pub fn find_rules_country<'a>(
+        &'a self,
+        z: &Zone,
+        country: &'a str,
+        inclusion: &[ZoneIndex],
+    ) -> &'a str {
+        let countries = || {
+            inclusion
+                .iter()
+                .chain(std::iter::once(&z.id))
+                .filter_map(|parent_index| self.countries.get(parent_index))
+        };
+        if !countries().any(|c| c.without_rules && c.iso == country) {
+            return country;
+        }
+        countries()
+            .filter(|c| !c.without_rules)
             .max_by_key(|c| c.admin_level.unwrap_or(0u32))
+            .map_or(country, |c| c.iso.as_str())
+    }
+
+    fn find_fallback_country(&self, z: &Zone) -> Option<String> {
+        let fallback = self.fallback.as_ref()?;
+        let center = zone_center(z)?;
+        fallback.find(&center).map(|iso| iso.to_string())
+    }
+
+    /// find all the countries (admin_level 2 zones) containing a zone
+    ///
+    /// A zone contained by more than one country is claimed by all of them
+    pub fn find_zone_claimants(&self, z: &Zone, inclusion: &[ZoneIndex]) -> Vec<String> {
+        self.including_countries(z, inclusion)
+            .into_iter()
+            .filter(|c| c.admin_level == Some(2))
             .map(|c| c.iso.clone())
+            .sorted()
+            .dedup()
+            .collect()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.countries.is_empty()
+        self.countries.is_empty() && self.fallback.as_ref().is_none_or(|f| f.is_empty())
+    }
+}
+
+fn zone_center(z: &Zone) -> Option<Point<f64>> {
+    z.center.or_else(|| z.bbox.map(|b| b.center().into()))
+}
+
+/// check if an osm object is the land area of a country (a `boundary=land_area` relation with an
+/// `ISO3166-1:alpha2` code)
+pub fn is_country_land_area(obj: &OsmObj) -> bool {
+    obj.relation().is_some_and(|rel| {
+        rel.tags.contains("boundary", "land_area") && rel.tags.contains_key(COUNTRY_CODE_TAG)
+    })
+}
+
+/// Read the land areas of the countries, by country code
+pub fn read_country_land_areas(osm: &dyn OsmObjects) -> BTreeMap<String, MultiPolygon<f64>> {
+    let mut land_areas = BTreeMap::<String, MultiPolygon<f64>>::new();
+    for obj in osm.objects() {
+        let obj = obj.as_ref();
+        let Some(relation) = obj.relation().filter(|_| is_country_land_area(obj)) else {
+            continue;
+        };
+        let objects = osm.dependencies(obj);
+        let zone = Zone::from_osm_relation(relation, &objects, ZoneIndex { index: 0 });
+        let Some(boundary) = zone.and_then(|z| z.boundary) else {
+            warn!(
+                "impossible to build the land area relation:{}",
+                relation.id.0
+            );
+            continue;
+        };
+        let iso = relation.tags.get(COUNTRY_CODE_TAG).unwrap().to_uppercase();
+        // a country can have several land areas
+        land_areas
+            .entry(iso)
+            .or_insert_with(|| MultiPolygon(vec![]))
+            .0
+            .extend(boundary);
+    }
+    info!("{} countries land areas read", land_areas.len());
+    land_areas
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::square;
+    use geo_types::Point;
+
+    #[test]
+    fn bundled_fallback_country() {
+        let typer = ZoneTyper::new().unwrap();
+        let finder = CountryFinder::init(&[], &typer);
+        assert!(finder.is_empty());
+        let finder = finder.with_bundled_fallback(&typer).unwrap();
+        assert!(!finder.is_empty());
+
+        let zone = |lon, lat| Zone {
+            center: Some(Point::new(lon, lat)),
+            ..Default::default()
+        };
+        // Luxembourg city, Paris and the middle of the atlantic ocean
+        assert_eq!(
+            finder.find_zone_country(&zone(6.13, 49.61), &[]).as_deref(),
+            Some("LU")
+        );
+        assert_eq!(
+            finder.find_zone_country(&zone(2.35, 48.85), &[]).as_deref(),
+            Some("FR")
+        );
+        assert_eq!(finder.find_zone_country(&zone(-30., 30.), &[]), None);
+    }
+
+    #[test]
+    fn dependent_territories() {
+        let typer = ZoneTyper::new().unwrap();
+        let zone = |index, admin_level, tag: &str, code: &str| Zone {
+            id: ZoneIndex { index },
+            admin_level: Some(admin_level),
+            tags: [(tag, code)].into_iter().collect(),
+            ..Default::default()
+        };
+        let zones = vec![
+            zone(0, 2, COUNTRY_CODE_TAG, "FR"),
+            // a (fictional) territory of France, without libpostal rules
+            zone(1, 4, DEPENDENCY_CODE_TAG, "XT"),
+            zone(2, 4, DEPENDENCY_CODE_TAG, "250"),
+            zone(3, 6, DEPENDENCY_CODE_TAG, "XU"),
+        ];
+        let city = Zone {
+            id: ZoneIndex { index: 4 },
+            ..Default::default()
+        };
+        let in_territory = [ZoneIndex { index: 0 }, ZoneIndex { index: 1 }];
+
+        let finder = CountryFinder::init(&zones, &typer);
+        assert_eq!(
+            finder.find_zone_country(&city, &in_territory).as_deref(),
+            Some("FR")
+        );
+
+        let finder = finder.with_dependent_territories(&zones, &typer);
+        assert_eq!(finder.countries.len(), 2);
+        assert_eq!(
+            finder.find_zone_country(&city, &in_territory).as_deref(),
+            Some("XT")
+        );
+        // the zones of the territory, and the territory itself, are typed with the french rules
+        assert_eq!(finder.find_rules_country(&city, "XT", &in_territory), "FR");
+        assert_eq!(
+            finder.find_rules_country(&zones[1], "XT", &in_territory[..1]),
+            "FR"
+        );
+        assert_eq!(
+            finder.find_rules_country(&city, "FR", &in_territory[..1]),
+            "FR"
+        );
+        // without the country including it, the territory has no rules
+        assert_eq!(
+            finder.find_rules_country(&city, "XT", &in_territory[1..]),
+            "XT"
+        );
+    }
+
+    #[test]
+    fn countries_land_areas() {
+        let typer = ZoneTyper::new().unwrap();
+        let country = |index, code: &str| Zone {
+            id: ZoneIndex { index },
+            admin_level: Some(2),
+            tags: [(COUNTRY_CODE_TAG, code)].into_iter().collect(),
+            ..Default::default()
+        };
+        let zones = vec![country(0, "LU"), country(1, "FR")];
+        // an island of LU, in the maritime area of FR
+        let island = Zone {
+            id: ZoneIndex { index: 2 },
+            center: Some(Point::new(5., 5.)),
+            ..Default::default()
+        };
+        let inclusion = [ZoneIndex { index: 0 }, ZoneIndex { index: 1 }];
+
+        let finder = CountryFinder::init(&zones, &typer);
+        assert_eq!(
+            finder.find_zone_country(&island, &inclusion).as_deref(),
+            Some("FR")
+        );
+        assert_eq!(finder.find_zone_claimants(&island, &inclusion).len(), 2);
+
+        let land_areas = [
+            ("LU".into(), square((4., 4.), (6., 6.))),
+            ("FR".into(), square((0., 0.), (2., 2.))),
+        ];
+        let finder = finder.with_land_areas(land_areas.into_iter().collect());
+        assert_eq!(
+            finder.find_zone_country(&island, &inclusion).as_deref(),
+            Some("LU")
+        );
+        assert_eq!(finder.find_zone_claimants(&island, &inclusion), vec!["LU"]);
+        // a zone out of all the land areas keeps all its countries
+        let offshore = Zone {
+            center: Some(Point::new(9., 1.)),
+            ..island
+        };
+        assert_eq!(finder.find_zone_claimants(&offshore, &inclusion).len(), 2);
     }
 }
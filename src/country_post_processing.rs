@@ -0,0 +1,281 @@
+// Some countries have administrative oddities that libpostal's admin_level rules cannot express
+// (eg. the unincorporated areas of Germany are tagged like municipalities). They are fixed
+// after the typing by the post-processors of a registry, each one working on the zones of its
+// country.
+
+use cosmogony::{Zone, ZoneType};
+use log::info;
+use std::collections::BTreeMap;
+
+/// A fix of the zones of a country, run after the zones have been typed
+pub trait CountryPostProcessor: Send + Sync {
+    /// the name of the processor, for the logs
+    fn name(&self) -> &'static str;
+
+    /// the ISO3166-1 alpha2 code of the country whose zones are processed
+    fn country_code(&self) -> &str;
+
+    /// Fix the typed zones of the country (the zones without a country are never processed)
+    ///
+    /// Returns the number of modified zones.
+    fn process(&self, zones: &mut [&mut Zone]) -> usize;
+}
+
+/// The post-processors run after the typing, in their registration order
+///
+/// The default registry has some built-in processors, and more can be registered when building
+/// a cosmogony with the library:
+///
+/// ```no_run
+/// use cosmogony::{Zone, ZoneType};
+/// use cosmogony_builder::country_post_processing::{CountryPostProcessor, CountryPostProcessors};
+/// use cosmogony_builder::pipeline::Pipeline;
+///
+/// struct NoSuburbs;
+///
+/// impl CountryPostProcessor for NoSuburbs {
+///     fn name(&self) -> &'static str {
+///         "no_suburbs"
+///     }
+///     fn country_code(&self) -> &str {
+///         "LU"
+///     }
+///     fn process(&self, zones: &mut [&mut Zone]) -> usize {
+///         let mut nb_changed = 0;
+///         for z in zones.iter_mut().filter(|z| z.zone_type == Some(ZoneType::Suburb)) {
+///             z.zone_type = Some(ZoneType::NonAdministrative);
+///             nb_changed += 1;
+///         }
+///         nb_changed
+///     }
+/// }
+///
+/// let processors = CountryPostProcessors::default().with(NoSuburbs);
+/// let pipeline = Pipeline::default().with_country_post_processors(processors);
+/// ```
+pub struct CountryPostProcessors {
+    processors: Vec<Box<dyn CountryPostProcessor>>,
+}
+
+impl Default for CountryPostProcessors {
+    /// the built-in processors
+    fn default() -> Self {
+        CountryPostProcessors::empty()
+            .with(FrenchMunicipalArrondissements)
+            .with(GermanUnincorporatedAreas)
+    }
+}
+
+impl CountryPostProcessors {
+    /// a registry without any processor
+    pub fn empty() -> Self {
+        CountryPostProcessors { processors: vec![] }
+    }
+
+    /// register a processor, run after the already registered ones
+    pub fn with(mut self, processor: impl CountryPostProcessor + 'static) -> Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.processors.iter().map(|p| p.name()).collect()
+    }
+
+    /// Run all the processors on the zones of their country
+    ///
+    /// Returns the number of modified zones.
+    pub fn process(&self, zones: &mut [Zone]) -> usize {
+        if self.processors.is_empty() {
+            return 0;
+        }
+        let mut zones_by_country: BTreeMap<String, Vec<&mut Zone>> = BTreeMap::new();
+        for z in zones.iter_mut() {
+            if let Some(country) = z.country_code.clone() {
+                zones_by_country.entry(country).or_default().push(z);
+            }
+        }
+        let mut nb_changed = 0;
+        for processor in &self.processors {
+            let country = processor.country_code().to_uppercase();
+            let Some(country_zones) = zones_by_country.get_mut(&country) else {
+                continue;
+            };
+            let nb = processor.process(country_zones);
+            if nb > 0 {
+                info!("{}: {} zones of {} fixed", processor.name(), nb, country);
+            }
+            nb_changed += nb;
+        }
+        nb_changed
+    }
+}
+
+/// The municipal arrondissements of Paris, Lyon and Marseille are city districts, even when they
+/// are not tagged with the admin_level of the city districts.
+///
+/// They are found by their INSEE code, as some other zones (like the merged former communes)
+/// share their admin_level.
+pub struct FrenchMunicipalArrondissements;
+
+fn is_municipal_arrondissement(insee: &str) -> bool {
+    let Ok(code) = insee.parse::<u32>() else {
+        return false;
+    };
+    insee.len() == 5
+        && matches!(code,
+            75101..=75120 // Paris
+            | 69381..=69389 // Lyon
+            | 13201..=13216 // Marseille
+        )
+}
+
+impl CountryPostProcessor for FrenchMunicipalArrondissements {
+    fn name(&self) -> &'static str {
+        "french_municipal_arrondissements"
+    }
+
+    fn country_code(&self) -> &str {
+        "FR"
+    }
+
+    fn process(&self, zones: &mut [&mut Zone]) -> usize {
+        let mut nb_changed = 0;
+        for z in zones.iter_mut() {
+            let is_arrondissement = z
+                .tags
+                .get("ref:INSEE")
+                .is_some_and(is_municipal_arrondissement);
+            if is_arrondissement && z.zone_type != Some(ZoneType::CityDistrict) {
+                z.zone_type = Some(ZoneType::CityDistrict);
+                nb_changed += 1;
+            }
+        }
+        nb_changed
+    }
+}
+
+/// The unincorporated areas of Germany (gemeindefreie Gebiete, mostly forests and lakes) are
+/// tagged like the municipalities, but nobody lives there: they are not cities.
+pub struct GermanUnincorporatedAreas;
+
+fn is_unincorporated_area(zone: &Zone) -> bool {
+    let is_gemeindefrei = |v: &str| v.to_lowercase().contains("gemeindefrei");
+    is_gemeindefrei(&zone.name)
+        || ["designation", "de:place", "official_status"]
+            .iter()
+            .any(|k| zone.tags.get(k).is_some_and(is_gemeindefrei))
+}
+
+impl CountryPostProcessor for GermanUnincorporatedAreas {
+    fn name(&self) -> &'static str {
+        "german_unincorporated_areas"
+    }
+
+    fn country_code(&self) -> &str {
+        "DE"
+    }
+
+    fn process(&self, zones: &mut [&mut Zone]) -> usize {
+        let mut nb_changed = 0;
+        for z in zones.iter_mut() {
+            if z.zone_type == Some(ZoneType::City) && is_unincorporated_area(z) {
+                z.zone_type = Some(ZoneType::NonAdministrative);
+                nb_changed += 1;
+            }
+        }
+        nb_changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::ZoneTags;
+
+    fn zone(name: &str, country: &str, zone_type: ZoneType, tags: &[(&str, &str)]) -> Zone {
+        let mut zone_tags = ZoneTags::new();
+        for (k, v) in tags {
+            zone_tags.insert_if_absent(k, v);
+        }
+        Zone {
+            name: name.into(),
+            country_code: Some(country.into()),
+            zone_type: Some(zone_type),
+            tags: zone_tags,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn built_in_post_processors() {
+        let mut zones = vec![
+            zone(
+                "Paris 1er Arrondissement",
+                "FR",
+                ZoneType::City,
+                &[("ref:INSEE", "75101")],
+            ),
+            zone(
+                "Ligny",
+                "FR",
+                ZoneType::CityDistrict,
+                &[("ref:INSEE", "55291")],
+            ),
+            zone(
+                "Sachsenwald",
+                "DE",
+                ZoneType::City,
+                &[("designation", "gemeindefreies Gebiet")],
+            ),
+            zone("Hamburg", "DE", ZoneType::City, &[]),
+            // only the zones of the processor's country are processed
+            zone(
+                "Lyon 1er Arrondissement",
+                "BE",
+                ZoneType::City,
+                &[("ref:INSEE", "69381")],
+            ),
+        ];
+
+        assert_eq!(CountryPostProcessors::default().process(&mut zones), 2);
+        let types: Vec<_> = zones.iter().map(|z| z.zone_type.unwrap()).collect();
+        assert_eq!(
+            types,
+            vec![
+                ZoneType::CityDistrict,
+                ZoneType::CityDistrict,
+                ZoneType::NonAdministrative,
+                ZoneType::City,
+                ZoneType::City,
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_post_processor() {
+        struct Rename;
+        impl CountryPostProcessor for Rename {
+            fn name(&self) -> &'static str {
+                "rename"
+            }
+            fn country_code(&self) -> &str {
+                "lu"
+            }
+            fn process(&self, zones: &mut [&mut Zone]) -> usize {
+                zones.iter_mut().for_each(|z| z.name.push_str(" (LU)"));
+                zones.len()
+            }
+        }
+
+        let processors = CountryPostProcessors::empty().with(Rename);
+        assert_eq!(processors.names(), vec!["rename"]);
+        let mut zones = vec![
+            zone("Luxembourg", "LU", ZoneType::City, &[]),
+            zone("Metz", "FR", ZoneType::City, &[]),
+        ];
+        assert_eq!(processors.process(&mut zones), 1);
+        assert_eq!(zones[0].name, "Luxembourg (LU)");
+        assert_eq!(zones[1].name, "Metz");
+    }
+}
@@ -0,0 +1,174 @@
+// Some areas are mapped twice: an old closed way kept along the new relation, or two
+// relations for the same city. Both end up in the cosmogony, with the same name, and
+// the duplicate makes an extra level in the hierarchy.
+//
+// The zones with the same name and admin_level whose boundaries are nearly identical are
+// duplicates: only the canonical one (a relation rather than a way, preferably with a
+// wikidata id and the most tags) is kept, the others are reported in the stats.
+
+use cosmogony::{CosmogonyStats, Zone, ZoneIndex};
+use geo::prelude::Intersects;
+use geos::{Geom, Geometry};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The minimum ratio of the intersection area over the union area of 2 duplicate boundaries
+pub const DUPLICATE_SIMILARITY: f64 = 0.95;
+
+/// the zones with the highest score are kept
+fn canonical_score(zone: &Zone) -> (bool, bool, usize) {
+    (
+        zone.osm_id.starts_with("relation:"),
+        zone.wikidata.is_some(),
+        zone.tags.len(),
+    )
+}
+
+fn similarity(zone: &Zone, geom: &Geometry<'_>, other: &Zone, other_geom: &Geometry<'_>) -> f64 {
+    let bboxes_intersect = match (zone.bbox, other.bbox) {
+        (Some(bbox), Some(other_bbox)) => bbox.intersects(&other_bbox),
+        _ => false,
+    };
+    if !bboxes_intersect {
+        return 0.;
+    }
+    let areas = || -> geos::GResult<(f64, f64, f64)> {
+        let intersection = geom.intersection(other_geom)?.area()?;
+        Ok((intersection, geom.area()?, other_geom.area()?))
+    };
+    match areas() {
+        Ok((intersection, area, other_area)) if area + other_area - intersection > 0. => {
+            intersection / (area + other_area - intersection)
+        }
+        Ok(_) => 0.,
+        Err(e) => {
+            warn!(
+                "failed to compare {} and {}: {}",
+                zone.osm_id, other.osm_id, e
+            );
+            0.
+        }
+    }
+}
+
+/// Find the duplicates among zones with the same name and admin_level
+///
+/// Returns the positions of the duplicates, with the position of the zone kept for each.
+fn find_duplicates(zones: &[Zone], candidates: &[usize]) -> Vec<(usize, usize)> {
+    let mut candidates: Vec<(usize, Geometry<'_>)> = candidates
+        .iter()
+        .filter_map(|&idx| {
+            let geom = Geometry::try_from(zones[idx].boundary.as_ref()?)
+                .map_err(|e| warn!("failed to convert {} to geos: {}", zones[idx].osm_id, e))
+                .ok()?;
+            Some((idx, geom))
+        })
+        .collect();
+    // the stable sort keeps the osm order between the zones of the same score
+    candidates.sort_by_key(|(idx, _)| std::cmp::Reverse(canonical_score(&zones[*idx])));
+
+    let mut duplicates = vec![];
+    let mut removed = BTreeSet::new();
+    for (i, (kept, geom)) in candidates.iter().enumerate() {
+        if removed.contains(kept) {
+            continue;
+        }
+        for (other, other_geom) in &candidates[i + 1..] {
+            if removed.contains(other) {
+                continue;
+            }
+            let zone = &zones[*kept];
+            if similarity(zone, geom, &zones[*other], other_geom) >= DUPLICATE_SIMILARITY {
+                debug!("{} is a duplicate of {}", zones[*other].osm_id, zone.osm_id);
+                removed.insert(*other);
+                duplicates.push((*other, *kept));
+            }
+        }
+    }
+    duplicates
+}
+
+/// Remove the zones duplicating another zone, before the zones are typed
+///
+/// The zones are re-indexed, so this must be done before any zone references another one.
+/// Returns the number of removed zones.
+pub fn remove_duplicate_zones(zones: &mut Vec<Zone>, stats: &mut CosmogonyStats) -> usize {
+    info!("looking for duplicate zones");
+    let mut groups = BTreeMap::<(String, Option<u32>), Vec<usize>>::new();
+    for (idx, zone) in zones.iter().enumerate() {
+        if zone.boundary.is_some() && !zone.name.is_empty() {
+            let name = zone.name.trim().to_lowercase();
+            groups
+                .entry((name, zone.admin_level))
+                .or_default()
+                .push(idx);
+        }
+    }
+
+    let mut removed = BTreeSet::new();
+    for candidates in groups.values().filter(|g| g.len() > 1) {
+        for (duplicate, kept) in find_duplicates(zones, candidates) {
+            stats
+                .duplicate_zones
+                .insert(zones[duplicate].osm_id.clone(), zones[kept].osm_id.clone());
+            removed.insert(duplicate);
+        }
+    }
+    if removed.is_empty() {
+        return 0;
+    }
+
+    let mut idx = 0;
+    zones.retain(|_| {
+        idx += 1;
+        !removed.contains(&(idx - 1))
+    });
+    for (index, zone) in zones.iter_mut().enumerate() {
+        zone.id = ZoneIndex { index };
+    }
+    info!("{} duplicate zones removed", removed.len());
+    removed.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::prelude::BoundingRect;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+
+    fn zone(index: usize, osm_id: &str, name: &str, max: f64) -> Zone {
+        let ring = LineString::from(vec![(0., 0.), (max, 0.), (max, max), (0., max), (0., 0.)]);
+        let boundary = MultiPolygon(vec![Polygon::new(ring, vec![])]);
+        Zone {
+            id: ZoneIndex { index },
+            osm_id: osm_id.into(),
+            name: name.into(),
+            admin_level: Some(8),
+            bbox: boundary.bounding_rect(),
+            boundary: Some(boundary),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn duplicates_are_removed() {
+        let mut zones = vec![
+            zone(0, "way:1", "Springfield", 1.),
+            zone(1, "relation:2", "Springfield", 1.01),
+            zone(2, "relation:3", "Springfield", 0.5),
+            zone(3, "relation:4", "Shelbyville", 1.),
+        ];
+        let mut stats = CosmogonyStats::default();
+
+        assert_eq!(remove_duplicate_zones(&mut zones, &mut stats), 1);
+        let osm_ids: Vec<_> = zones.iter().map(|z| z.osm_id.as_str()).collect();
+        assert_eq!(osm_ids, vec!["relation:2", "relation:3", "relation:4"]);
+        let ids: Vec<_> = zones.iter().map(|z| z.id.index).collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(
+            stats.duplicate_zones,
+            [("way:1".to_string(), "relation:2".to_string())]
+                .into_iter()
+                .collect()
+        );
+    }
+}
@@ -0,0 +1,255 @@
+// An enclave is a zone surrounded by another zone of the same admin_level, like Llívia
+// (a Spanish municipality surrounded by France) or the Belgian parts of Baarle.
+//
+// The enclave should be a hole of the boundary of the zone surrounding it. When this hole
+// is missing, the enclave is geometrically contained in the surrounding zone and in all its
+// ancestors, so it would get the country and the parents of the surrounding zone.
+//
+// The enclaves are detected before the typing. If an enclave is also contained in some zones
+// that do not contain the surrounding zone (its real hierarchy, eg. the Spanish province of
+// Llívia), the zones of the surrounding zone hierarchy are removed from its inclusions,
+// so it is attached to its own country.
+
+use crate::hierarchy_builder::ZonesTree;
+use cosmogony::{CosmogonyStats, Zone, ZoneIndex, ZoneType};
+use geo_types::{MultiPolygon, Polygon};
+use geos::{Geom, Geometry};
+use rayon::prelude::*;
+use std::sync::OnceLock;
+
+/// A zone surrounded by another zone of the same admin_level
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enclave {
+    pub zone: ZoneIndex,
+    pub surrounding_zone: ZoneIndex,
+}
+
+/// the boundary without its holes
+fn exteriors(boundary: &MultiPolygon<f64>) -> MultiPolygon<f64> {
+    boundary
+        .iter()
+        .map(|p| Polygon::new(p.exterior().clone(), vec![]))
+        .collect()
+}
+
+/// the geos geometry of a zone's boundary (None if it cannot be converted)
+fn to_geos(zone: &Zone, boundary: &MultiPolygon<f64>) -> Option<Geometry<'static>> {
+    Geometry::try_from(boundary)
+        .map_err(|e| {
+            warn!(
+                "impossible to convert to geos for zone {}: {}",
+                zone.osm_id, e
+            )
+        })
+        .ok()
+}
+
+fn surrounds(zone: &Zone, exteriors: &Geometry, enclave: &Zone, enclave_g: &Geometry) -> bool {
+    match exteriors.covers(enclave_g) {
+        Ok(covers) => covers,
+        Err(e) => {
+            warn!(
+                "impossible to check if {} surrounds {}: {}",
+                zone.osm_id, enclave.osm_id, e
+            );
+            false
+        }
+    }
+}
+
+fn bbox_contains(zone: &Zone, other: &Zone) -> bool {
+    match (zone.bbox, other.bbox) {
+        (Some(b), Some(o)) => {
+            b.min().x <= o.min().x
+                && b.min().y <= o.min().y
+                && b.max().x >= o.max().x
+                && b.max().y >= o.max().y
+        }
+        _ => false,
+    }
+}
+
+fn is_administrative(zone: &Zone) -> bool {
+    zone.admin_level.is_some() && zone.zone_type != Some(ZoneType::NonAdministrative)
+}
+
+/// Find the zones surrounded by another zone of the same admin_level
+///
+/// The boundaries (without their holes) of the candidate surrounding zones are converted to geos
+/// once, and shared by all the zones they may surround.
+pub fn find_enclaves(
+    zones: &[Zone],
+    ztree: &ZonesTree,
+    inclusions: &[Vec<ZoneIndex>],
+) -> Vec<Enclave> {
+    info!("looking for the enclaves");
+    let geos_exteriors: Vec<OnceLock<Option<Geometry<'static>>>> =
+        zones.iter().map(|_| OnceLock::new()).collect();
+    let exteriors_of = |zone: &Zone| {
+        geos_exteriors[zone.id.index]
+            .get_or_init(|| to_geos(zone, &exteriors(zone.boundary.as_ref()?)))
+            .as_ref()
+    };
+    zones
+        .par_iter()
+        .filter(|z| z.boundary.is_some() && is_administrative(z))
+        .filter_map(|z| {
+            let mut candidates: Vec<&Zone> = ztree
+                .fetch_zone_bbox(z)
+                .into_iter()
+                .filter(|idx| *idx != z.id)
+                .map(|idx| &zones[idx.index])
+                .filter(|c| c.admin_level == z.admin_level && is_administrative(c))
+                .filter(|c| bbox_contains(c, z))
+                .collect();
+            candidates.sort_by(|a, b| a.osm_id.cmp(&b.osm_id));
+            // the zone's boundary is only converted if it is not already known to be included
+            let mut geometry = None;
+            candidates
+                .into_iter()
+                .find(|c| {
+                    if inclusions[z.id.index].contains(&c.id) {
+                        return true;
+                    }
+                    let Some(exteriors) = exteriors_of(c) else {
+                        return false;
+                    };
+                    geometry
+                        .get_or_insert_with(|| to_geos(z, z.boundary.as_ref()?))
+                        .as_ref()
+                        .is_some_and(|g| surrounds(c, exteriors, z, g))
+                })
+                .map(|c| Enclave {
+                    zone: z.id,
+                    surrounding_zone: c.id,
+                })
+        })
+        .collect()
+}
+
+/// Mark the enclaves, and attach the enclaves missing from the holes of the surrounding zone
+/// to their own hierarchy
+///
+/// Returns the number of enclaves.
+pub fn handle_enclaves(
+    zones: &mut [Zone],
+    inclusions: &mut [Vec<ZoneIndex>],
+    ztree: &ZonesTree,
+    stats: &mut CosmogonyStats,
+) -> usize {
+    let enclaves = find_enclaves(zones, ztree, inclusions);
+    for enclave in &enclaves {
+        let (z, surrounding) = (enclave.zone.index, enclave.surrounding_zone.index);
+        zones[z].is_enclave = true;
+        stats.enclaves += 1;
+        if !inclusions[z].contains(&enclave.surrounding_zone) {
+            continue;
+        }
+        stats.enclaves_without_hole += 1;
+        warn!(
+            "{} is an enclave of {}, but it is not a hole of its boundary",
+            zones[z].osm_id, zones[surrounding].osm_id
+        );
+        let is_host =
+            |c: &ZoneIndex| *c == enclave.surrounding_zone || inclusions[surrounding].contains(c);
+        let has_own_hierarchy = inclusions[z].iter().any(|c| {
+            !is_host(c)
+                && is_administrative(&zones[c.index])
+                && zones[c.index].admin_level < zones[z].admin_level
+        });
+        if has_own_hierarchy {
+            debug!("{} attached to its own hierarchy", zones[z].osm_id);
+            let own: Vec<ZoneIndex> = inclusions[z]
+                .iter()
+                .filter(|c| !is_host(c))
+                .copied()
+                .collect();
+            inclusions[z] = own;
+        }
+    }
+    info!("{} enclaves found", enclaves.len());
+    enclaves.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hierarchy_builder::find_inclusions;
+    use crate::options::CancellationToken;
+    use crate::test_utils::square_ring;
+    use geo::prelude::BoundingRect;
+
+    fn zone(index: usize, level: u32, polygons: Vec<Polygon<f64>>) -> Zone {
+        let boundary = MultiPolygon(polygons);
+        Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            admin_level: Some(level),
+            bbox: boundary.bounding_rect(),
+            boundary: Some(boundary),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn enclaves_are_attached_to_their_country() {
+        let mut zones = vec![
+            // a country without a hole for the enclave of its neighbour
+            zone(
+                0,
+                2,
+                vec![Polygon::new(square_ring((0., 0.), (10., 10.)), vec![])],
+            ),
+            zone(
+                1,
+                8,
+                vec![Polygon::new(square_ring((4., 4.), (6., 6.)), vec![])],
+            ),
+            // the enclave, and its country (made of a main part and of the enclave)
+            zone(
+                2,
+                8,
+                vec![Polygon::new(square_ring((4.5, 4.5), (5., 5.)), vec![])],
+            ),
+            zone(
+                3,
+                2,
+                vec![
+                    Polygon::new(square_ring((4.5, 4.5), (5., 5.)), vec![]),
+                    Polygon::new(square_ring((20., 20.), (30., 30.)), vec![]),
+                ],
+            ),
+            // a proper enclave, that is a hole of the surrounding zone
+            zone(
+                4,
+                8,
+                vec![Polygon::new(
+                    square_ring((7., 7.), (9., 9.)),
+                    vec![square_ring((7.5, 7.5), (8., 8.))],
+                )],
+            ),
+            zone(
+                5,
+                8,
+                vec![Polygon::new(square_ring((7.5, 7.5), (8., 8.)), vec![])],
+            ),
+        ];
+        let (mut inclusions, ztree) =
+            find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        // the enclave is geometrically in both countries
+        assert_eq!(inclusions[2].len(), 3);
+        let mut stats = CosmogonyStats::default();
+
+        assert_eq!(
+            handle_enclaves(&mut zones, &mut inclusions, &ztree, &mut stats),
+            2
+        );
+        let enclaves: Vec<_> = zones.iter().map(|z| z.is_enclave).collect();
+        assert_eq!(enclaves, vec![false, false, true, false, false, true]);
+        assert_eq!(stats.enclaves, 2);
+        assert_eq!(stats.enclaves_without_hole, 1);
+        // the enclave is only in its own country
+        assert_eq!(inclusions[2], vec![ZoneIndex { index: 3 }]);
+        assert_eq!(inclusions[5], vec![ZoneIndex { index: 0 }]);
+    }
+}
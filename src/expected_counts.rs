@@ -0,0 +1,139 @@
+// A mapping change in osm (or a bug) can silently remove a whole kind of zones from a build,
+// eg. all the cities of a country whose admin_level has been changed.
+//
+// The expected number of zones of some countries and types can be given in a yaml file, eg.
+//
+// ```yaml
+// FR:
+//   state: 18
+//   city: { count: 34900, tolerance: 0.02 }
+// LU:
+//   city: 102
+// ```
+//
+// The tolerance is a ratio of the expected count (10% by default). The counts deviating more
+// than their tolerance are reported in the stats (and can make the build fail).
+
+use anyhow::{Context, Error};
+use cosmogony::{CosmogonyStats, CountDeviation, ZoneType};
+use log::{info, warn};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// the accepted deviation from an expected count, if not given
+pub const DEFAULT_TOLERANCE: f64 = 0.1;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ExpectedCount {
+    Count(u64),
+    WithTolerance { count: u64, tolerance: f64 },
+}
+
+impl ExpectedCount {
+    fn count(&self) -> u64 {
+        match *self {
+            ExpectedCount::Count(c) | ExpectedCount::WithTolerance { count: c, .. } => c,
+        }
+    }
+
+    fn accepts(&self, actual: u64) -> bool {
+        let tolerance = match *self {
+            ExpectedCount::Count(_) => DEFAULT_TOLERANCE,
+            ExpectedCount::WithTolerance { tolerance, .. } => tolerance,
+        };
+        let expected = self.count() as f64;
+        (actual as f64 - expected).abs() <= expected * tolerance
+    }
+}
+
+/// The expected number of zones by country code and zone type
+pub type ExpectedCounts = BTreeMap<String, BTreeMap<ZoneType, ExpectedCount>>;
+
+pub fn read_expected_counts(path: &Path) -> Result<ExpectedCounts, Error> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("impossible to read {}", path.display()))?;
+    let counts: ExpectedCounts = serde_yaml::from_str(&contents)
+        .with_context(|| format!("invalid expected counts file {}", path.display()))?;
+    Ok(counts
+        .into_iter()
+        .map(|(country, counts)| (country.to_uppercase(), counts))
+        .collect())
+}
+
+/// Compare the counts computed in the stats with the expected ones
+pub fn check_counts(expected: &ExpectedCounts, stats: &CosmogonyStats) -> Vec<CountDeviation> {
+    info!("checking the expected zones counts");
+    let mut deviations = vec![];
+    for (country_code, counts) in expected {
+        let country_stats = stats.country_stats.get(country_code);
+        for (zone_type, expected_count) in counts {
+            let actual = country_stats
+                .and_then(|s| s.zone_type_counts.get(&format!("{:?}", zone_type)))
+                .copied()
+                .unwrap_or(0);
+            if !expected_count.accepts(actual) {
+                warn!(
+                    "{} {} zones in {}, {} expected",
+                    actual,
+                    zone_type.as_str(),
+                    country_code,
+                    expected_count.count()
+                );
+                deviations.push(CountDeviation {
+                    country_code: country_code.clone(),
+                    zone_type: *zone_type,
+                    expected: expected_count.count(),
+                    actual,
+                });
+            }
+        }
+    }
+    deviations
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::CountryStats;
+
+    #[test]
+    fn unexpected_counts_are_reported() {
+        let expected: ExpectedCounts = serde_yaml::from_str(
+            r#"
+            LU:
+              city: 100 # 102 is in the default tolerance
+              suburb: { count: 60, tolerance: 0.01 }
+              state: 1
+            FR:
+              city: 34900
+            "#,
+        )
+        .unwrap();
+        let mut stats = CosmogonyStats::default();
+        stats.country_stats.insert(
+            "LU".into(),
+            CountryStats {
+                zone_type_counts: [("City".to_string(), 102), ("Suburb".to_string(), 56)]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+        );
+
+        let deviations = check_counts(&expected, &stats);
+        let deviations: Vec<_> = deviations
+            .iter()
+            .map(|d| (d.country_code.as_str(), d.zone_type, d.expected, d.actual))
+            .collect();
+        assert_eq!(
+            deviations,
+            vec![
+                ("FR", ZoneType::City, 34900, 0),
+                ("LU", ZoneType::Suburb, 60, 56),
+                ("LU", ZoneType::State, 1, 0),
+            ]
+        );
+    }
+}
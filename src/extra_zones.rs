@@ -0,0 +1,170 @@
+// Extra zones are user provided zones (eg. from a custom dataset) blended with the osm zones.
+// As both datasets inevitably collide, a policy decides which zone to keep when a custom zone
+// overlaps an osm zone of the same type.
+
+use crate::hierarchy_builder::ZonesTree;
+use crate::options::ExtraZonesPolicy;
+use anyhow::{Context, Error};
+use cosmogony::{read_zones_from_file, CosmogonyStats, Zone, ZoneIndex};
+use geo::prelude::BoundingRect;
+use geos::{Geom, Geometry};
+use std::ops::Range;
+use std::path::Path;
+
+/// minimum part of the smallest zone's area that need to be covered by
+/// the other zone for 2 zones to be considered in conflict
+const OVERLAP_THRESHOLD: f64 = 0.5;
+
+/// Read the extra zones from a cosmogony file and add them at the end of the zones.
+///
+/// The extra zones must already be typed, the zones without a type or a boundary are skipped.
+/// Returns the range of the extra zones in the zone's vector.
+pub fn add_extra_zones(zones: &mut Vec<Zone>, path: &Path) -> Result<Range<usize>, Error> {
+    info!("reading extra zones from {}", path.display());
+    let first_extra_zone = zones.len();
+    for zone in read_zones_from_file(path)
+        .with_context(|| format!("impossible to read extra zones from {}", path.display()))?
+    {
+        let mut zone = zone?;
+        if zone.zone_type.is_none() || zone.boundary.is_none() {
+            warn!(
+                "extra zone {} has no zone_type or no boundary, skipping it",
+                zone.osm_id
+            );
+            continue;
+        }
+        zone.id = ZoneIndex { index: zones.len() };
+        // the hierarchy will be rebuilt
+        zone.parent = None;
+        zone.parents.clear();
+        if zone.bbox.is_none() {
+            zone.bbox = zone.boundary.as_ref().and_then(|b| b.bounding_rect());
+        }
+        zones.push(zone);
+    }
+    info!("{} extra zones added", zones.len() - first_extra_zone);
+    Ok(first_extra_zone..zones.len())
+}
+
+fn overlap_ratio(zone: &Zone, other: &Zone) -> Option<f64> {
+    let g: Geometry = zone.boundary.as_ref()?.try_into().ok()?;
+    let other_g: Geometry = other.boundary.as_ref()?.try_into().ok()?;
+    let smallest_area = g.area().ok()?.min(other_g.area().ok()?);
+    if smallest_area <= 0. {
+        return None;
+    }
+    let intersection_area = g.intersection(&other_g).ok()?.area().ok()?;
+    Some(intersection_area / smallest_area)
+}
+
+/// Find the osm zones of the same type overlapping each extra zone, and apply the policy
+///
+/// The discarded zones are untyped, so they are not used in the hierarchy and
+/// are removed at the end of the process.
+pub fn resolve_extra_zones_conflicts(
+    zones: &mut [Zone],
+    extra_zones: Range<usize>,
+    zones_rtree: &ZonesTree,
+    policy: ExtraZonesPolicy,
+    stats: &mut CosmogonyStats,
+) {
+    let first_extra_zone = extra_zones.start;
+    let conflicts: Vec<(usize, usize)> = extra_zones
+        .filter(|idx| zones[*idx].zone_type.is_some())
+        .flat_map(|idx| {
+            let extra_zone = &zones[idx];
+            zones_rtree
+                .fetch_zone_bbox(extra_zone)
+                .into_iter()
+                .filter(|z_idx| z_idx.index < first_extra_zone)
+                .filter(|z_idx| zones[z_idx.index].zone_type == extra_zone.zone_type)
+                .filter(|z_idx| {
+                    overlap_ratio(extra_zone, &zones[z_idx.index]).unwrap_or(0.)
+                        >= OVERLAP_THRESHOLD
+                })
+                .map(move |z_idx| (idx, z_idx.index))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    for (extra_idx, osm_idx) in conflicts {
+        let extra_osm_id = zones[extra_idx].osm_id.clone();
+        let osm_id = zones[osm_idx].osm_id.clone();
+        info!(
+            "extra zone {} overlaps zone {} ({:?})",
+            extra_osm_id, osm_id, policy
+        );
+        stats
+            .extra_zones_conflicts
+            .entry(extra_osm_id.clone())
+            .or_default()
+            .push(osm_id.clone());
+        match policy {
+            ExtraZonesPolicy::PreferExtra => zones[osm_idx].zone_type = None,
+            ExtraZonesPolicy::PreferOsm => zones[extra_idx].zone_type = None,
+            ExtraZonesPolicy::KeepBoth => {
+                zones[extra_idx].overlaps.push(osm_id);
+                zones[osm_idx].overlaps.push(extra_osm_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::square_zone;
+    use cosmogony::ZoneType;
+
+    fn zone(idx: usize, osm_id: &str, min: f64, max: f64) -> Zone {
+        let mut z = Zone {
+            osm_id: osm_id.into(),
+            zone_type: Some(ZoneType::City),
+            ..square_zone((min, min), (max, max))
+        };
+        z.id.index = idx;
+        z
+    }
+
+    /// 2 osm zones, and an extra zone overlapping a lot the first one and a bit the second one
+    fn resolve(policy: ExtraZonesPolicy) -> (Vec<Zone>, CosmogonyStats) {
+        let mut zones = vec![
+            zone(0, "relation:0", 0., 10.),
+            zone(1, "relation:1", 9., 19.),
+            zone(2, "extra:0", 1., 10.),
+        ];
+        let mut stats = CosmogonyStats::default();
+        let ztree: ZonesTree = zones.iter().collect();
+        resolve_extra_zones_conflicts(&mut zones, 2..3, &ztree, policy, &mut stats);
+        (zones, stats)
+    }
+
+    #[test]
+    fn extra_zones_prefer_extra() {
+        let (zones, stats) = resolve(ExtraZonesPolicy::PreferExtra);
+        assert_eq!(zones[0].zone_type, None);
+        assert_eq!(zones[1].zone_type, Some(ZoneType::City));
+        assert_eq!(zones[2].zone_type, Some(ZoneType::City));
+        assert_eq!(
+            stats.extra_zones_conflicts.get("extra:0"),
+            Some(&vec!["relation:0".to_string()])
+        );
+    }
+
+    #[test]
+    fn extra_zones_prefer_osm() {
+        let (zones, _) = resolve(ExtraZonesPolicy::PreferOsm);
+        assert_eq!(zones[0].zone_type, Some(ZoneType::City));
+        assert_eq!(zones[1].zone_type, Some(ZoneType::City));
+        assert_eq!(zones[2].zone_type, None);
+    }
+
+    #[test]
+    fn extra_zones_keep_both() {
+        let (zones, _) = resolve(ExtraZonesPolicy::KeepBoth);
+        assert!(zones.iter().all(|z| z.zone_type.is_some()));
+        assert_eq!(zones[0].overlaps, vec!["extra:0".to_string()]);
+        assert!(zones[1].overlaps.is_empty());
+        assert_eq!(zones[2].overlaps, vec!["relation:0".to_string()]);
+    }
+}
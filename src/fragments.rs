@@ -0,0 +1,233 @@
+// Output of a cosmogony as one fragment by country, to ease its re-ingestion by the
+// downstream indexes when only a few countries changed since the previous build.
+//
+// There is no real incremental build (the whole osm file is always processed), so the changed
+// countries are found by comparing a digest of their zones with the manifest of the previous
+// build written in the same directory.
+//
+// The fragments reference the zones by their ids (the `id` and the `parent` of the zones), which
+// are the positions of the zones in the whole cosmogony and are not stable between 2 builds: a
+// zone added in a country shifts the ids of the zones of the following countries. The digest is
+// the hash of the fragment as written, ids included, so the fragments whose ids shifted are
+// rewritten too, and the ids of all the fragments of the directory stay consistent.
+
+use anyhow::{anyhow, Context, Error, Result};
+use cosmogony::{Cosmogony, Zone};
+use serde_derive::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// How the cosmogony is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmitMode {
+    /// all the zones in one output file
+    #[default]
+    Full,
+    /// one fragment by country, only for the countries that changed since the previous build
+    ChangedOnly,
+}
+
+impl FromStr for EmitMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(EmitMode::Full),
+            "changed-only" => Ok(EmitMode::ChangedOnly),
+            _ => Err(anyhow!(
+                "invalid emit mode '{}', accepted values are: full, changed-only",
+                s
+            )),
+        }
+    }
+}
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// country code used for the zones without country
+const UNKNOWN_COUNTRY: &str = "unknown";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CountryFragment {
+    /// digest of the fragment of the country (the sha256 of its zones, ids included)
+    pub digest: String,
+    pub nb_zones: usize,
+}
+
+/// The manifest listing the fragments of the output directory
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub countries: BTreeMap<String, CountryFragment>,
+    /// the countries whose fragment has been (re)written by this build
+    #[serde(default)]
+    pub changed: Vec<String>,
+    /// the countries whose fragment is the same as in the previous build (and was not rewritten)
+    #[serde(default)]
+    pub unchanged: Vec<String>,
+    /// the countries of the previous build without any zone in this build
+    #[serde(default)]
+    pub removed: Vec<String>,
+}
+
+fn fragment_path(dir: &Path, country: &str) -> std::path::PathBuf {
+    dir.join(format!("{}.jsonl", country))
+}
+
+fn digest(zones: &[&Zone]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for z in zones {
+        serde_json::to_writer(&mut hasher, z)?;
+        hasher.update(b"\n");
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn read_manifest(dir: &Path) -> Result<Option<Manifest>> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = File::open(&path).with_context(|| format!("unable to open {}", path.display()))?;
+    let manifest = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("invalid manifest {}", path.display()))?;
+    Ok(Some(manifest))
+}
+
+fn write_fragment(path: &Path, zones: &[&Zone]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for z in zones {
+        serde_json::to_writer(&mut writer, z)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write in `dir` a `<country_code>.jsonl` fragment for each country that changed since
+/// the previous build and a `manifest.json` listing all the countries
+///
+/// The fragments of the unchanged countries are kept as is, and those of the removed
+/// countries are deleted.
+pub fn write_changed_countries(cosmogony: &Cosmogony, dir: &Path) -> Result<Manifest> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("unable to create directory {}", dir.display()))?;
+    let previous = read_manifest(dir)?.unwrap_or_default();
+
+    let mut zones_by_country = BTreeMap::<String, Vec<&Zone>>::new();
+    for z in &cosmogony.zones {
+        let country = z
+            .country_code
+            .clone()
+            .unwrap_or_else(|| UNKNOWN_COUNTRY.to_string());
+        zones_by_country.entry(country).or_default().push(z);
+    }
+
+    let mut manifest = Manifest::default();
+    for (country, zones) in &zones_by_country {
+        let fragment = CountryFragment {
+            digest: digest(zones)?,
+            nb_zones: zones.len(),
+        };
+        let path = fragment_path(dir, country);
+        if previous.countries.get(country) == Some(&fragment) && path.exists() {
+            manifest.unchanged.push(country.clone());
+        } else {
+            write_fragment(&path, zones)
+                .with_context(|| format!("unable to write {}", path.display()))?;
+            manifest.changed.push(country.clone());
+        }
+        manifest.countries.insert(country.clone(), fragment);
+    }
+
+    for country in previous.countries.keys() {
+        if !manifest.countries.contains_key(country) {
+            let path = fragment_path(dir, country);
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("unable to remove {}", path.display()))?;
+            }
+            manifest.removed.push(country.clone());
+        }
+    }
+
+    let manifest_file = File::create(dir.join(MANIFEST_FILE))?;
+    serde_json::to_writer_pretty(BufWriter::new(manifest_file), &manifest)?;
+    info!(
+        "{} countries written, {} unchanged, {} removed",
+        manifest.changed.len(),
+        manifest.unchanged.len(),
+        manifest.removed.len()
+    );
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::ZoneIndex;
+
+    fn zone(id: usize, osm_id: &str, country: &str, parent: Option<usize>) -> Zone {
+        Zone {
+            id: ZoneIndex { index: id },
+            osm_id: osm_id.into(),
+            name: osm_id.into(),
+            country_code: Some(country.into()),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_changed_countries_are_written() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let mut cosmogony = Cosmogony {
+            zones: vec![
+                zone(0, "relation:1", "FR", None),
+                zone(1, "relation:2", "FR", Some(0)),
+                zone(2, "relation:3", "LU", None),
+                zone(3, "relation:4", "BE", None),
+            ],
+            ..Default::default()
+        };
+        let manifest = write_changed_countries(&cosmogony, dir).unwrap();
+        assert_eq!(manifest.changed, vec!["BE", "FR", "LU"]);
+        assert!(manifest.unchanged.is_empty());
+
+        // a renamed zone in LU only changes LU
+        cosmogony.zones[2].name = "Luxembourg".into();
+        let manifest = write_changed_countries(&cosmogony, dir).unwrap();
+        assert_eq!(manifest.changed, vec!["LU"]);
+        assert_eq!(manifest.unchanged, vec!["BE", "FR"]);
+
+        // a new zone in FR shifts the ids of the LU zone, its fragment is rewritten so its id
+        // does not collide with the new FR zone
+        cosmogony.zones = vec![
+            zone(0, "relation:1", "FR", None),
+            zone(1, "relation:2", "FR", Some(0)),
+            zone(2, "relation:5", "FR", Some(0)),
+            zone(3, "relation:3", "LU", None),
+        ];
+        let manifest = write_changed_countries(&cosmogony, dir).unwrap();
+        assert_eq!(manifest.changed, vec!["FR", "LU"]);
+        assert!(manifest.unchanged.is_empty());
+        assert_eq!(manifest.removed, vec!["BE"]);
+        assert_eq!(manifest.countries["FR"].nb_zones, 3);
+        assert!(!dir.join("BE.jsonl").exists());
+        let lu: Zone =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("LU.jsonl")).unwrap()).unwrap();
+        assert_eq!(lu.id, ZoneIndex { index: 3 });
+
+        let written = read_manifest(dir).unwrap().unwrap();
+        assert_eq!(written.countries, manifest.countries);
+    }
+}
@@ -0,0 +1,152 @@
+// Many osm boundaries are not valid multipolygons (self intersecting rings, degenerated rings, ...)
+// and GEOS cannot compute reliable inclusions with them.
+// We try to repair them before computing the inclusions.
+
+use crate::additional_zones::convert_to_geo;
+use cosmogony::Zone;
+use geo::prelude::BoundingRect;
+use geo_types::{MultiPolygon, Polygon};
+use geos::{Geom, Geometry};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RepairStatus {
+    /// the boundary was already valid
+    Valid,
+    /// the boundary has been repaired
+    Repaired,
+    /// the boundary could not be repaired and has been removed
+    Rejected,
+}
+
+/// remove the rings that cannot make a polygon (less than 3 distincts points)
+fn remove_degenerated_rings(boundary: &MultiPolygon<f64>) -> MultiPolygon<f64> {
+    let is_valid_ring = |ring: &geo_types::LineString<f64>| ring.0.len() >= 4;
+    boundary
+        .iter()
+        .filter(|p| is_valid_ring(p.exterior()))
+        .map(|p| {
+            Polygon::new(
+                p.exterior().clone(),
+                p.interiors()
+                    .iter()
+                    .filter(|r| is_valid_ring(r))
+                    .cloned()
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+/// Check the validity of the zone's boundary, and try to fix it if needed.
+///
+/// The invalid geometries are fixed with a `buffer(0)`, that removes the self intersections.
+/// If the boundary cannot be fixed, it is removed.
+pub fn repair_boundary(zone: &mut Zone) -> RepairStatus {
+    let boundary = match zone.boundary {
+        Some(ref b) => b,
+        None => return RepairStatus::Valid,
+    };
+    let cleaned_boundary = remove_degenerated_rings(boundary);
+    let has_degenerated_rings = &cleaned_boundary != boundary;
+
+    let repaired = Geometry::try_from(&cleaned_boundary)
+        .map_err(|e| {
+            warn!(
+                "impossible to convert to geos for zone {}: {}",
+                zone.osm_id, e
+            )
+        })
+        .ok()
+        .and_then(|g| {
+            if g.is_valid() && !has_degenerated_rings {
+                return Some(None);
+            }
+            debug!(
+                "invalid geometry for zone {}: {}",
+                zone.osm_id,
+                g.is_valid_reason().unwrap_or_default()
+            );
+            g.buffer(0., 8)
+                .map_err(|e| warn!("impossible to repair zone {}: {}", zone.osm_id, e))
+                .ok()
+                .and_then(|g| {
+                    convert_to_geo(g)
+                        .map_err(|e| warn!("impossible to repair zone {}: {}", zone.osm_id, e))
+                        .ok()
+                        .flatten()
+                })
+                .map(Some)
+        });
+
+    match repaired {
+        Some(None) => RepairStatus::Valid,
+        Some(Some(b)) => {
+            info!("the boundary of zone {} has been repaired", zone.osm_id);
+            zone.bbox = b.bounding_rect();
+            zone.boundary = Some(b);
+            RepairStatus::Repaired
+        }
+        None => {
+            info!(
+                "the boundary of zone {} is invalid and cannot be repaired",
+                zone.osm_id
+            );
+            zone.boundary = None;
+            zone.bbox = None;
+            RepairStatus::Rejected
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo_types::LineString;
+
+    fn zone_with_rings(rings: Vec<Vec<(f64, f64)>>) -> Zone {
+        Zone {
+            boundary: Some(MultiPolygon(
+                rings
+                    .into_iter()
+                    .map(|r| Polygon::new(LineString::from(r), vec![]))
+                    .collect(),
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn valid_boundary_is_not_changed() {
+        let mut zone = zone_with_rings(vec![vec![(0., 0.), (0., 1.), (1., 1.), (1., 0.)]]);
+        let boundary = zone.boundary.clone();
+        assert_eq!(repair_boundary(&mut zone), RepairStatus::Valid);
+        assert_eq!(zone.boundary, boundary);
+    }
+
+    #[test]
+    fn self_intersecting_boundary_is_repaired() {
+        // a bow-tie polygon
+        let mut zone = zone_with_rings(vec![vec![(0., 0.), (1., 1.), (1., 0.), (0., 1.)]]);
+        assert_eq!(repair_boundary(&mut zone), RepairStatus::Repaired);
+        let boundary = Geometry::try_from(zone.boundary.as_ref().unwrap()).unwrap();
+        assert!(boundary.is_valid());
+        assert!(zone.bbox.is_some());
+    }
+
+    #[test]
+    fn degenerated_ring_is_removed() {
+        let mut zone = zone_with_rings(vec![
+            vec![(0., 0.), (0., 1.), (1., 1.), (1., 0.)],
+            vec![(5., 5.), (6., 6.)],
+        ]);
+        assert_eq!(repair_boundary(&mut zone), RepairStatus::Repaired);
+        assert_eq!(zone.boundary.unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn unrepairable_boundary_is_rejected() {
+        let mut zone = zone_with_rings(vec![vec![(5., 5.), (6., 6.)]]);
+        assert_eq!(repair_boundary(&mut zone), RepairStatus::Rejected);
+        assert!(zone.boundary.is_none());
+    }
+}
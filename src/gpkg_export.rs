@@ -0,0 +1,327 @@
+// Export of the zones as a GeoPackage (http://www.geopackage.org/spec130/), the OGC
+// single file format based on SQLite, that QGIS or ArcGIS can open directly.
+//
+// There is one feature table by zone type, each with its rtree spatial index.
+// The rtree is filled when the zones are written, the triggers keeping it up to date
+// are only created afterwards since they use the `ST_*` functions that only the GIS tools
+// provide.
+
+use crate::wkb::write_multipolygon;
+use anyhow::{Context, Error};
+use cosmogony::{Zone, ZoneOrigin, ZoneType};
+use geo::prelude::BoundingRect;
+use geo_types::{MultiPolygon, Rect};
+use rusqlite::{params, Transaction};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// 'GPKG' in ASCII
+const APPLICATION_ID: i32 = 0x4750_4B47;
+/// version 1.3.0 of the standard
+const USER_VERSION: i32 = 10300;
+const WGS84_SRS_ID: i32 = 4326;
+const WGS84_DEFINITION: &str = r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]]"#;
+
+const METADATA_TABLES: &str = "
+CREATE TABLE gpkg_spatial_ref_sys (
+  srs_name TEXT NOT NULL,
+  srs_id INTEGER NOT NULL PRIMARY KEY,
+  organization TEXT NOT NULL,
+  organization_coordsys_id INTEGER NOT NULL,
+  definition TEXT NOT NULL,
+  description TEXT
+);
+CREATE TABLE gpkg_contents (
+  table_name TEXT NOT NULL PRIMARY KEY,
+  data_type TEXT NOT NULL,
+  identifier TEXT UNIQUE,
+  description TEXT DEFAULT '',
+  last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+  min_x DOUBLE,
+  min_y DOUBLE,
+  max_x DOUBLE,
+  max_y DOUBLE,
+  srs_id INTEGER,
+  CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+CREATE TABLE gpkg_geometry_columns (
+  table_name TEXT NOT NULL,
+  column_name TEXT NOT NULL,
+  geometry_type_name TEXT NOT NULL,
+  srs_id INTEGER NOT NULL,
+  z TINYINT NOT NULL,
+  m TINYINT NOT NULL,
+  CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+  CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+  CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id)
+);
+CREATE TABLE gpkg_extensions (
+  table_name TEXT,
+  column_name TEXT,
+  extension_name TEXT NOT NULL,
+  definition TEXT NOT NULL,
+  scope TEXT NOT NULL,
+  CONSTRAINT ge_tce UNIQUE (table_name, column_name, extension_name)
+);
+";
+
+/// the rtree triggers of the standard, `{t}` being the table name
+const RTREE_TRIGGERS: &str = "
+CREATE TRIGGER rtree_{t}_geom_insert AFTER INSERT ON {t}
+WHEN (new.geom NOT NULL AND NOT ST_IsEmpty(NEW.geom))
+BEGIN
+  INSERT OR REPLACE INTO rtree_{t}_geom VALUES (
+    NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom)
+  );
+END;
+CREATE TRIGGER rtree_{t}_geom_update1 AFTER UPDATE OF geom ON {t}
+WHEN OLD.fid = NEW.fid AND (NEW.geom NOTNULL AND NOT ST_IsEmpty(NEW.geom))
+BEGIN
+  INSERT OR REPLACE INTO rtree_{t}_geom VALUES (
+    NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom)
+  );
+END;
+CREATE TRIGGER rtree_{t}_geom_update2 AFTER UPDATE OF geom ON {t}
+WHEN OLD.fid = NEW.fid AND (NEW.geom IS NULL OR ST_IsEmpty(NEW.geom))
+BEGIN
+  DELETE FROM rtree_{t}_geom WHERE id = OLD.fid;
+END;
+CREATE TRIGGER rtree_{t}_geom_update3 AFTER UPDATE ON {t}
+WHEN OLD.fid != NEW.fid AND (NEW.geom NOTNULL AND NOT ST_IsEmpty(NEW.geom))
+BEGIN
+  DELETE FROM rtree_{t}_geom WHERE id = OLD.fid;
+  INSERT OR REPLACE INTO rtree_{t}_geom VALUES (
+    NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom)
+  );
+END;
+CREATE TRIGGER rtree_{t}_geom_update4 AFTER UPDATE ON {t}
+WHEN OLD.fid != NEW.fid AND (NEW.geom IS NULL OR ST_IsEmpty(NEW.geom))
+BEGIN
+  DELETE FROM rtree_{t}_geom WHERE id IN (OLD.fid, NEW.fid);
+END;
+CREATE TRIGGER rtree_{t}_geom_delete AFTER DELETE ON {t}
+WHEN old.geom NOT NULL
+BEGIN
+  DELETE FROM rtree_{t}_geom WHERE id = OLD.fid;
+END;
+";
+
+/// The layer (table) name of a zone
+fn layer_name(zone: &Zone) -> &'static str {
+    zone.zone_type.as_ref().map_or("untyped", ZoneType::as_str)
+}
+
+/// The GeoPackage binary encoding of a multipolygon: a header with the srs and
+/// the envelope, followed by the little endian WKB geometry
+fn gpkg_geometry(boundary: &MultiPolygon<f64>, bbox: &Rect<f64>) -> Vec<u8> {
+    let mut blob = b"GP".to_vec();
+    blob.push(0); // version 1
+    blob.push(0b0000_0011); // little endian, with a [minx, maxx, miny, maxy] envelope
+    blob.extend(WGS84_SRS_ID.to_le_bytes());
+    for v in [bbox.min().x, bbox.max().x, bbox.min().y, bbox.max().y] {
+        blob.extend(v.to_le_bytes());
+    }
+    write_multipolygon(&mut blob, boundary);
+    blob
+}
+
+fn create_layer(transaction: &Transaction<'_>, name: &str, zones: &[&Zone]) -> Result<(), Error> {
+    transaction.execute_batch(&format!(
+        "CREATE TABLE {name} (
+           fid INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+           geom MULTIPOLYGON,
+           id INTEGER NOT NULL,
+           osm_id TEXT NOT NULL,
+           admin_level INTEGER,
+           zone_type TEXT,
+           name TEXT,
+           label TEXT,
+           zip_codes TEXT,
+           wikidata TEXT,
+           country_code TEXT,
+           parent INTEGER,
+           population INTEGER,
+           is_generated BOOLEAN,
+           origin TEXT,
+           disputed BOOLEAN
+         );
+         CREATE VIRTUAL TABLE rtree_{name}_geom USING rtree(id, minx, maxx, miny, maxy);"
+    ))?;
+
+    let mut extent: Option<Rect<f64>> = None;
+    for zone in zones {
+        let geometry = zone
+            .boundary
+            .as_ref()
+            .and_then(|b| Some((b, b.bounding_rect()?)));
+        transaction.execute(
+            &format!(
+                "INSERT INTO {name} (geom, id, osm_id, admin_level, zone_type, name, label,
+                   zip_codes, wikidata, country_code, parent, population, is_generated, origin,
+                   disputed)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)"
+            ),
+            params![
+                geometry.map(|(b, bbox)| gpkg_geometry(b, &bbox)),
+                zone.id.index as i64,
+                zone.osm_id,
+                zone.admin_level,
+                zone.zone_type.as_ref().map(ZoneType::as_str),
+                zone.name,
+                zone.label,
+                zone.zip_codes.join(";"),
+                zone.wikidata,
+                zone.country_code,
+                zone.parent.map(|p| p.index as i64),
+                zone.population.map(|p| p as i64),
+                zone.is_generated,
+                zone.origin.as_ref().map(ZoneOrigin::as_str),
+                zone.disputed,
+            ],
+        )?;
+        let Some((_, bbox)) = geometry else {
+            continue;
+        };
+        transaction.execute(
+            &format!("INSERT INTO rtree_{name}_geom VALUES (?1, ?2, ?3, ?4, ?5)"),
+            params![
+                transaction.last_insert_rowid(),
+                bbox.min().x,
+                bbox.max().x,
+                bbox.min().y,
+                bbox.max().y
+            ],
+        )?;
+        extent = Some(match extent {
+            Some(e) => Rect::new(
+                (e.min().x.min(bbox.min().x), e.min().y.min(bbox.min().y)),
+                (e.max().x.max(bbox.max().x), e.max().y.max(bbox.max().y)),
+            ),
+            None => bbox,
+        });
+    }
+
+    transaction.execute(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, min_x, min_y, max_x, max_y, srs_id)
+         VALUES (?1, 'features', ?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            name,
+            extent.map(|e| e.min().x),
+            extent.map(|e| e.min().y),
+            extent.map(|e| e.max().x),
+            extent.map(|e| e.max().y),
+            WGS84_SRS_ID
+        ],
+    )?;
+    transaction.execute(
+        "INSERT INTO gpkg_geometry_columns VALUES (?1, 'geom', 'MULTIPOLYGON', ?2, 0, 0)",
+        params![name, WGS84_SRS_ID],
+    )?;
+    transaction.execute(
+        "INSERT INTO gpkg_extensions VALUES (?1, 'geom', 'gpkg_rtree_index',
+           'http://www.geopackage.org/spec120/#extension_rtree', 'write-only')",
+        params![name],
+    )?;
+    transaction.execute_batch(&RTREE_TRIGGERS.replace("{t}", name))?;
+    Ok(())
+}
+
+/// Write the zones in a GeoPackage, with one layer by zone type
+/// (the zones without type are in an `untyped` layer)
+pub fn write_zones_as_gpkg(zones: &[Zone], path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("impossible to remove {}", path.display()))?;
+    }
+    let mut db = rusqlite::Connection::open(path)
+        .with_context(|| format!("impossible to create {}", path.display()))?;
+    db.execute_batch(&format!(
+        "PRAGMA application_id = {APPLICATION_ID};
+         PRAGMA user_version = {USER_VERSION};"
+    ))?;
+
+    let transaction = db.transaction()?;
+    transaction.execute_batch(METADATA_TABLES)?;
+    transaction.execute(
+        "INSERT INTO gpkg_spatial_ref_sys VALUES
+           ('WGS 84 geodetic', ?1, 'EPSG', ?1, ?2, 'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid'),
+           ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined cartesian coordinate reference system'),
+           ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system')",
+        params![WGS84_SRS_ID, WGS84_DEFINITION],
+    )?;
+
+    let mut layers = BTreeMap::<&str, Vec<&Zone>>::new();
+    for zone in zones {
+        layers.entry(layer_name(zone)).or_default().push(zone);
+    }
+    for (name, layer_zones) in &layers {
+        info!("writing {} zones in the {} layer", layer_zones.len(), name);
+        create_layer(&transaction, name, layer_zones)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::ZoneIndex;
+    use geo_types::{LineString, Polygon};
+
+    fn zone(id: usize, zone_type: ZoneType, min: (f64, f64), max: (f64, f64)) -> Zone {
+        let ring = LineString::from(vec![min, (max.0, min.1), max, (min.0, max.1), min]);
+        Zone {
+            id: ZoneIndex { index: id },
+            osm_id: format!("relation:{}", id),
+            name: format!("zone {}", id),
+            zone_type: Some(zone_type),
+            boundary: Some(MultiPolygon(vec![Polygon::new(ring, vec![])])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zones_written_as_geopackage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cosmogony.gpkg");
+        let zones = vec![
+            zone(0, ZoneType::State, (0., 0.), (10., 10.)),
+            zone(1, ZoneType::City, (1., 1.), (2., 2.)),
+            zone(2, ZoneType::City, (5., 4.), (6., 8.)),
+        ];
+        write_zones_as_gpkg(&zones, &path).unwrap();
+
+        let db = rusqlite::Connection::open(&path).unwrap();
+        let application_id: i32 = db
+            .query_row("PRAGMA application_id", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(application_id, APPLICATION_ID);
+
+        let layers: Vec<(String, f64)> = db
+            .prepare("SELECT table_name, max_y FROM gpkg_contents ORDER BY table_name")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(layers, vec![("city".into(), 8.), ("state".into(), 10.)]);
+
+        // the zone 2 is the only city intersecting this bbox
+        let found: String = db
+            .query_row(
+                "SELECT osm_id FROM city JOIN rtree_city_geom r ON city.fid = r.id
+                 WHERE r.maxx >= 4.5 AND r.minx <= 7. AND r.maxy >= 3. AND r.miny <= 5.",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(found, "relation:2");
+
+        let blob: Vec<u8> = db
+            .query_row("SELECT geom FROM state", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(&blob[..4], b"GP\x00\x03");
+        // header (8 bytes) + envelope (32 bytes) + multipolygon with 1 ring of 5 points
+        assert_eq!(blob.len(), 8 + 32 + 9 + 9 + 4 + 5 * 16);
+    }
+}
@@ -0,0 +1,92 @@
+// The h3 cells covering the zones (https://h3geo.org), for the location analytics joining their
+// events to the zones by cell id instead of running a polyfill of the zones' boundaries.
+//
+// A cell covers a zone if its center is in the zone's boundary, so the cells of adjacent zones
+// do not overlap, but some cells overshoot the borders and a zone smaller than a cell can have
+// no cell at all.
+
+use anyhow::{anyhow, Error};
+use cosmogony::Zone;
+use geo_types::MultiPolygon;
+use h3o::geom::{ContainmentMode, TilerBuilder};
+use h3o::Resolution;
+use rayon::prelude::*;
+
+/// The ids of the h3 cells (at a resolution) whose center is in a boundary, sorted
+pub fn h3_cells(
+    boundary: &MultiPolygon<f64>,
+    resolution: Resolution,
+) -> Result<Vec<String>, Error> {
+    let mut tiler = TilerBuilder::new(resolution)
+        .containment_mode(ContainmentMode::ContainsCentroid)
+        .build();
+    for polygon in boundary {
+        tiler.add(polygon.clone())?;
+    }
+    let mut cells: Vec<_> = tiler.into_coverage().collect();
+    cells.sort();
+    Ok(cells.into_iter().map(|c| c.to_string()).collect())
+}
+
+/// Give to each zone the h3 cells covering its boundary, at a resolution (between 0 and 15)
+pub fn assign_h3_cells(zones: &mut [Zone], resolution: u8) -> Result<(), Error> {
+    let resolution = Resolution::try_from(resolution)
+        .map_err(|e| anyhow!("invalid h3 resolution {}: {}", resolution, e))?;
+    info!(
+        "computing the h3 cells of the zones at resolution {}",
+        resolution
+    );
+    zones.par_iter_mut().for_each(|z| {
+        let Some(boundary) = &z.boundary else {
+            return;
+        };
+        z.h3_cells = h3_cells(boundary, resolution).unwrap_or_else(|e| {
+            warn!("impossible to compute the h3 cells of {}: {}", z.osm_id, e);
+            vec![]
+        });
+    });
+    let nb_cells: usize = zones.iter().map(|z| z.h3_cells.len()).sum();
+    let nb_without_cells = zones.iter().filter(|z| z.h3_cells.is_empty()).count();
+    info!(
+        "{} h3 cells computed, {} zones without cells",
+        nb_cells, nb_without_cells
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::square;
+
+    #[test]
+    fn zones_h3_cells() {
+        let mut zones = vec![
+            Zone {
+                osm_id: "relation:1".into(),
+                boundary: Some(square((6., 49.5), (6.1, 49.6))),
+                ..Default::default()
+            },
+            // the cells of adjacent zones do not overlap
+            Zone {
+                osm_id: "relation:2".into(),
+                boundary: Some(square((6.1, 49.5), (6.2, 49.6))),
+                ..Default::default()
+            },
+            Zone::default(),
+        ];
+        assign_h3_cells(&mut zones, 7).unwrap();
+        // a resolution 7 cell is about 5 km², the squares are about 80 km²
+        assert!((10..25).contains(&zones[0].h3_cells.len()));
+        assert!(zones[0].h3_cells.iter().all(|c| c.len() == 15));
+        assert!(zones[0].h3_cells.windows(2).all(|w| w[0] < w[1]));
+        assert!(!zones[1].h3_cells.is_empty());
+        assert!(zones[1]
+            .h3_cells
+            .iter()
+            .all(|c| !zones[0].h3_cells.contains(c)));
+        assert!(zones[2].h3_cells.is_empty());
+
+        assert!(assign_h3_cells(&mut zones, 16).is_err());
+    }
+}
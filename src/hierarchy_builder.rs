@@ -1,14 +1,23 @@
 extern crate geo;
 
+use crate::options::{CancellationToken, Cancelled, DisputedStrategy};
+use anyhow::{Context, Error};
 use cosmogony::mutable_slice::MutableSlice;
-use cosmogony::{Zone, ZoneIndex};
+use cosmogony::{BuildWarning, BuildWarningKind, Zone, ZoneIndex};
 use geo_types::{Point, Rect};
-use log::{info, warn};
+use geos::{Geom, Geometry};
+use log::{debug, info, warn};
+use rayon::prelude::*;
 use rstar::{RTree, RTreeObject, AABB};
+use std::collections::{BTreeMap, HashMap};
 use std::iter::FromIterator;
+use std::path::Path;
 
 use crate::zone_ext::ZoneExt;
 
+/// the number of zones by partition when computing the inclusions
+const ZONES_BY_PARTITION: usize = 256;
+
 #[derive(Debug)]
 struct ZoneIndexAndBbox {
     index: ZoneIndex,
@@ -40,19 +49,41 @@ fn envelope(bbox: &Rect<f64>) -> AABB<Point<f64>> {
 }
 
 impl ZonesTree {
+    /// Index the zones by their position in the slice, instead of their id
+    ///
+    /// This is needed once the zones have been cleaned, as their ids are no longer their position.
+    pub fn by_position(zones: &[Zone]) -> Self {
+        let z = zones
+            .iter()
+            .enumerate()
+            .filter_map(|(index, z)| {
+                z.bbox
+                    .as_ref()
+                    .map(|b| ZoneIndexAndBbox::new(ZoneIndex { index }, b))
+            })
+            .collect();
+        ZonesTree {
+            tree: RTree::bulk_load(z),
+        }
+    }
+
     pub fn fetch_zone_bbox(&self, z: &Zone) -> Vec<ZoneIndex> {
         match z.bbox {
             None => {
                 warn!("No bbox: Cannot fetch zone with osm_id {}", z.osm_id);
                 vec![]
             }
-            Some(ref bbox) => self
-                .tree
-                .locate_in_envelope_intersecting(&envelope(bbox))
-                .map(|z_and_bbox| z_and_bbox.index)
-                .collect(),
+            Some(ref bbox) => self.fetch_bbox(bbox),
         }
     }
+
+    /// the zones whose bbox intersects the given bbox
+    pub fn fetch_bbox(&self, bbox: &Rect<f64>) -> Vec<ZoneIndex> {
+        self.tree
+            .locate_in_envelope_intersecting(&envelope(bbox))
+            .map(|z_and_bbox| z_and_bbox.index)
+            .collect()
+    }
 }
 
 impl<'a> FromIterator<&'a Zone> for ZonesTree {
@@ -73,25 +104,242 @@ impl<'a> FromIterator<&'a Zone> for ZonesTree {
     }
 }
 
-pub fn find_inclusions(zones: &[Zone]) -> (Vec<Vec<ZoneIndex>>, ZonesTree) {
-    use rayon::prelude::*;
+/// The geos geometries of the zones' boundaries, converted once for all the zones of a partition
+struct GeosBoundaries<'a> {
+    zones: &'a [Zone],
+    geometries: HashMap<usize, Option<Geometry<'static>>>,
+    /// the part of a zone's area a larger zone must cover to include it (if None, it must cover
+    /// the whole zone)
+    inclusion_threshold: Option<f64>,
+}
+
+impl<'a> GeosBoundaries<'a> {
+    fn new(zones: &'a [Zone], inclusion_threshold: Option<f64>) -> Self {
+        GeosBoundaries {
+            zones,
+            geometries: HashMap::new(),
+            inclusion_threshold,
+        }
+    }
+
+    fn get(&mut self, index: usize) -> Option<&Geometry<'static>> {
+        let zone = &self.zones[index];
+        self.geometries
+            .entry(index)
+            .or_insert_with(|| {
+                Geometry::try_from(zone.boundary.as_ref()?)
+                    .map_err(|e| {
+                        info!(
+                            "impossible to convert to geos for zone {:?}, error {}",
+                            &zone.osm_id, e
+                        )
+                    })
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// check if the boundary of a zone covers the boundary of another one (false if they cannot
+    /// be converted to geos)
+    fn covers(&mut self, parent: usize, child: usize) -> bool {
+        if self.get(parent).is_none() || self.get(child).is_none() {
+            return false;
+        }
+        let (Some(parent_boundary), Some(child_boundary)) =
+            (&self.geometries[&parent], &self.geometries[&child])
+        else {
+            return false;
+        };
+        // In GEOS, "covers" is less strict than "contains".
+        // eg: a polygon does NOT "contain" its boundary, but "covers" it.
+        parent_boundary
+            .covers(child_boundary)
+            .map_err(|e| {
+                info!(
+                    "impossible to compute geometries coverage for zone {:?}/{:?}: error {}",
+                    &self.zones[parent].osm_id, &self.zones[child].osm_id, e
+                )
+            })
+            .unwrap_or(false)
+    }
+
+    /// check if the boundary of a larger zone covers at least the inclusion threshold of the
+    /// area of another one
+    fn mostly_covers(&mut self, parent: usize, child: usize, threshold: f64) -> bool {
+        if self.get(parent).is_none() || self.get(child).is_none() {
+            return false;
+        }
+        let (Some(parent_boundary), Some(child_boundary)) = (
+            self.geometries.get(&parent).and_then(Option::as_ref),
+            self.geometries.get(&child).and_then(Option::as_ref),
+        ) else {
+            return false;
+        };
+        let overlap = || -> Result<Option<f64>, geos::Error> {
+            let (parent_area, child_area) = (parent_boundary.area()?, child_boundary.area()?);
+            // the larger zone is the parent, 2 zones cannot include each other
+            if child_area <= 0. || parent_area <= child_area {
+                return Ok(None);
+            }
+            let covered = parent_boundary.intersection(child_boundary)?.area()?;
+            Ok(Some(covered / child_area))
+        };
+        match overlap() {
+            Ok(ratio) => ratio.is_some_and(|r| r > 0. && r >= threshold),
+            Err(e) => {
+                info!(
+                    "impossible to compute the overlap of the zones {:?}/{:?}: error {}",
+                    &self.zones[parent].osm_id, &self.zones[child].osm_id, e
+                );
+                false
+            }
+        }
+    }
+
+    /// check if a zone is included in another one: covered by it, or, with an inclusion
+    /// threshold, mostly covered by a larger one
+    fn includes(&mut self, parent: usize, child: usize) -> bool {
+        if self.covers(parent, child) {
+            return true;
+        }
+        match self.inclusion_threshold {
+            Some(threshold) => self.mostly_covers(parent, child, threshold),
+            None => false,
+        }
+    }
+}
+
+/// Group the zones (by position) in partitions of close zones, sharing most of their candidate
+/// parents: the zones are split in vertical slabs by the center of their bbox, and each slab in
+/// cells (like the sort-tile-recursive packing of the rtrees)
+fn spatial_partitions(zones: &[Zone], nb_partitions: usize) -> Vec<Vec<usize>> {
+    let center = |i: &usize| zones[*i].bbox.map_or((0., 0.), |b| b.center().x_y());
+    let nb_slabs = (nb_partitions as f64).sqrt().ceil().max(1.) as usize;
+    let mut positions: Vec<usize> = (0..zones.len()).collect();
+    positions.sort_by(|a, b| center(a).0.total_cmp(&center(b).0));
+    positions
+        .chunks(zones.len().div_ceil(nb_slabs).max(1))
+        .flat_map(|slab| {
+            let mut slab = slab.to_vec();
+            slab.sort_by(|a, b| center(a).1.total_cmp(&center(b).1));
+            slab.chunks(slab.len().div_ceil(nb_slabs).max(1))
+                .map(|cell| cell.to_vec())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// the zones including the zone at a position, among the zones whose bbox intersects its bbox
+fn zone_inclusions(
+    ztree: &ZonesTree,
+    zones: &[Zone],
+    pos: usize,
+    boundaries: &mut GeosBoundaries<'_>,
+) -> Vec<ZoneIndex> {
+    let z = &zones[pos];
+    ztree
+        .fetch_zone_bbox(z)
+        .into_iter()
+        .filter(|z_idx| z_idx != &z.id)
+        .filter(|z_idx| {
+            // the zones without boundary are located by their center
+            if z.boundary.is_some() {
+                boundaries.includes(z_idx.index, pos)
+            } else {
+                zones[z_idx.index].contains_center(z)
+            }
+        })
+        .collect()
+}
+
+/// Find the zones including each zone
+///
+/// With an `inclusion_threshold`, a zone is also included in the larger zones covering at least
+/// this ratio of its area.
+///
+/// The zones are split in spatial partitions, whose inclusions are computed in parallel. The
+/// boundaries of the zones of a partition and of their candidate parents are converted to geos
+/// only once for the partition, instead of once for each pair of zones.
+///
+/// The search stops with a `Cancelled` error once the cancellation token is cancelled.
+pub fn find_inclusions(
+    zones: &[Zone],
+    inclusion_threshold: Option<f64>,
+    cancellation: &CancellationToken,
+) -> Result<(Vec<Vec<ZoneIndex>>, ZonesTree), Cancelled> {
+    let nb_partitions = (zones.len() / ZONES_BY_PARTITION).max(rayon::current_num_threads() * 4);
+    find_inclusions_by_partition(zones, nb_partitions, inclusion_threshold, cancellation)
+}
+
+fn find_inclusions_by_partition(
+    zones: &[Zone],
+    nb_partitions: usize,
+    inclusion_threshold: Option<f64>,
+    cancellation: &CancellationToken,
+) -> Result<(Vec<Vec<ZoneIndex>>, ZonesTree), Cancelled> {
     info!("finding all the inclusions");
     let ztree: ZonesTree = zones.iter().collect();
-    let mut result = vec![vec![]; zones.len()];
+    let partitions = spatial_partitions(zones, nb_partitions);
+    debug!("{} spatial partitions of the zones", partitions.len());
 
-    zones
+    let partitions_inclusions: Vec<Vec<(usize, Vec<ZoneIndex>)>> = partitions
         .par_iter()
-        .map(|z| {
-            ztree
-                .fetch_zone_bbox(z)
-                .into_iter()
-                .filter(|z_idx| z_idx != &z.id)
-                .filter(|z_idx| zones[z_idx.index].contains(z))
+        .map(|partition| {
+            let mut boundaries = GeosBoundaries::new(zones, inclusion_threshold);
+            partition
+                .iter()
+                .map(|&pos| {
+                    cancellation.check()?;
+                    let inclusions = zone_inclusions(&ztree, zones, pos, &mut boundaries);
+                    Ok((pos, inclusions))
+                })
                 .collect()
         })
-        .collect_into_vec(&mut result);
+        .collect::<Result<_, _>>()?;
 
-    (result, ztree)
+    let mut result = vec![vec![]; zones.len()];
+    for (pos, inclusions) in partitions_inclusions.into_iter().flatten() {
+        result[pos] = inclusions;
+    }
+    Ok((result, ztree))
+}
+
+/// Restrict the zones that can be the parent of the disputed zones,
+/// depending on the chosen strategy
+///
+/// The zones belonging to a claimant that has not been chosen are removed from the inclusions
+/// of the disputed zone, so they will not be considered by `build_hierarchy`.
+pub fn restrict_disputed_inclusions(
+    zones: &mut [Zone],
+    zones_inclusions: &mut [Vec<ZoneIndex>],
+    strategy: DisputedStrategy,
+) {
+    if strategy == DisputedStrategy::Both {
+        return;
+    }
+    for (i, inclusions) in zones_inclusions.iter_mut().enumerate() {
+        let (mslice, z) = MutableSlice::init(zones, i);
+        if !z.disputed || z.claimed_by.is_empty() {
+            continue;
+        }
+        let chosen = match strategy {
+            DisputedStrategy::FirstClaimant => z.claimed_by.first(),
+            DisputedStrategy::SecondClaimant => z.claimed_by.get(1),
+            DisputedStrategy::Both | DisputedStrategy::Neutral => None,
+        }
+        .cloned();
+
+        inclusions.retain(|c_idx| {
+            let parent_country = &mslice.get(c_idx).country_code;
+            match parent_country {
+                Some(c) if z.claimed_by.contains(c) => Some(c) == chosen.as_ref(),
+                _ => true,
+            }
+        });
+        if chosen.is_some() {
+            z.country_code = chosen;
+        }
+    }
 }
 
 /// Build the cosmogony hierarchy for all the zones
@@ -102,40 +350,238 @@ pub fn find_inclusions(zones: &[Zone]) -> (Vec<Vec<ZoneIndex>>, ZonesTree) {
 /// Some additional checks are done:
 /// * a zone can be attached only to an administrative zone
 /// * a zone must be attached to zone with a 'greater' zone_type
-///     a City cannot be attached to a CityDistrict or a Suburb, it should be attached to a
-///     StateDistrict, a State, a CountryRegion or a Country
-pub fn build_hierarchy(zones: &mut [Zone], zones_inclusions: Vec<Vec<ZoneIndex>>) {
+///     a City cannot be attached to a Borough, a CityDistrict or a Suburb, it should be attached
+///     to a StateDistrict, a State, a CountryRegion or a Country
+///
+/// Returns a warning for each zone with several possible parents of the same type, or a
+/// `Cancelled` error once the cancellation token is cancelled.
+pub fn build_hierarchy(
+    zones: &mut [Zone],
+    zones_inclusions: Vec<Vec<ZoneIndex>>,
+    cancellation: &CancellationToken,
+) -> Result<Vec<BuildWarning>, Cancelled> {
+    attach_zones(zones, zones_inclusions, false, cancellation)
+}
+
+/// Build the cosmogony hierarchy like `build_hierarchy`, also recording in the zones' `parents`
+/// all the zones that could be their parent (the zones of the same type as the chosen parent
+/// including them, eg. the 2 counties of a city spanning both), the chosen parent first
+pub fn build_multi_parent_hierarchy(
+    zones: &mut [Zone],
+    zones_inclusions: Vec<Vec<ZoneIndex>>,
+    cancellation: &CancellationToken,
+) -> Result<Vec<BuildWarning>, Cancelled> {
+    attach_zones(zones, zones_inclusions, true, cancellation)
+}
+
+fn attach_zones(
+    zones: &mut [Zone],
+    zones_inclusions: Vec<Vec<ZoneIndex>>,
+    all_parents: bool,
+    cancellation: &CancellationToken,
+) -> Result<Vec<BuildWarning>, Cancelled> {
     info!("building the zones's hierarchy");
     assert_eq!(zones.len(), zones_inclusions.len());
+    zones.par_iter_mut().try_for_each(|z| {
+        cancellation.check()?;
+        z.compute_measures();
+        Ok(())
+    })?;
 
+    let mut warnings = vec![];
     zones_inclusions
         .iter()
         .enumerate()
-        .for_each(|(i, inclusions)| {
+        .try_for_each(|(i, inclusions)| {
+            cancellation.check()?;
             let (mslice, z) = MutableSlice::init(zones, i);
 
-            let parent = inclusions
+            let mut candidates: Vec<&Zone> = inclusions
                 .iter()
-                .filter_map(|c_idx| {
-                    let c = mslice.get(c_idx);
-                    if z.can_be_child_of(c) {
-                        Some(c)
-                    } else {
-                        None
-                    }
-                })
-                .min_by_key(|z| z.zone_type);
+                .map(|c_idx| mslice.get(c_idx))
+                .filter(|c| z.can_be_child_of(c))
+                .collect();
+            // for several parents of the same type, the smallest one is chosen
+            candidates.sort_by(|a, b| {
+                let area = |z: &Zone| z.area.unwrap_or(f64::INFINITY);
+                a.zone_type
+                    .cmp(&b.zone_type)
+                    .then(area(a).total_cmp(&area(b)))
+                    .then_with(|| a.osm_id.cmp(&b.osm_id))
+            });
+            let parent = candidates.first().map(|p| p.id);
+            let parent_type = candidates.first().and_then(|p| p.zone_type);
+            let same_type: Vec<&Zone> = candidates
+                .iter()
+                .take_while(|c| c.zone_type == parent_type)
+                .copied()
+                .collect();
+            if same_type.len() > 1 {
+                let others: Vec<&str> = same_type[1..].iter().map(|c| c.osm_id.as_str()).collect();
+                warnings.push(BuildWarning {
+                    kind: BuildWarningKind::AmbiguousParent,
+                    osm_id: z.osm_id.clone(),
+                    message: format!(
+                        "{} chosen as parent, {} could also be",
+                        same_type[0].osm_id,
+                        others.join(", ")
+                    ),
+                });
+            }
+            if all_parents {
+                z.parents = same_type.iter().map(|c| c.id).collect();
+            }
 
-            z.set_parent(parent.map(|z| z.id));
-        })
+            z.set_parent(parent);
+            Ok(())
+        })?;
+    Ok(warnings)
+}
+
+/// Record in the zones' `included_in` all the zones containing them
+///
+/// The zones are sorted by area (the smallest first), so this needs the measures computed by
+/// `build_hierarchy`.
+pub fn record_inclusions(zones: &mut [Zone], zones_inclusions: &[Vec<ZoneIndex>]) {
+    info!("recording the zones' inclusions");
+    for (i, inclusions) in zones_inclusions.iter().enumerate() {
+        let (mslice, z) = MutableSlice::init(zones, i);
+        let mut containers: Vec<&Zone> = inclusions.iter().map(|c| mslice.get(c)).collect();
+        containers.sort_by(|a, b| {
+            let area = |z: &Zone| z.area.unwrap_or(f64::INFINITY);
+            area(a)
+                .total_cmp(&area(b))
+                .then_with(|| a.osm_id.cmp(&b.osm_id))
+        });
+        z.included_in = containers.iter().map(|c| c.id).collect();
+    }
+}
+
+/// Read a yaml file mapping zones osm ids to the osm ids of their parent, eg.
+/// `relation:123: relation:456`. A null parent removes the parent of the zone.
+pub fn read_parent_overrides(path: &Path) -> Result<BTreeMap<String, Option<String>>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("impossible to read {}", path.display()))?;
+    serde_yaml::from_str(&contents)
+        .with_context(|| format!("invalid parent overrides file {}", path.display()))
+}
+
+/// Replace the parents computed by `build_hierarchy` with the overridden ones
+///
+/// The overrides not matching any zone, or that would create a cycle in the hierarchy,
+/// are ignored with a warning. Returns the number of applied overrides.
+pub fn apply_parent_overrides(
+    zones: &mut [Zone],
+    overrides: &BTreeMap<String, Option<String>>,
+) -> usize {
+    info!("applying {} parent overrides", overrides.len());
+    let zones_by_osm_id: BTreeMap<&str, ZoneIndex> =
+        zones.iter().map(|z| (z.osm_id.as_str(), z.id)).collect();
+    let mut new_parents = vec![];
+    for (osm_id, parent_osm_id) in overrides {
+        let Some(&zone) = zones_by_osm_id.get(osm_id.as_str()) else {
+            warn!("parent override: no zone {}, override ignored", osm_id);
+            continue;
+        };
+        let parent = match parent_osm_id {
+            None => None,
+            Some(parent_osm_id) => match zones_by_osm_id.get(parent_osm_id.as_str()) {
+                Some(&parent) => Some(parent),
+                None => {
+                    warn!(
+                        "parent override: no parent zone {} for {}, override ignored",
+                        parent_osm_id, osm_id
+                    );
+                    continue;
+                }
+            },
+        };
+        new_parents.push((zone, parent));
+    }
+
+    let mut nb_applied = 0;
+    for (zone, parent) in new_parents {
+        // the zone must not be an ancestor of its new parent
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == zone {
+                break;
+            }
+            ancestor = zones[a.index].parent;
+        }
+        if ancestor.is_some() {
+            warn!(
+                "parent override: {} cannot be the parent of {}, it would create a cycle",
+                zones[parent.unwrap().index].osm_id,
+                zones[zone.index].osm_id
+            );
+            continue;
+        }
+        let z = &mut zones[zone.index];
+        // the overridden parent becomes the first of the candidate parents, if recorded
+        match parent {
+            None => z.parents.clear(),
+            Some(p) if !z.parents.is_empty() => {
+                z.parents.retain(|c| *c != p);
+                z.parents.insert(0, p);
+            }
+            Some(_) => {}
+        }
+        z.set_parent(parent);
+        nb_applied += 1;
+    }
+    nb_applied
+}
+
+/// Give to the zones without population the sum of their children's population
+///
+/// The children are processed before their parents, so a population can be aggregated
+/// over several levels (eg. from the cities to a state without population).
+pub fn propagate_population(zones: &mut [Zone]) {
+    info!("propagating the zones's population");
+    let depth = |idx: usize| {
+        let mut depth = 0;
+        let mut parent = zones[idx].parent;
+        while let Some(p) = parent {
+            parent = zones[p.index].parent;
+            depth += 1;
+        }
+        depth
+    };
+    let mut zones_by_depth: Vec<(usize, usize)> =
+        (0..zones.len()).map(|idx| (depth(idx), idx)).collect();
+    zones_by_depth.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut children_population = vec![0u64; zones.len()];
+    for (_, idx) in zones_by_depth {
+        let zone = &mut zones[idx];
+        if zone.zone_type.is_none() {
+            continue;
+        }
+        if zone.population.is_none() && children_population[idx] > 0 {
+            zone.population = Some(children_population[idx]);
+        }
+        if let (Some(parent), Some(population)) = (zone.parent, zone.population) {
+            children_population[parent.index] += population;
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::hierarchy_builder::{build_hierarchy, find_inclusions};
-    use cosmogony::{Zone, ZoneType};
+    use crate::hierarchy_builder::{
+        apply_parent_overrides, build_hierarchy, build_multi_parent_hierarchy, find_inclusions,
+        find_inclusions_by_partition, propagate_population, record_inclusions,
+        restrict_disputed_inclusions,
+    };
+    use crate::options::{CancellationToken, Cancelled, DisputedStrategy};
+    use crate::test_utils::square_ring;
+    use crate::zone_ext::ZoneExt;
+    use cosmogony::{BuildWarningKind, Zone, ZoneType};
     use geo::bounding_rect::BoundingRect;
     use geo_types::{Coordinate, LineString, MultiPolygon, Polygon};
+    use geos::Geom;
+    use std::collections::BTreeMap;
 
     fn zone_factory(idx: usize, ls: LineString<f64>, zone_type: Option<ZoneType>) -> Zone {
         let p = Polygon::new(ls, vec![]);
@@ -194,6 +640,72 @@ mod test {
         vec![z0, z1, z2, z3]
     }
 
+    #[test]
+    fn inclusions_do_not_depend_on_the_partitions() {
+        let mut zones = create_zones();
+        // a grid of small squares over z0, and places without boundary
+        for i in 0..50 {
+            let (x, y) = ((i % 10) as f64 + 0.2, (i / 10) as f64 * 2. + 0.2);
+            let square = LineString::from(vec![
+                (x, y),
+                (x, y + 0.5),
+                (x + 0.5, y + 0.5),
+                (x + 0.5, y),
+                (x, y),
+            ]);
+            zones.push(zone_factory(zones.len(), square, Some(ZoneType::Suburb)));
+            let mut place = Zone::default();
+            place.id.index = zones.len();
+            place.center = Some((x + 0.1, y + 0.1).into());
+            place.bbox = place.center.map(|c| c.bounding_rect());
+            zones.push(place);
+        }
+        // the inclusions of each zone, by checking all the other zones
+        let expected: Vec<Vec<usize>> = zones
+            .iter()
+            .map(|z| {
+                zones
+                    .iter()
+                    .filter(|p| p.id != z.id)
+                    .filter(|p| {
+                        if let (Some(parent), Some(child)) = (&p.boundary, &z.boundary) {
+                            let parent = geos::Geometry::try_from(parent).unwrap();
+                            let child = geos::Geometry::try_from(child).unwrap();
+                            parent.covers(&child).unwrap()
+                        } else if z.boundary.is_some() {
+                            false
+                        } else {
+                            p.contains_center(z)
+                        }
+                    })
+                    .map(|p| p.id.index)
+                    .collect()
+            })
+            .collect();
+
+        let (reference, _) =
+            find_inclusions_by_partition(&zones, 1, None, &CancellationToken::default()).unwrap();
+        for nb_partitions in [2, 7, 1000] {
+            let (inclusions, _) = find_inclusions_by_partition(
+                &zones,
+                nb_partitions,
+                None,
+                &CancellationToken::default(),
+            )
+            .unwrap();
+            assert_eq!(inclusions, reference);
+        }
+        let mut sorted = reference.clone();
+        sorted.iter_mut().for_each(|i| i.sort());
+        let sorted: Vec<Vec<usize>> = sorted
+            .into_iter()
+            .map(|i| i.into_iter().map(|z| z.index).collect())
+            .collect();
+        assert_eq!(sorted, expected);
+        assert_eq!(expected[2], vec![0, 1]);
+        assert_eq!(expected[5], vec![0, 3, 4]);
+    }
+
     fn assert_parent(zones: &[Zone], idx: usize, expected_parent: Option<usize>) {
         match (expected_parent, zones[idx].parent) {
             (None, None) => (),
@@ -207,8 +719,8 @@ mod test {
     fn hierarchy_test() {
         let mut zones = create_zones();
 
-        let inclusions = find_inclusions(&zones);
-        build_hierarchy(&mut zones, inclusions.0);
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
 
         assert_parent(&zones, 0, None); // z0 has no parent
         assert_parent(&zones, 1, Some(0)); // z1 parent is z0
@@ -216,6 +728,52 @@ mod test {
         assert_parent(&zones, 3, Some(0)); // z3 parent is z0
     }
 
+    #[test]
+    fn hierarchy_test_cancelled() {
+        let mut zones = create_zones();
+        let (inclusions, _) = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        assert!(matches!(
+            find_inclusions(&zones, None, &cancellation),
+            Err(Cancelled)
+        ));
+        assert!(matches!(
+            build_hierarchy(&mut zones, inclusions, &cancellation),
+            Err(Cancelled)
+        ));
+        assert!(zones.iter().all(|z| z.parent.is_none()));
+    }
+
+    #[test]
+    fn hierarchy_test_inclusion_threshold() {
+        let square = |min_x: f64, max_x: f64, min_y: f64, max_y: f64| {
+            square_ring((min_x, min_y), (max_x, max_y))
+        };
+        let mut zones = vec![
+            zone_factory(0, square(0., 5., 0., 10.), Some(ZoneType::State)),
+            zone_factory(1, square(5., 10., 0., 10.), Some(ZoneType::State)),
+            // a city straddling the border, 2/3 in z0 and 1/3 in z1
+            zone_factory(2, square(3., 6., 2., 4.), Some(ZoneType::City)),
+        ];
+
+        let (inclusions, _) = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        assert!(inclusions[2].is_empty());
+
+        let (inclusions, _) =
+            find_inclusions(&zones, Some(0.5), &CancellationToken::default()).unwrap();
+        assert_eq!(inclusions[2], vec![zones[0].id]);
+        // the larger zones are not included in the smaller ones
+        assert!(inclusions[0].is_empty());
+        build_hierarchy(&mut zones, inclusions, &CancellationToken::default()).unwrap();
+        assert_parent(&zones, 2, Some(0));
+
+        let (inclusions, _) =
+            find_inclusions(&zones, Some(0.3), &CancellationToken::default()).unwrap();
+        assert_eq!(inclusions[2].len(), 2);
+    }
+
     #[test]
     fn hierarchy_test_parent_only_admin() {
         let mut zones = create_zones();
@@ -224,8 +782,8 @@ mod test {
         // it should not be a parent anymore
         zones[1].zone_type = Some(ZoneType::NonAdministrative);
 
-        let inclusions = find_inclusions(&zones);
-        build_hierarchy(&mut zones, inclusions.0);
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
 
         assert_parent(&zones, 0, None); // z0 has no parent
         assert_parent(&zones, 1, Some(0)); // z1 parent is z0
@@ -241,8 +799,8 @@ mod test {
         // so it cannot have a state as parent anymore
         zones[2].zone_type = Some(ZoneType::State);
 
-        let inclusions = find_inclusions(&zones);
-        build_hierarchy(&mut zones, inclusions.0);
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
 
         assert_parent(&zones, 0, None); // z0 has no parent
         assert_parent(&zones, 1, Some(0)); // z1 parent is z0
@@ -259,8 +817,8 @@ mod test {
         // so it cannot have a state as parent anymore
         zones[2].zone_type = Some(ZoneType::CountryRegion);
 
-        let inclusions = find_inclusions(&zones);
-        build_hierarchy(&mut zones, inclusions.0);
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
 
         assert_parent(&zones, 0, None); // z0 has no parent
         assert_parent(&zones, 1, Some(0)); // z1 parent is z0
@@ -277,12 +835,200 @@ mod test {
         // now we change the zone type of z1 to None, so it cannot be parent anymore
         zones[1].zone_type = None;
 
-        let inclusions = find_inclusions(&zones);
-        build_hierarchy(&mut zones, inclusions.0);
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
 
         assert_parent(&zones, 0, None); // z0 has no parent
         assert_parent(&zones, 1, Some(0)); // z1 parent is z0
         assert_parent(&zones, 2, Some(0)); // z2 parent is z0 even if it is contained by z1
         assert_parent(&zones, 3, Some(0)); // z3 parent is z0
     }
+
+    /// when several zones of the same type contain a zone, the smallest one is its parent
+    #[test]
+    fn hierarchy_test_smallest_parent() {
+        let mut zones = create_zones();
+        let l4 = LineString(coords(vec![
+            (0.5, 0.5),
+            (0.5, 9.5),
+            (9.5, 9.5),
+            (9.5, 0.5),
+            (0.5, 0.5),
+        ]));
+        zones.push(zone_factory(4, l4, Some(ZoneType::State)));
+
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
+
+        assert_parent(&zones, 2, Some(1)); // z1 is smaller than z4
+        assert_parent(&zones, 1, Some(0)); // z4 is a State too, it cannot be z1's parent
+        assert!(zones[1].area < zones[4].area);
+    }
+
+    #[test]
+    fn hierarchy_test_multi_parents() {
+        let mut zones = create_zones();
+        let l4 = LineString(coords(vec![
+            (0.5, 0.5),
+            (0.5, 9.5),
+            (9.5, 9.5),
+            (9.5, 0.5),
+            (0.5, 0.5),
+        ]));
+        zones.push(zone_factory(4, l4, Some(ZoneType::State)));
+
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        let warnings =
+            build_multi_parent_hierarchy(&mut zones, inclusions.0, &CancellationToken::default())
+                .unwrap();
+
+        // z2 could be attached to both states
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, BuildWarningKind::AmbiguousParent);
+        assert_eq!(warnings[0].osm_id, zones[2].osm_id);
+        let parents =
+            |idx: usize| -> Vec<usize> { zones[idx].parents.iter().map(|p| p.index).collect() };
+        assert_parent(&zones, 2, Some(1));
+        assert_eq!(parents(2), vec![1, 4]); // the 2 states containing z2, the smallest first
+        assert_eq!(parents(1), vec![0]);
+        assert!(parents(0).is_empty());
+    }
+
+    #[test]
+    fn hierarchy_test_inclusions() {
+        let mut zones = create_zones();
+        // a non administrative zone is not a parent, but it is recorded as an including zone
+        zones[1].zone_type = Some(ZoneType::NonAdministrative);
+
+        let (inclusions, _) = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(
+            &mut zones,
+            inclusions.clone(),
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        record_inclusions(&mut zones, &inclusions);
+
+        let included_in =
+            |idx: usize| -> Vec<usize> { zones[idx].included_in.iter().map(|p| p.index).collect() };
+        assert_parent(&zones, 2, Some(0));
+        assert_eq!(included_in(2), vec![1, 0]); // the smallest first
+        assert_eq!(included_in(1), vec![0]);
+        assert!(included_in(0).is_empty());
+    }
+
+    #[test]
+    fn hierarchy_test_parent_overrides() {
+        let mut zones = create_zones();
+        for z in zones.iter_mut() {
+            z.osm_id = format!("relation:{}", z.id.index);
+        }
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
+
+        let overrides: BTreeMap<String, Option<String>> = serde_yaml::from_str(
+            r#"
+            relation:2: relation:0
+            relation:3: null
+            relation:0: relation:2 # cycle
+            relation:42: relation:0 # unknown zone
+            relation:1: relation:42 # unknown parent
+            "#,
+        )
+        .unwrap();
+        assert_eq!(apply_parent_overrides(&mut zones, &overrides), 2);
+
+        assert_parent(&zones, 0, None);
+        assert_parent(&zones, 1, Some(0));
+        assert_parent(&zones, 2, Some(0));
+        assert_parent(&zones, 3, None);
+    }
+
+    /// z2 is claimed by 2 countries: z0 (which contains z1 and z3) and z4 (same shape as z0)
+    #[rustfmt::skip]
+    fn create_disputed_zones() -> Vec<Zone> {
+        let mut zones = create_zones();
+        let l4 = LineString(coords(vec![
+            (0., 0.),
+            (0., 10.),
+            (10., 10.),
+            (10., 0.),
+            (0., 0.),
+        ]));
+        zones.push(zone_factory(4, l4, Some(ZoneType::Country)));
+        for z in zones.iter_mut() {
+            z.country_code = Some(if z.id.index == 4 { "BB" } else { "AA" }.into());
+        }
+        zones[2].disputed = true;
+        zones[2].claimed_by = vec!["AA".into(), "BB".into()];
+        zones
+    }
+
+    fn build_disputed_hierarchy(strategy: DisputedStrategy) -> Vec<Zone> {
+        let mut zones = create_disputed_zones();
+        let (mut inclusions, _) =
+            find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        restrict_disputed_inclusions(&mut zones, &mut inclusions, strategy);
+        build_hierarchy(&mut zones, inclusions, &CancellationToken::default()).unwrap();
+        zones
+    }
+
+    #[test]
+    fn hierarchy_test_disputed_both() {
+        let zones = build_disputed_hierarchy(DisputedStrategy::Both);
+        assert_parent(&zones, 2, Some(1)); // geometrical attachment
+        assert_eq!(zones[2].country_code, Some("AA".into()));
+    }
+
+    #[test]
+    fn hierarchy_test_disputed_first_claimant() {
+        let zones = build_disputed_hierarchy(DisputedStrategy::FirstClaimant);
+        assert_parent(&zones, 2, Some(1));
+        assert_eq!(zones[2].country_code, Some("AA".into()));
+    }
+
+    #[test]
+    fn hierarchy_test_disputed_second_claimant() {
+        let zones = build_disputed_hierarchy(DisputedStrategy::SecondClaimant);
+        assert_parent(&zones, 2, Some(4)); // z1 is part of the other claimant
+        assert_eq!(zones[2].country_code, Some("BB".into()));
+    }
+
+    #[test]
+    fn hierarchy_test_disputed_neutral() {
+        let zones = build_disputed_hierarchy(DisputedStrategy::Neutral);
+        assert_parent(&zones, 2, None);
+        assert!(zones[1].parent.is_some()); // the other zones are not impacted
+    }
+
+    #[test]
+    fn population_propagation() {
+        let mut zones = create_zones();
+        zones[2].population = Some(100);
+        zones[3].population = Some(50);
+
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
+        propagate_population(&mut zones);
+
+        assert_eq!(zones[0].population, Some(150)); // z1 + z3
+        assert_eq!(zones[1].population, Some(100)); // z2
+        assert_eq!(zones[2].population, Some(100));
+        assert_eq!(zones[3].population, Some(50));
+    }
+
+    #[test]
+    fn population_propagation_keeps_tagged_population() {
+        let mut zones = create_zones();
+        zones[0].population = Some(1000);
+        zones[2].population = Some(100);
+
+        let inclusions = find_inclusions(&zones, None, &CancellationToken::default()).unwrap();
+        build_hierarchy(&mut zones, inclusions.0, &CancellationToken::default()).unwrap();
+        propagate_population(&mut zones);
+
+        assert_eq!(zones[0].population, Some(1000));
+        assert_eq!(zones[1].population, Some(100));
+        assert_eq!(zones[3].population, None);
+    }
 }
@@ -0,0 +1,173 @@
+// Spatial queries on the zones of a cosmogony, for example to experiment reverse geocoding
+// without having to build another index from the cosmogony file.
+//
+// The zones can also be searched by name, see `name_search`.
+
+use crate::hierarchy_builder::ZonesTree;
+use crate::name_search::find_zones_by_name;
+use cosmogony::{Cosmogony, Zone, ZoneIndex, ZoneType};
+use geo::prelude::{Contains, Intersects};
+use geo_types::{Coord, Point, Rect};
+use std::collections::BTreeMap;
+
+/// An R-tree index of the zones of a cosmogony
+pub struct CosmogonyIndex<'a> {
+    zones: &'a [Zone],
+    tree: ZonesTree,
+    /// the zones ids are not their position once the zones have been cleaned
+    zones_by_id: BTreeMap<ZoneIndex, &'a Zone>,
+}
+
+impl<'a> CosmogonyIndex<'a> {
+    pub fn new(zones: &'a [Zone]) -> Self {
+        CosmogonyIndex {
+            zones,
+            tree: ZonesTree::by_position(zones),
+            zones_by_id: zones.iter().map(|z| (z.id, z)).collect(),
+        }
+    }
+
+    fn candidates(&self, bbox: &Rect<f64>) -> impl Iterator<Item = &'a Zone> + '_ {
+        self.tree
+            .fetch_bbox(bbox)
+            .into_iter()
+            .map(move |idx| &self.zones[idx.index])
+    }
+
+    /// The zones containing a point, from the smallest zone type to the largest
+    pub fn zones_containing(&self, lon: f64, lat: f64) -> Vec<&'a Zone> {
+        let point = Point::new(lon, lat);
+        let coord = Coord { x: lon, y: lat };
+        let mut zones: Vec<_> = self
+            .candidates(&Rect::new(coord, coord))
+            .filter(|z| z.boundary.as_ref().is_some_and(|b| b.contains(&point)))
+            .collect();
+        zones.sort_by_key(|z| z.zone_type);
+        zones
+    }
+
+    /// The hierarchy of a point: the smallest zone containing it, then its parent, the parent
+    /// of its parent... up to its country
+    pub fn resolve(&self, lon: f64, lat: f64) -> Vec<&'a Zone> {
+        let Some(smallest) = self.zones_containing(lon, lat).first().copied() else {
+            return vec![];
+        };
+        let mut chain = vec![smallest];
+        // the chain is bounded, in case of a cycle in the hierarchy
+        while let Some(parent) = chain
+            .last()
+            .and_then(|z| z.parent)
+            .and_then(|p| self.zones_by_id.get(&p))
+            .filter(|_| chain.len() < self.zones.len())
+        {
+            chain.push(parent);
+        }
+        chain
+    }
+
+    /// The zones whose boundary intersects a bbox
+    pub fn zones_intersecting(&self, bbox: &Rect<f64>) -> Vec<&'a Zone> {
+        let bbox_polygon = bbox.to_polygon();
+        self.candidates(bbox)
+            .filter(|z| {
+                z.boundary
+                    .as_ref()
+                    .is_some_and(|b| bbox_polygon.intersects(b))
+            })
+            .collect()
+    }
+}
+
+impl<'a> From<&'a Cosmogony> for CosmogonyIndex<'a> {
+    fn from(cosmogony: &'a Cosmogony) -> Self {
+        CosmogonyIndex::new(&cosmogony.zones)
+    }
+}
+
+/// The queries on the zones of a cosmogony
+pub trait CosmogonyExt {
+    /// The zones hierarchy of a point, from the smallest zone containing it to its country
+    ///
+    /// The zones are indexed at each call, a [`CosmogonyIndex`] should be built once to
+    /// resolve many points.
+    fn resolve(&self, lon: f64, lat: f64) -> Vec<&Zone>;
+
+    /// The zones whose names (the main name, the alternative and international names) match a
+    /// query, optionally of a given type, the best candidates first
+    ///
+    /// The names are compared without case, diacritics and punctuation.
+    fn find_by_name(&self, query: &str, zone_type: Option<ZoneType>) -> Vec<&Zone>;
+}
+
+impl CosmogonyExt for Cosmogony {
+    fn resolve(&self, lon: f64, lat: f64) -> Vec<&Zone> {
+        CosmogonyIndex::from(self).resolve(lon, lat)
+    }
+
+    fn find_by_name(&self, query: &str, zone_type: Option<ZoneType>) -> Vec<&Zone> {
+        find_zones_by_name(&self.zones, query, zone_type)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::prelude::BoundingRect;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+
+    fn zone(id: usize, zone_type: ZoneType, min: f64, max: f64) -> Zone {
+        let ring = LineString::from(vec![(min, min), (min, max), (max, max), (max, min)]);
+        let boundary = MultiPolygon(vec![Polygon::new(ring, vec![])]);
+        Zone {
+            // the ids do not match the positions, like after the zones cleaning
+            id: ZoneIndex { index: id },
+            zone_type: Some(zone_type),
+            bbox: boundary.bounding_rect(),
+            boundary: Some(boundary),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn query_index() {
+        let zones = vec![
+            zone(10, ZoneType::Country, 0., 10.),
+            zone(3, ZoneType::City, 1., 2.),
+            zone(7, ZoneType::City, 5., 6.),
+        ];
+        let index = CosmogonyIndex::new(&zones);
+
+        let ids = |zones: Vec<&Zone>| zones.iter().map(|z| z.id.index).collect::<Vec<_>>();
+        assert_eq!(ids(index.zones_containing(1.5, 1.5)), vec![3, 10]);
+        assert_eq!(ids(index.zones_containing(3., 3.)), vec![10]);
+        assert!(index.zones_containing(11., 3.).is_empty());
+
+        let bbox = Rect::new(Coord { x: 1.5, y: 1.5 }, Coord { x: 4., y: 4. });
+        let mut found = ids(index.zones_intersecting(&bbox));
+        found.sort();
+        assert_eq!(found, vec![3, 10]);
+    }
+
+    #[test]
+    fn resolve_point() {
+        let mut zones = vec![
+            zone(10, ZoneType::Country, 0., 10.),
+            zone(4, ZoneType::State, 0., 5.),
+            zone(3, ZoneType::City, 1., 2.),
+            zone(7, ZoneType::City, 5.5, 6.),
+        ];
+        zones[1].parent = Some(ZoneIndex { index: 10 });
+        zones[2].parent = Some(ZoneIndex { index: 4 });
+        let cosmogony = Cosmogony {
+            zones,
+            ..Default::default()
+        };
+
+        let ids = |zones: Vec<&Zone>| zones.iter().map(|z| z.id.index).collect::<Vec<_>>();
+        assert_eq!(ids(cosmogony.resolve(1.5, 1.5)), vec![3, 4, 10]);
+        assert_eq!(ids(cosmogony.resolve(3., 3.)), vec![4, 10]);
+        // the chain follows the hierarchy, the city 7 has no parent
+        assert_eq!(ids(cosmogony.resolve(5.8, 5.8)), vec![7]);
+        assert!(cosmogony.resolve(11., 3.).is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+// The default label of a zone is the comma-joined names of the zone and of all its parents
+// (eg. "Paris (75000-75116), Île-de-France, France"), but the addresses are not written the same
+// way in every country.
+//
+// The labels of the zones of some countries can be built from a template, given by country code
+// in a yaml file, eg.
+//
+// ```yaml
+// US: "{city}, {state} {zip}, {country}"
+// CA: "{city} ({state}), {country}"
+// ```
+//
+// The placeholders are the zone types (`{city}`, `{state}`...), replaced by the name of the zone
+// or of its parent of this type, and `{zip}`, replaced by the zip codes of the zone. The zones
+// whose type is not in the template are skipped, except the zone itself, whose name is put
+// before the templated label. The placeholders without zone (eg. `{city}` for a state) are
+// removed with their separators.
+
+use anyhow::{anyhow, Context, Error};
+use cosmogony::ZoneType;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    Zone(ZoneType),
+    Zip,
+}
+
+/// A label template, like `{city}, {state} {zip}, {country}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelTemplate(Vec<TemplatePart>);
+
+/// The label templates by country code
+pub type LabelTemplates = BTreeMap<String, LabelTemplate>;
+
+impl FromStr for LabelTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("unclosed placeholder in the label template '{}'", s))?;
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].to_string()));
+            }
+            let placeholder = &rest[start + 1..start + end];
+            parts.push(match placeholder {
+                "zip" => TemplatePart::Zip,
+                _ => ZoneType::parse(placeholder)
+                    .filter(|t| t.as_str() == placeholder)
+                    .map(TemplatePart::Zone)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "invalid placeholder {{{}}} in the label template '{}', a zone type or zip is expected",
+                            placeholder,
+                            s
+                        )
+                    })?,
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        if !parts.iter().any(|p| matches!(p, TemplatePart::Zone(_))) {
+            return Err(anyhow!("the label template '{}' has no zone type", s));
+        }
+        Ok(LabelTemplate(parts))
+    }
+}
+
+/// Remove the empty parentheses and the separators left by the removed placeholders
+///
+/// The parentheses around the first name are removed too (eg. the state of `{city} ({state})`
+/// for a state).
+fn clean_label(label: &str) -> String {
+    let label = label.replace("()", "");
+    let label = label
+        .split(',')
+        .map(|part| part.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut label = label
+        .trim_matches(|c: char| c.is_whitespace() || c == '-' || c == '/')
+        .to_string();
+    if label.starts_with('(') {
+        if let Some(end) = label.find(')') {
+            label.remove(end);
+            label.remove(0);
+        }
+    }
+    label
+}
+
+impl LabelTemplate {
+    fn has_zone_type(&self, zone_type: ZoneType) -> bool {
+        self.0.contains(&TemplatePart::Zone(zone_type))
+    }
+
+    /// Build the label of a zone from the types and names of the zone and of its parents (from
+    /// the zone up to the root of the hierarchy), and from its formatted zip codes
+    pub fn format(&self, hierarchy: &[(Option<ZoneType>, String)], zip: &str) -> String {
+        let mut names = BTreeMap::<ZoneType, &str>::new();
+        for (zone_type, name) in hierarchy {
+            if let Some(zone_type) = zone_type {
+                names.entry(*zone_type).or_insert(name);
+            }
+        }
+        let label: String = self
+            .0
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.as_str(),
+                TemplatePart::Zone(zone_type) => names.get(zone_type).copied().unwrap_or(""),
+                TemplatePart::Zip => zip,
+            })
+            .collect();
+        let label = clean_label(&label);
+
+        match hierarchy.first() {
+            Some((zone_type, name)) if !zone_type.is_some_and(|t| self.has_zone_type(t)) => {
+                if label.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}, {}", name, label)
+                }
+            }
+            _ => label,
+        }
+    }
+}
+
+pub fn read_label_templates(path: &Path) -> Result<LabelTemplates, Error> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("impossible to read {}", path.display()))?;
+    let templates: BTreeMap<String, String> = serde_yaml::from_str(&contents)
+        .with_context(|| format!("invalid label templates file {}", path.display()))?;
+    templates
+        .into_iter()
+        .map(|(country, template)| {
+            let template = template
+                .parse()
+                .with_context(|| format!("invalid label template for {}", country))?;
+            Ok((country.to_uppercase(), template))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hierarchy(zones: &[(ZoneType, &str)]) -> Vec<(Option<ZoneType>, String)> {
+        zones
+            .iter()
+            .map(|(t, name)| (Some(*t), name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn labels_from_templates() {
+        let us: LabelTemplate = "{city}, {state} {zip}, {country}".parse().unwrap();
+        let springfield = hierarchy(&[
+            (ZoneType::City, "Springfield"),
+            (ZoneType::StateDistrict, "Sangamon County"),
+            (ZoneType::State, "Illinois"),
+            (ZoneType::Country, "United States"),
+        ]);
+        assert_eq!(
+            us.format(&springfield, "62701"),
+            "Springfield, Illinois 62701, United States"
+        );
+        // no zip code, and no city for a state
+        assert_eq!(us.format(&springfield[2..], ""), "Illinois, United States");
+        // the name of a zone whose type is not in the template is kept
+        assert_eq!(
+            us.format(&springfield[1..], ""),
+            "Sangamon County, Illinois, United States"
+        );
+
+        let ca: LabelTemplate = "{city} ({state}), {country}".parse().unwrap();
+        let montreal = hierarchy(&[
+            (ZoneType::City, "Montréal"),
+            (ZoneType::State, "Québec"),
+            (ZoneType::Country, "Canada"),
+        ]);
+        assert_eq!(ca.format(&montreal, ""), "Montréal (Québec), Canada");
+        assert_eq!(ca.format(&montreal[1..], ""), "Québec, Canada");
+        assert_eq!(ca.format(&montreal[2..], ""), "Canada");
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!("{city}, {county}".parse::<LabelTemplate>().is_err());
+        assert!("{town}, {country}".parse::<LabelTemplate>().is_err());
+        assert!("{city, {country}".parse::<LabelTemplate>().is_err());
+        assert!("{zip}".parse::<LabelTemplate>().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// The countries and many coastal zones extend far into the sea, up to their maritime borders.
+// For the reverse geocoding of coastal points, the zones can be clipped to the land, with
+// a land polygons dataset (like https://osmdata.openstreetmap.de/data/land-polygons.html
+// in its split WGS84 version).
+
+use crate::additional_zones::convert_to_geo;
+use crate::named_polygons::NamedPolygons;
+use crate::zone_ext::ZoneExt;
+use anyhow::{anyhow, Context, Error};
+use cosmogony::Zone;
+use geo::prelude::BoundingRect;
+use geo_types::{Geometry as GeoGeometry, MultiPolygon, Polygon};
+use geojson::FeatureReader;
+use geos::{Geom, Geometry};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// The land polygons, in the spatial index of the named polygons (they have no name)
+pub struct LandPolygons(NamedPolygons);
+
+impl LandPolygons {
+    pub fn new(polygons: Vec<Polygon<f64>>) -> Self {
+        LandPolygons(NamedPolygons::new(
+            polygons
+                .into_iter()
+                .map(|p| (String::new(), MultiPolygon(vec![p])))
+                .collect(),
+        ))
+    }
+
+    /// Read the land polygons from a shapefile (`.shp`) or a geojson file
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        info!("reading land polygons from {}", path.display());
+        let polygons = match path.extension().and_then(|e| e.to_str()) {
+            Some("shp") => read_shapefile(path),
+            Some("json") | Some("geojson") => read_geojson(path),
+            _ => Err(anyhow!(
+                "the land polygons must be a '.shp' or a '.geojson' file"
+            )),
+        }
+        .with_context(|| format!("invalid land polygons file {}", path.display()))?;
+        info!("{} land polygons read", polygons.len());
+        Ok(Self::new(polygons))
+    }
+}
+
+fn read_shapefile(path: &Path) -> Result<Vec<Polygon<f64>>, Error> {
+    let shapes = shapefile::read_shapes_as::<_, shapefile::Polygon>(path)?;
+    let mut polygons = vec![];
+    for shape in shapes {
+        let mpoly: MultiPolygon<f64> = shape.try_into()?;
+        polygons.extend(mpoly);
+    }
+    Ok(polygons)
+}
+
+fn read_geojson(path: &Path) -> Result<Vec<Polygon<f64>>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut polygons = vec![];
+    for feature in FeatureReader::from_reader(reader).features() {
+        let geometry = feature?
+            .geometry
+            .and_then(|g| GeoGeometry::<f64>::try_from(&g.value).ok());
+        match geometry {
+            Some(GeoGeometry::Polygon(p)) => polygons.push(p),
+            Some(GeoGeometry::MultiPolygon(mp)) => polygons.extend(mp),
+            _ => warn!("land feature without polygonal geometry, skipping it"),
+        }
+    }
+    Ok(polygons)
+}
+
+/// The land part of the boundary, None if the boundary is entirely on land (or at sea)
+///
+/// The boundary is clipped by each land polygon around it, and only those clipped parts are
+/// merged: the land polygons are not merged together.
+fn land_boundary(zone: &Zone, land: &LandPolygons) -> Result<Option<MultiPolygon<f64>>, Error> {
+    let (Some(boundary), Some(bbox)) = (&zone.boundary, zone.bbox) else {
+        return Ok(None);
+    };
+    let boundary = Geometry::try_from(boundary)?;
+    let mut land_parts = vec![];
+    for land_polygon in land.0.intersecting(&bbox) {
+        let land_polygon = Geometry::try_from(land_polygon)?;
+        if land_polygon.covers(&boundary)? {
+            return Ok(None);
+        }
+        let land_part = boundary.intersection(&land_polygon)?;
+        if !land_part.is_empty()? {
+            land_parts.push(land_part);
+        }
+    }
+    if land_parts.is_empty() {
+        // no land around, the zone is a maritime zone
+        return Ok(None);
+    }
+    let clipped = Geometry::create_geometry_collection(land_parts)?.unary_union()?;
+    // the zone can also be entirely on land, across the limits of several land polygons
+    if clipped.area()? >= boundary.area()? * (1. - 1e-9) {
+        return Ok(None);
+    }
+    let clipped = convert_to_geo(clipped)?;
+    Ok(clipped.filter(|b| !b.0.is_empty()))
+}
+
+/// Clip the zones' boundaries to the land, returns the number of clipped zones
+///
+/// The zones entirely at sea are kept as is.
+pub fn clip_zones_to_land(zones: &mut [Zone], land: &LandPolygons) -> usize {
+    info!("clipping the zones to the land");
+    zones
+        .par_iter_mut()
+        .map(|zone| match land_boundary(zone, land) {
+            Ok(Some(boundary)) => {
+                zone.bbox = boundary.bounding_rect();
+                zone.boundary = Some(boundary);
+                zone.compute_measures();
+                1
+            }
+            Ok(None) => 0,
+            Err(e) => {
+                warn!("impossible to clip zone {} to the land: {}", zone.osm_id, e);
+                0
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{square_ring, square_zone as zone};
+    use geo::prelude::Area;
+
+    fn square(min: (f64, f64), max: (f64, f64)) -> Polygon<f64> {
+        Polygon::new(square_ring(min, max), vec![])
+    }
+
+    #[test]
+    fn zones_clipped_to_land() {
+        // the land is split in 2 polygons, from x=0 to x=10
+        let land = LandPolygons::new(vec![
+            square((0., 0.), (5., 10.)),
+            square((5., 0.), (10., 10.)),
+        ]);
+        let mut zones = vec![
+            zone((2., 2.), (8., 8.)),   // on land
+            zone((8., 2.), (12., 4.)),  // on the coast
+            zone((20., 2.), (22., 4.)), // at sea
+        ];
+        let boundaries: Vec<_> = zones.iter().map(|z| z.boundary.clone()).collect();
+        assert_eq!(clip_zones_to_land(&mut zones, &land), 1);
+
+        assert_eq!(zones[0].boundary, boundaries[0]);
+        assert_eq!(zones[2].boundary, boundaries[2]);
+        let coastal = zones[1].boundary.as_ref().unwrap();
+        approx::assert_relative_eq!(coastal.unsigned_area(), 4., epsilon = 1e-9);
+        let bbox = zones[1].bbox.unwrap();
+        approx::assert_relative_eq!(bbox.max().x, 10., epsilon = 1e-9);
+    }
+
+    #[test]
+    fn zone_clipped_across_land_polygons() {
+        let land = LandPolygons::new(vec![
+            square((0., 0.), (5., 10.)),
+            square((5., 0.), (10., 10.)),
+        ]);
+        let mut zones = vec![zone((3., 2.), (12., 4.))];
+        assert_eq!(clip_zones_to_land(&mut zones, &land), 1);
+
+        // the parts clipped by both land polygons are merged
+        let coastal = zones[0].boundary.as_ref().unwrap();
+        assert_eq!(coastal.0.len(), 1);
+        approx::assert_relative_eq!(coastal.unsigned_area(), 14., epsilon = 1e-9);
+    }
+}
@@ -0,0 +1,169 @@
+// The `name:<lang>` osm tags are supposed to use BCP-47 language tags (like `fr`, `zh-Hant`
+// or `sr-Latn-RS`), but the case is not always consistent and some suffixes are not
+// languages at all (`name:left`, `name:fr-x-old`, `name:zh_pinyin`, ...).
+
+fn is_alpha(s: &str, len: std::ops::RangeInclusive<usize>) -> bool {
+    len.contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn is_region(s: &str) -> bool {
+    is_alpha(s, 2..=2) || (s.len() == 3 && s.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_variant(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_alphanumeric())
+        && ((5..=8).contains(&s.len())
+            || (s.len() == 4 && s.chars().next().is_some_and(|c| c.is_ascii_digit())))
+}
+
+/// Canonicalize a language tag: `language[-Script][-REGION][-variant]*`
+///
+/// The language must be an ISO 639 code (2 or 3 letters), the private use subtags
+/// and the extensions are not accepted.
+/// Returns None if the tag is not a valid language tag.
+pub fn canonical_lang_tag(tag: &str) -> Option<String> {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().filter(|l| is_alpha(l, 2..=3))?;
+    let mut canonical = language.to_ascii_lowercase();
+
+    // the subtags must be in this order: script, region, variants
+    let mut position = 0;
+    for subtag in subtags {
+        let canonical_subtag = if position < 1 && is_alpha(subtag, 4..=4) {
+            position = 1;
+            let (first, others) = subtag.split_at(1);
+            format!(
+                "{}{}",
+                first.to_ascii_uppercase(),
+                others.to_ascii_lowercase()
+            )
+        } else if position < 2 && is_region(subtag) {
+            position = 2;
+            subtag.to_ascii_uppercase()
+        } else if is_variant(subtag) {
+            position = 3;
+            subtag.to_ascii_lowercase()
+        } else {
+            return None;
+        };
+        canonical.push('-');
+        canonical.push_str(&canonical_subtag);
+    }
+    Some(canonical)
+}
+
+#[derive(Debug)]
+enum LangPattern {
+    Exact(String),
+    /// `zh*` matches all the chinese variants (`zh`, `zh-Hant`, `zh-Hans`, ...)
+    Prefix(String),
+}
+
+impl LangPattern {
+    fn parse(pattern: &str) -> Self {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => LangPattern::Prefix(prefix.to_ascii_lowercase()),
+            None => LangPattern::Exact(
+                canonical_lang_tag(pattern).unwrap_or_else(|| pattern.to_string()),
+            ),
+        }
+    }
+
+    fn matches(&self, lang: &str) -> bool {
+        match self {
+            LangPattern::Exact(l) => l == lang,
+            LangPattern::Prefix(p) => lang.to_ascii_lowercase().starts_with(p.as_str()),
+        }
+    }
+}
+
+/// The languages to keep, built from a list of patterns:
+///
+/// * `fr` keeps the french
+/// * `zh*` keeps all the languages starting with `zh`
+/// * `!ja` removes the japanese (if there are only negated patterns, all the other languages are kept)
+/// * `default+` also trims the names of the zones to the kept languages (by default only the
+///   labels are filtered), the default name of the zones being always kept
+#[derive(Debug, Default)]
+pub struct LangFilter {
+    included: Vec<LangPattern>,
+    excluded: Vec<LangPattern>,
+    pub trim_names: bool,
+}
+
+impl LangFilter {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let mut filter = LangFilter::default();
+        for pattern in patterns.iter().map(|p| p.as_ref().trim()) {
+            if pattern == "default+" {
+                filter.trim_names = true;
+            } else if let Some(excluded) = pattern.strip_prefix('!') {
+                filter.excluded.push(LangPattern::parse(excluded));
+            } else if !pattern.is_empty() {
+                filter.included.push(LangPattern::parse(pattern));
+            }
+        }
+        filter
+    }
+
+    pub fn accepts(&self, lang: &str) -> bool {
+        !self.excluded.iter().any(|p| p.matches(lang))
+            && (self.included.is_empty() || self.included.iter().any(|p| p.matches(lang)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{canonical_lang_tag, LangFilter};
+
+    #[test]
+    fn valid_lang_tags() {
+        assert_eq!(canonical_lang_tag("fr").as_deref(), Some("fr"));
+        assert_eq!(canonical_lang_tag("FR").as_deref(), Some("fr"));
+        assert_eq!(canonical_lang_tag("zh-hant").as_deref(), Some("zh-Hant"));
+        assert_eq!(
+            canonical_lang_tag("SR-latn-rs").as_deref(),
+            Some("sr-Latn-RS")
+        );
+        assert_eq!(canonical_lang_tag("es-419").as_deref(), Some("es-419"));
+        assert_eq!(
+            canonical_lang_tag("be-Tarask").as_deref(),
+            Some("be-tarask")
+        );
+        assert_eq!(canonical_lang_tag("de-1901").as_deref(), Some("de-1901"));
+    }
+
+    #[test]
+    fn invalid_lang_tags() {
+        assert_eq!(canonical_lang_tag(""), None);
+        assert_eq!(canonical_lang_tag("left"), None);
+        assert_eq!(canonical_lang_tag("prefix"), None);
+        assert_eq!(canonical_lang_tag("fr-x-old"), None);
+        assert_eq!(canonical_lang_tag("zh_pinyin"), None);
+        assert_eq!(canonical_lang_tag("fr-"), None);
+        // the region cannot be before the script
+        assert_eq!(canonical_lang_tag("zh-TW-Hant"), None);
+    }
+
+    #[test]
+    fn lang_filter() {
+        let filter = LangFilter::new(&["FR", "zh*"]);
+        assert!(filter.accepts("fr"));
+        assert!(filter.accepts("zh"));
+        assert!(filter.accepts("zh-Hant"));
+        assert!(!filter.accepts("de"));
+        assert!(!filter.trim_names);
+
+        let filter = LangFilter::new(&["!ja", "!zh*", "default+"]);
+        assert!(filter.accepts("fr"));
+        assert!(!filter.accepts("ja"));
+        assert!(!filter.accepts("zh-Hans"));
+        assert!(filter.trim_names);
+
+        let filter = LangFilter::new(&["zh*", "!zh-Hant"]);
+        assert!(filter.accepts("zh-Hans"));
+        assert!(!filter.accepts("zh-Hant"));
+
+        assert!(LangFilter::new::<&str>(&[]).accepts("fr"));
+    }
+}
@@ -1,50 +1,209 @@
+//! Build a cosmogony (the typed and structured hierarchy of the administrative zones)
+//! from an osm pbf file.
+//!
+//! The main entry points are:
+//!
+//! * [`build_cosmogony`] to build the whole cosmogony, with [`BuildOptions`] to configure it,
+//!   or [`stream_cosmogony`] to write its zones as a json stream once built
+//! * [`pipeline`] to customize the stages of the build
+//! * [`CosmogonyIndex`] to query the zones of a cosmogony by point or bbox
+//! * [`merger`] to merge several cosmogonies
+//!
+//! The `examples/` directory shows some common workflows (serving a reverse geocoding api,
+//! loading the zones in PostGIS, batch reverse lookups).
+
 #[macro_use]
 extern crate log;
 
 mod additional_zones;
+mod config;
+mod country_cache;
 mod country_finder;
+pub mod country_post_processing;
+mod dedup;
+mod enclaves;
+mod expected_counts;
+mod extra_zones;
+pub mod fragments;
+mod geometry_repair;
+#[cfg(feature = "gpkg")]
+pub mod gpkg_export;
+#[cfg(feature = "h3")]
+mod h3_cells;
 mod hierarchy_builder;
+mod index;
+mod label_templates;
+mod land_clip;
+mod lang_tags;
+mod lifecycle;
+#[cfg(feature = "mvt")]
+pub mod mbtiles_export;
 pub mod merger;
+pub mod metrics_export;
+mod missing_levels;
+mod name_collisions;
+mod name_search;
+mod named_polygons;
+mod neighbors;
+mod options;
+mod osm_change;
+mod osm_metadata;
+mod osm_store;
+#[cfg(feature = "geoparquet")]
+pub mod parquet_export;
+mod partial_assembly;
+mod pbf_reader;
+pub mod pipeline;
+mod precision;
+pub mod replication;
+mod rerun;
+mod residential_landuse;
+pub mod shapefile_export;
+mod sibling_overlaps;
+mod simplification;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_export;
+pub mod table_export;
+#[cfg(test)]
+mod test_utils;
+mod timezones;
+pub mod topojson_export;
+mod transliteration;
+pub mod tree_export;
+pub mod uncovered_areas;
+mod winding_order;
+#[cfg(any(feature = "gpkg", feature = "geoparquet", feature = "sqlite"))]
+mod wkb;
 mod zone_ext;
 pub mod zone_typer;
 
+use crate::country_cache::{CountryCache, CountryDetection};
 use crate::country_finder::CountryFinder;
-use crate::hierarchy_builder::{build_hierarchy, find_inclusions};
-use additional_zones::compute_additional_places;
+use crate::pipeline::{Pipeline, PipelineContext};
 use anyhow::{anyhow, Context, Error};
-use cosmogony::mutable_slice::MutableSlice;
-use cosmogony::{Cosmogony, CosmogonyMetadata, CosmogonyStats, ZoneType};
+use cosmogony::{
+    BuildWarning, BuildWarningKind, Cosmogony, CosmogonyMetadata, CosmogonyStats, ZoneType,
+};
+use geo_types::{MultiPolygon, Rect};
+use geometry_repair::{repair_boundary, RepairStatus};
 use log::{debug, info};
-use osmpbfreader::{OsmId, OsmObj, OsmPbfReader};
-use std::collections::BTreeMap;
+use osmpbfreader::{OsmObj, OsmPbfReader, WayId};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Write};
 use std::path::Path;
+use std::time::Instant;
 
 use cosmogony::{Zone, ZoneIndex};
 
+use crate::label_templates::{LabelTemplate, LabelTemplates};
+use crate::lifecycle::is_historic;
 use crate::zone_ext::ZoneExt;
 
+pub use crate::config::Config;
+pub use crate::index::{CosmogonyExt, CosmogonyIndex};
+pub use crate::lang_tags::canonical_lang_tag;
+pub use crate::name_search::normalize_name;
+pub use crate::options::{
+    check_inclusion_threshold, AdditionalZonesStrategy, AdminLevelRemap, BuildOptions,
+    CancellationToken, Cancelled, DisputedStrategy, ExtraZonesPolicy, MemorySize, OsmFilter,
+    OsmIds, PlaceTypes, SecondaryLabelFormat, SimplifyTolerances, WindingOrder,
+    DEFAULT_SIBLING_OVERLAP_THRESHOLD,
+};
+pub use crate::osm_store::{OsmObjects, SpillingStore};
+pub use crate::rerun::rerun_stages;
+pub use crate::residential_landuse::is_residential_landuse;
+
 const FILE_BUF_SIZE: usize = 1024 * 1024; // 1MB
 
 #[rustfmt::skip]
 pub fn is_admin(obj: &OsmObj) -> bool {
     match *obj {
-        OsmObj::Relation(ref rel) => {
-            rel.tags
-                .get("boundary")
-                .map_or(false, |v| v == "administrative")
-            &&
-            rel.tags.get("admin_level").is_some()
-        }
+        OsmObj::Relation(ref rel) => is_admin_tags(&rel.tags) || is_neighbourhood_tags(&rel.tags),
+        // small admins (like some admin_level 9 or 10) are sometimes mapped as a closed way
+        OsmObj::Way(ref way) => way.is_closed() && is_admin_tags(&way.tags),
         _ => false,
     }
 }
 
+#[rustfmt::skip]
+fn is_admin_tags(tags: &osmpbfreader::Tags) -> bool {
+    (tags
+        .get("boundary")
+        .map_or(false, |v| v == "administrative")
+    &&
+    tags.get("admin_level").is_some())
+    ||
+    is_disputed(tags)
+}
+
+/// check if the object is an administrative boundary without admin_level, only extracted if
+/// enabled in the build options
+pub fn is_admin_without_level(obj: &OsmObj) -> bool {
+    let is_levelless = |tags: &osmpbfreader::Tags| {
+        tags.contains("boundary", "administrative") && tags.get("admin_level").is_none()
+    };
+    match *obj {
+        OsmObj::Relation(ref rel) => is_levelless(&rel.tags),
+        OsmObj::Way(ref way) => way.is_closed() && is_levelless(&way.tags),
+        _ => false,
+    }
+}
+
+/// Some countries map their neighbourhoods as place relations instead of admin boundaries
+fn is_neighbourhood_tags(tags: &osmpbfreader::Tags) -> bool {
+    tags.get("place")
+        .is_some_and(|v| v == "neighbourhood" || v == "quarter")
+}
+
+/// check if the object is a ceremonial or traditional boundary relation (like the UK's
+/// ceremonial counties), they are only extracted if enabled in the build options
+pub fn is_ceremonial(obj: &OsmObj) -> bool {
+    obj.relation().is_some_and(|rel| {
+        rel.tags
+            .get("boundary")
+            .is_some_and(|v| v == "ceremonial" || v == "traditional")
+    })
+}
+
+/// check if the object is a zone to extract with these options
+fn is_extracted_zone(obj: &OsmObj, options: &BuildOptions) -> bool {
+    let is_zone = match options.osm_filter {
+        Some(ref filter) => !obj.is_node() && filter.accepts(obj),
+        None => {
+            is_admin(obj)
+                || (options.ceremonial_boundaries && is_ceremonial(obj))
+                || (options.infer_missing_levels && is_admin_without_level(obj))
+        }
+    };
+    is_zone
+        && (options.keep_historic || !is_historic(obj.tags()))
+        && !options.excluded_osm_ids.contains(&obj.id())
+}
+
+/// check if the object is a place to extract with these options
+pub(crate) fn is_extracted_place(obj: &OsmObj, options: &BuildOptions) -> bool {
+    is_place_of_types(obj, &options.place_types)
+        && (options.osm_filter.as_ref()).is_none_or(|filter| filter.accepts(obj))
+        && (options.keep_historic || !is_historic(obj.tags()))
+        && !options.excluded_osm_ids.contains(&obj.id())
+}
+
+fn is_disputed(tags: &osmpbfreader::Tags) -> bool {
+    tags.contains("boundary", "disputed")
+}
+
 pub fn is_place(obj: &OsmObj) -> bool {
+    is_place_of_types(obj, &PlaceTypes::default())
+}
+
+/// check if the object is a place node whose kind is enabled in `place_types`
+pub fn is_place_of_types(obj: &OsmObj, place_types: &PlaceTypes) -> bool {
     match *obj {
         OsmObj::Node(ref node) => matches!(
-            node.tags.get("place").and_then(|s| ZoneType::parse(s)),
+            node.tags
+                .get("place")
+                .and_then(|s| place_types.zone_type(s)),
             Some(ZoneType::City | ZoneType::Suburb)
         ),
         _ => false,
@@ -52,23 +211,123 @@ pub fn is_place(obj: &OsmObj) -> bool {
 }
 
 pub fn get_zones_and_stats(
-    pbf: &BTreeMap<OsmId, OsmObj>,
+    pbf: &dyn OsmObjects,
+    options: &BuildOptions,
+) -> Result<(Vec<Zone>, CosmogonyStats), Error> {
+    read_zones(pbf, options, &mut vec![])
+}
+
+/// the warnings of a zone read from the osm objects
+fn zone_warnings(obj: &OsmObj, zone: &Zone) -> Vec<BuildWarning> {
+    let Some(relation) = obj.relation() else {
+        return vec![];
+    };
+    let warning = |kind, message: String| BuildWarning {
+        kind,
+        osm_id: zone.osm_id.clone(),
+        message,
+    };
+    let mut warnings = vec![];
+    if zone.clipped {
+        warnings.push(warning(
+            BuildWarningKind::ClippedBoundary,
+            "the boundary is clipped by the extract, it has been closed along its bounds".into(),
+        ));
+    } else if zone.boundary.is_none() && relation.refs.iter().any(|r| r.member.is_way()) {
+        warnings.push(warning(
+            BuildWarningKind::UnclosedRings,
+            "the ways of the boundary do not form closed rings".into(),
+        ));
+    }
+    let centre = (relation.refs.iter())
+        .find(|r| r.role == "admin_centre")
+        .or_else(|| relation.refs.iter().find(|r| r.role == "label"));
+    if let Some(centre) = centre.filter(|_| zone.admin_centre.is_none()) {
+        warnings.push(warning(
+            BuildWarningKind::MissingAdminCentre,
+            format!(
+                "the {} member {} is missing",
+                centre.role,
+                centre.member.inner_id()
+            ),
+        ));
+    }
+    warnings
+}
+
+/// Read the zones like `get_zones_and_stats`, collecting their warnings
+pub(crate) fn read_zones(
+    pbf: &dyn OsmObjects,
+    options: &BuildOptions,
+    warnings: &mut Vec<BuildWarning>,
 ) -> Result<(Vec<Zone>, CosmogonyStats), Error> {
-    let stats = CosmogonyStats::default();
+    let mut stats = CosmogonyStats::default();
     let mut zones = Vec::with_capacity(1000);
 
-    for obj in pbf.values() {
-        if !is_admin(obj) {
+    // the closed ways used by an admin relation are already part of the relation's zone
+    let relations_ways: BTreeSet<WayId> = pbf
+        .objects()
+        .filter(|o| is_extracted_zone(o, options))
+        .flat_map(|o| match o.relation() {
+            Some(r) => r.refs.iter().filter_map(|r| r.member.way()).collect(),
+            None => vec![],
+        })
+        .collect();
+    let extract_bounds = if options.partial_assembly {
+        partial_assembly::extract_bounds(pbf)
+    } else {
+        None
+    };
+
+    for obj in pbf.objects() {
+        options.cancellation.check()?;
+        let obj = obj.as_ref();
+        if !is_extracted_zone(obj, options) {
             continue;
         }
-        if let OsmObj::Relation(ref relation) = *obj {
-            let next_index = ZoneIndex { index: zones.len() };
-            if let Some(zone) = Zone::from_osm_relation(relation, pbf, next_index) {
-                // Ignore zone without boundary polygon for the moment
-                if zone.boundary.is_some() {
-                    zones.push(zone);
+        let next_index = ZoneIndex { index: zones.len() };
+        let zone = match *obj {
+            OsmObj::Relation(ref relation) => {
+                let objects = pbf.dependencies(obj);
+                let mut zone = Zone::from_osm_relation(relation, &objects, next_index);
+                if let (Some(zone), Some(bounds)) = (&mut zone, &extract_bounds) {
+                    if zone.boundary.is_none() && relation.refs.iter().any(|r| r.member.is_way()) {
+                        partial_assembly::assemble_clipped_zone(zone, relation, &objects, bounds);
+                    }
                 }
-            };
+                zone
+            }
+            OsmObj::Way(ref way) if !relations_ways.contains(&way.id) => {
+                Zone::from_osm_way(way, &pbf.dependencies(obj), next_index)
+            }
+            _ => None,
+        };
+        if let Some(mut zone) = zone {
+            warnings.extend(zone_warnings(obj, &zone));
+            if !options.disable_geometry_repair {
+                match repair_boundary(&mut zone) {
+                    RepairStatus::Valid => {}
+                    RepairStatus::Repaired => stats.repaired_geometries += 1,
+                    RepairStatus::Rejected => {
+                        stats.rejected_geometries += 1;
+                        continue;
+                    }
+                }
+            }
+            if zone.boundary.is_none() {
+                stats.zones_without_boundary += 1;
+                // the zone can only be kept if it can be located
+                let Some(center) = zone.center.filter(|_| options.keep_boundaryless_zones) else {
+                    debug!("{}: no boundary polygon, skipped", zone.osm_id);
+                    continue;
+                };
+                zone.bbox = Some(Rect::new(center.0, center.0));
+            }
+            if !is_admin(obj) && !is_admin_without_level(obj) {
+                // the ceremonial boundaries are not part of the administrative hierarchy
+                zone.zone_type = Some(ZoneType::NonAdministrative);
+            }
+            zones.push(zone);
         }
     }
 
@@ -88,24 +347,190 @@ fn get_country_code<'a>(
     }
 }
 
-fn type_zones(
-    zones: &mut [Zone],
-    stats: &mut CosmogonyStats,
-    country_code: Option<String>,
+type TypingResult = Option<Result<(String, ZoneType), zone_typer::ZoneTyperError>>;
+
+/// For the zones without country, use the most frequent country of the zones including them
+/// or included in them, and type them again with this country.
+///
+/// Returns the number of zones with a guessed country.
+fn guess_missing_countries(
+    zones: &[Zone],
+    zones_type: &mut [TypingResult],
+    zone_typer: &zone_typer::ZoneTyper,
     inclusions: &[Vec<ZoneIndex>],
-) -> Result<(), Error> {
-    use rayon::prelude::*;
-    info!("reading libpostal's rules");
-    let zone_typer = zone_typer::ZoneTyper::new()?;
+) -> usize {
+    use zone_typer::ZoneTyperError;
+    let country = |idx: usize| -> Option<&str> {
+        match &zones_type[idx] {
+            Some(Ok((c, _)))
+            | Some(Err(ZoneTyperError::InvalidCountry(c)))
+            | Some(Err(ZoneTyperError::UnkownLevel(_, c))) => Some(c),
+            None => zones[idx].country_code.as_deref(),
+        }
+    };
+    let mut included = vec![vec![]; zones.len()];
+    for (idx, zone_inclusions) in inclusions.iter().enumerate() {
+        for parent in zone_inclusions {
+            included[parent.index].push(idx);
+        }
+    }
+
+    let guesses: Vec<(usize, String)> = (0..zones.len())
+        .filter(|idx| country(*idx).is_none())
+        .filter_map(|idx| {
+            let mut votes = BTreeMap::<&str, usize>::new();
+            inclusions[idx]
+                .iter()
+                .map(|p| p.index)
+                .chain(included[idx].iter().copied())
+                .filter_map(country)
+                .for_each(|c| *votes.entry(c).or_insert(0) += 1);
+            // on a tie, the first country in alphabetical order is chosen
+            let (guessed, _) = votes
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))?;
+            Some((idx, guessed.to_string()))
+        })
+        .collect();
 
+    for (idx, country) in &guesses {
+        debug!(
+            "guessed country {} for {} ({})",
+            country, zones[*idx].osm_id, zones[*idx].name
+        );
+        zones_type[*idx] = Some(
+            zone_typer
+                .get_zone_type(&zones[*idx], country, &inclusions[*idx], zones)
+                .map(|zone_type| (country.clone(), zone_type)),
+        );
+    }
+    info!(
+        "{} zones with a country guessed from their neighbors",
+        guesses.len()
+    );
+    guesses.len()
+}
+
+/// Type the zones by their admin_level only, without country
+fn type_zones_without_country(zones: &mut [Zone]) {
+    info!("typing zones by their admin_level, without country");
+    let mut nb_untyped = 0;
+    for z in zones.iter_mut().filter(|z| z.zone_type.is_none()) {
+        z.zone_type = zone_typer::generic_zone_type(z);
+        if z.zone_type.is_none() {
+            debug!(
+                "no type for the admin_level {:?} of {}",
+                z.admin_level, z.osm_id
+            );
+            nb_untyped += 1;
+        }
+    }
+    info!("{} zones without a type for their admin_level", nb_untyped);
+}
+
+fn build_country_finder(
+    zones: &[Zone],
+    zone_typer: &zone_typer::ZoneTyper,
+    options: &BuildOptions,
+    land_areas: BTreeMap<String, MultiPolygon<f64>>,
+) -> Result<CountryFinder, Error> {
     info!("creating a countries rtree");
-    let country_finder: CountryFinder = CountryFinder::init(zones, &zone_typer);
-    if country_code.is_none() && country_finder.is_empty() {
+    let mut country_finder: CountryFinder = CountryFinder::init(zones, zone_typer);
+    if options.dependent_territories {
+        country_finder = country_finder.with_dependent_territories(zones, zone_typer);
+    }
+    if !land_areas.is_empty() {
+        country_finder = country_finder.with_land_areas(land_areas);
+    }
+    if options.country_fallback {
+        country_finder = country_finder.with_bundled_fallback(zone_typer)?;
+    }
+    if options.country_code.is_none() && country_finder.is_empty() {
         return Err(anyhow!(
             "no country_code has been provided and no country have been found, \
-             we won't be able to make a cosmogony",
+             we won't be able to make a cosmogony (the --country-fallback option can help)",
         ));
     }
+    Ok(country_finder)
+}
+
+/// find the country of the zones, the country whose rules type them and the countries
+/// claiming them
+fn detect_countries(
+    country_finder: &CountryFinder,
+    zones: &[Zone],
+    country_code: &Option<String>,
+    inclusions: &[Vec<ZoneIndex>],
+) -> Vec<CountryDetection> {
+    use rayon::prelude::*;
+    zones
+        .par_iter()
+        .map(|z| {
+            let inclusion = &inclusions[z.id.index];
+            let country = get_country_code(country_finder, z, country_code, inclusion);
+            let rules_country = country.as_ref().map(|c| {
+                country_finder
+                    .find_rules_country(z, c, inclusion)
+                    .to_string()
+            });
+            let claimants = if z.claimed_by.is_empty() {
+                country_finder.find_zone_claimants(z, inclusion)
+            } else {
+                vec![]
+            };
+            CountryDetection {
+                country,
+                rules_country,
+                claimants,
+            }
+        })
+        .collect()
+}
+
+fn type_zones(
+    zones: &mut [Zone],
+    stats: &mut CosmogonyStats,
+    options: &BuildOptions,
+    inclusions: &[Vec<ZoneIndex>],
+    land_areas: BTreeMap<String, MultiPolygon<f64>>,
+    cache: Option<&CountryCache>,
+) -> Result<(), Error> {
+    use rayon::prelude::*;
+    if options.no_country {
+        type_zones_without_country(zones);
+        return Ok(());
+    }
+    let mut zone_typer = match options.rules_dir {
+        Some(ref dir) => {
+            info!("reading the typing rules of {}", dir.display());
+            zone_typer::ZoneTyper::from_rules_dir(dir)?
+        }
+        None => {
+            info!("reading libpostal's rules");
+            zone_typer::ZoneTyper::new()?
+        }
+    };
+    if let Some(ref path) = options.admin_level_overrides {
+        info!("reading admin_level overrides from {}", path.display());
+        zone_typer = zone_typer.with_level_overrides_file(path)?;
+    }
+    let country_code = &options.country_code;
+
+    let detections = match cache.and_then(|c| c.read(zones)) {
+        Some(detections) => {
+            info!("reading the countries of the zones from the cache");
+            detections
+        }
+        None => {
+            let country_finder = build_country_finder(zones, &zone_typer, options, land_areas)?;
+            info!("finding the countries of the zones");
+            let detections = detect_countries(&country_finder, zones, country_code, inclusions);
+            if let Some(Err(e)) = cache.map(|c| c.write(zones, &detections)) {
+                warn!("impossible to write the country cache: {:#}", e);
+            }
+            detections
+        }
+    };
 
     info!("typing zones");
     // We type all the zones in parallele
@@ -113,17 +538,33 @@ fn type_zones(
     // (the borrow checker would not be happy since we also need to access to the zone's vector
     // to be able to transform the ZoneIndex to a zone)
     // we collect all the types in a Vector, and assign the zone's zone_type as a post process
-    let zones_type: Vec<_> = zones
+    let countries: Vec<_> = detections.iter().map(|d| d.country.clone()).collect();
+    if let Some(ref remap) = options.admin_level_remap {
+        remap_admin_levels(zones, &countries, remap);
+    }
+    let mut zones_type: Vec<_> = zones
         .par_iter()
-        .map(|z| {
-            get_country_code(&country_finder, z, &country_code, &inclusions[z.id.index]).map(|c| {
+        .zip(countries)
+        .zip(&detections)
+        .map(|((z, country), detection)| {
+            country.map(|c| {
+                if z.zone_type == Some(ZoneType::NonAdministrative) {
+                    // the non administrative zones have no admin_level to type them
+                    return Ok((c, ZoneType::NonAdministrative));
+                }
+                let rules_country = detection.rules_country.as_deref().unwrap_or(&c);
                 zone_typer
-                    .get_zone_type(z, &c, &inclusions[z.id.index], zones)
+                    .get_zone_type(z, rules_country, &inclusions[z.id.index], zones)
                     .map(|zone_type| (c, zone_type))
             })
         })
         .collect();
 
+    if options.guess_missing_countries {
+        stats.zone_with_guessed_country =
+            guess_missing_countries(zones, &mut zones_type, &zone_typer, inclusions);
+    }
+
     zones
         .iter_mut()
         .zip(zones_type.into_iter())
@@ -137,14 +578,20 @@ fn type_zones(
                     stats.zone_without_country += 1;
                 }
                 Some(Ok((country_code, t))) => {
-                    z.country_code = Some(country_code);
-                    z.zone_type = Some(t)
+                    // the extra zones are already typed and can have a country
+                    z.country_code.get_or_insert(country_code);
+                    z.zone_type.get_or_insert(t);
                 }
                 Some(Err(zone_typer::ZoneTyperError::InvalidCountry(c))) => {
                     z.country_code = Some(c.clone());
                     info!("impossible to find rules for country {}", c);
                     *stats.zone_with_unkwown_country_rules.entry(c).or_insert(0) += 1;
                 }
+                Some(Err(zone_typer::ZoneTyperError::UnkownLevel(None, country))) if z.disputed => {
+                    // disputed areas often have no admin_level, we keep them as non administrative zones
+                    z.country_code = Some(country);
+                    z.zone_type = Some(ZoneType::NonAdministrative);
+                }
                 Some(Err(zone_typer::ZoneTyperError::UnkownLevel(lvl, country))) => {
                     z.country_code = Some(country.clone());
                     debug!(
@@ -161,15 +608,149 @@ fn type_zones(
             },
         );
 
+    mark_disputed_zones(zones, &detections);
+
     Ok(())
 }
 
-fn compute_labels(zones: &mut [Zone], filter_langs: &[String]) {
+/// Change the admin_level of the zones whose level is remapped for their country
+fn remap_admin_levels(zones: &mut [Zone], countries: &[Option<String>], remap: &AdminLevelRemap) {
+    let mut nb_remapped = 0;
+    for (z, country) in zones.iter_mut().zip(countries) {
+        let (Some(country), Some(level)) = (country, z.admin_level) else {
+            continue;
+        };
+        if let Some(new_level) = remap.remap(country, level) {
+            debug!(
+                "admin_level of {} ({}) remapped from {} to {}",
+                z.osm_id, z.name, level, new_level
+            );
+            z.admin_level = Some(new_level);
+            nb_remapped += 1;
+        }
+    }
+    info!("{} zones with a remapped admin_level", nb_remapped);
+}
+
+/// flag the zones claimed by several countries as disputed
+///
+/// The claimants of a zone are taken from the osm tags if present,
+/// else from the countries containing the zone.
+fn mark_disputed_zones(zones: &mut [Zone], detections: &[CountryDetection]) {
+    for (z, detection) in zones.iter_mut().zip(detections) {
+        if !z.claimed_by.is_empty() {
+            continue;
+        }
+        let claimants = detection.claimants.clone();
+        if claimants.len() > 1 {
+            debug!("{} ({}) is claimed by {:?}", z.osm_id, z.name, claimants);
+            z.disputed = true;
+            z.claimed_by = claimants;
+        } else if z.disputed {
+            z.claimed_by = claimants;
+        }
+    }
+}
+
+/// The label template of the country of a zone, if any
+fn zone_label_template<'a>(
+    zone: &Zone,
+    templates: &'a LabelTemplates,
+) -> Option<&'a LabelTemplate> {
+    let country_code = zone.country_code.as_ref()?;
+    templates.get(&country_code.to_uppercase())
+}
+
+/// The label templates of a build, if a file is given
+fn label_templates(options: &BuildOptions) -> Result<LabelTemplates, Error> {
+    match &options.label_templates {
+        Some(path) => label_templates::read_label_templates(path),
+        None => Ok(LabelTemplates::default()),
+    }
+}
+
+/// Build the labels of the zones, and their secondary labels if asked
+///
+/// Only the labels of the zones of `only` are built if it is given. The ids of the zones must
+/// be their position in `zones`.
+fn compute_all_labels(
+    zones: &mut [Zone],
+    options: &BuildOptions,
+    templates: &LabelTemplates,
+    only: Option<&BTreeSet<usize>>,
+) {
+    if let Some(lang) = &options.secondary_label_lang {
+        let format = &options.secondary_label_format;
+        compute_secondary_labels(zones, lang, format, templates, only);
+    }
+    compute_labels(zones, &options.filter_langs, templates, only);
+}
+
+/// The zones whose labels are built, with their index
+fn labelled_zones<'a>(
+    zones: &'a [Zone],
+    only: Option<&'a BTreeSet<usize>>,
+) -> impl rayon::iter::ParallelIterator<Item = (usize, &'a Zone)> {
+    use rayon::prelude::*;
+    zones
+        .par_iter()
+        .enumerate()
+        .filter(move |(idx, _)| only.is_none_or(|only| only.contains(idx)))
+}
+
+fn compute_labels(
+    zones: &mut [Zone],
+    filter_langs: &[String],
+    templates: &LabelTemplates,
+    only: Option<&BTreeSet<usize>>,
+) {
+    use rayon::prelude::*;
     info!("computing all zones's label");
-    let nb_zones = zones.len();
-    for i in 0..nb_zones {
-        let (mslice, z) = MutableSlice::init(zones, i);
-        z.compute_labels(&mslice, filter_langs);
+    let lang_filter = lang_tags::LangFilter::new(filter_langs);
+    // the labels are built from the names of the parents, so they are all built before being
+    // set, to only read the zones while building them
+    let labels: Vec<_> = labelled_zones(zones, only)
+        .map(|(idx, z)| {
+            let template = zone_label_template(z, templates);
+            (idx, z.build_labels(zones, &lang_filter, template))
+        })
+        .collect();
+    for (idx, (label, international_labels)) in labels {
+        zones[idx].label = label;
+        zones[idx].international_labels = international_labels;
+    }
+}
+
+/// Trim the names of the zones to the kept languages, with `default+`
+///
+/// The names are trimmed once all the labels are built (and disambiguated), as they use the
+/// parents' names.
+fn trim_names(zones: &mut [Zone], filter_langs: &[String]) {
+    let lang_filter = lang_tags::LangFilter::new(filter_langs);
+    if lang_filter.trim_names {
+        for z in zones.iter_mut() {
+            z.international_names.retain(|l, _| lang_filter.accepts(l));
+        }
+    }
+}
+
+fn compute_secondary_labels(
+    zones: &mut [Zone],
+    lang: &str,
+    format: &SecondaryLabelFormat,
+    templates: &LabelTemplates,
+    only: Option<&BTreeSet<usize>>,
+) {
+    use rayon::prelude::*;
+    info!("computing all zones's secondary label in {}", lang);
+    let labels: Vec<_> = labelled_zones(zones, only)
+        .map(|(idx, z)| {
+            let template = zone_label_template(z, templates);
+            (idx, z.build_secondary_label(zones, lang, format, template))
+        })
+        .collect();
+    for (idx, label) in labels {
+        zones[idx].secondary_label = Some(label);
     }
 }
 
@@ -179,80 +760,849 @@ fn clean_untagged_zones(zones: &mut Vec<Zone>) {
     let nb_zones = zones.len();
     zones.retain(|z| z.zone_type.is_some());
     info!("{} zones cleaned", (nb_zones - zones.len()));
+    // the inclusions must not reference the removed zones
+    let kept: BTreeSet<ZoneIndex> = zones.iter().map(|z| z.id).collect();
+    for z in zones.iter_mut() {
+        z.included_in.retain(|c| kept.contains(c));
+    }
+}
+
+/// Only keep the zones' tags whose key is in `kept_tags` (a key can be a prefix like `name:*`)
+fn filter_tags(zones: &mut [Zone], kept_tags: &[String]) {
+    if kept_tags.is_empty() {
+        return;
+    }
+    info!("only keeping the tags {}", kept_tags.join(", "));
+    let is_kept = |key: &str| {
+        kept_tags.iter().any(|k| match k.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == k,
+        })
+    };
+    for z in zones {
+        z.tags.retain(|k, _| is_kept(k));
+        z.center_tags.retain(|k, _| is_kept(k));
+    }
+}
+
+/// Give to the zones their position as id, the links to the zones being updated (the links to
+/// the zones no longer there are removed)
+pub(crate) fn renumber_zones(zones: &mut [Zone]) {
+    let new_ids: BTreeMap<ZoneIndex, ZoneIndex> = zones
+        .iter()
+        .enumerate()
+        .map(|(index, z)| (z.id, ZoneIndex { index }))
+        .collect();
+    for z in zones.iter_mut() {
+        z.id = new_ids[&z.id];
+        z.parent = z.parent.and_then(|p| new_ids.get(&p).copied());
+        z.parents = z
+            .parents
+            .iter()
+            .filter_map(|p| new_ids.get(p).copied())
+            .collect();
+        z.included_in = z
+            .included_in
+            .iter()
+            .filter_map(|p| new_ids.get(p).copied())
+            .collect();
+        z.neighbors = z
+            .neighbors
+            .iter()
+            .filter_map(|n| new_ids.get(n).copied())
+            .collect();
+    }
+}
+
+/// Sort the zones by osm id (their ids are renumbered accordingly) and the lists of the zones
+/// and of the stats, so that the output of a build is reproducible
+fn sort_zones(zones: &mut [Zone], stats: &mut CosmogonyStats) {
+    info!("sorting the zones by osm id");
+    zones.sort_by(|a, b| a.osm_id.cmp(&b.osm_id));
+    renumber_zones(zones);
+    for z in zones.iter_mut() {
+        z.claimed_by.sort();
+        z.overlaps.sort();
+        z.neighbors.sort();
+    }
+    stats
+        .extra_zones_conflicts
+        .values_mut()
+        .for_each(|conflicts| conflicts.sort());
+    stats
+        .sibling_overlaps
+        .sort_by(|a, b| a.osm_ids.cmp(&b.osm_ids));
+    stats
+        .name_collisions
+        .sort_by(|a, b| a.osm_ids.cmp(&b.osm_ids));
 }
 
 pub fn create_ontology(
     zones: &mut Vec<Zone>,
     stats: &mut CosmogonyStats,
-    country_code: Option<String>,
-    disable_voronoi: bool,
-    parsed_pbf: &BTreeMap<OsmId, OsmObj>,
-    filter_langs: &[String],
+    parsed_pbf: &dyn OsmObjects,
+    options: &BuildOptions,
 ) -> Result<(), Error> {
     info!("creating ontology for {} zones", zones.len());
-    let (inclusions, ztree) = find_inclusions(zones);
+    let mut ctx = PipelineContext::new(zones, stats, parsed_pbf, options);
+    Pipeline::ontology().run(&mut ctx)
+}
 
-    type_zones(zones, stats, country_code, &inclusions)?;
+/// Compute again the labels of the zones of a cosmogony (eg. loaded from a file), for other
+/// langs, without building it again
+///
+/// The labels are built like in a build with these options: with their `filter_langs`, their
+/// `label_templates` and their `secondary_label_lang` (the secondary labels are removed without
+/// it). They are built from the zones' names, the zones built keeping only some langs (with
+/// `default+`) have no names in the other langs. The siblings with the same label are
+/// disambiguated again.
+///
+/// ```no_run
+/// # use cosmogony::load_cosmogony_from_file;
+/// # use cosmogony_builder::BuildOptions;
+/// let mut cosmogony = load_cosmogony_from_file("cosmogony.json").unwrap();
+/// let options = BuildOptions {
+///     filter_langs: vec!["fr".to_string(), "de".to_string()],
+///     ..Default::default()
+/// };
+/// cosmogony_builder::recompute_labels(&mut cosmogony, &options).unwrap();
+/// ```
+pub fn recompute_labels(cosmogony: &mut Cosmogony, options: &BuildOptions) -> Result<(), Error> {
+    let templates = label_templates(options)?;
+    // the labels are built with the ids of the zones being their position, which is no longer
+    // the case once the untyped zones have been cleaned
+    let zones = &mut cosmogony.zones;
+    let positions: BTreeMap<ZoneIndex, ZoneIndex> = zones
+        .iter()
+        .enumerate()
+        .map(|(index, z)| (z.id, ZoneIndex { index }))
+        .collect();
+    let ids: Vec<(ZoneIndex, Option<ZoneIndex>)> = zones.iter().map(|z| (z.id, z.parent)).collect();
+    for (index, z) in zones.iter_mut().enumerate() {
+        z.id = ZoneIndex { index };
+        z.parent = z.parent.and_then(|p| positions.get(&p).copied());
+        // the loaded secondary labels are already disambiguated
+        z.secondary_label = None;
+    }
+    compute_all_labels(zones, options, &templates, None);
+    cosmogony.meta.stats.name_collisions =
+        name_collisions::disambiguate_labels(zones, |zones, only| {
+            compute_all_labels(zones, options, &templates, Some(only))
+        });
+    trim_names(zones, &options.filter_langs);
+    for (z, (id, parent)) in zones.iter_mut().zip(ids) {
+        z.id = id;
+        z.parent = parent;
+    }
+    Ok(())
+}
 
-    build_hierarchy(zones, inclusions);
+pub fn build_cosmogony(pbf_path: String, options: &BuildOptions) -> Result<Cosmogony, Error> {
+    build_cosmogony_with_pipeline(pbf_path, options, &Pipeline::default())
+}
 
-    if !disable_voronoi {
-        compute_additional_places(zones, parsed_pbf, ztree);
-    }
+/// Build a cosmogony with a custom pipeline
+pub fn build_cosmogony_with_pipeline(
+    pbf_path: String,
+    options: &BuildOptions,
+    pipeline: &Pipeline,
+) -> Result<Cosmogony, Error> {
+    let (zones, meta) = build_zones(pbf_path, options, pipeline)?;
+    Ok(Cosmogony { zones, meta })
+}
 
-    zones.iter_mut().for_each(|z| z.compute_names());
+/// The receiver of the zones of a streamed cosmogony, see [`stream_cosmogony_to_sink`]
+pub trait ZoneSink {
+    /// Write a zone, the zone being freed once written
+    fn write_zone(&mut self, zone: Zone) -> Result<(), Error>;
+}
+
+/// A [`ZoneSink`] writing each zone as a json line, flushed once written so that a downstream
+/// loader can read the zones while they are written
+pub struct JsonLinesSink<W: Write>(pub W);
+
+impl<W: Write> ZoneSink for JsonLinesSink<W> {
+    fn write_zone(&mut self, zone: Zone) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.0, &zone)?;
+        self.0.write_all(b"\n")?;
+        self.0.flush()?;
+        Ok(())
+    }
+}
 
-    compute_labels(zones, filter_langs);
+/// Build a cosmogony and write its zones as a json stream (one zone by line)
+///
+/// See [`stream_cosmogony_to_sink`], each zone being flushed once written.
+pub fn stream_cosmogony(
+    pbf_path: String,
+    options: &BuildOptions,
+    writer: impl Write,
+) -> Result<CosmogonyMetadata, Error> {
+    stream_cosmogony_to_sink(pbf_path, options, &mut JsonLinesSink(writer))
+}
 
-    // We remove the useless zones from cosmogony.
-    //
-    // WARNING: this invalidates the different indexes  (we can no longer lookup a Zone by it's id
-    // in the zones's vector) this should be removed later on (and switch to a map by osm_id ?) as
-    // it's not elegant, but for the moment it'll do.
-    clean_untagged_zones(zones);
+/// Build a cosmogony and send its zones one by one to a sink
+///
+/// The stages need all the zones, but once the last one has run, the osm objects are freed and
+/// each zone is sent to the sink then freed, so the whole `Cosmogony` is never serialized in
+/// memory.
+pub fn stream_cosmogony_to_sink(
+    pbf_path: String,
+    options: &BuildOptions,
+    sink: &mut impl ZoneSink,
+) -> Result<CosmogonyMetadata, Error> {
+    let (zones, meta) = build_zones(pbf_path, options, &Pipeline::default())?;
+    info!("streaming {} zones", zones.len());
+    for z in zones {
+        sink.write_zone(z)?;
+    }
+    Ok(meta)
+}
 
+/// Check the user's typing rules before the build, instead of failing after reading the pbf
+fn check_typing_rules(options: &BuildOptions) -> Result<(), Error> {
+    if options.no_country
+        || (options.rules_dir.is_none() && options.admin_level_overrides.is_none())
+    {
+        return Ok(());
+    }
+    let errors = zone_typer::ZoneTyper::validate_rules(
+        options.rules_dir.as_deref(),
+        options.admin_level_overrides.as_deref(),
+    );
+    for e in &errors {
+        error!("{}", e);
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{} problems in the typing rules", errors.len()));
+    }
     Ok(())
 }
 
-pub fn build_cosmogony(
+fn build_zones(
     pbf_path: String,
-    country_code: Option<String>,
-    disable_voronoi: bool,
-    filter_langs: &[String],
-) -> Result<Cosmogony, Error> {
+    options: &BuildOptions,
+    pipeline: &Pipeline,
+) -> Result<(Vec<Zone>, CosmogonyMetadata), Error> {
     let path = Path::new(&pbf_path);
+    check_typing_rules(options)?;
+    let read_start = Instant::now();
     info!("Reading pbf with geometries...");
     let file = File::open(&path).context("no pbf file")?;
     let file = BufReader::with_capacity(FILE_BUF_SIZE, file);
 
-    let parsed_pbf = OsmPbfReader::new(file)
-        .get_objs_and_deps(|o| is_admin(o) || is_place(o))
-        .context("invalid osm file")?;
+    let mut reader = OsmPbfReader::new(file);
+    let with_landuses = options.additional_zones_strategy == AdditionalZonesStrategy::Landuse;
+    let is_needed = |o: &OsmObj| {
+        is_extracted_zone(o, options)
+            || is_extracted_place(o, options)
+            || (with_landuses && is_residential_landuse(o))
+            || (options.country_land_areas && country_finder::is_country_land_area(o))
+    };
+    let mut osm_metadata = options.osm_metadata.then(BTreeMap::new);
+    let changes = osm_change::OsmChange::read_all(&options.osm_changes)?;
+    let parsed_pbf: Box<dyn OsmObjects> = match options.max_memory {
+        None => {
+            let mut objects = BTreeMap::new();
+            let metadata = osm_metadata.as_mut();
+            pbf_reader::read_objs_and_deps(
+                &mut reader,
+                is_needed,
+                &mut objects,
+                metadata,
+                &changes,
+                &options.cancellation,
+            )
+            .context("invalid osm file")?;
+            Box::new(objects)
+        }
+        Some(MemorySize(max_memory)) => {
+            let mut store = SpillingStore::new(max_memory, &std::env::temp_dir())?;
+            let metadata = osm_metadata.as_mut();
+            pbf_reader::read_objs_and_deps(
+                &mut reader,
+                is_needed,
+                &mut store,
+                metadata,
+                &changes,
+                &options.cancellation,
+            )
+            .context("invalid osm file")?;
+            Box::new(store.finish()?)
+        }
+    };
     info!("reading pbf done.");
 
-    let (mut zones, mut stats) = get_zones_and_stats(&parsed_pbf)?;
-
-    create_ontology(
-        &mut zones,
-        &mut stats,
-        country_code,
-        disable_voronoi,
-        &parsed_pbf,
-        filter_langs,
-    )?;
+    let mut zones = vec![];
+    let mut stats = CosmogonyStats::default();
+    let mut ctx = PipelineContext::new(&mut zones, &mut stats, parsed_pbf.as_ref(), options);
+    ctx.record_duration("read", read_start.elapsed());
+    if options.country_cache.is_some() {
+        ctx.input_fingerprint = Some(country_cache::input_fingerprint(path)?);
+    }
+    pipeline.run(&mut ctx)?;
+    let mut warnings = std::mem::take(&mut ctx.warnings);
+    let phase_durations = std::mem::take(&mut ctx.phase_durations);
+    if !warnings.is_empty() {
+        info!("{} build warnings", warnings.len());
+    }
+    if let Some(ref metadata) = osm_metadata {
+        osm_metadata::assign_osm_metadata(&mut zones, metadata, parsed_pbf.as_ref());
+        parsed_pbf.check()?;
+    }
 
+    if options.deterministic {
+        sort_zones(&mut zones, &mut stats);
+        warnings.sort_by(|a, b| (&a.osm_id, a.kind).cmp(&(&b.osm_id, b.kind)));
+    } else {
+        // the durations would make the output of the deterministic builds differ
+        stats.phase_durations = phase_durations;
+    }
     stats.compute(&zones);
+    if let Some(ref path) = options.expected_counts {
+        let expected = expected_counts::read_expected_counts(path)?;
+        stats.count_deviations = expected_counts::check_counts(&expected, &stats);
+        if options.fail_on_unexpected_counts && !stats.count_deviations.is_empty() {
+            // the deviations have been logged by the check
+            return Err(anyhow!(
+                "{} zones counts deviate from the expected ones",
+                stats.count_deviations.len()
+            ));
+        }
+    }
 
-    let cosmogony = Cosmogony {
-        zones,
-        meta: CosmogonyMetadata {
-            osm_filename: path
-                .file_name()
-                .and_then(|f| f.to_str())
-                .map(|f| f.to_string())
-                .unwrap_or_else(|| "invalid file name".into()),
-            stats,
-        },
+    let meta = CosmogonyMetadata {
+        schema_version: cosmogony::SCHEMA_VERSION,
+        osm_filename: path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .map(|f| f.to_string())
+            .unwrap_or_else(|| "invalid file name".into()),
+        stats,
+        warnings,
     };
-    Ok(cosmogony)
+    Ok((zones, meta))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use osmpbfreader::OsmId;
+
+    #[test]
+    fn neighbourhood_relations_are_extracted() {
+        let relation = |tags: &[(&str, &str)]| {
+            OsmObj::Relation(osmpbfreader::Relation {
+                id: osmpbfreader::RelationId(1),
+                tags: tags
+                    .iter()
+                    .map(|(k, v)| ((*k).into(), (*v).into()))
+                    .collect(),
+                refs: vec![],
+            })
+        };
+        assert!(is_admin(&relation(&[("place", "neighbourhood")])));
+        assert!(is_admin(&relation(&[("place", "quarter")])));
+        assert!(!is_admin(&relation(&[("place", "city")])));
+        assert!(is_admin(&relation(&[
+            ("boundary", "administrative"),
+            ("admin_level", "11")
+        ])));
+    }
+
+    #[test]
+    fn boundaries_without_level_are_opt_in() {
+        let relation = OsmObj::Relation(osmpbfreader::Relation {
+            id: osmpbfreader::RelationId(1),
+            tags: [("boundary", "administrative"), ("name", "Nowhere")]
+                .iter()
+                .map(|(k, v)| ((*k).into(), (*v).into()))
+                .collect(),
+            refs: vec![],
+        });
+        assert!(!is_admin(&relation));
+        assert!(is_admin_without_level(&relation));
+        assert!(!is_extracted_zone(&relation, &BuildOptions::default()));
+        let options = BuildOptions {
+            infer_missing_levels: true,
+            ..Default::default()
+        };
+        assert!(is_extracted_zone(&relation, &options));
+    }
+
+    #[test]
+    fn ceremonial_boundaries_are_opt_in() {
+        use osmpbfreader::{Node, NodeId, Ref, Relation, RelationId, Way, WayId};
+
+        let nodes: Vec<_> = [(0, 0), (0, 1), (1, 1), (1, 0)]
+            .iter()
+            .enumerate()
+            .map(|(i, (lat, lon))| Node {
+                id: NodeId(i as i64 + 1),
+                tags: Default::default(),
+                decimicro_lat: lat * 10_000_000,
+                decimicro_lon: lon * 10_000_000,
+            })
+            .collect();
+        let way = Way {
+            id: WayId(10),
+            tags: Default::default(),
+            nodes: vec![NodeId(1), NodeId(2), NodeId(3), NodeId(4), NodeId(1)],
+        };
+        let relation = Relation {
+            id: RelationId(20),
+            tags: [("boundary", "ceremonial"), ("name", "Greater London")]
+                .iter()
+                .map(|(k, v)| ((*k).into(), (*v).into()))
+                .collect(),
+            refs: vec![Ref {
+                member: way.id.into(),
+                role: "outer".into(),
+            }],
+        };
+        assert!(is_ceremonial(&OsmObj::Relation(relation.clone())));
+        assert!(!is_admin(&OsmObj::Relation(relation.clone())));
+
+        let mut pbf: BTreeMap<OsmId, OsmObj> = nodes
+            .into_iter()
+            .map(|n| (n.id.into(), OsmObj::Node(n)))
+            .collect();
+        pbf.insert(way.id.into(), OsmObj::Way(way));
+        pbf.insert(relation.id.into(), OsmObj::Relation(relation));
+
+        let (zones, _) = get_zones_and_stats(&pbf, &BuildOptions::default()).unwrap();
+        assert!(zones.is_empty());
+
+        let options = BuildOptions {
+            ceremonial_boundaries: true,
+            ..Default::default()
+        };
+        let (zones, _) = get_zones_and_stats(&pbf, &options).unwrap();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].name, "Greater London");
+        assert_eq!(zones[0].zone_type, Some(ZoneType::NonAdministrative));
+
+        // a custom filter replaces the default one
+        let options = BuildOptions {
+            osm_filter: Some(OsmFilter::new(|o| {
+                o.tags().contains("name", "Greater London")
+            })),
+            ..Default::default()
+        };
+        let (zones, _) = get_zones_and_stats(&pbf, &options).unwrap();
+        assert_eq!(zones.len(), 1);
+        let options = BuildOptions {
+            ceremonial_boundaries: true,
+            osm_filter: Some(OsmFilter::new(|o| is_admin(o) || is_place(o))),
+            ..Default::default()
+        };
+        let (zones, _) = get_zones_and_stats(&pbf, &options).unwrap();
+        assert!(zones.is_empty());
+
+        // the excluded relations are not extracted
+        let options = BuildOptions {
+            ceremonial_boundaries: true,
+            excluded_osm_ids: "relation:20".parse().unwrap(),
+            ..Default::default()
+        };
+        let (zones, _) = get_zones_and_stats(&pbf, &options).unwrap();
+        assert!(zones.is_empty());
+    }
+
+    #[test]
+    fn historic_zones_are_skipped() {
+        use osmpbfreader::{Node, NodeId, Ref, Relation, RelationId, Way, WayId};
+
+        let nodes: Vec<_> = [(0, 0), (0, 1), (1, 1), (1, 0)]
+            .iter()
+            .enumerate()
+            .map(|(i, (lat, lon))| Node {
+                id: NodeId(i as i64 + 1),
+                tags: Default::default(),
+                decimicro_lat: lat * 10_000_000,
+                decimicro_lon: lon * 10_000_000,
+            })
+            .collect();
+        let way = Way {
+            id: WayId(10),
+            tags: Default::default(),
+            nodes: vec![NodeId(1), NodeId(2), NodeId(3), NodeId(4), NodeId(1)],
+        };
+        let relation = |id: i64, name: &str, end_date: &str| Relation {
+            id: RelationId(id),
+            tags: [
+                ("boundary", "administrative"),
+                ("admin_level", "8"),
+                ("name", name),
+                ("start_date", "1790"),
+                ("end_date", end_date),
+            ]
+            .iter()
+            .map(|(k, v)| ((*k).into(), (*v).into()))
+            .collect(),
+            refs: vec![Ref {
+                member: way.id.into(),
+                role: "outer".into(),
+            }],
+        };
+        let mut pbf: BTreeMap<OsmId, OsmObj> = nodes
+            .into_iter()
+            .map(|n| (n.id.into(), OsmObj::Node(n)))
+            .collect();
+        for relation in [relation(20, "Old", "2016-01-01"), relation(21, "New", "")] {
+            pbf.insert(relation.id.into(), OsmObj::Relation(relation));
+        }
+        pbf.insert(way.id.into(), OsmObj::Way(way));
+
+        let (zones, _) = get_zones_and_stats(&pbf, &BuildOptions::default()).unwrap();
+        let names: Vec<_> = zones.iter().map(|z| z.name.as_str()).collect();
+        assert_eq!(names, vec!["New"]);
+        assert!(!zones[0].historic);
+
+        let options = BuildOptions {
+            keep_historic: true,
+            ..Default::default()
+        };
+        let (zones, _) = get_zones_and_stats(&pbf, &options).unwrap();
+        assert_eq!(zones.len(), 2);
+        assert_eq!(zones[0].name, "Old");
+        assert!(zones[0].historic);
+        assert_eq!(zones[0].start_date.as_deref(), Some("1790"));
+        assert_eq!(zones[0].end_date.as_deref(), Some("2016-01-01"));
+    }
+
+    #[test]
+    fn boundaryless_zones_can_be_kept() {
+        use osmpbfreader::{Node, NodeId, Ref, Relation, RelationId};
+
+        let node = Node {
+            id: NodeId(1),
+            tags: [("name".into(), "Village".into())].into_iter().collect(),
+            decimicro_lat: 10_000_000,
+            decimicro_lon: 20_000_000,
+        };
+        // the relation's outer way is missing, its boundary cannot be built
+        let relation = Relation {
+            id: RelationId(2),
+            tags: [
+                ("boundary", "administrative"),
+                ("admin_level", "8"),
+                ("name", "Village"),
+            ]
+            .iter()
+            .map(|(k, v)| ((*k).into(), (*v).into()))
+            .collect(),
+            refs: vec![
+                Ref {
+                    member: OsmId::Node(node.id),
+                    role: "admin_centre".into(),
+                },
+                Ref {
+                    member: OsmId::Way(WayId(3)),
+                    role: "outer".into(),
+                },
+            ],
+        };
+        let pbf: BTreeMap<_, _> = [
+            (OsmId::Node(node.id), OsmObj::Node(node)),
+            (OsmId::Relation(relation.id), OsmObj::Relation(relation)),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut warnings = vec![];
+        let (zones, stats) = read_zones(&pbf, &BuildOptions::default(), &mut warnings).unwrap();
+        assert!(zones.is_empty());
+        assert_eq!(stats.zones_without_boundary, 1);
+        let kinds: Vec<_> = warnings.iter().map(|w| w.kind).collect();
+        assert_eq!(kinds, vec![BuildWarningKind::UnclosedRings]);
+
+        let options = BuildOptions {
+            keep_boundaryless_zones: true,
+            ..Default::default()
+        };
+        let (zones, stats) = get_zones_and_stats(&pbf, &options).unwrap();
+        assert_eq!(stats.zones_without_boundary, 1);
+        assert_eq!(zones.len(), 1);
+        assert!(zones[0].boundary.is_none());
+        assert_eq!(zones[0].center, Some(cosmogony::Coord::new(2., 1.)));
+        assert_eq!(zones[0].admin_centre, zones[0].center);
+        assert_eq!(zones[0].admin_centre_name.as_deref(), Some("Village"));
+        assert!(zones[0].bbox.is_some());
+    }
+
+    #[test]
+    fn missing_countries_are_guessed() {
+        let zone_typer = zone_typer::ZoneTyper::new().unwrap();
+        let zone = |index: usize| Zone {
+            id: ZoneIndex { index },
+            admin_level: Some(8),
+            ..Default::default()
+        };
+        let zones = vec![zone(0), zone(1), zone(2), zone(3)];
+        // z1 is included in z0 (in FR) and includes z3 (in LU), z2 has no neighbors
+        let inclusions = vec![
+            vec![],
+            vec![ZoneIndex { index: 0 }],
+            vec![],
+            vec![ZoneIndex { index: 1 }],
+        ];
+        let mut zones_type: Vec<TypingResult> = vec![
+            Some(Ok(("FR".into(), ZoneType::Country))),
+            None,
+            None,
+            Some(Ok(("LU".into(), ZoneType::Suburb))),
+        ];
+        let nb_guessed = guess_missing_countries(&zones, &mut zones_type, &zone_typer, &inclusions);
+
+        assert_eq!(nb_guessed, 1);
+        // on a tie, the first country in alphabetical order is chosen
+        assert!(matches!(&zones_type[1], Some(Ok((c, ZoneType::City))) if c == "FR"));
+        assert!(zones_type[2].is_none());
+    }
+
+    #[test]
+    fn admin_levels_are_remapped_before_typing() {
+        let zone = |index: usize, admin_level| Zone {
+            id: ZoneIndex { index },
+            admin_level: Some(admin_level),
+            ..Default::default()
+        };
+        let mut zones = vec![zone(0, 6), zone(1, 7)];
+        let options = BuildOptions {
+            country_code: Some("LU".into()),
+            admin_level_remap: Some("LU:6=8,FR:7=8".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut stats = CosmogonyStats::default();
+        type_zones(
+            &mut zones,
+            &mut stats,
+            &options,
+            &[vec![], vec![]],
+            BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(zones[0].admin_level, Some(8));
+        assert_eq!(zones[0].zone_type, Some(ZoneType::City));
+        // only the levels of the zone's country are remapped
+        assert_eq!(zones[1].admin_level, Some(7));
+        assert_eq!(zones[1].zone_type, None);
+    }
+
+    #[test]
+    fn zones_are_typed_without_country() {
+        let zone = |index: usize, admin_level| Zone {
+            id: ZoneIndex { index },
+            admin_level: Some(admin_level),
+            ..Default::default()
+        };
+        let mut zones = vec![zone(0, 4), zone(1, 8), zone(2, 12)];
+        zones[2].zone_type = Some(ZoneType::NonAdministrative);
+        let options = BuildOptions {
+            no_country: true,
+            ..Default::default()
+        };
+        let mut stats = CosmogonyStats::default();
+        // there are no countries, it would fail without `no_country`
+        type_zones(
+            &mut zones,
+            &mut stats,
+            &options,
+            &[vec![], vec![], vec![]],
+            BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+
+        let types: Vec<_> = zones.iter().map(|z| z.zone_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                Some(ZoneType::State),
+                Some(ZoneType::City),
+                Some(ZoneType::NonAdministrative)
+            ]
+        );
+        assert!(zones.iter().all(|z| z.country_code.is_none()));
+        assert_eq!(stats.zone_without_country, 0);
+    }
+
+    #[test]
+    fn labels_are_recomputed() {
+        let zone = |index: usize, name: &str, parent: Option<usize>| Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            name: name.into(),
+            international_names: [("fr".to_string(), format!("{} fr", name))].into(),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        };
+        // the ids are not the positions, like in a cleaned cosmogony
+        let mut cosmogony = Cosmogony {
+            zones: vec![zone(3, "Country", None), zone(7, "City", Some(3))],
+            meta: CosmogonyMetadata::default(),
+        };
+        recompute_labels(&mut cosmogony, &BuildOptions::default()).unwrap();
+        let city = &cosmogony.zones[1];
+        assert_eq!(city.label, "City, Country");
+        assert_eq!(
+            city.international_labels.get("fr").unwrap(),
+            "City fr, Country fr"
+        );
+        assert_eq!(city.id, ZoneIndex { index: 7 });
+        assert_eq!(city.parent, Some(ZoneIndex { index: 3 }));
+
+        let options = BuildOptions {
+            filter_langs: vec!["de".to_string()],
+            ..Default::default()
+        };
+        recompute_labels(&mut cosmogony, &options).unwrap();
+        assert!(cosmogony.zones[1].international_labels.is_empty());
+    }
+
+    #[test]
+    fn recomputed_secondary_labels_are_suffixed_once() {
+        let zone = |index: usize, name: &str, parent: Option<usize>, reference: &str| Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            name: name.into(),
+            international_names: [("fr".to_string(), format!("{} fr", name))].into(),
+            tags: [("ref", reference)].into_iter().collect(),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        };
+        let mut cosmogony = Cosmogony {
+            zones: vec![
+                zone(0, "Country", None, "1"),
+                zone(1, "Saint-Martin", Some(0), "12"),
+                zone(2, "Saint-Martin", Some(0), "34"),
+            ],
+            meta: CosmogonyMetadata::default(),
+        };
+        let options = BuildOptions {
+            secondary_label_lang: Some("fr".to_string()),
+            ..Default::default()
+        };
+        // like a cosmogony built then loaded (twice)
+        recompute_labels(&mut cosmogony, &options).unwrap();
+        let secondary_label = cosmogony.zones[1].secondary_label.clone().unwrap();
+        recompute_labels(&mut cosmogony, &options).unwrap();
+        let saint_martin = &cosmogony.zones[1];
+        assert_eq!(saint_martin.label, "Saint-Martin (12), Country");
+        assert_eq!(saint_martin.secondary_label, Some(secondary_label));
+        assert_eq!(
+            saint_martin
+                .secondary_label
+                .as_ref()
+                .unwrap()
+                .matches("(12)")
+                .count(),
+            1
+        );
+
+        // without secondary lang, the secondary labels are removed
+        recompute_labels(&mut cosmogony, &BuildOptions::default()).unwrap();
+        assert_eq!(cosmogony.zones[1].secondary_label, None);
+    }
+
+    #[test]
+    fn only_kept_tags_are_filtered() {
+        let tags = |t: &[(&str, &str)]| t.iter().copied().collect::<cosmogony::ZoneTags>();
+        let mut zones = vec![Zone {
+            tags: tags(&[
+                ("admin_level", "8"),
+                ("name", "Paris"),
+                ("name:fr", "Paris"),
+                ("ref", "75056"),
+                ("wikidata", "Q90"),
+            ]),
+            center_tags: tags(&[("name", "Paris"), ("place", "city")]),
+            ..Default::default()
+        }];
+        filter_tags(&mut zones, &[]);
+        assert_eq!(zones[0].tags.len(), 5);
+
+        filter_tags(
+            &mut zones,
+            &["wikidata".into(), "ref".into(), "name:*".into()],
+        );
+        let keys: Vec<_> = zones[0].tags.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["name:fr", "ref", "wikidata"]);
+        assert!(zones[0].center_tags.is_empty());
+    }
+
+    #[test]
+    fn zones_are_sorted_by_osm_id() {
+        let zone = |id: usize, osm_id: &str, parent: Option<usize>| Zone {
+            id: ZoneIndex { index: id },
+            osm_id: osm_id.into(),
+            parent: parent.map(|index| ZoneIndex { index }),
+            overlaps: vec!["b".into(), "a".into()],
+            ..Default::default()
+        };
+        // the ids do not match the positions, like after the zones cleaning
+        let mut zones = vec![
+            zone(4, "relation:3", Some(7)),
+            zone(7, "relation:1", None),
+            zone(9, "relation:2", Some(4)),
+        ];
+        sort_zones(&mut zones, &mut CosmogonyStats::default());
+
+        let ids = |zones: &[Zone]| {
+            zones
+                .iter()
+                .map(|z| (z.id.index, z.osm_id.clone(), z.parent.map(|p| p.index)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(&zones),
+            vec![
+                (0, "relation:1".into(), None),
+                (1, "relation:2".into(), Some(2)),
+                (2, "relation:3".into(), Some(0)),
+            ]
+        );
+        assert_eq!(zones[0].overlaps, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn each_streamed_zone_is_flushed() {
+        #[derive(Default)]
+        struct Lines {
+            written: Vec<u8>,
+            flushed: Vec<String>,
+        }
+        impl Write for Lines {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                let line = String::from_utf8(std::mem::take(&mut self.written)).unwrap();
+                self.flushed.push(line);
+                Ok(())
+            }
+        }
+        let mut sink = JsonLinesSink(Lines::default());
+        for name in ["a", "b"] {
+            let zone = Zone {
+                name: name.into(),
+                ..Default::default()
+            };
+            sink.write_zone(zone).unwrap();
+        }
+        let names: Vec<String> = sink
+            .0
+            .flushed
+            .iter()
+            .map(|l| {
+                assert!(l.ends_with('\n'));
+                serde_json::from_str::<Zone>(l).unwrap().name
+            })
+            .collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
 }
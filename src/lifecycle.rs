@@ -0,0 +1,104 @@
+// OSM keeps some historic boundaries (the former municipalities merged in a new one, the
+// boundaries before a reform...), tagged with an `end_date` in the past or with their main tags
+// behind a lifecycle prefix (like `was:boundary=administrative` or `disused:admin_level=8`).
+//
+// These zones would compete with the current ones in the hierarchy, so they are skipped by
+// default (before reading their geometries). They can be kept flagged as `historic` instead.
+
+use osmpbfreader::Tags;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The prefixes of the tags of the features that no longer exist
+const LIFECYCLE_PREFIXES: [&str; 4] = ["disused:", "abandoned:", "was:", "removed:"];
+
+/// The tags making an object a zone, only considered historic behind a lifecycle prefix (a
+/// `was:name` is only the former name of a current zone)
+const LIFECYCLE_KEYS: [&str; 3] = ["boundary", "admin_level", "place"];
+
+lazy_static::lazy_static! {
+    static ref TODAY: String = today();
+}
+
+/// The current date, as `YYYY-MM-DD`
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+    // the civil date from the days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// check if a date (`YYYY`, `YYYY-MM` or `YYYY-MM-DD`) is before another one (`YYYY-MM-DD`)
+///
+/// A date with only a year or a month lasts the whole year or month. The dates in another
+/// format (like `~1900` or `1990s`) are never before.
+fn is_before(date: &str, today: &str) -> bool {
+    let is_date = match date.len() {
+        4 | 7 | 10 => date.chars().enumerate().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        }),
+        _ => false,
+    };
+    is_date && date < &today[..date.len()]
+}
+
+fn is_historic_at(tags: &Tags, today: &str) -> bool {
+    let ended = tags.get("end_date").is_some_and(|d| is_before(d, today));
+    ended
+        || tags.keys().any(|k| {
+            LIFECYCLE_PREFIXES
+                .iter()
+                .filter_map(|p| k.strip_prefix(p))
+                .any(|k| LIFECYCLE_KEYS.contains(&k))
+        })
+}
+
+/// check if the osm object is a historic zone: its `end_date` is past, or its main tags have a
+/// lifecycle prefix (`disused:`, `abandoned:`, `was:` or `removed:`)
+pub fn is_historic(tags: &Tags) -> bool {
+    is_historic_at(tags, &TODAY)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter()
+            .map(|(k, v)| (k.to_string().into(), v.to_string().into()))
+            .collect()
+    }
+
+    #[test]
+    fn historic_zones() {
+        let today = "2024-06-15";
+        let is_historic = |t: &[(&str, &str)]| is_historic_at(&tags(t), today);
+        assert!(!is_historic(&[("boundary", "administrative")]));
+        assert!(is_historic(&[("end_date", "2015-12-31")]));
+        assert!(is_historic(&[("end_date", "2024-05")]));
+        // the zones ending this year, or later, or at an unknown date still exist
+        assert!(!is_historic(&[("end_date", "2024")]));
+        assert!(!is_historic(&[("end_date", "2024-06-15")]));
+        assert!(!is_historic(&[("end_date", "2030-01-01")]));
+        assert!(!is_historic(&[("end_date", "1990s")]));
+        assert!(!is_historic(&[("start_date", "1790")]));
+
+        assert!(is_historic(&[("was:boundary", "administrative")]));
+        assert!(is_historic(&[("disused:admin_level", "8")]));
+        assert!(!is_historic(&[
+            ("boundary", "administrative"),
+            ("was:name", "Old")
+        ]));
+        assert!(TODAY.len() == 10 && is_before("2024-06-15", &TODAY));
+    }
+}
@@ -0,0 +1,555 @@
+// Export of the zones' boundaries as Mapbox Vector Tiles in a MBTiles file, to visually check
+// a cosmogony in any vector tiles viewer without going through tippecanoe.
+//
+// The tiles have a single `zones` layer, with a polygon feature by zone (the feature id is the
+// zone id) and the main attributes of the zone. The boundaries are only clipped to the tiles
+// (with a small buffer) and snapped to the tiles grid, they are not simplified.
+// The vector tile encoding is simple enough to be done here, see
+// https://github.com/mapbox/vector-tile-spec/tree/master/2.1 and
+// https://github.com/mapbox/mbtiles-spec/blob/master/1.3/spec.md
+
+use anyhow::{anyhow, Context, Error};
+use cosmogony::Zone;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use geo::prelude::BoundingRect;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// name of the layer of the zones
+const LAYER: &str = "zones";
+/// size of a tile, in tile coordinates
+const EXTENT: u32 = 4096;
+/// the geometries are clipped a bit outside of the tiles, to hide the tiles borders
+const BUFFER: f64 = 64.;
+/// the web mercator projection is not defined beyond this latitude
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// The zoom levels of the generated tiles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileOptions {
+    pub zooms: RangeInclusive<u8>,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        TileOptions { zooms: 0..=8 }
+    }
+}
+
+/// (x, y) coordinates in the web mercator projection, between 0 and 1, y going down
+type WorldPoint = (f64, f64);
+/// a closed ring, without repeating its first point
+type Ring = Vec<WorldPoint>;
+/// a polygon in the coordinates of a tile, as (exterior, interiors) rings
+type TilePolygon = (Vec<(i32, i32)>, Vec<Vec<(i32, i32)>>);
+
+fn project(lon: f64, lat: f64) -> WorldPoint {
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.) / 360.;
+    let y = (1. - (lat.tan() + 1. / lat.cos()).ln() / std::f64::consts::PI) / 2.;
+    (x.clamp(0., 1.), y.clamp(0., 1.))
+}
+
+/// the polygons of a zone boundary, as (exterior, interiors) rings
+fn projected_polygons(zone: &Zone) -> Vec<(Ring, Vec<Ring>)> {
+    let ring = |line: &geo_types::LineString<f64>| -> Ring {
+        let mut points: Ring = line.0.iter().map(|c| project(c.x, c.y)).collect();
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        points
+    };
+    zone.boundary
+        .iter()
+        .flat_map(|b| b.0.iter())
+        .map(|p| (ring(p.exterior()), p.interiors().iter().map(ring).collect()))
+        .collect()
+}
+
+/// Sutherland-Hodgman clipping of a ring on one side of an axis-aligned line
+fn clip_side(ring: &[WorldPoint], axis: usize, limit: f64, keep_above: bool) -> Ring {
+    let coord = |p: &WorldPoint| if axis == 0 { p.0 } else { p.1 };
+    let inside = |p: &WorldPoint| (coord(p) >= limit) == keep_above;
+    let intersection = |a: &WorldPoint, b: &WorldPoint| {
+        let t = (limit - coord(a)) / (coord(b) - coord(a));
+        (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1))
+    };
+    let mut clipped = Vec::with_capacity(ring.len());
+    for (i, current) in ring.iter().enumerate() {
+        let previous = &ring[(i + ring.len() - 1) % ring.len()];
+        match (inside(previous), inside(current)) {
+            (true, true) => clipped.push(*current),
+            (true, false) => clipped.push(intersection(previous, current)),
+            (false, true) => {
+                clipped.push(intersection(previous, current));
+                clipped.push(*current);
+            }
+            (false, false) => {}
+        }
+    }
+    clipped
+}
+
+fn clip(ring: &[WorldPoint], axis: usize, min: f64, max: f64) -> Ring {
+    clip_side(&clip_side(ring, axis, min, true), axis, max, false)
+}
+
+fn clip_polygons(
+    polygons: &[(Ring, Vec<Ring>)],
+    axis: usize,
+    min: f64,
+    max: f64,
+) -> Vec<(Ring, Vec<Ring>)> {
+    polygons
+        .iter()
+        .map(|(exterior, interiors)| {
+            let interiors = interiors
+                .iter()
+                .map(|r| clip(r, axis, min, max))
+                .filter(|r| r.len() >= 3)
+                .collect();
+            (clip(exterior, axis, min, max), interiors)
+        })
+        .filter(|(exterior, _)| exterior.len() >= 3)
+        .collect()
+}
+
+/// a ring in the coordinates of a tile, without consecutive duplicated points
+fn tile_ring(ring: &[WorldPoint], scale: f64, origin: (f64, f64)) -> Vec<(i32, i32)> {
+    let mut points: Vec<(i32, i32)> = ring
+        .iter()
+        .map(|p| {
+            (
+                (p.0 * scale - origin.0).round() as i32,
+                (p.1 * scale - origin.1).round() as i32,
+            )
+        })
+        .collect();
+    points.dedup();
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    points
+}
+
+/// twice the area of the ring with the surveyor's formula, positive for the exterior rings
+/// of the vector tiles (clockwise, as the y axis goes down)
+fn signed_area(ring: &[(i32, i32)]) -> i64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a.0 as i64 * b.1 as i64 - b.0 as i64 * a.1 as i64
+        })
+        .sum()
+}
+
+fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    (id & 0x7) | ((count as u32) << 3)
+}
+
+/// The geometry commands of the polygons of a tile (empty if all the rings are degenerated)
+fn encode_polygons(polygons: &[TilePolygon]) -> Vec<u32> {
+    const MOVE_TO: u32 = 1;
+    const LINE_TO: u32 = 2;
+    const CLOSE_PATH: u32 = 7;
+    let mut geometry = vec![];
+    let mut cursor = (0, 0);
+    let mut encode_ring = |ring: &[(i32, i32)], is_exterior: bool| {
+        let area = signed_area(ring);
+        if ring.len() < 3 || area == 0 {
+            return false;
+        }
+        let mut ring = ring.to_vec();
+        if (area > 0) != is_exterior {
+            ring.reverse();
+        }
+        for (i, p) in ring.iter().enumerate() {
+            if i == 0 {
+                geometry.push(command(MOVE_TO, 1));
+            } else if i == 1 {
+                geometry.push(command(LINE_TO, ring.len() - 1));
+            }
+            geometry.push(zigzag(p.0 - cursor.0));
+            geometry.push(zigzag(p.1 - cursor.1));
+            cursor = *p;
+        }
+        geometry.push(command(CLOSE_PATH, 1));
+        true
+    };
+    for (exterior, interiors) in polygons {
+        if encode_ring(exterior, true) {
+            for interior in interiors {
+                encode_ring(interior, false);
+            }
+        }
+    }
+    geometry
+}
+
+/// The zone's geometry, clipped to all the tiles of a zoom level it intersects
+fn tile_geometries(
+    polygons: &[(Ring, Vec<Ring>)],
+    bbox: geo_types::Rect<f64>,
+    zoom: u8,
+) -> Vec<((u32, u32), Vec<u32>)> {
+    let nb_tiles = 1u32 << zoom;
+    let scale = nb_tiles as f64 * EXTENT as f64;
+    let buffer = BUFFER / scale;
+    // the bbox is in lon/lat, the y axis is reversed in the projection
+    let (min_x, min_y) = project(bbox.min().x, bbox.max().y);
+    let (max_x, max_y) = project(bbox.max().x, bbox.min().y);
+    let tile_range = |min: f64, max: f64| {
+        let to_tile = |v: f64| ((v * nb_tiles as f64).floor().max(0.) as u32).min(nb_tiles - 1);
+        to_tile(min - buffer)..=to_tile(max + buffer)
+    };
+    let tile_min = |t: u32| t as f64 / nb_tiles as f64 - buffer;
+    let tile_max = |t: u32| (t + 1) as f64 / nb_tiles as f64 + buffer;
+
+    let mut geometries = vec![];
+    for x in tile_range(min_x, max_x) {
+        let column = clip_polygons(polygons, 0, tile_min(x), tile_max(x));
+        if column.is_empty() {
+            continue;
+        }
+        for y in tile_range(min_y, max_y) {
+            let origin = (x as f64 * EXTENT as f64, y as f64 * EXTENT as f64);
+            let tile_polygons: Vec<_> = clip_polygons(&column, 1, tile_min(y), tile_max(y))
+                .iter()
+                .map(|(exterior, interiors)| {
+                    (
+                        tile_ring(exterior, scale, origin),
+                        interiors
+                            .iter()
+                            .map(|r| tile_ring(r, scale, origin))
+                            .collect(),
+                    )
+                })
+                .collect();
+            let geometry = encode_polygons(&tile_polygons);
+            if !geometry.is_empty() {
+                geometries.push(((x, y), geometry));
+            }
+        }
+    }
+    geometries
+}
+
+/// minimal protobuf writer, with only what is needed to write the vector tiles
+#[derive(Default)]
+struct ProtobufWriter(Vec<u8>);
+
+impl ProtobufWriter {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn uint(&mut self, field: u32, v: u64) {
+        self.varint((field << 3) as u64);
+        self.varint(v);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.varint(((field << 3) | 2) as u64);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u32]) {
+        let mut packed = ProtobufWriter::default();
+        values.iter().for_each(|v| packed.varint(*v as u64));
+        self.bytes(field, &packed.0);
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Value {
+    String(String),
+    Uint(u64),
+}
+
+/// the attributes of the zones' features
+fn properties(zone: &Zone) -> Vec<(&'static str, Value)> {
+    let mut properties = vec![
+        ("osm_id", Value::String(zone.osm_id.clone())),
+        ("name", Value::String(zone.name.clone())),
+        ("label", Value::String(zone.label.clone())),
+    ];
+    if let Some(zone_type) = zone.zone_type {
+        properties.push(("zone_type", Value::String(zone_type.as_str().into())));
+    }
+    if let Some(admin_level) = zone.admin_level {
+        properties.push(("admin_level", Value::Uint(admin_level.into())));
+    }
+    if let Some(ref country_code) = zone.country_code {
+        properties.push(("country_code", Value::String(country_code.clone())));
+    }
+    properties
+}
+
+struct Feature<'a> {
+    zone: &'a Zone,
+    geometry: Vec<u32>,
+}
+
+/// The protobuf of a vector tile with the zones' features
+fn encode_tile(features: &[Feature<'_>]) -> Vec<u8> {
+    const POLYGON: u64 = 3;
+    let mut keys: Vec<&str> = vec![];
+    let mut values: Vec<Value> = vec![];
+    let mut values_index: HashMap<Value, u32> = HashMap::new();
+    let mut layer = ProtobufWriter::default();
+    layer.uint(15, 2); // version
+    layer.bytes(1, LAYER.as_bytes());
+    for feature in features {
+        let mut tags = vec![];
+        for (key, value) in properties(feature.zone) {
+            let key_idx = keys.iter().position(|k| *k == key).unwrap_or_else(|| {
+                keys.push(key);
+                keys.len() - 1
+            });
+            let value_idx = *values_index.entry(value.clone()).or_insert_with(|| {
+                values.push(value);
+                values.len() as u32 - 1
+            });
+            tags.push(key_idx as u32);
+            tags.push(value_idx);
+        }
+        let mut f = ProtobufWriter::default();
+        f.uint(1, feature.zone.id.index as u64);
+        f.packed(2, &tags);
+        f.uint(3, POLYGON);
+        f.packed(4, &feature.geometry);
+        layer.bytes(2, &f.0);
+    }
+    for key in keys {
+        layer.bytes(3, key.as_bytes());
+    }
+    for value in values {
+        let mut v = ProtobufWriter::default();
+        match value {
+            Value::String(s) => v.bytes(1, s.as_bytes()),
+            Value::Uint(u) => v.uint(5, u),
+        }
+        layer.bytes(4, &v.0);
+    }
+    layer.uint(5, EXTENT as u64);
+
+    let mut tile = ProtobufWriter::default();
+    tile.bytes(3, &layer.0);
+    tile.0
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn metadata(zones: &[Zone], options: &TileOptions) -> Vec<(&'static str, String)> {
+    let bounds = zones
+        .iter()
+        .filter_map(|z| z.bbox)
+        .reduce(|a, b| {
+            geo_types::Rect::new(
+                (a.min().x.min(b.min().x), a.min().y.min(b.min().y)),
+                (a.max().x.max(b.max().x), a.max().y.max(b.max().y)),
+            )
+        })
+        .map_or("-180,-85,180,85".to_string(), |b| {
+            format!("{},{},{},{}", b.min().x, b.min().y, b.max().x, b.max().y)
+        });
+    let fields: BTreeMap<&str, &str> = [
+        ("osm_id", "String"),
+        ("name", "String"),
+        ("label", "String"),
+        ("zone_type", "String"),
+        ("admin_level", "Number"),
+        ("country_code", "String"),
+    ]
+    .into_iter()
+    .collect();
+    let json = serde_json::json!({
+        "vector_layers": [{
+            "id": LAYER,
+            "fields": fields,
+            "minzoom": options.zooms.start(),
+            "maxzoom": options.zooms.end(),
+        }]
+    });
+    vec![
+        ("name", "cosmogony".to_string()),
+        ("format", "pbf".to_string()),
+        ("type", "overlay".to_string()),
+        ("minzoom", options.zooms.start().to_string()),
+        ("maxzoom", options.zooms.end().to_string()),
+        ("bounds", bounds),
+        ("json", json.to_string()),
+    ]
+}
+
+/// Write the zones' boundaries as vector tiles in a MBTiles file
+///
+/// The file is overwritten if it exists. Returns the number of tiles written.
+pub fn write_zones_as_mbtiles(
+    zones: &[Zone],
+    path: &Path,
+    options: &TileOptions,
+) -> Result<usize, Error> {
+    if *options.zooms.end() > 20 {
+        return Err(anyhow!("the max zoom of the tiles cannot exceed 20"));
+    }
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("impossible to remove {}", path.display()))?;
+    }
+    let mut db = rusqlite::Connection::open(path)
+        .with_context(|| format!("impossible to create {}", path.display()))?;
+    db.execute_batch(
+        "CREATE TABLE metadata (name TEXT, value TEXT);
+         CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+         CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);",
+    )?;
+    let transaction = db.transaction()?;
+    for (name, value) in metadata(zones, options) {
+        transaction.execute(
+            "INSERT INTO metadata (name, value) VALUES (?1, ?2)",
+            (name, value),
+        )?;
+    }
+
+    let zones_polygons: Vec<_> = zones
+        .par_iter()
+        .filter_map(|z| {
+            let bbox = z.boundary.as_ref()?.bounding_rect()?;
+            Some((z, bbox, projected_polygons(z)))
+        })
+        .collect();
+    let mut nb_tiles = 0;
+    for zoom in options.zooms.clone() {
+        let mut tiles = BTreeMap::<(u32, u32), Vec<Feature<'_>>>::new();
+        let zones_tiles: Vec<_> = zones_polygons
+            .par_iter()
+            .map(|(z, bbox, polygons)| (*z, tile_geometries(polygons, *bbox, zoom)))
+            .collect();
+        for (zone, geometries) in zones_tiles {
+            for (tile, geometry) in geometries {
+                tiles
+                    .entry(tile)
+                    .or_default()
+                    .push(Feature { zone, geometry });
+            }
+        }
+        // the biggest zones are drawn first
+        tiles
+            .values_mut()
+            .for_each(|features| features.sort_by_key(|f| f.zone.admin_level.unwrap_or(u32::MAX)));
+        let encoded: Vec<_> = tiles
+            .par_iter()
+            .map(|(tile, features)| Ok((*tile, gzip(&encode_tile(features))?)))
+            .collect::<Result<_, Error>>()?;
+
+        let mut insert = transaction.prepare(
+            "INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for ((x, y), data) in &encoded {
+            // the rows of the mbtiles follow the TMS scheme, from the south
+            let row = (1u32 << zoom) - 1 - y;
+            insert.execute((zoom, x, row, data))?;
+        }
+        info!("{} tiles written for zoom {}", encoded.len(), zoom);
+        nb_tiles += encoded.len();
+    }
+    transaction.commit()?;
+    Ok(nb_tiles)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+    use cosmogony::ZoneType;
+
+    fn square_zone(min: (f64, f64), max: (f64, f64)) -> Zone {
+        Zone {
+            zone_type: Some(ZoneType::City),
+            name: "square".into(),
+            ..test_utils::square_zone(min, max)
+        }
+    }
+
+    #[test]
+    fn polygon_commands() {
+        // counter clockwise in the tile coordinates: the ring is reversed to be an exterior
+        let square = vec![(0, 0), (0, 10), (10, 10), (10, 0)];
+        let geometry = encode_polygons(&[(square, vec![])]);
+        assert_eq!(
+            geometry,
+            vec![9, 20, 0, 26, 0, 20, 19, 0, 0, 19, 15],
+            "MoveTo(10,0) LineTo(10,10) (0,10) (0,0) ClosePath"
+        );
+        // a degenerated ring gives no geometry
+        assert!(encode_polygons(&[(vec![(0, 0), (5, 5), (10, 10)], vec![])]).is_empty());
+    }
+
+    #[test]
+    fn zone_tiles() {
+        // a small zone in Luxembourg, in one tile at each zoom
+        let zone = square_zone((6.1, 49.6), (6.2, 49.7));
+        let polygons = projected_polygons(&zone);
+        let tiles = tile_geometries(&polygons, zone.bbox.unwrap(), 0);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].0, (0, 0));
+        let tiles = tile_geometries(&polygons, zone.bbox.unwrap(), 8);
+        assert_eq!(
+            tiles.iter().map(|t| t.0).collect::<Vec<_>>(),
+            vec![(132, 87)]
+        );
+
+        // a zone around the greenwich meridian and the equator, in 4 tiles at zoom 1
+        let zone = square_zone((-1., -1.), (1., 1.));
+        let tiles = tile_geometries(&projected_polygons(&zone), zone.bbox.unwrap(), 1);
+        assert_eq!(
+            tiles.iter().map(|t| t.0).collect::<Vec<_>>(),
+            vec![(0, 0), (0, 1), (1, 0), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn mbtiles_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cosmogony.mbtiles");
+        let zones = vec![square_zone((6.1, 49.6), (6.2, 49.7))];
+        let options = TileOptions { zooms: 0..=2 };
+        assert_eq!(write_zones_as_mbtiles(&zones, &path, &options).unwrap(), 3);
+
+        let db = rusqlite::Connection::open(&path).unwrap();
+        let rows: Vec<(u8, u32, u32)> = db
+            .prepare("SELECT zoom_level, tile_column, tile_row FROM tiles ORDER BY zoom_level")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows, vec![(0, 0, 0), (1, 1, 1), (2, 2, 2)]);
+        let maxzoom: String = db
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'maxzoom'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(maxzoom, "2");
+    }
+}
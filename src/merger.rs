@@ -1,9 +1,23 @@
-use anyhow::Result;
-use cosmogony::{file_format::OutputFormat, read_zones_from_file, Zone, ZoneIndex};
+use crate::additional_zones::convert_to_geo;
+use crate::winding_order::{orient_boundary, winding_order_of};
+use crate::zone_ext::ZoneExt;
+use anyhow::{bail, Result};
+use cosmogony::{
+    file_format::OutputFormat, read_zones_from_file, Cosmogony, CosmogonyStats, Zone, ZoneIndex,
+    ZoneType,
+};
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use geo::prelude::{Area, BoundingRect};
+use geo_types::MultiPolygon;
+use geos::{Geom, Geometry};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::path::{Path, PathBuf};
 
+/// relative area difference above which 2 versions of a zone are considered in conflict
+const AREA_TOLERANCE: f64 = 1e-6;
+
 #[derive(Default)]
 struct CosmogonyMerger {
     id_offset: usize,
@@ -41,6 +55,17 @@ impl CosmogonyMerger {
                 z.id = self.get_updated_id(z.id);
                 max_id = std::cmp::max(max_id, z.id.index);
                 z.parent = z.parent.map(|p| self.get_updated_id(p));
+                z.parents = z.parents.iter().map(|p| self.get_updated_id(*p)).collect();
+                z.included_in = z
+                    .included_in
+                    .iter()
+                    .map(|p| self.get_updated_id(*p))
+                    .collect();
+                z.neighbors = z
+                    .neighbors
+                    .iter()
+                    .map(|n| self.get_updated_id(*n))
+                    .collect();
                 z
             });
         to_json_stream(writer, zones)?;
@@ -49,6 +74,34 @@ impl CosmogonyMerger {
         Ok(())
     }
 
+    /// read all the zones of the files, with non overlapping ids
+    fn read_all_zones(&mut self, files: &[PathBuf]) -> Result<Vec<Zone>> {
+        let mut all_zones = vec![];
+        for f in files {
+            let mut max_id = 0;
+            for z in read_zones_from_file(f)?.filter_map(|z| z.ok()) {
+                let mut z = z;
+                z.id = self.get_updated_id(z.id);
+                max_id = std::cmp::max(max_id, z.id.index);
+                z.parent = z.parent.map(|p| self.get_updated_id(p));
+                z.parents = z.parents.iter().map(|p| self.get_updated_id(*p)).collect();
+                z.included_in = z
+                    .included_in
+                    .iter()
+                    .map(|p| self.get_updated_id(*p))
+                    .collect();
+                z.neighbors = z
+                    .neighbors
+                    .iter()
+                    .map(|n| self.get_updated_id(*n))
+                    .collect();
+                all_zones.push(z);
+            }
+            self.id_offset = max_id + 1;
+        }
+        Ok(all_zones)
+    }
+
     fn get_updated_id(&self, idx: ZoneIndex) -> ZoneIndex {
         ZoneIndex {
             index: idx.index + self.id_offset,
@@ -56,23 +109,680 @@ impl CosmogonyMerger {
     }
 }
 
-pub fn merge_cosmogony(files: &[PathBuf], output: &Path) -> Result<()> {
-    let mut merger = CosmogonyMerger::default();
+/// Statistics of the merge of overlapping cosmogonies
+#[derive(Debug, Default)]
+pub struct MergeStats {
+    pub nb_zones: usize,
+    /// number of zones present in several cosmogonies
+    pub duplicated_zones: usize,
+    /// osm_id of the duplicated zones whose versions differ (geometry or type)
+    pub conflicts: Vec<String>,
+}
+
+impl fmt::Display for MergeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Zones: {}", self.nb_zones)?;
+        writeln!(f, "Duplicated zones: {}", self.duplicated_zones)?;
+        writeln!(f, "Conflicting zones: {}", self.conflicts.len())?;
+        for osm_id in &self.conflicts {
+            writeln!(f, "    {}", osm_id)?;
+        }
+        Ok(())
+    }
+}
+
+fn area(zone: &Zone) -> f64 {
+    zone.boundary.as_ref().map_or(0., |b| b.unsigned_area())
+}
+
+/// Keep only one version of the zones present several times (matched by osm_id).
+///
+/// As the extracts are cut on their borders, the largest geometry is kept
+/// (or the newest one, i.e. the one of the last cosmogony, if they have the same size).
+/// The parents referencing a discarded zone are re-linked to the kept one, and the zones are
+/// renumbered.
+fn dedup_zones(zones: Vec<Zone>) -> (Vec<Zone>, MergeStats) {
+    let mut stats = MergeStats::default();
+    let mut kept: Vec<Zone> = Vec::with_capacity(zones.len());
+    let mut position_by_osm_id: BTreeMap<String, usize> = BTreeMap::new();
+    // the id of the discarded zones, with the id of the zone replacing them
+    let mut replaced_ids: BTreeMap<ZoneIndex, ZoneIndex> = BTreeMap::new();
+
+    for zone in zones {
+        let pos = match position_by_osm_id.get(&zone.osm_id) {
+            None => {
+                position_by_osm_id.insert(zone.osm_id.clone(), kept.len());
+                kept.push(zone);
+                continue;
+            }
+            Some(pos) => *pos,
+        };
+        stats.duplicated_zones += 1;
+        let existing = &mut kept[pos];
+        let (existing_area, new_area) = (area(existing), area(&zone));
+        let same_size =
+            (existing_area - new_area).abs() <= AREA_TOLERANCE * existing_area.max(new_area);
+        if !same_size || existing.zone_type != zone.zone_type {
+            stats.conflicts.push(zone.osm_id.clone());
+        }
+        let (mut winner, loser) = if new_area >= existing_area || same_size {
+            (zone, existing.clone())
+        } else {
+            (existing.clone(), zone)
+        };
+        if winner.parent.is_none() {
+            winner.parent = loser.parent;
+            winner.parents = loser.parents;
+        }
+        replaced_ids.insert(loser.id, winner.id);
+        *existing = winner;
+    }
+
+    let final_id = |mut id: ZoneIndex| {
+        while let Some(new_id) = replaced_ids.get(&id) {
+            id = *new_id;
+        }
+        id
+    };
+    for z in kept.iter_mut() {
+        z.parent = z.parent.map(final_id);
+        z.parents = z.parents.iter().map(|p| final_id(*p)).collect();
+        z.parents.dedup();
+        z.included_in = z.included_in.iter().map(|p| final_id(*p)).collect();
+        z.included_in.dedup();
+        z.neighbors = z.neighbors.iter().map(|n| final_id(*n)).collect();
+        z.neighbors.sort();
+        z.neighbors.dedup();
+    }
+    // the discarded zones leave some gaps in the ids
+    crate::renumber_zones(&mut kept);
+    stats.conflicts.sort();
+    stats.conflicts.dedup();
+    stats.nb_zones = kept.len();
+    (kept, stats)
+}
 
+fn create_writer(output: &Path) -> Result<Box<dyn std::io::Write>> {
     let format = OutputFormat::from_filename(output)?;
     let file = std::fs::File::create(output)?;
-    let mut stream = std::io::BufWriter::new(file);
+    let stream = std::io::BufWriter::new(file);
     match format {
-        OutputFormat::JsonGz | OutputFormat::Json => panic!(
-            "cannot write real cosmogonies, only jsonl/jsonl.gz to be able to stream the files"
+        OutputFormat::JsonGz
+        | OutputFormat::JsonZst
+        | OutputFormat::Json
+        | OutputFormat::Shapefile
+        | OutputFormat::Csv
+        | OutputFormat::Tsv
+        | OutputFormat::GeoPackage
+        | OutputFormat::Sqlite
+        | OutputFormat::TopoJson
+        | OutputFormat::GeoParquet
+        | OutputFormat::Tree
+        | OutputFormat::TreeStream => panic!(
+            "cannot write real cosmogonies, only jsonl/jsonl.gz/jsonl.zst to be able to stream the files"
         ),
-        OutputFormat::JsonStream => {
-            merger.merge_cosmogony(files, &mut stream)?;
+        OutputFormat::JsonStream => Ok(Box::new(stream)),
+        OutputFormat::JsonStreamGz => Ok(Box::new(GzEncoder::new(stream, Compression::default()))),
+        OutputFormat::JsonStreamZst => Ok(Box::new(
+            zstd::Encoder::new(stream, zstd::DEFAULT_COMPRESSION_LEVEL)?.auto_finish(),
+        )),
+    }
+}
+
+pub fn merge_cosmogony(files: &[PathBuf], output: &Path) -> Result<()> {
+    let mut merger = CosmogonyMerger::default();
+    let writer = create_writer(output)?;
+    merger.merge_cosmogony(files, writer)
+}
+
+/// Merge cosmogonies built from overlapping extracts
+///
+/// Unlike `merge_cosmogony`, all the zones are loaded in memory, to remove the duplicated zones.
+pub fn merge_overlapping_cosmogonies(files: &[PathBuf], output: &Path) -> Result<MergeStats> {
+    let mut merger = CosmogonyMerger::default();
+    let zones = merger.read_all_zones(files)?;
+    let (zones, stats) = dedup_zones(zones);
+    let writer = create_writer(output)?;
+    to_json_stream(writer, zones.into_iter())?;
+    Ok(stats)
+}
+
+/// Statistics of the append of a country build into an existing build
+#[derive(Debug, Default)]
+pub struct AppendStats {
+    /// the replaced countries
+    pub country_codes: Vec<String>,
+    pub removed_zones: usize,
+    pub added_zones: usize,
+    /// number of zones whose parent has been re-linked to a zone of the other build
+    pub relinked_zones: usize,
+    /// osm_id of the zones whose parent has been removed, without replacement in the new build
+    pub orphaned_zones: Vec<String>,
+}
+
+impl fmt::Display for AppendStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Replaced countries: {}", self.country_codes.join(", "))?;
+        writeln!(f, "Removed zones: {}", self.removed_zones)?;
+        writeln!(f, "Added zones: {}", self.added_zones)?;
+        writeln!(f, "Re-linked zones: {}", self.relinked_zones)?;
+        writeln!(f, "Orphaned zones: {}", self.orphaned_zones.len())?;
+        for osm_id in &self.orphaned_zones {
+            writeln!(f, "    {}", osm_id)?;
         }
-        OutputFormat::JsonStreamGz => {
-            let mut e = GzEncoder::new(stream, Compression::default());
-            merger.merge_cosmogony(files, &mut e)?;
+        Ok(())
+    }
+}
+
+/// the zones counts of the stats, computed again after the zones changed
+pub(crate) fn recount_zones(stats: &mut CosmogonyStats, zones: &[Zone]) {
+    stats.level_counts.clear();
+    stats.zone_type_counts.clear();
+    stats.wikidata_counts.clear();
+    stats.country_zone_counts.clear();
+    stats.country_stats.clear();
+    stats.disputed_zones = 0;
+    stats.compute(zones);
+}
+
+/// Replace the zones of some countries of an existing build (eg. a planet) by the zones of a
+/// new build of these countries
+///
+/// The replaced countries are the countries of the new build (its zones of type country), so
+/// the new build should be made from an extract of these countries. The other zones of the new
+/// build (the zones of the neighbours at the edges of the extract) are discarded, as the
+/// existing build already has them, complete.
+///
+/// The parents links crossing the borders are repaired: the zones of the existing build whose
+/// parent has been removed are attached to the new version of their parent (matched by
+/// osm_id), and the new zones whose parent is a neighbour's zone are attached to the existing
+/// version of this zone.
+pub fn append_country(existing: &mut Cosmogony, new: Cosmogony) -> Result<AppendStats> {
+    let country_codes: BTreeSet<String> = new
+        .zones
+        .iter()
+        .filter(|z| z.zone_type == Some(ZoneType::Country))
+        .filter_map(|z| z.country_code.clone())
+        .collect();
+    if country_codes.is_empty() {
+        bail!("the new cosmogony has no country, no zone can be replaced");
+    }
+    let is_replaced = |z: &Zone| {
+        z.country_code
+            .as_ref()
+            .is_some_and(|c| country_codes.contains(c))
+    };
+
+    let (removed, kept): (Vec<Zone>, Vec<Zone>) = std::mem::take(&mut existing.zones)
+        .into_iter()
+        .partition(is_replaced);
+    // the new zones are given ids after the ones of the existing build
+    let merger = CosmogonyMerger {
+        id_offset: kept
+            .iter()
+            .chain(&removed)
+            .map(|z| z.id.index + 1)
+            .max()
+            .unwrap_or(0),
+    };
+
+    let kept_ids: BTreeMap<&str, ZoneIndex> =
+        kept.iter().map(|z| (z.osm_id.as_str(), z.id)).collect();
+    let added_ids: BTreeMap<&str, ZoneIndex> = new
+        .zones
+        .iter()
+        .filter(|z| is_replaced(z))
+        .map(|z| (z.osm_id.as_str(), merger.get_updated_id(z.id)))
+        .collect();
+    // the ids of the new build in the result (the neighbours are their existing version)
+    let new_ids: BTreeMap<ZoneIndex, ZoneIndex> = new
+        .zones
+        .iter()
+        .filter_map(|z| {
+            let id = match added_ids.get(z.osm_id.as_str()) {
+                Some(id) if is_replaced(z) => *id,
+                _ => *kept_ids.get(z.osm_id.as_str())?,
+            };
+            Some((z.id, id))
+        })
+        .collect();
+    // the ids of the removed zones, with the id of their new version
+    let removed_ids: BTreeMap<ZoneIndex, Option<ZoneIndex>> = removed
+        .iter()
+        .map(|z| (z.id, added_ids.get(z.osm_id.as_str()).copied()))
+        .collect();
+
+    let mut stats = AppendStats {
+        country_codes: country_codes.iter().cloned().collect(),
+        removed_zones: removed.len(),
+        ..Default::default()
+    };
+    let mut zones = Vec::with_capacity(kept.len() + added_ids.len());
+    for mut zone in kept {
+        let remap = |id: &ZoneIndex| match removed_ids.get(id) {
+            Some(new_id) => *new_id,
+            None => Some(*id),
+        };
+        if let Some(parent) = zone.parent {
+            zone.parent = remap(&parent);
+            match zone.parent {
+                Some(p) if p != parent => stats.relinked_zones += 1,
+                None => stats.orphaned_zones.push(zone.osm_id.clone()),
+                _ => {}
+            }
         }
+        zone.parents = zone.parents.iter().filter_map(remap).collect();
+        zone.included_in = zone.included_in.iter().filter_map(remap).collect();
+        zone.neighbors = zone.neighbors.iter().filter_map(remap).collect();
+        zones.push(zone);
+    }
+    for mut zone in new.zones.into_iter().filter(|z| is_replaced(z)) {
+        zone.id = merger.get_updated_id(zone.id);
+        if let Some(parent) = zone.parent {
+            zone.parent = new_ids.get(&parent).copied();
+            match zone.parent {
+                Some(p) if p != merger.get_updated_id(parent) => stats.relinked_zones += 1,
+                None => stats.orphaned_zones.push(zone.osm_id.clone()),
+                _ => {}
+            }
+        }
+        zone.parents = zone
+            .parents
+            .iter()
+            .filter_map(|p| new_ids.get(p))
+            .copied()
+            .collect();
+        zone.included_in = (zone.included_in.iter())
+            .filter_map(|p| new_ids.get(p))
+            .copied()
+            .collect();
+        zone.neighbors = (zone.neighbors.iter())
+            .filter_map(|n| new_ids.get(n))
+            .copied()
+            .collect();
+        stats.added_zones += 1;
+        zones.push(zone);
+    }
+    stats.orphaned_zones.sort();
+
+    recount_zones(&mut existing.meta.stats, &zones);
+    existing.zones = zones;
+    Ok(stats)
+}
+
+/// Statistics of the fusion of the zones of the same admin entity
+#[derive(Debug, Default)]
+pub struct FuseStats {
+    /// the osm_id of the canonical zones, with the osm_id of the zones fused into them
+    pub fused_zones: BTreeMap<String, Vec<String>>,
+}
+
+impl fmt::Display for FuseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Fused zones: {}", self.fused_zones.len())?;
+        for (osm_id, fused) in &self.fused_zones {
+            writeln!(f, "    {} <- {}", osm_id, fused.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The key of the zones of the same admin entity: their country, type and wikidata id (or
+/// `ref` tag and parent, if they have no wikidata)
+///
+/// The `ref` tags are only unique in their parent (eg. the numbers of the cantons of a
+/// department), so the zones without parent are not fused by their `ref`.
+fn fusion_key(zone: &Zone) -> Option<(&str, ZoneType, String)> {
+    let entity = match (&zone.wikidata, zone.tags.get("ref")) {
+        (Some(wikidata), _) => format!("wikidata:{}", wikidata),
+        (None, Some(r)) => format!("ref:{}:{}", zone.parent?.index, r),
+        (None, None) => return None,
     };
-    Ok(())
+    Some((zone.country_code.as_deref()?, zone.zone_type?, entity))
+}
+
+/// The union of the boundaries of some zones
+fn union_boundaries(zones: &[&Zone]) -> Result<Option<MultiPolygon<f64>>> {
+    let boundaries = zones
+        .iter()
+        .filter_map(|z| z.boundary.as_ref())
+        .map(Geometry::try_from)
+        .collect::<geos::GResult<Vec<_>>>()?;
+    let union = Geometry::create_geometry_collection(boundaries)?.unary_union()?;
+    convert_to_geo(union)
+}
+
+/// Fuse the zones of the same admin entity mapped as several osm objects (eg. a city split by
+/// an exclave), matched by their wikidata id (or `ref` tag in the same parent) in the same
+/// country and of the same type
+///
+/// The largest zone is the canonical one, its boundary becomes the union of the boundaries of
+/// all the fragments, and the other fragments are removed. The zones referencing a removed
+/// fragment (as parent, possible parent or inclusion) reference the canonical zone instead, and
+/// the zones are renumbered.
+pub fn fuse_zones(cosmogony: &mut Cosmogony) -> Result<FuseStats> {
+    let zones = &mut cosmogony.zones;
+    let mut groups = BTreeMap::<_, Vec<usize>>::new();
+    for (idx, zone) in zones.iter().enumerate() {
+        if let Some(key) = fusion_key(zone) {
+            groups.entry(key).or_default().push(idx);
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    for group in groups.iter_mut() {
+        // the largest fragment first, the others keep their order
+        let largest = (0..group.len())
+            .max_by(|a, b| area(&zones[group[*a]]).total_cmp(&area(&zones[group[*b]])))
+            .unwrap_or(0);
+        let canonical = group.remove(largest);
+        group.insert(0, canonical);
+    }
+
+    let mut stats = FuseStats::default();
+    // the id of the removed fragments, with the id of their canonical zone
+    let mut replaced_ids = BTreeMap::<ZoneIndex, ZoneIndex>::new();
+    for group in groups {
+        let fragments: Vec<&Zone> = group.iter().map(|idx| &zones[*idx]).collect();
+        let boundary = match union_boundaries(&fragments) {
+            // the union has the orientation of GEOS, the fused zone keeps the one of the build
+            Ok(boundary) => boundary.map(|b| {
+                let winding_order = fragments[0].boundary.as_ref().and_then(winding_order_of);
+                orient_boundary(&b, winding_order.unwrap_or_default())
+            }),
+            Err(e) => {
+                warn!(
+                    "impossible to fuse the boundaries of {}: {}, the zones are kept apart",
+                    fragments[0].osm_id, e
+                );
+                continue;
+            }
+        };
+        let mut zip_codes: Vec<String> = fragments
+            .iter()
+            .flat_map(|z| z.zip_codes.iter().cloned())
+            .collect();
+        zip_codes.sort();
+        zip_codes.dedup();
+        let population = fragments.iter().find_map(|z| z.population);
+        let neighbors = fragments.iter().flat_map(|z| z.neighbors.clone()).collect();
+        stats.fused_zones.insert(
+            fragments[0].osm_id.clone(),
+            fragments[1..].iter().map(|z| z.osm_id.clone()).collect(),
+        );
+        for fragment in &fragments[1..] {
+            replaced_ids.insert(fragment.id, fragments[0].id);
+        }
+
+        let canonical = &mut zones[group[0]];
+        canonical.bbox = boundary.as_ref().and_then(|b| b.bounding_rect());
+        canonical.boundary = boundary;
+        if canonical.area.is_some() {
+            canonical.compute_measures();
+        }
+        canonical.zip_codes = zip_codes;
+        canonical.population = population;
+        canonical.neighbors = neighbors;
+    }
+
+    zones.retain(|z| !replaced_ids.contains_key(&z.id));
+    let canonical_id = |id: &ZoneIndex| *replaced_ids.get(id).unwrap_or(id);
+    for z in zones.iter_mut() {
+        let id = z.id;
+        z.parent = z.parent.map(|p| canonical_id(&p)).filter(|p| *p != id);
+        z.parents = z.parents.iter().map(canonical_id).collect();
+        z.parents.retain(|p| *p != id);
+        z.parents.dedup();
+        z.included_in = z.included_in.iter().map(canonical_id).collect();
+        z.included_in.retain(|p| *p != id);
+        z.included_in.dedup();
+        z.neighbors = z.neighbors.iter().map(canonical_id).collect();
+        z.neighbors.retain(|n| *n != id);
+        z.neighbors.sort();
+        z.neighbors.dedup();
+    }
+    // the ids of the zones are their positions
+    crate::renumber_zones(zones);
+    if !stats.fused_zones.is_empty() {
+        info!(
+            "{} zones fused into {} zones",
+            replaced_ids.len(),
+            stats.fused_zones.len()
+        );
+    }
+    recount_zones(&mut cosmogony.meta.stats, zones);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+
+    fn zone(id: usize, osm_id: &str, size: f64, parent: Option<usize>) -> Zone {
+        let ring = LineString::from(vec![(0., 0.), (0., size), (size, size), (size, 0.)]);
+        Zone {
+            id: ZoneIndex { index: id },
+            osm_id: osm_id.into(),
+            parent: parent.map(|index| ZoneIndex { index }),
+            boundary: Some(MultiPolygon(vec![Polygon::new(ring, vec![])])),
+            ..Default::default()
+        }
+    }
+
+    fn country_zone(id: usize, osm_id: &str, country: &str, parent: Option<usize>) -> Zone {
+        Zone {
+            country_code: Some(country.into()),
+            zone_type: Some(ZoneType::City),
+            ..zone(id, osm_id, 1., parent)
+        }
+    }
+
+    #[test]
+    fn append_a_country() {
+        let country = |id, osm_id: &str, code: &str| Zone {
+            zone_type: Some(ZoneType::Country),
+            ..country_zone(id, osm_id, code, None)
+        };
+        let mut existing = Cosmogony {
+            zones: vec![
+                country(0, "relation:fr", "FR"),
+                country_zone(1, "relation:old_city", "FR", Some(0)),
+                country(2, "relation:be", "BE"),
+                country_zone(3, "relation:be_city", "BE", Some(2)),
+                // zones of another country, attached to zones of the replaced country
+                country_zone(4, "relation:enclave", "XX", Some(0)),
+                country_zone(5, "relation:orphan", "XX", Some(1)),
+            ],
+            meta: Default::default(),
+        };
+        let new = Cosmogony {
+            zones: vec![
+                country(0, "relation:fr", "FR"),
+                country_zone(1, "relation:new_city", "FR", Some(0)),
+                // a zone of the neighbour, cut by the extract
+                country_zone(2, "relation:be_city", "BE", None),
+                country_zone(3, "relation:border_city", "FR", Some(2)),
+            ],
+            meta: Default::default(),
+        };
+
+        let stats = append_country(&mut existing, new).unwrap();
+        assert_eq!(stats.country_codes, vec!["FR".to_string()]);
+        assert_eq!(stats.removed_zones, 2);
+        assert_eq!(stats.added_zones, 3);
+        assert_eq!(stats.relinked_zones, 2);
+        assert_eq!(stats.orphaned_zones, vec!["relation:orphan".to_string()]);
+
+        let osm_ids: Vec<_> = existing.zones.iter().map(|z| z.osm_id.as_str()).collect();
+        assert_eq!(
+            osm_ids,
+            vec![
+                "relation:be",
+                "relation:be_city",
+                "relation:enclave",
+                "relation:orphan",
+                "relation:fr",
+                "relation:new_city",
+                "relation:border_city",
+            ]
+        );
+        let get = |osm_id: &str| existing.zones.iter().find(|z| z.osm_id == osm_id).unwrap();
+        let fr = get("relation:fr").id;
+        assert_eq!(fr, ZoneIndex { index: 6 });
+        assert_eq!(get("relation:new_city").parent, Some(fr));
+        assert_eq!(get("relation:enclave").parent, Some(fr));
+        assert_eq!(get("relation:orphan").parent, None);
+        assert_eq!(
+            get("relation:border_city").parent,
+            Some(ZoneIndex { index: 3 })
+        );
+        assert_eq!(existing.meta.stats.country_zone_counts["FR"], 3);
+    }
+
+    #[test]
+    fn merge_overlapping_zones() {
+        let mut zones = vec![
+            // first cosmogony: the country is cut by the extract
+            zone(0, "relation:country", 5., None),
+            zone(1, "relation:city_a", 1., Some(0)),
+            zone(2, "relation:border_city", 1., Some(0)),
+            // second cosmogony
+            zone(10, "relation:country", 10., None),
+            zone(11, "relation:city_b", 1., Some(10)),
+            zone(12, "relation:border_city", 1., None),
+        ];
+        zones[5].wikidata = Some("newest".into());
+        let (zones, stats) = dedup_zones(zones);
+
+        assert_eq!(stats.nb_zones, 4);
+        assert_eq!(stats.duplicated_zones, 2);
+        // the border city is the same in both cosmogonies, there is no conflict
+        assert_eq!(stats.conflicts, vec!["relation:country".to_string()]);
+
+        // the zones are renumbered
+        assert!(zones.iter().enumerate().all(|(i, z)| z.id.index == i));
+        let get = |osm_id: &str| zones.iter().find(|z| z.osm_id == osm_id).unwrap();
+        // the largest country is kept, and the cities are attached to it
+        let country = get("relation:country");
+        assert_eq!(area(country), 100.);
+        assert_eq!(get("relation:city_a").parent, Some(country.id));
+        assert_eq!(get("relation:city_b").parent, Some(country.id));
+        // the newest border city is kept, with the parent of the other version
+        assert_eq!(
+            get("relation:border_city").wikidata.as_deref(),
+            Some("newest")
+        );
+        assert_eq!(get("relation:border_city").parent, Some(country.id));
+    }
+
+    #[test]
+    fn fuse_fragments() {
+        let fragment = |id, osm_id: &str, min: f64, max: f64, country: &str| {
+            let ring = LineString::from(vec![(min, 0.), (min, 1.), (max, 1.), (max, 0.)]);
+            let boundary = MultiPolygon(vec![Polygon::new(ring, vec![])]);
+            Zone {
+                wikidata: Some("Q42".into()),
+                zip_codes: vec![format!("{}", id)],
+                bbox: boundary.bounding_rect(),
+                boundary: Some(boundary),
+                ..country_zone(id, osm_id, country, Some(0))
+            }
+        };
+        let mut child = country_zone(4, "relation:child", "FR", Some(2));
+        child.zone_type = Some(ZoneType::Suburb);
+        let mut cosmogony = Cosmogony {
+            zones: vec![
+                country_zone(0, "relation:state", "FR", None),
+                fragment(1, "relation:main", 0., 2., "FR"),
+                fragment(2, "relation:exclave", 2., 3., "FR"),
+                // the same wikidata in another country is another zone
+                fragment(3, "relation:other", 3., 4., "BE"),
+                child,
+            ],
+            ..Default::default()
+        };
+        let stats = fuse_zones(&mut cosmogony).unwrap();
+
+        assert_eq!(
+            stats.fused_zones,
+            BTreeMap::from([(
+                "relation:main".to_string(),
+                vec!["relation:exclave".to_string()]
+            )])
+        );
+        let zones = &cosmogony.zones;
+        let osm_ids: Vec<_> = zones.iter().map(|z| z.osm_id.as_str()).collect();
+        assert_eq!(
+            osm_ids,
+            vec![
+                "relation:state",
+                "relation:main",
+                "relation:other",
+                "relation:child"
+            ]
+        );
+        let main = &zones[1];
+        assert_eq!(area(main), 3.);
+        assert_eq!(main.bbox.unwrap().max().x, 3.);
+        assert_eq!(main.zip_codes, vec!["1", "2"]);
+        // the child of the exclave is attached to the fused zone
+        assert_eq!(zones[3].parent, Some(ZoneIndex { index: 1 }));
+        assert!(zones.iter().enumerate().all(|(i, z)| z.id.index == i));
+        assert_eq!(cosmogony.meta.stats.zone_type_counts.get("City"), Some(&3));
+    }
+
+    #[test]
+    fn fused_boundaries_keep_their_orientation() {
+        use crate::options::WindingOrder;
+        use crate::winding_order::orient_boundaries;
+
+        // counterclockwise boundaries
+        let fragment = |id, min: f64, max: f64| Zone {
+            wikidata: Some("Q42".into()),
+            boundary: Some(crate::test_utils::square((min, 0.), (max, 1.))),
+            ..country_zone(id, &format!("relation:{}", id), "FR", None)
+        };
+        for winding_order in [WindingOrder::Rfc7946, WindingOrder::Clockwise] {
+            let mut zones = vec![fragment(0, 0., 2.), fragment(1, 2., 3.)];
+            orient_boundaries(&mut zones, winding_order);
+            let mut cosmogony = Cosmogony {
+                zones,
+                ..Default::default()
+            };
+            fuse_zones(&mut cosmogony).unwrap();
+            assert_eq!(cosmogony.zones.len(), 1);
+            let boundary = cosmogony.zones[0].boundary.as_ref().unwrap();
+            assert_eq!(winding_order_of(boundary), Some(winding_order));
+        }
+    }
+
+    #[test]
+    fn fuse_fragments_by_ref() {
+        let canton = |id, parent| {
+            let mut zone = country_zone(id, &format!("relation:{}", id), "FR", parent);
+            zone.tags.insert("ref", "12");
+            zone
+        };
+        let mut cosmogony = Cosmogony {
+            zones: vec![
+                country_zone(0, "relation:department_a", "FR", None),
+                country_zone(1, "relation:department_b", "FR", None),
+                canton(2, Some(0)),
+                canton(3, Some(0)),
+                // the same ref in another department is another canton
+                canton(4, Some(1)),
+                // and without parent, the ref does not identify the canton
+                canton(5, None),
+                canton(6, None),
+            ],
+            ..Default::default()
+        };
+        let stats = fuse_zones(&mut cosmogony).unwrap();
+        // the cantons have no boundary, the last one is kept
+        assert_eq!(
+            stats.fused_zones,
+            BTreeMap::from([("relation:3".to_string(), vec!["relation:2".to_string()])])
+        );
+        assert_eq!(cosmogony.zones.len(), 6);
+    }
 }
@@ -0,0 +1,193 @@
+// Export of the build metrics in the Prometheus text format, to monitor the scheduled builds
+// (with the textfile collector of the node exporter, or any OpenMetrics scraper) without
+// parsing their logs.
+//
+// The metrics are gauges describing the last build: the durations of its phases, the number of
+// zones by type and by country, the warnings by kind and the zones with a problem.
+
+use anyhow::{Context, Error};
+use cosmogony::CosmogonyMetadata;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Escape a label value (the backslashes, double quotes and line feeds)
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Write a metric, with a value by label value (or a single value without label)
+fn write_metric<'a>(
+    writer: &mut impl Write,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: impl IntoIterator<Item = (&'a str, f64)>,
+) -> Result<(), Error> {
+    writeln!(writer, "# HELP cosmogony_{} {}", name, help)?;
+    writeln!(writer, "# TYPE cosmogony_{} gauge", name)?;
+    for (label_value, value) in values {
+        if label.is_empty() {
+            writeln!(writer, "cosmogony_{} {}", name, value)?;
+        } else {
+            let label_value = escape(label_value);
+            writeln!(
+                writer,
+                "cosmogony_{}{{{}=\"{}\"}} {}",
+                name, label, label_value, value
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn counts(counts: &BTreeMap<String, u64>) -> impl Iterator<Item = (&str, f64)> {
+    counts.iter().map(|(k, v)| (k.as_str(), *v as f64))
+}
+
+/// Write the metrics of a build, with the durations of its phases recorded in its stats and
+/// the durations of the other phases (eg. `build`, `write`)
+pub fn write_build_metrics(
+    meta: &CosmogonyMetadata,
+    durations: &[(&str, Duration)],
+    mut writer: impl Write,
+) -> Result<(), Error> {
+    let stats = &meta.stats;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    write_metric(
+        &mut writer,
+        "build_timestamp_seconds",
+        "End of the build, in seconds since the unix epoch.",
+        "",
+        [("", now.as_secs() as f64)],
+    )?;
+    write_metric(
+        &mut writer,
+        "build_duration_seconds",
+        "Duration of the phases of the build.",
+        "phase",
+        stats
+            .phase_durations
+            .iter()
+            .map(|d| (d.phase.as_str(), d.seconds))
+            .chain(durations.iter().map(|(phase, d)| (*phase, d.as_secs_f64()))),
+    )?;
+    write_metric(
+        &mut writer,
+        "zones",
+        "Number of zones by zone type.",
+        "zone_type",
+        counts(&stats.zone_type_counts),
+    )?;
+    write_metric(
+        &mut writer,
+        "country_zones",
+        "Number of zones by country.",
+        "country",
+        counts(&stats.country_zone_counts),
+    )?;
+    let mut warnings = BTreeMap::<&str, f64>::new();
+    for warning in &meta.warnings {
+        *warnings.entry(warning.kind.as_str()).or_default() += 1.;
+    }
+    write_metric(
+        &mut writer,
+        "warnings",
+        "Number of build warnings by kind.",
+        "kind",
+        warnings,
+    )?;
+    let problems = [
+        ("without_country", stats.zone_without_country),
+        ("guessed_country", stats.zone_with_guessed_country),
+        ("disputed", stats.disputed_zones),
+        ("repaired_geometry", stats.repaired_geometries),
+        ("rejected_geometry", stats.rejected_geometries),
+        ("without_boundary", stats.zones_without_boundary),
+        ("duplicate", stats.duplicate_zones.len()),
+        ("sibling_overlap", stats.sibling_overlaps.len()),
+        ("name_collision", stats.name_collisions.len()),
+    ];
+    write_metric(
+        &mut writer,
+        "problem_zones",
+        "Number of zones with a problem, by problem.",
+        "problem",
+        problems.iter().map(|(problem, n)| (*problem, *n as f64)),
+    )?;
+    write_metric(
+        &mut writer,
+        "count_deviations",
+        "Number of zones counts deviating from the expected ones.",
+        "",
+        [("", stats.count_deviations.len() as f64)],
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the metrics of a build in a file
+///
+/// The metrics are written in a temporary file renamed at the end, so a collector never reads
+/// a partial file.
+pub fn write_build_metrics_file(
+    meta: &CosmogonyMetadata,
+    durations: &[(&str, Duration)],
+    path: &Path,
+) -> Result<(), Error> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("impossible to create {}", path.display()))?;
+    write_build_metrics(meta, durations, BufWriter::new(file))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("impossible to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::{BuildWarning, BuildWarningKind};
+
+    #[test]
+    fn build_metrics() {
+        let mut meta = CosmogonyMetadata::default();
+        meta.stats.zone_type_counts.insert("City".into(), 12);
+        meta.stats.country_zone_counts.insert("LU".into(), 15);
+        meta.stats.disputed_zones = 2;
+        meta.stats.phase_durations = vec![cosmogony::PhaseDuration {
+            phase: "hierarchy".into(),
+            seconds: 0.25,
+        }];
+        meta.warnings = vec![
+            BuildWarning {
+                kind: BuildWarningKind::UnclosedRings,
+                osm_id: "relation:1".into(),
+                message: "unclosed \"rings\"".into(),
+            };
+            3
+        ];
+        let durations = [("build", Duration::from_millis(1500))];
+        let mut metrics = vec![];
+        write_build_metrics(&meta, &durations, &mut metrics).unwrap();
+        let metrics = String::from_utf8(metrics).unwrap();
+
+        assert!(metrics.contains("# TYPE cosmogony_zones gauge\n"));
+        assert!(metrics.contains("\ncosmogony_zones{zone_type=\"City\"} 12\n"));
+        assert!(metrics.contains("\ncosmogony_country_zones{country=\"LU\"} 15\n"));
+        assert!(metrics.contains("\ncosmogony_build_duration_seconds{phase=\"build\"} 1.5\n"));
+        assert!(metrics.contains("\ncosmogony_build_duration_seconds{phase=\"hierarchy\"} 0.25\n"));
+        assert!(metrics.contains("\ncosmogony_warnings{kind=\"unclosed_rings\"} 3\n"));
+        assert!(metrics.contains("\ncosmogony_problem_zones{problem=\"disputed\"} 2\n"));
+        assert!(metrics.contains("\ncosmogony_count_deviations 0\n"));
+        assert_eq!(escape("a \"b\"\\"), "a \\\"b\\\"\\\\");
+    }
+}
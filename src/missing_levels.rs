@@ -0,0 +1,114 @@
+// Some `boundary=administrative` zones have no admin_level tag (a mapping mistake, or new
+// subdivisions whose level has not been decided yet). They are skipped by default, as their
+// type is given by their admin_level.
+//
+// When they are kept, a zone without admin_level gets the admin_level of the deepest
+// administrative zone containing it + 1, as if it was one of its subdivisions, and is flagged
+// as `level_inferred`. The zones without administrative zone containing them keep no
+// admin_level (and get no type).
+
+use cosmogony::{Zone, ZoneIndex, ZoneType};
+use geo::Area;
+
+fn is_missing_level(zone: &Zone) -> bool {
+    zone.admin_level.is_none()
+        && !zone.is_generated
+        && zone.zone_type != Some(ZoneType::NonAdministrative)
+        && zone.tags.contains("boundary", "administrative")
+}
+
+/// Infer the admin_level of the administrative zones without admin_level, returns the number
+/// of inferred levels
+pub fn infer_missing_levels(zones: &mut [Zone], inclusions: &[Vec<ZoneIndex>]) -> usize {
+    // the largest zones first, so a zone without admin_level containing another one gets its
+    // level first (the zones' areas are only computed with the hierarchy, after this stage)
+    let mut missing: Vec<(usize, f64)> = zones
+        .iter()
+        .filter(|z| is_missing_level(z))
+        .map(|z| {
+            let area = z.boundary.as_ref().map_or(0., |b| b.unsigned_area());
+            (z.id.index, area)
+        })
+        .collect();
+    missing.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    let mut nb_inferred = 0;
+    for (idx, _) in missing {
+        let parent_level = inclusions[idx]
+            .iter()
+            .map(|p| &zones[p.index])
+            .filter(|p| p.zone_type != Some(ZoneType::NonAdministrative))
+            .filter_map(|p| p.admin_level)
+            .max();
+        let zone = &mut zones[idx];
+        match parent_level {
+            Some(level) => {
+                debug!(
+                    "{} ({}) has no admin_level, inferred to {}",
+                    zone.osm_id,
+                    zone.name,
+                    level + 1
+                );
+                zone.admin_level = Some(level + 1);
+                zone.level_inferred = true;
+                nb_inferred += 1;
+            }
+            None => debug!(
+                "{} ({}) has no admin_level and no administrative zone containing it",
+                zone.osm_id, zone.name
+            ),
+        }
+    }
+    info!("{} admin_levels inferred", nb_inferred);
+    nb_inferred
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::square_zone;
+    use cosmogony::ZoneTags;
+
+    /// a zone whose boundary is a square of side `size`
+    fn zone(index: usize, admin_level: Option<u32>, size: f64) -> Zone {
+        let mut tags = ZoneTags::new();
+        tags.insert("boundary", "administrative");
+        Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            admin_level,
+            tags,
+            is_generated: false,
+            ..square_zone((0., 0.), (size, size))
+        }
+    }
+
+    #[test]
+    fn missing_levels_inferred() {
+        let mut zones = vec![
+            zone(0, Some(4), 30.),
+            zone(1, None, 1.),
+            zone(2, None, 10.),
+            zone(3, Some(6), 20.),
+            zone(4, None, 5.),
+        ];
+        zones[4].tags = ZoneTags::new();
+        let id = |index| ZoneIndex { index };
+        let inclusions = vec![
+            vec![],
+            vec![id(0), id(2), id(3)],
+            vec![id(0), id(3)],
+            vec![id(0)],
+            vec![id(0)],
+        ];
+        assert_eq!(infer_missing_levels(&mut zones, &inclusions), 2);
+
+        // the zone 2 is inferred first, the zone 1 is inside it
+        assert_eq!(zones[2].admin_level, Some(7));
+        assert!(zones[2].level_inferred);
+        assert_eq!(zones[1].admin_level, Some(8));
+        assert!(!zones[3].level_inferred);
+        // not a boundary=administrative zone
+        assert_eq!(zones[4].admin_level, None);
+    }
+}
@@ -0,0 +1,257 @@
+// Some siblings have the same name (eg. 2 villages "Saint-Martin" in the same county, or a city
+// and its homonymous district), so the same label, and the autocompletes show indistinguishable
+// duplicates.
+//
+// The labels of those zones get a suffix telling them apart: their `ref` tag, else their type,
+// else their admin_level, the first one distinct for all the zones of the collision. The labels
+// of their descendants, built with their names, are built again with their suffixed names (eg.
+// "Le Bourg, Saint-Martin (12), County"). The collisions are reported in the stats.
+
+use cosmogony::{NameCollision, Zone, ZoneIndex, ZoneType};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The ways to tell apart the zones with the same label, in order of preference
+const SUFFIXES: [fn(&Zone) -> Option<String>; 3] = [
+    |z| z.tags.get("ref").map(|r| r.to_string()),
+    |z| {
+        z.zone_type
+            .as_ref()
+            .map(ZoneType::as_str)
+            .map(str::to_string)
+    },
+    |z| z.admin_level.map(|l| format!("admin_level {}", l)),
+];
+
+/// The suffixes of the zones, if they are all distinct
+fn distinct_suffixes(zones: &[&Zone]) -> Option<Vec<String>> {
+    SUFFIXES.iter().find_map(|suffix| {
+        let suffixes: Vec<String> = zones.iter().map(|z| suffix(z)).collect::<Option<_>>()?;
+        let distinct: BTreeSet<&String> = suffixes.iter().collect();
+        (distinct.len() == suffixes.len()).then_some(suffixes)
+    })
+}
+
+/// Add a suffix to the name at the start of a label
+fn add_suffix(label: &mut String, name: &str, suffix: &str) {
+    if label.starts_with(name) {
+        label.insert_str(name.len(), &format!(" ({})", suffix));
+    }
+}
+
+/// Build again the labels of the descendants of the suffixed zones with `relabel`, the names of
+/// the suffixed zones being suffixed while it runs
+fn relabel_descendants(
+    zones: &mut [Zone],
+    suffixes: &BTreeMap<usize, &str>,
+    relabel: impl FnOnce(&mut [Zone], &BTreeSet<usize>),
+) {
+    let descendants: BTreeSet<usize> = (0..zones.len())
+        .filter(|idx| {
+            std::iter::successors(zones[*idx].parent, |p| zones[p.index].parent)
+                .any(|p| suffixes.contains_key(&p.index))
+        })
+        .collect();
+    if descendants.is_empty() {
+        return;
+    }
+    let mut names = vec![];
+    for (idx, suffix) in suffixes {
+        let zone = &mut zones[*idx];
+        let suffixed = |name: &str| format!("{} ({})", name, suffix);
+        let international_names = zone
+            .international_names
+            .iter()
+            .map(|(lang, name)| (lang.clone(), suffixed(name)))
+            .collect();
+        let name = suffixed(&zone.name);
+        let name = std::mem::replace(&mut zone.name, name);
+        let international_names =
+            std::mem::replace(&mut zone.international_names, international_names);
+        names.push((*idx, name, international_names));
+    }
+    relabel(zones, &descendants);
+    for (idx, name, international_names) in names {
+        zones[idx].name = name;
+        zones[idx].international_names = international_names;
+    }
+}
+
+/// Add a suffix to the labels of the siblings with the same label, returning the collisions
+///
+/// The labels of the descendants of the suffixed zones are built again by `relabel`, given the
+/// zones and the indexes of the zones to label. The ids of the zones must be their position in
+/// `zones`.
+pub fn disambiguate_labels(
+    zones: &mut [Zone],
+    relabel: impl FnOnce(&mut [Zone], &BTreeSet<usize>),
+) -> Vec<NameCollision> {
+    let mut siblings = BTreeMap::<(Option<ZoneIndex>, &str), Vec<usize>>::new();
+    for (idx, zone) in zones.iter().enumerate() {
+        siblings
+            .entry((zone.parent, &zone.label))
+            .or_default()
+            .push(idx);
+    }
+    let collisions: Vec<(Vec<usize>, Option<Vec<String>>)> = siblings
+        .into_values()
+        .filter(|indexes| indexes.len() > 1)
+        .map(|indexes| {
+            let group: Vec<&Zone> = indexes.iter().map(|idx| &zones[*idx]).collect();
+            let suffixes = distinct_suffixes(&group);
+            (indexes, suffixes)
+        })
+        .collect();
+
+    let mut name_collisions = vec![];
+    for (indexes, suffixes) in &collisions {
+        let first = &zones[indexes[0]];
+        name_collisions.push(NameCollision {
+            name: first.name.clone(),
+            parent: first.parent.map(|p| zones[p.index].osm_id.clone()),
+            osm_ids: indexes
+                .iter()
+                .map(|idx| zones[*idx].osm_id.clone())
+                .collect(),
+            unresolved: suffixes.is_none(),
+        });
+        for (idx, suffix) in indexes.iter().zip(suffixes.iter().flatten()) {
+            let zone = &mut zones[*idx];
+            add_suffix(&mut zone.label, &zone.name, suffix);
+            for (lang, label) in zone.international_labels.iter_mut() {
+                let name = zone.international_names.get(lang).unwrap_or(&zone.name);
+                add_suffix(label, name, suffix);
+            }
+            if let Some(label) = zone.secondary_label.as_mut() {
+                add_suffix(label, &zone.name, suffix);
+            }
+        }
+    }
+    let suffixes: BTreeMap<usize, &str> = collisions
+        .iter()
+        .filter_map(|(indexes, suffixes)| Some(indexes.iter().zip(suffixes.as_ref()?)))
+        .flatten()
+        .map(|(idx, suffix)| (*idx, suffix.as_str()))
+        .collect();
+    relabel_descendants(zones, &suffixes, relabel);
+    if !name_collisions.is_empty() {
+        info!(
+            "{} siblings with the same label, {} could not be told apart",
+            name_collisions.len(),
+            name_collisions.iter().filter(|c| c.unresolved).count()
+        );
+    }
+    name_collisions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn zone(index: usize, name: &str, zone_type: ZoneType, tags: &[(&str, &str)]) -> Zone {
+        Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            name: name.into(),
+            label: format!("{}, County", name),
+            international_labels: [("fr".to_string(), format!("{}, Comté", name))].into(),
+            zone_type: Some(zone_type),
+            admin_level: Some(8),
+            tags: tags.iter().copied().collect(),
+            parent: Some(ZoneIndex { index: 0 }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn siblings_labels_are_disambiguated() {
+        let mut zones = vec![
+            Zone {
+                osm_id: "relation:0".into(),
+                name: "County".into(),
+                label: "County".into(),
+                ..Default::default()
+            },
+            zone(1, "Saint-Martin", ZoneType::City, &[("ref", "12")]),
+            zone(2, "Saint-Martin", ZoneType::City, &[("ref", "34")]),
+            // without ref, the types tell the zones apart
+            zone(3, "Beaumont", ZoneType::City, &[("ref", "56")]),
+            zone(4, "Beaumont", ZoneType::CityDistrict, &[]),
+            zone(5, "Villeneuve", ZoneType::City, &[]),
+            zone(6, "Villeneuve", ZoneType::City, &[]),
+            zone(7, "Unique", ZoneType::City, &[]),
+        ];
+        let collisions = disambiguate_labels(&mut zones, |_, _| {});
+
+        let labels: Vec<_> = zones[1..].iter().map(|z| z.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "Saint-Martin (12), County",
+                "Saint-Martin (34), County",
+                "Beaumont (city), County",
+                "Beaumont (city_district), County",
+                "Villeneuve, County",
+                "Villeneuve, County",
+                "Unique, County",
+            ]
+        );
+        assert_eq!(
+            zones[1].international_labels.get("fr").unwrap(),
+            "Saint-Martin (12), Comté"
+        );
+
+        assert_eq!(collisions.len(), 3);
+        let saint_martin = collisions
+            .iter()
+            .find(|c| c.name == "Saint-Martin")
+            .unwrap();
+        assert_eq!(saint_martin.parent.as_deref(), Some("relation:0"));
+        assert_eq!(saint_martin.osm_ids, vec!["relation:1", "relation:2"]);
+        assert!(!saint_martin.unresolved);
+        let villeneuve = collisions.iter().find(|c| c.name == "Villeneuve").unwrap();
+        assert!(villeneuve.unresolved);
+    }
+
+    #[test]
+    fn descendants_labels_are_disambiguated() {
+        use crate::label_templates::LabelTemplates;
+        use crate::options::BuildOptions;
+
+        let zone = |index: usize, name: &str, parent: Option<usize>, tags: &[(&str, &str)]| Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            name: name.into(),
+            international_names: [("fr".to_string(), format!("{} fr", name))].into(),
+            zone_type: Some(ZoneType::City),
+            tags: tags.iter().copied().collect(),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        };
+        let mut zones = vec![
+            zone(0, "County", None, &[]),
+            zone(1, "Saint-Martin", Some(0), &[("ref", "12")]),
+            zone(2, "Saint-Martin", Some(0), &[("ref", "34")]),
+            zone(3, "Le Bourg", Some(1), &[]),
+            zone(4, "Le Bourg", Some(2), &[]),
+        ];
+        let options = BuildOptions::default();
+        let templates = LabelTemplates::default();
+        crate::compute_all_labels(&mut zones, &options, &templates, None);
+        let collisions = disambiguate_labels(&mut zones, |zones, only| {
+            crate::compute_all_labels(zones, &options, &templates, Some(only))
+        });
+
+        // the zones 3 and 4 are not siblings
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(zones[3].label, "Le Bourg, Saint-Martin (12), County");
+        assert_eq!(zones[4].label, "Le Bourg, Saint-Martin (34), County");
+        assert_eq!(
+            zones[4].international_labels.get("fr").unwrap(),
+            "Le Bourg fr, Saint-Martin fr (34), County fr"
+        );
+        // the names are not suffixed
+        assert_eq!(zones[1].name, "Saint-Martin");
+        assert_eq!(zones[1].international_names["fr"], "Saint-Martin fr");
+        assert_eq!(zones[1].label, "Saint-Martin (12), County");
+    }
+}
@@ -0,0 +1,172 @@
+// Search of the zones by name, for the tests and the QA scripts needing "the zone called X"
+// without grepping the json.
+//
+// The names are compared normalized: without case, diacritics and punctuation, so
+// "saint-étienne" finds "Saint-Étienne". All the names of a zone are searched (its name, its
+// alternative and local names and its international names), and the candidates are ranked
+// from the exact matches of the main name to the partial matches of the other names.
+
+use cosmogony::{Zone, ZoneType};
+use deunicode::deunicode;
+
+/// How a zone matches the query, the best matches first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum NameMatch {
+    Name,
+    OtherName,
+    NamePrefix,
+    OtherNamePrefix,
+    Partial,
+}
+
+/// The name lowercased and transliterated to ascii, the punctuation replaced by spaces
+pub fn normalize_name(name: &str) -> String {
+    deunicode(name)
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the other names of a zone, the osm tags with several names are split
+fn other_names(zone: &Zone) -> impl Iterator<Item = &str> {
+    [zone.alt_name.as_str(), zone.loc_name.as_str()]
+        .into_iter()
+        .chain(zone.alternate_names.names())
+        .flat_map(|n| n.split(';').map(str::trim))
+        .chain(zone.international_names.values().map(|n| n.as_str()))
+        .filter(|n| !n.is_empty())
+}
+
+fn name_match(zone: &Zone, query: &str) -> Option<NameMatch> {
+    let name = normalize_name(&zone.name);
+    let others: Vec<String> = other_names(zone).map(normalize_name).collect();
+    if name == query {
+        Some(NameMatch::Name)
+    } else if others.iter().any(|n| n == query) {
+        Some(NameMatch::OtherName)
+    } else if name.starts_with(query) {
+        Some(NameMatch::NamePrefix)
+    } else if others.iter().any(|n| n.starts_with(query)) {
+        Some(NameMatch::OtherNamePrefix)
+    } else if name.contains(query) || others.iter().any(|n| n.contains(query)) {
+        Some(NameMatch::Partial)
+    } else {
+        None
+    }
+}
+
+/// The zones whose names match a query, optionally of a given type, the best candidates first
+///
+/// The candidates matching the query equally well are sorted by decreasing population, so the
+/// largest "Springfield" comes first.
+pub fn find_zones_by_name<'a>(
+    zones: &'a [Zone],
+    query: &str,
+    zone_type: Option<ZoneType>,
+) -> Vec<&'a Zone> {
+    let query = normalize_name(query);
+    if query.is_empty() {
+        return vec![];
+    }
+    let mut candidates: Vec<(NameMatch, &Zone)> = zones
+        .iter()
+        .filter(|z| zone_type.is_none() || z.zone_type == zone_type)
+        .filter_map(|z| Some((name_match(z, &query)?, z)))
+        .collect();
+    candidates.sort_by(|(a_match, a), (b_match, b)| {
+        a_match
+            .cmp(b_match)
+            .then_with(|| b.population.cmp(&a.population))
+            .then_with(|| a.osm_id.cmp(&b.osm_id))
+    });
+    candidates.into_iter().map(|(_, z)| z).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn zone(osm_id: &str, name: &str, zone_type: ZoneType) -> Zone {
+        Zone {
+            osm_id: osm_id.into(),
+            name: name.into(),
+            zone_type: Some(zone_type),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zones_found_by_name() {
+        assert_eq!(normalize_name(" Saint-Étienne "), "saint etienne");
+        let zones = vec![
+            zone("relation:1", "Saint-Étienne-du-Rouvray", ZoneType::City),
+            Zone {
+                alt_name: "Sainté;St-Étienne".into(),
+                population: Some(170_000),
+                ..zone("relation:2", "Saint-Étienne", ZoneType::City)
+            },
+            Zone {
+                population: Some(1_000),
+                ..zone("relation:3", "Saint-Étienne", ZoneType::City)
+            },
+            zone(
+                "relation:4",
+                "Arrondissement de Saint-Étienne",
+                ZoneType::StateDistrict,
+            ),
+            Zone {
+                international_names: [("ru".to_string(), "Сент-Этьен".to_string())]
+                    .into_iter()
+                    .collect(),
+                ..zone("relation:5", "Sainte", ZoneType::City)
+            },
+        ];
+        let ids = |zones: Vec<&Zone>| zones.iter().map(|z| z.osm_id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            ids(find_zones_by_name(&zones, "SAINT ETIENNE", None)),
+            vec!["relation:2", "relation:3", "relation:1", "relation:4"]
+        );
+        assert_eq!(
+            ids(find_zones_by_name(
+                &zones,
+                "saint-étienne",
+                Some(ZoneType::StateDistrict)
+            )),
+            vec!["relation:4"]
+        );
+        // the main names first, then the alternative names
+        assert_eq!(
+            ids(find_zones_by_name(&zones, "sainté", None)),
+            vec!["relation:5", "relation:2"]
+        );
+        assert_eq!(
+            ids(find_zones_by_name(&zones, "Сент-Этьен", None)),
+            vec!["relation:5"]
+        );
+        assert!(find_zones_by_name(&zones, " - ", None).is_empty());
+    }
+
+    #[test]
+    fn zones_found_by_alternate_name() {
+        let mut paris = zone("relation:1", "Paris", ZoneType::City);
+        paris.alternate_names.alt = [("default".to_string(), "Lutèce; Paname".to_string())]
+            .into_iter()
+            .collect();
+        let zones = vec![paris, zone("relation:2", "Paname-sur-Mer", ZoneType::City)];
+        let ids = |zones: Vec<&Zone>| zones.iter().map(|z| z.osm_id.clone()).collect::<Vec<_>>();
+
+        // each name of the tag is an exact match, before the prefix of another zone's name
+        assert_eq!(
+            ids(find_zones_by_name(&zones, "paname", None)),
+            vec!["relation:1", "relation:2"]
+        );
+        assert_eq!(
+            ids(find_zones_by_name(&zones, "Lutece", None)),
+            vec!["relation:1"]
+        );
+        assert_eq!(name_match(&zones[0], "lutece"), Some(NameMatch::OtherName));
+    }
+}
@@ -0,0 +1,90 @@
+// A spatial index of named polygons (like timezones or countries) read from a geojson file,
+// to find the polygon containing a point.
+
+use anyhow::{Context, Error};
+use geo::prelude::{BoundingRect, Contains};
+use geo_types::{Geometry, MultiPolygon, Point, Rect};
+use geojson::FeatureReader;
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+use std::io::Read;
+
+type PolygonBbox = GeomWithData<Rectangle<[f64; 2]>, usize>;
+
+pub struct NamedPolygons {
+    polygons: Vec<(String, MultiPolygon<f64>)>,
+    tree: RTree<PolygonBbox>,
+}
+
+impl NamedPolygons {
+    pub fn new(polygons: Vec<(String, MultiPolygon<f64>)>) -> Self {
+        let bboxes = polygons
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, (_, boundary))| {
+                let bbox = boundary.bounding_rect()?;
+                let rect =
+                    Rectangle::from_corners(bbox.min().x_y().into(), bbox.max().x_y().into());
+                Some(GeomWithData::new(rect, idx))
+            })
+            .collect();
+        NamedPolygons {
+            polygons,
+            tree: RTree::bulk_load(bboxes),
+        }
+    }
+
+    /// Read the polygons of a geojson feature collection, named by their `property`
+    pub fn from_geojson(reader: impl Read, property: &str) -> Result<Self, Error> {
+        let mut polygons = vec![];
+        for feature in FeatureReader::from_reader(reader).features() {
+            let feature = feature.context("invalid geojson feature")?;
+            let name = match feature.property(property).and_then(|v| v.as_str()) {
+                Some(name) => name.to_string(),
+                None => {
+                    warn!("feature without {}, skipping it", property);
+                    continue;
+                }
+            };
+            let boundary = feature
+                .geometry
+                .as_ref()
+                .and_then(|g| Geometry::<f64>::try_from(&g.value).ok());
+            match boundary {
+                Some(Geometry::MultiPolygon(mpoly)) => polygons.push((name, mpoly)),
+                Some(Geometry::Polygon(poly)) => polygons.push((name, MultiPolygon(vec![poly]))),
+                _ => warn!("{} has no polygonal geometry, skipping it", name),
+            }
+        }
+        Ok(Self::new(polygons))
+    }
+
+    pub fn into_polygons(self) -> Vec<(String, MultiPolygon<f64>)> {
+        self.polygons
+    }
+
+    pub fn len(&self) -> usize {
+        self.polygons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    /// the polygons whose bbox intersects the given bbox
+    pub fn intersecting(&self, bbox: &Rect<f64>) -> impl Iterator<Item = &MultiPolygon<f64>> {
+        let envelope = AABB::from_corners(bbox.min().x_y().into(), bbox.max().x_y().into());
+        self.tree
+            .locate_in_envelope_intersecting(&envelope)
+            .map(|bbox| &self.polygons[bbox.data].1)
+    }
+
+    /// the name of the polygon containing a point
+    pub fn find(&self, point: &Point<f64>) -> Option<&str> {
+        self.tree
+            .locate_in_envelope_intersecting(&AABB::from_point(point.x_y().into()))
+            .map(|bbox| &self.polygons[bbox.data])
+            .find(|(_, boundary)| boundary.contains(point))
+            .map(|(name, _)| name.as_str())
+    }
+}
@@ -0,0 +1,124 @@
+// The zones sharing a border with a zone, for the cartographic coloring (the adjacent zones
+// must have different colors) or to suggest the nearby areas.
+//
+// Two zones are neighbors when their boundaries share a border segment, touching at a single
+// point is not enough. Only the zones of the same type are neighbors (the cities of a city, the
+// states of a state...), the zones of other types sharing a border with it being its parents
+// or children. This is expensive (the borders of all the zones with intersecting bboxes are
+// intersected), so it is only done if asked.
+
+use crate::hierarchy_builder::ZonesTree;
+use cosmogony::{Zone, ZoneIndex};
+use geos::{Geom, Geometry};
+use rayon::prelude::*;
+
+/// the border of a zone, as a geos line
+fn border(zone: &Zone) -> Option<Geometry<'static>> {
+    let boundary = zone.boundary.as_ref()?;
+    Geometry::try_from(boundary)
+        .and_then(|g| g.boundary())
+        .map_err(|e| warn!("failed to convert {} to geos: {}", zone.osm_id, e))
+        .ok()
+}
+
+/// check if 2 borders share a segment
+fn share_segment(border: &Geometry<'_>, other: &Geometry<'_>) -> geos::GResult<bool> {
+    if !border.intersects(other)? {
+        return Ok(false);
+    }
+    Ok(border.intersection(other)?.length()? > 0.)
+}
+
+/// Give to the zones the ids of the zones of the same type sharing a border with them
+pub fn find_neighbors(zones: &mut [Zone]) {
+    info!("looking for the neighbors of {} zones", zones.len());
+    let tree = ZonesTree::by_position(zones);
+    let borders: Vec<Option<Geometry<'static>>> = zones.par_iter().map(border).collect();
+    let neighbors: Vec<Vec<ZoneIndex>> = (0..zones.len())
+        .into_par_iter()
+        .map(|idx| {
+            let zone = &zones[idx];
+            let (Some(bbox), Some(zone_border)) = (zone.bbox, &borders[idx]) else {
+                return vec![];
+            };
+            let mut neighbors: Vec<ZoneIndex> = tree
+                .fetch_bbox(&bbox)
+                .into_iter()
+                .map(|other| other.index)
+                .filter(|other| *other != idx && zones[*other].zone_type == zone.zone_type)
+                .filter(|other| {
+                    let Some(other_border) = &borders[*other] else {
+                        return false;
+                    };
+                    share_segment(zone_border, other_border).unwrap_or_else(|e| {
+                        warn!(
+                            "failed to compare the borders of {} and {}: {}",
+                            zone.osm_id, zones[*other].osm_id, e
+                        );
+                        false
+                    })
+                })
+                .map(|other| zones[other].id)
+                .collect();
+            neighbors.sort();
+            neighbors
+        })
+        .collect();
+    for (zone, neighbors) in zones.iter_mut().zip(neighbors) {
+        zone.neighbors = neighbors;
+    }
+    let nb_zones = zones.iter().filter(|z| !z.neighbors.is_empty()).count();
+    info!("{} zones with neighbors", nb_zones);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::ZoneType;
+    use geo::prelude::BoundingRect;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+
+    fn zone(index: usize, zone_type: ZoneType, (x, y): (f64, f64), size: f64) -> Zone {
+        let ring = LineString::from(vec![
+            (x, y),
+            (x, y + size),
+            (x + size, y + size),
+            (x + size, y),
+            (x, y),
+        ]);
+        let boundary = MultiPolygon(vec![Polygon::new(ring, vec![])]);
+        Zone {
+            // the ids are not the positions, like in a cleaned cosmogony
+            id: ZoneIndex { index: index * 10 },
+            osm_id: format!("relation:{}", index),
+            zone_type: Some(zone_type),
+            bbox: boundary.bounding_rect(),
+            boundary: Some(boundary),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zones_neighbors() {
+        let mut zones = vec![
+            zone(0, ZoneType::City, (0., 0.), 1.),
+            zone(1, ZoneType::City, (1., 0.), 1.),
+            // only touching the first city at a corner
+            zone(2, ZoneType::City, (1., 1.), 1.),
+            // sharing a border with the first city, but not a city
+            zone(3, ZoneType::State, (-1., 0.), 1.),
+        ];
+        find_neighbors(&mut zones);
+
+        let ids = |indexes: &[usize]| -> Vec<ZoneIndex> {
+            indexes
+                .iter()
+                .map(|index| ZoneIndex { index: *index })
+                .collect()
+        };
+        assert_eq!(zones[0].neighbors, ids(&[10]));
+        assert_eq!(zones[1].neighbors, ids(&[0, 20]));
+        assert_eq!(zones[2].neighbors, ids(&[10]));
+        assert!(zones[3].neighbors.is_empty());
+    }
+}
@@ -0,0 +1,806 @@
+use anyhow::{anyhow, Context, Error};
+use cosmogony::ZoneType;
+use osmpbfreader::{NodeId, OsmId, OsmObj, RelationId, WayId};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// How a disputed zone is attached to the hierarchy
+///
+/// A disputed zone is claimed by several countries (listed in the zone's `claimed_by`).
+/// The strategy decides which claimant's zones can be its parents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum DisputedStrategy {
+    /// attach the zone to the first claimant (in the order of its `claimed_by` tag)
+    FirstClaimant,
+    /// attach the zone to the second claimant
+    SecondClaimant,
+    /// keep the geometrical attachment, whichever claimant it is
+    #[default]
+    Both,
+    /// do not attach the zone to any of the claimants
+    Neutral,
+}
+
+impl FromStr for DisputedStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(DisputedStrategy::FirstClaimant),
+            "second" => Ok(DisputedStrategy::SecondClaimant),
+            "both" => Ok(DisputedStrategy::Both),
+            "neutral" => Ok(DisputedStrategy::Neutral),
+            _ => Err(anyhow!(
+                "invalid disputed strategy '{}', accepted values are: first, second, both, neutral",
+                s
+            )),
+        }
+    }
+}
+
+/// The orientation of the rings of the boundaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindingOrder {
+    /// counterclockwise exteriors and clockwise holes, as required by the GeoJSON RFC 7946
+    #[default]
+    Rfc7946,
+    /// clockwise exteriors and counterclockwise holes, for the legacy consumers
+    Clockwise,
+}
+
+impl FromStr for WindingOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rfc7946" => Ok(WindingOrder::Rfc7946),
+            "clockwise" => Ok(WindingOrder::Clockwise),
+            _ => Err(anyhow!(
+                "invalid winding order '{}', accepted values are: rfc7946, clockwise",
+                s
+            )),
+        }
+    }
+}
+
+/// Which zone to keep when an extra zone overlaps an osm zone of the same type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum ExtraZonesPolicy {
+    /// keep the extra zone and discard the osm zone
+    PreferExtra,
+    /// keep the osm zone and discard the extra zone
+    PreferOsm,
+    /// keep both zones, each one referencing the other in its `overlaps` field
+    #[default]
+    KeepBoth,
+}
+
+impl FromStr for ExtraZonesPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefer-extra" => Ok(ExtraZonesPolicy::PreferExtra),
+            "prefer-osm" => Ok(ExtraZonesPolicy::PreferOsm),
+            "keep-both" => Ok(ExtraZonesPolicy::KeepBoth),
+            _ => Err(anyhow!(
+                "invalid extra zones policy '{}', accepted values are: prefer-extra, prefer-osm, keep-both",
+                s
+            )),
+        }
+    }
+}
+
+/// How the boundaries of the places without boundary are computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdditionalZonesStrategy {
+    /// a voronoi partition of their parent zone
+    #[default]
+    Voronoi,
+    /// the residential landuses of their voronoi cell (the cell if there is no landuse)
+    Landuse,
+}
+
+impl FromStr for AdditionalZonesStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "voronoi" => Ok(AdditionalZonesStrategy::Voronoi),
+            "landuse" => Ok(AdditionalZonesStrategy::Landuse),
+            _ => Err(anyhow!(
+                "invalid additional zones strategy '{}', accepted values are: voronoi, landuse",
+                s
+            )),
+        }
+    }
+}
+
+/// The osm `place` values used to compute additional zones (with voronoi) for the places
+/// without boundary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PlaceTypes {
+    pub city: bool,
+    pub town: bool,
+    pub village: bool,
+    pub hamlet: bool,
+    pub suburb: bool,
+    pub quarter: bool,
+    pub neighbourhood: bool,
+}
+
+impl Default for PlaceTypes {
+    fn default() -> Self {
+        PlaceTypes {
+            city: true,
+            town: true,
+            village: true,
+            hamlet: false,
+            suburb: true,
+            quarter: true,
+            neighbourhood: true,
+        }
+    }
+}
+
+impl PlaceTypes {
+    const ALL: [&'static str; 7] = [
+        "city",
+        "town",
+        "village",
+        "hamlet",
+        "suburb",
+        "quarter",
+        "neighbourhood",
+    ];
+
+    fn toggle(&mut self, place: &str) -> Option<&mut bool> {
+        Some(match place {
+            "city" => &mut self.city,
+            "town" => &mut self.town,
+            "village" => &mut self.village,
+            "hamlet" => &mut self.hamlet,
+            "suburb" => &mut self.suburb,
+            "quarter" => &mut self.quarter,
+            "neighbourhood" => &mut self.neighbourhood,
+            _ => return None,
+        })
+    }
+
+    /// the zone type of a place node, if this kind of place is enabled
+    pub fn zone_type(&self, place: &str) -> Option<ZoneType> {
+        let enabled = match place {
+            "city" => self.city,
+            "town" => self.town,
+            "village" => self.village,
+            "hamlet" => self.hamlet,
+            "suburb" => self.suburb,
+            "quarter" => self.quarter,
+            "neighbourhood" => self.neighbourhood,
+            _ => false,
+        };
+        if !enabled {
+            return None;
+        }
+        match place {
+            "hamlet" => Some(ZoneType::City),
+            _ => ZoneType::parse(place),
+        }
+    }
+}
+
+impl FromStr for PlaceTypes {
+    type Err = Error;
+
+    /// parse a comma separated list of place values, eg. "city,town,village"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut place_types = PlaceTypes {
+            city: false,
+            town: false,
+            village: false,
+            hamlet: false,
+            suburb: false,
+            quarter: false,
+            neighbourhood: false,
+        };
+        for place in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            *place_types.toggle(place).ok_or_else(|| {
+                anyhow!(
+                    "invalid place type '{}', accepted values are: {}",
+                    place,
+                    PlaceTypes::ALL.join(", ")
+                )
+            })? = true;
+        }
+        Ok(place_types)
+    }
+}
+
+/// Tolerances (in degrees) used to simplify the zones' boundaries
+///
+/// Each zone type can have its own tolerance (eg. the countries can be simplified more than the
+/// suburbs), the other types use the default tolerance.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimplifyTolerances {
+    pub default: f64,
+    pub by_zone_type: Vec<(ZoneType, f64)>,
+}
+
+impl SimplifyTolerances {
+    /// the tolerance of a zone type
+    pub fn tolerance(&self, zone_type: Option<ZoneType>) -> f64 {
+        self.by_zone_type
+            .iter()
+            .find(|(t, _)| Some(*t) == zone_type)
+            .map_or(self.default, |(_, tolerance)| *tolerance)
+    }
+}
+
+/// Check a simplification tolerance, that cannot be negative
+pub(crate) fn check_tolerance(tolerance: f64) -> Result<f64, Error> {
+    if tolerance >= 0. {
+        Ok(tolerance)
+    } else {
+        Err(anyhow!(
+            "invalid simplification tolerance {}, it cannot be negative",
+            tolerance
+        ))
+    }
+}
+
+impl FromStr for SimplifyTolerances {
+    type Err = Error;
+
+    /// parse a comma separated list of tolerances, the default tolerance and
+    /// tolerances by zone type, eg. "0.001,country=0.01,state=0.005"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_tolerance = |t: &str| {
+            t.parse::<f64>()
+                .map_err(|_| anyhow!("invalid simplification tolerance '{}'", t))
+                .and_then(check_tolerance)
+        };
+        let mut tolerances = SimplifyTolerances::default();
+        for value in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            match value.split_once('=') {
+                Some((zone_type, tolerance)) => {
+                    let zone_type = ZoneType::parse(zone_type.trim())
+                        .ok_or_else(|| anyhow!("invalid zone type '{}'", zone_type))?;
+                    tolerances
+                        .by_zone_type
+                        .push((zone_type, parse_tolerance(tolerance.trim())?));
+                }
+                None => tolerances.default = parse_tolerance(value)?,
+            }
+        }
+        Ok(tolerances)
+    }
+}
+
+/// New admin_levels of some countries' zones, applied before typing the zones to fix the
+/// levels tagged inconsistently in a region (eg. admin_level=6 used for the cities)
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct AdminLevelRemap {
+    /// the new level of the remapped levels, by country code
+    pub levels: BTreeMap<String, BTreeMap<u32, u32>>,
+}
+
+impl AdminLevelRemap {
+    /// the new admin_level of a zone of a country, if its level is remapped
+    pub fn remap(&self, country_code: &str, admin_level: u32) -> Option<u32> {
+        self.levels.get(country_code)?.get(&admin_level).copied()
+    }
+}
+
+impl FromStr for AdminLevelRemap {
+    type Err = Error;
+
+    /// parse a comma separated list of remapped levels by country, eg. "FR:6=8,FR:7=9,DE:5=6"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut remap = AdminLevelRemap::default();
+        for value in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let invalid = || anyhow!("invalid admin_level remapping '{}', eg. FR:6=8", value);
+            let (country, levels) = value.split_once(':').ok_or_else(invalid)?;
+            let (old, new) = levels.split_once('=').ok_or_else(invalid)?;
+            let level = |l: &str| l.trim().parse::<u32>().map_err(|_| invalid());
+            let country = country.trim().to_uppercase();
+            if country.is_empty() {
+                return Err(invalid());
+            }
+            remap
+                .levels
+                .entry(country)
+                .or_default()
+                .insert(level(old)?, level(new)?);
+        }
+        Ok(remap)
+    }
+}
+
+/// A memory size, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemorySize(pub u64);
+
+impl FromStr for MemorySize {
+    type Err = Error;
+
+    /// parse a number of bytes with an optional unit, eg. "512M" or "16G"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("invalid memory size '{}', eg. 512M or 16G", s);
+        let size = s.trim().trim_end_matches(['B', 'b']);
+        let (number, unit) = match size.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&size[..i], c.to_ascii_uppercase()),
+            _ => (size, ' '),
+        };
+        let multiplier: u64 = match unit {
+            ' ' => 1,
+            'K' => 1 << 10,
+            'M' => 1 << 20,
+            'G' => 1 << 30,
+            'T' => 1 << 40,
+            _ => return Err(invalid()),
+        };
+        let number: u64 = number.trim().parse().map_err(|_| invalid())?;
+        number
+            .checked_mul(multiplier)
+            .map(MemorySize)
+            .ok_or_else(invalid)
+    }
+}
+
+/// A set of osm ids, like `relation:42, way:7` (the numbers alone being relations ids)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct OsmIds(pub BTreeSet<OsmId>);
+
+impl OsmIds {
+    pub fn contains(&self, id: &OsmId) -> bool {
+        self.0.contains(id)
+    }
+
+    /// read a file with osm ids separated by commas or new lines, the `#` starting comments
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("impossible to read {}", path.display()))?;
+        let ids: Vec<&str> = contents
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or_default())
+            .collect();
+        ids.join(",")
+            .parse()
+            .with_context(|| format!("invalid osm ids file {}", path.display()))
+    }
+}
+
+fn parse_osm_id(s: &str) -> Result<OsmId, Error> {
+    let invalid = || anyhow!("invalid osm id '{}', eg. relation:42, way:7 or node:3", s);
+    let (kind, id) = s.split_once(':').unwrap_or(("relation", s));
+    let id: i64 = id.trim().parse().map_err(|_| invalid())?;
+    match kind.trim() {
+        "relation" => Ok(OsmId::Relation(RelationId(id))),
+        "way" => Ok(OsmId::Way(WayId(id))),
+        "node" => Ok(OsmId::Node(NodeId(id))),
+        _ => Err(invalid()),
+    }
+}
+
+impl FromStr for OsmIds {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(parse_osm_id)
+            .collect::<Result<_, _>>()
+            .map(OsmIds)
+    }
+}
+
+/// A custom filter of the osm objects to extract, replacing the default one (the admin
+/// boundaries and the place nodes)
+///
+/// The relations and the ways accepted by the filter are extracted as zones, the accepted
+/// nodes are extracted as places if they are of one of the enabled place types.
+///
+/// ```no_run
+/// use cosmogony_builder::{is_admin, is_place, OsmFilter};
+///
+/// // also extract the political boundaries
+/// let filter = OsmFilter::new(|obj| {
+///     is_admin(obj) || is_place(obj) || obj.tags().contains("boundary", "political")
+/// });
+/// ```
+#[derive(Clone)]
+pub struct OsmFilter(Arc<dyn Fn(&OsmObj) -> bool + Send + Sync>);
+
+impl OsmFilter {
+    pub fn new(filter: impl Fn(&OsmObj) -> bool + Send + Sync + 'static) -> Self {
+        OsmFilter(Arc::new(filter))
+    }
+
+    /// check if the object is to be extracted
+    pub fn accepts(&self, obj: &OsmObj) -> bool {
+        (self.0)(obj)
+    }
+}
+
+impl fmt::Debug for OsmFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OsmFilter")
+    }
+}
+
+/// A token to cancel a build from another thread (eg. a service embedding the builds)
+///
+/// The build checks it between its stages and in its long loops, and returns a [`Cancelled`]
+/// error once it is cancelled.
+///
+/// ```no_run
+/// use cosmogony_builder::{build_cosmogony, BuildOptions, CancellationToken, Cancelled};
+///
+/// let cancellation = CancellationToken::new();
+/// let options = BuildOptions {
+///     cancellation: cancellation.clone(),
+///     ..Default::default()
+/// };
+/// let build = std::thread::spawn(move || build_cosmogony("planet.osm.pbf".into(), &options));
+/// cancellation.cancel();
+/// let error = build.join().unwrap().unwrap_err();
+/// assert!(error.downcast_ref::<Cancelled>().is_some());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// cancel the builds using this token (or one of its clones)
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// fail with a [`Cancelled`] error if the build has been cancelled
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error of a cancelled build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the build has been cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The format of the secondary labels, with the `{name}` (the name of the zone), `{label}` (its
+/// label in the secondary language) and `{local_label}` (its default label) placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecondaryLabelFormat(String);
+
+const SECONDARY_LABEL_PLACEHOLDERS: [&str; 3] = ["{name}", "{label}", "{local_label}"];
+
+impl Default for SecondaryLabelFormat {
+    fn default() -> Self {
+        SecondaryLabelFormat("{name}, {label}".to_string())
+    }
+}
+
+impl SecondaryLabelFormat {
+    pub fn format(&self, name: &str, label: &str, local_label: &str) -> String {
+        self.0
+            .replace("{name}", name)
+            .replace("{label}", label)
+            .replace("{local_label}", local_label)
+    }
+}
+
+impl FromStr for SecondaryLabelFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("unclosed placeholder in the label format '{}'", s))?;
+            let placeholder = &rest[start..start + end + 1];
+            if !SECONDARY_LABEL_PLACEHOLDERS.contains(&placeholder) {
+                return Err(anyhow!(
+                    "invalid placeholder {} in the label format '{}', accepted placeholders are: {}",
+                    placeholder,
+                    s,
+                    SECONDARY_LABEL_PLACEHOLDERS.join(", ")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if !s.contains("{label}") {
+            return Err(anyhow!("the label format '{}' has no {{label}}", s));
+        }
+        Ok(SecondaryLabelFormat(s.to_string()))
+    }
+}
+
+/// The sibling overlaps reported by default, as a ratio of the smallest sibling
+pub const DEFAULT_SIBLING_OVERLAP_THRESHOLD: f64 = 0.1;
+
+/// Check an inclusion threshold, a ratio of the area of a zone in (0, 1]
+pub fn check_inclusion_threshold(threshold: f64) -> Result<f64, Error> {
+    if threshold > 0. && threshold <= 1. {
+        Ok(threshold)
+    } else {
+        Err(anyhow!(
+            "invalid inclusion threshold {}, it must be in (0, 1]",
+            threshold
+        ))
+    }
+}
+
+/// Options used to build a cosmogony
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Country code to use if the osm file does not contains any country
+    pub country_code: Option<String>,
+    /// Prevent voronoi geometries computation and generation
+    pub disable_voronoi: bool,
+    /// Only generates labels for the given langs (all langs if empty)
+    ///
+    /// The langs can be patterns like `zh*` or `!ja`, and `default+` also trims the zones' names
+    /// to the given langs.
+    pub filter_langs: Vec<String>,
+    /// Discard the `name:<lang>` tags whose language is not a valid language tag
+    pub reject_invalid_lang_tags: bool,
+    /// Add a romanized `latin` name (and label) to the zones whose name is not in the latin
+    /// script, from their `int_name` tag or a transliteration of their name
+    pub romanize_names: bool,
+    /// Extract the `official_name`, `short_name`, `alt_name` and `old_name` tags of the zones, by
+    /// language, in their `alternate_names`
+    pub alternate_names: bool,
+    /// Also build for each zone a `secondary_label` with its label in this language (eg. `en`,
+    /// or `latin` with `romanize_names`), next to its local name (no secondary label if None)
+    pub secondary_label_lang: Option<String>,
+    /// The format of the secondary labels
+    pub secondary_label_format: SecondaryLabelFormat,
+    /// Yaml file with the label templates by country code (eg. `US: "{city}, {state} {zip},
+    /// {country}"`), the labels of the other countries are the comma-joined names of the zone
+    /// and its parents
+    pub label_templates: Option<PathBuf>,
+    /// How the disputed zones are attached to the hierarchy
+    pub disputed_strategy: DisputedStrategy,
+    /// Cosmogony file with additional typed zones to blend with the osm zones
+    pub extra_zones: Option<PathBuf>,
+    /// What to do when an extra zone overlaps an osm zone
+    pub extra_zones_policy: ExtraZonesPolicy,
+    /// Do not try to repair the invalid boundaries before computing the inclusions
+    pub disable_geometry_repair: bool,
+    /// Keep the zones whose boundary polygon could not be built, located by their
+    /// admin_centre (or label) node
+    pub keep_boundaryless_zones: bool,
+    /// Close the boundaries clipped by the extract (their ways outside of it being missing)
+    /// along the extract's bounds, flagging the zones as `clipped`, instead of dropping them
+    pub partial_assembly: bool,
+    /// Also extract the `boundary=ceremonial` and `boundary=traditional` relations (like the UK's
+    /// ceremonial counties), as non administrative zones
+    pub ceremonial_boundaries: bool,
+    /// Also extract the `boundary=administrative` zones without admin_level, with the admin_level
+    /// of their parent + 1, flagged as `level_inferred`
+    pub infer_missing_levels: bool,
+    /// Keep the historic zones (with an `end_date` in the past, or their tags behind a lifecycle
+    /// prefix like `was:`) flagged as `historic`, instead of skipping them
+    pub keep_historic: bool,
+    /// Kinds of place nodes used to compute the additional zones
+    pub place_types: PlaceTypes,
+    /// Largest type of the zones the voronoi cells of the places are clipped to (eg. a
+    /// `StateDistrict` to keep the cells inside the counties), the places only inside larger
+    /// zones get no boundary. Any type smaller than a country if None.
+    pub voronoi_clip_level: Option<ZoneType>,
+    /// How the boundaries of the places are computed
+    pub additional_zones_strategy: AdditionalZonesStrategy,
+    /// Simplify the boundaries, keeping the borders shared by adjacent zones edge-matched
+    pub simplify: Option<SimplifyTolerances>,
+    /// Snap the borders of the zones closer than this tolerance (in degrees) before the
+    /// simplification, so the borders shared by adjacent zones have exactly the same points
+    pub snap_tolerance: Option<f64>,
+    /// Round the coordinates to this number of decimals (full precision if None)
+    pub coordinates_precision: Option<u32>,
+    /// The orientation of the rings of the boundaries
+    pub winding_order: WindingOrder,
+    /// Give to the zones without population the sum of their children's population
+    pub propagate_population: bool,
+    /// Names of the pipeline stages not to run
+    pub skipped_stages: Vec<String>,
+    /// Dump the zones in `<dir>/<stage>.jsonl` after each of these stages (for debugging)
+    pub dump_after: Vec<(String, PathBuf)>,
+    /// Stop the pipeline after this stage (for debugging)
+    pub stop_after: Option<String>,
+    /// New admin_levels of the zones of some countries, applied before typing them
+    pub admin_level_remap: Option<AdminLevelRemap>,
+    /// Directory of libpostal-like typing rules files (`<country>.yaml`) used instead of the
+    /// bundled libpostal rules
+    pub rules_dir: Option<PathBuf>,
+    /// Yaml file overriding libpostal's admin_level mapping for some countries
+    pub admin_level_overrides: Option<PathBuf>,
+    /// Json file persisting the countries detected for the zones, reused by the next builds of
+    /// the same input file with the same options
+    pub country_cache: Option<PathBuf>,
+    /// Yaml file mapping zones osm ids to the osm id of their parent, to fix the known errors
+    /// of the hierarchy (`null` to remove the parent of a zone)
+    pub parent_overrides: Option<PathBuf>,
+    /// Record in the zones' `parents` all the zones that could be their parent, not only the
+    /// chosen one
+    pub multi_parents: bool,
+    /// Record in the zones' `included_in` all the zones containing them (the whole inclusion
+    /// graph, of which the hierarchy only keeps a tree)
+    pub keep_inclusions: bool,
+    /// Include a zone in the larger zones covering at least this ratio of its area, not only in
+    /// the zones covering it entirely (if None)
+    pub inclusion_threshold: Option<f64>,
+    /// Record in the zones' `neighbors` the zones of the same type sharing a border with them
+    /// (expensive)
+    pub neighbors: bool,
+    /// Shapefile or geojson file with the land polygons, used to clip the zones to the coastline
+    pub land_polygons: Option<PathBuf>,
+    /// Report the siblings overlapping more than this ratio of the smallest one
+    /// ([`DEFAULT_SIBLING_OVERLAP_THRESHOLD`] if None)
+    pub sibling_overlap_threshold: Option<f64>,
+    /// Yaml file with the expected number of zones by country and zone type, the deviating
+    /// counts are reported in the stats
+    pub expected_counts: Option<PathBuf>,
+    /// Fail the build if some zones counts deviate from the expected ones
+    pub fail_on_unexpected_counts: bool,
+    /// Geojson file with the timezones boundaries (with a `tzid` property), used to give
+    /// a timezone to the zones
+    pub timezones: Option<PathBuf>,
+    /// Resolution (0 to 15) of the h3 cells covering the zones, given to the zones in their
+    /// `h3_cells` (no cells if None)
+    #[cfg(feature = "h3")]
+    pub h3_resolution: Option<u8>,
+    /// Sort the zones by osm id, to get a reproducible output
+    pub deterministic: bool,
+    /// Find the country of the zones not included in any country of the osm file with
+    /// the bundled low resolution countries boundaries
+    pub country_fallback: bool,
+    /// Give to the dependent territories (the admin_level 3 or 4 zones with an `ISO3166-1` code,
+    /// like French Guiana or Puerto Rico) their own country code instead of the one of the
+    /// country including them
+    pub dependent_territories: bool,
+    /// Choose the country of the zones included in several countries with the land areas of the
+    /// countries (the `boundary=land_area` relations), not with their maritime boundaries
+    pub country_land_areas: bool,
+    /// Give to the zones without country the most frequent country of the zones including them
+    /// or included in them
+    pub guess_missing_countries: bool,
+    /// Do not look for the countries of the zones, they are typed by their admin_level only and
+    /// have no country code (for the datasets without countries)
+    pub no_country: bool,
+    /// Only keep these osm tags in the zones' `tags` and `center_tags` (all the tags if empty)
+    ///
+    /// A tag can be a prefix like `name:*`.
+    pub kept_tags: Vec<String>,
+    /// Give to the zones the version, changeset and dates of their osm object (if the osm file
+    /// has the metadata)
+    pub osm_metadata: bool,
+    /// Spill the osm objects read from the pbf to a temporary file when they exceed this size
+    /// in memory (all the objects are kept in memory if None)
+    pub max_memory: Option<MemorySize>,
+    /// osmChange files (`.osc` or `.osc.gz`, eg. the replication diffs since the pbf was written)
+    /// applied, in this order, to the osm objects of the pbf
+    pub osm_changes: Vec<PathBuf>,
+    /// Osm objects not to extract, eg. the broken or vandalized relations breaking the builds
+    pub excluded_osm_ids: OsmIds,
+    /// Filter of the osm objects to extract, instead of the admin boundaries and the places
+    /// (only available through the library)
+    pub osm_filter: Option<OsmFilter>,
+    /// Token to cancel the build from another thread (only available through the library)
+    pub cancellation: CancellationToken,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn place_types_from_str() {
+        let place_types: PlaceTypes = "city, hamlet".parse().unwrap();
+        assert_eq!(place_types.zone_type("city"), Some(ZoneType::City));
+        assert_eq!(place_types.zone_type("hamlet"), Some(ZoneType::City));
+        assert_eq!(place_types.zone_type("town"), None);
+        assert_eq!(place_types.zone_type("suburb"), None);
+        assert!("city,farm".parse::<PlaceTypes>().is_err());
+    }
+
+    #[test]
+    fn default_place_types() {
+        let place_types = PlaceTypes::default();
+        assert_eq!(place_types.zone_type("village"), Some(ZoneType::City));
+        assert_eq!(place_types.zone_type("quarter"), Some(ZoneType::Suburb));
+        assert_eq!(place_types.zone_type("hamlet"), None);
+        assert_eq!(place_types.zone_type("state"), None);
+    }
+
+    #[test]
+    fn simplify_tolerances_from_str() {
+        let tolerances: SimplifyTolerances = "0.001, country=0.01".parse().unwrap();
+        assert_eq!(tolerances.tolerance(Some(ZoneType::Country)), 0.01);
+        assert_eq!(tolerances.tolerance(Some(ZoneType::City)), 0.001);
+        assert_eq!(tolerances.tolerance(None), 0.001);
+        assert!("planet=0.1".parse::<SimplifyTolerances>().is_err());
+        assert!("city=big".parse::<SimplifyTolerances>().is_err());
+        assert!("-0.001".parse::<SimplifyTolerances>().is_err());
+        assert!("0.001,city=-1".parse::<SimplifyTolerances>().is_err());
+    }
+
+    #[test]
+    fn admin_level_remap_from_str() {
+        let remap: AdminLevelRemap = "fr:6=8, FR:7 = 9,DE:5=6".parse().unwrap();
+        assert_eq!(remap.remap("FR", 6), Some(8));
+        assert_eq!(remap.remap("FR", 7), Some(9));
+        assert_eq!(remap.remap("FR", 8), None);
+        assert_eq!(remap.remap("DE", 5), Some(6));
+        assert_eq!(remap.remap("LU", 6), None);
+        assert!("6=8".parse::<AdminLevelRemap>().is_err());
+        assert!("FR:6".parse::<AdminLevelRemap>().is_err());
+        assert!("FR:six=8".parse::<AdminLevelRemap>().is_err());
+    }
+
+    #[test]
+    fn memory_size_from_str() {
+        assert_eq!("1024".parse::<MemorySize>().unwrap(), MemorySize(1024));
+        assert_eq!("512M".parse::<MemorySize>().unwrap(), MemorySize(512 << 20));
+        assert_eq!("16gb".parse::<MemorySize>().unwrap(), MemorySize(16 << 30));
+        assert!("16X".parse::<MemorySize>().is_err());
+        assert!("lots".parse::<MemorySize>().is_err());
+    }
+
+    #[test]
+    fn secondary_label_format_from_str() {
+        let format: SecondaryLabelFormat = "{label} ({name})".parse().unwrap();
+        assert_eq!(
+            format.format("東京都", "Tokyo, Japan", "東京都, 日本"),
+            "Tokyo, Japan (東京都)"
+        );
+        assert_eq!(
+            SecondaryLabelFormat::default().format("東京都", "Tokyo, Japan", "東京都, 日本"),
+            "東京都, Tokyo, Japan"
+        );
+        assert!("{local_label} / {label}"
+            .parse::<SecondaryLabelFormat>()
+            .is_ok());
+        assert!("{name}, {lbl}".parse::<SecondaryLabelFormat>().is_err());
+        assert!("{name}, {label".parse::<SecondaryLabelFormat>().is_err());
+        assert!("{name}".parse::<SecondaryLabelFormat>().is_err());
+    }
+
+    #[test]
+    fn osm_ids_from_str() {
+        let ids: OsmIds = "relation:42, way:7,node:3, 12,".parse().unwrap();
+        let expected = [
+            OsmId::Relation(RelationId(42)),
+            OsmId::Way(WayId(7)),
+            OsmId::Node(NodeId(3)),
+            OsmId::Relation(RelationId(12)),
+        ];
+        assert_eq!(ids, OsmIds(expected.into_iter().collect()));
+        assert!("area:42".parse::<OsmIds>().is_err());
+        assert!("relation:r42".parse::<OsmIds>().is_err());
+    }
+}
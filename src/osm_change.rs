@@ -0,0 +1,280 @@
+// The osmChange files (`.osc`, or `.osc.gz` like the replication diffs) list the osm objects
+// created, modified or deleted since an extract was written. Instead of applying them to the
+// extract with an external tool, they are applied to the objects read from the pbf: an object
+// of the changes replaces the one of the pbf (or removes it if it has been deleted), and the
+// objects created by the changes are read like the ones of the pbf.
+//
+// Only the last version of each object is kept when several changes are applied, in order.
+
+use anyhow::{anyhow, Context, Error, Result};
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::events::{BytesStart, Event};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// The osm objects changed by some osmChange files
+#[derive(Debug, Default)]
+pub struct OsmChange {
+    /// the last version of the changed objects, None for the deleted ones
+    objects: BTreeMap<OsmId, Option<OsmObj>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// The attribute of an xml element
+fn attribute(e: &BytesStart<'_>, name: &str) -> Result<Option<String>> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key.as_ref() == name.as_bytes() {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn required<T: std::str::FromStr>(e: &BytesStart<'_>, name: &str) -> Result<T> {
+    let element = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+    let value = attribute(e, name)?
+        .ok_or_else(|| anyhow!("no '{}' attribute in a '{}' element", name, element))?;
+    value.parse().map_err(|_| {
+        anyhow!(
+            "invalid '{}' attribute '{}' in a '{}'",
+            name,
+            value,
+            element
+        )
+    })
+}
+
+/// degrees to the decimicro degrees of the osm objects
+fn decimicro(e: &BytesStart<'_>, name: &str) -> Result<i32> {
+    // the deleted nodes can have no coordinates
+    Ok(attribute(e, name)?
+        .map(|v| v.parse::<f64>())
+        .transpose()
+        .map_err(|_| anyhow!("invalid '{}' coordinate of a node", name))?
+        .map_or(0, |v| (v * 1e7).round() as i32))
+}
+
+impl OsmChange {
+    /// Read an osmChange xml document
+    pub fn from_xml(reader: impl BufRead) -> Result<Self> {
+        let mut reader = quick_xml::Reader::from_reader(reader);
+        reader.trim_text(true);
+        let mut change = OsmChange::default();
+        let mut action = None;
+        let mut current: Option<OsmObj> = None;
+        let mut buf = vec![];
+        loop {
+            let event = reader.read_event_into(&mut buf)?;
+            let is_empty = matches!(event, Event::Empty(_));
+            match event {
+                Event::Start(ref e) | Event::Empty(ref e) => {
+                    match e.name().as_ref() {
+                        b"create" => action = Some(Action::Create),
+                        b"modify" => action = Some(Action::Modify),
+                        b"delete" => action = Some(Action::Delete),
+                        b"node" => {
+                            current = Some(OsmObj::Node(Node {
+                                id: NodeId(required(e, "id")?),
+                                tags: Tags::new(),
+                                decimicro_lat: decimicro(e, "lat")?,
+                                decimicro_lon: decimicro(e, "lon")?,
+                            }))
+                        }
+                        b"way" => {
+                            current = Some(OsmObj::Way(Way {
+                                id: WayId(required(e, "id")?),
+                                tags: Tags::new(),
+                                nodes: vec![],
+                            }))
+                        }
+                        b"relation" => {
+                            current = Some(OsmObj::Relation(Relation {
+                                id: RelationId(required(e, "id")?),
+                                tags: Tags::new(),
+                                refs: vec![],
+                            }))
+                        }
+                        b"tag" => {
+                            let k: String = required(e, "k")?;
+                            let v: String = required(e, "v")?;
+                            match current.as_mut() {
+                                Some(OsmObj::Node(o)) => o.tags.insert(k.into(), v.into()),
+                                Some(OsmObj::Way(o)) => o.tags.insert(k.into(), v.into()),
+                                Some(OsmObj::Relation(o)) => o.tags.insert(k.into(), v.into()),
+                                None => None,
+                            };
+                        }
+                        b"nd" => {
+                            if let Some(OsmObj::Way(w)) = current.as_mut() {
+                                w.nodes.push(NodeId(required(e, "ref")?));
+                            }
+                        }
+                        b"member" => {
+                            if let Some(OsmObj::Relation(r)) = current.as_mut() {
+                                let id = required(e, "ref")?;
+                                let member = match required::<String>(e, "type")?.as_str() {
+                                    "node" => OsmId::Node(NodeId(id)),
+                                    "way" => OsmId::Way(WayId(id)),
+                                    "relation" => OsmId::Relation(RelationId(id)),
+                                    t => return Err(anyhow!("invalid member type '{}'", t)),
+                                };
+                                r.refs.push(Ref {
+                                    member,
+                                    role: attribute(e, "role")?.unwrap_or_default().into(),
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                    if is_empty {
+                        change.end_element(e.name().as_ref(), action, &mut current)?;
+                    }
+                }
+                Event::End(ref e) => change.end_element(e.name().as_ref(), action, &mut current)?,
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+        Ok(change)
+    }
+
+    fn end_element(
+        &mut self,
+        name: &[u8],
+        action: Option<Action>,
+        current: &mut Option<OsmObj>,
+    ) -> Result<()> {
+        if !matches!(name, b"node" | b"way" | b"relation") {
+            return Ok(());
+        }
+        let Some(obj) = current.take() else {
+            return Ok(());
+        };
+        match action {
+            Some(Action::Delete) => self.objects.insert(obj.id(), None),
+            Some(Action::Create | Action::Modify) => self.objects.insert(obj.id(), Some(obj)),
+            None => {
+                return Err(anyhow!(
+                    "{:?} outside of a create, modify or delete",
+                    obj.id()
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    /// Read an osmChange file, gzipped if its extension is `.gz`
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let error = || format!("invalid osmChange file {}", path.display());
+        let file = BufReader::new(File::open(path).with_context(error)?);
+        if path.extension().is_some_and(|e| e == "gz") {
+            Self::from_xml(BufReader::new(flate2::bufread::GzDecoder::new(file)))
+        } else {
+            Self::from_xml(file)
+        }
+        .with_context(error)
+    }
+
+    /// Read some osmChange files, applied in this order
+    pub fn read_all(paths: &[PathBuf]) -> Result<Self, Error> {
+        let mut change = OsmChange::default();
+        for path in paths {
+            info!("reading the osm changes of {}", path.display());
+            change.apply(Self::read(path)?);
+        }
+        if !paths.is_empty() {
+            info!("{} osm objects changed", change.objects.len());
+        }
+        Ok(change)
+    }
+
+    /// Apply some more recent changes
+    pub fn apply(&mut self, newer: OsmChange) {
+        self.objects.extend(newer.objects);
+    }
+
+    /// The change of an object: None if it has not changed, Some(None) if it has been deleted
+    pub fn get(&self, id: &OsmId) -> Option<Option<&OsmObj>> {
+        self.objects.get(id).map(Option::as_ref)
+    }
+
+    /// The objects created or modified
+    pub fn objects(&self) -> impl Iterator<Item = &OsmObj> {
+        self.objects.values().flatten()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CHANGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osmChange version="0.6" generator="test">
+  <create>
+    <node id="10" version="1" lat="49.6" lon="6.1">
+      <tag k="place" v="village"/>
+      <tag k="name" v="Bourg &amp; Co"/>
+    </node>
+  </create>
+  <modify>
+    <way id="20" version="2">
+      <nd ref="1"/>
+      <nd ref="2"/>
+      <nd ref="1"/>
+    </way>
+    <relation id="30" version="3">
+      <member type="way" ref="20" role="outer"/>
+      <member type="node" ref="10" role="admin_centre"/>
+      <tag k="boundary" v="administrative"/>
+    </relation>
+  </modify>
+  <delete>
+    <node id="11" version="2"/>
+  </delete>
+</osmChange>"#;
+
+    #[test]
+    fn osm_change_read() {
+        let mut change = OsmChange::from_xml(CHANGE.as_bytes()).unwrap();
+        let node = change.get(&NodeId(10).into()).unwrap().unwrap();
+        let node = node.node().unwrap();
+        assert_eq!(
+            (node.decimicro_lat, node.decimicro_lon),
+            (496_000_000, 61_000_000)
+        );
+        assert_eq!(
+            node.tags.get("name").map(|n| n.as_str()),
+            Some("Bourg & Co")
+        );
+        let way = change.get(&WayId(20).into()).unwrap().unwrap();
+        assert_eq!(
+            way.way().unwrap().nodes,
+            vec![NodeId(1), NodeId(2), NodeId(1)]
+        );
+        let relation = change.get(&RelationId(30).into()).unwrap().unwrap();
+        let relation = relation.relation().unwrap();
+        assert_eq!(relation.refs[1].member, NodeId(10).into());
+        assert_eq!(relation.refs[1].role.as_str(), "admin_centre");
+        assert_eq!(change.get(&NodeId(11).into()), Some(None));
+        assert_eq!(change.get(&NodeId(12).into()), None);
+
+        // a more recent change deleting the relation
+        let newer = r#"<osmChange><delete><relation id="30"/></delete></osmChange>"#;
+        change.apply(OsmChange::from_xml(newer.as_bytes()).unwrap());
+        assert_eq!(change.get(&RelationId(30).into()), Some(None));
+        assert_eq!(change.objects().count(), 2);
+
+        let invalid = r#"<osmChange><create><node lat="1" lon="1"/></create></osmChange>"#;
+        assert!(OsmChange::from_xml(invalid.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+// The version, changeset and dates of the osm objects of the zones, for the data-quality checks
+// (how stale a boundary is, which osm edit caused a regression).
+//
+// The metadata are read with the objects from the pbf (they are not in the osm objects of
+// osmpbfreader). The boundary of a relation changes when its ways or their nodes are moved,
+// without a new version of the relation (nor of the ways, for the nodes), so the `last_modified`
+// date of a zone is the latest date of its object, of its ways and of their nodes. The zones of
+// the nodes (the places) are not given metadata.
+
+use crate::osm_store::OsmObjects;
+use cosmogony::{OsmMetadata, Zone};
+use osmpbfreader::{OsmId, RelationId, WayId};
+use std::collections::BTreeMap;
+
+/// The osm id of a zone (eg. `relation:42`), if it is a relation or a way
+fn zone_osm_id(zone: &Zone) -> Option<OsmId> {
+    let (kind, id) = zone.osm_id.split_once(':')?;
+    let id = id.parse().ok()?;
+    match kind {
+        "relation" => Some(OsmId::Relation(RelationId(id))),
+        "way" => Some(OsmId::Way(WayId(id))),
+        _ => None,
+    }
+}
+
+/// The latest date of a way and of its nodes
+fn way_last_modified(
+    way: &OsmId,
+    metadata: &BTreeMap<OsmId, OsmMetadata>,
+    osm: &dyn OsmObjects,
+) -> Option<i64> {
+    let way_timestamp = metadata.get(way)?.timestamp;
+    let Some(way) = osm.get(way) else {
+        return Some(way_timestamp);
+    };
+    let nodes_timestamps = way
+        .way()
+        .into_iter()
+        .flat_map(|w| &w.nodes)
+        .filter_map(|n| metadata.get(&OsmId::Node(*n)))
+        .map(|m| m.timestamp);
+    Some(nodes_timestamps.fold(way_timestamp, i64::max))
+}
+
+/// Give to the zones the metadata of their osm object
+pub fn assign_osm_metadata(
+    zones: &mut [Zone],
+    metadata: &BTreeMap<OsmId, OsmMetadata>,
+    osm: &dyn OsmObjects,
+) {
+    info!("adding the osm metadata to the zones");
+    for zone in zones.iter_mut() {
+        let Some(id) = zone_osm_id(zone) else {
+            continue;
+        };
+        let Some(mut zone_metadata) = metadata.get(&id).copied() else {
+            continue;
+        };
+        let members_timestamps: Vec<i64> = match id {
+            OsmId::Way(_) => way_last_modified(&id, metadata, osm).into_iter().collect(),
+            _ => osm
+                .get(&id)
+                .as_ref()
+                .and_then(|o| o.relation())
+                .into_iter()
+                .flat_map(|r| &r.refs)
+                .filter_map(|r| match r.member {
+                    OsmId::Way(_) => way_last_modified(&r.member, metadata, osm),
+                    member => metadata.get(&member).map(|m| m.timestamp),
+                })
+                .collect(),
+        };
+        zone_metadata.last_modified = members_timestamps
+            .into_iter()
+            .fold(zone_metadata.timestamp, i64::max);
+        zone.osm_metadata = Some(zone_metadata);
+    }
+    let nb_zones = zones.iter().filter(|z| z.osm_metadata.is_some()).count();
+    info!("{} zones with osm metadata", nb_zones);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use osmpbfreader::{NodeId, OsmObj, Ref, Relation, Way};
+
+    fn metadata(version: u32, timestamp: i64) -> OsmMetadata {
+        OsmMetadata {
+            version,
+            changeset: timestamp / 10,
+            timestamp,
+            last_modified: timestamp,
+        }
+    }
+
+    #[test]
+    fn zones_osm_metadata() {
+        let relation_id = OsmId::Relation(RelationId(1));
+        let (way_1, way_2) = (OsmId::Way(WayId(10)), OsmId::Way(WayId(11)));
+        let relation = Relation {
+            id: RelationId(1),
+            tags: Default::default(),
+            refs: [way_1, way_2]
+                .into_iter()
+                .map(|member| Ref {
+                    member,
+                    role: "outer".into(),
+                })
+                .collect(),
+        };
+        let way = |id, nodes: Vec<i64>| Way {
+            id: WayId(id),
+            tags: Default::default(),
+            nodes: nodes.into_iter().map(NodeId).collect(),
+        };
+        let objects = BTreeMap::from([
+            (relation_id, OsmObj::Relation(relation)),
+            (way_1, OsmObj::Way(way(10, vec![100, 101]))),
+            (way_2, OsmObj::Way(way(11, vec![101, 102]))),
+        ]);
+        let node = |id| OsmId::Node(NodeId(id));
+        let metadata = BTreeMap::from([
+            (relation_id, metadata(3, 1_500_000_000)),
+            (way_1, metadata(7, 1_600_000_000)),
+            (way_2, metadata(2, 1_400_000_000)),
+            (node(100), metadata(1, 1_300_000_000)),
+            (node(101), metadata(4, 1_450_000_000)),
+            (node(102), metadata(5, 1_700_000_000)),
+        ]);
+        let zone = |osm_id: &str| Zone {
+            osm_id: osm_id.into(),
+            ..Default::default()
+        };
+        let mut zones = vec![zone("relation:1"), zone("way:11"), zone("node:12")];
+        assign_osm_metadata(&mut zones, &metadata, &objects);
+
+        let relation_metadata = zones[0].osm_metadata.unwrap();
+        assert_eq!(relation_metadata.version, 3);
+        assert_eq!(relation_metadata.timestamp, 1_500_000_000);
+        // a node of a way has been moved after the last versions of the relation and its ways
+        assert_eq!(relation_metadata.last_modified, 1_700_000_000);
+        let way_metadata = zones[1].osm_metadata.unwrap();
+        assert_eq!(way_metadata.version, 2);
+        assert_eq!(way_metadata.timestamp, 1_400_000_000);
+        assert_eq!(way_metadata.last_modified, 1_700_000_000);
+        assert_eq!(zones[2].osm_metadata, None);
+    }
+}
@@ -0,0 +1,413 @@
+// The osm objects read from the pbf (the zones and all their dependencies) are kept in memory
+// by default. For the planet, they are hundreds of millions of objects, most of them the nodes
+// of the boundaries, and they would need about 200 GB of RAM.
+//
+// With a memory limit, the objects are spilled in a temporary file as soon as the objects kept
+// in memory exceed the limit, and only the position of each object in the file stays in memory.
+// The objects are read back when needed: the members of a zone and their nodes when its
+// boundary is built, and all of them, in the osm ids order, when the zones and the places
+// are extracted. The reads are positional, so the parallel stages read the file concurrently.
+// The positions of the spilled objects count toward the memory limit.
+//
+// The stages get the objects through infallible accessors: an object that cannot be read back
+// is missing, and the read error is kept in the store, then reported by the pipeline at the
+// end of the stage (see `OsmObjects::check`).
+
+use anyhow::{anyhow, Context, Error};
+use itertools::{EitherOrBoth, Itertools};
+use osmpbfreader::{OsmId, OsmObj, StoreObjs};
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// the number of spill files created by this process, to give them unique names
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The osm objects read from the pbf
+pub trait OsmObjects: Sync {
+    /// The object of an osm id
+    fn get(&self, id: &OsmId) -> Option<Cow<'_, OsmObj>>;
+
+    /// All the objects, in the osm ids order
+    fn objects(&self) -> Box<dyn Iterator<Item = Cow<'_, OsmObj>> + '_>;
+
+    /// The objects needed to build the boundary of an object: its members and their nodes
+    fn dependencies(&self, obj: &OsmObj) -> Cow<'_, BTreeMap<OsmId, OsmObj>>;
+
+    /// Fail if some objects could not be read (they have been returned as missing)
+    fn check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl OsmObjects for BTreeMap<OsmId, OsmObj> {
+    fn get(&self, id: &OsmId) -> Option<Cow<'_, OsmObj>> {
+        BTreeMap::get(self, id).map(Cow::Borrowed)
+    }
+
+    fn objects(&self) -> Box<dyn Iterator<Item = Cow<'_, OsmObj>> + '_> {
+        Box::new(self.values().map(Cow::Borrowed))
+    }
+
+    fn dependencies(&self, _obj: &OsmObj) -> Cow<'_, BTreeMap<OsmId, OsmObj>> {
+        Cow::Borrowed(self)
+    }
+}
+
+/// A rough estimation of the memory used by an object in a `BTreeMap`
+fn estimated_size(obj: &OsmObj) -> u64 {
+    let strings = size_of::<String>() as u64;
+    let tags: u64 = obj
+        .tags()
+        .iter()
+        .map(|(k, v)| (k.len() + v.len()) as u64 + 2 * strings)
+        .sum();
+    let refs = match obj {
+        OsmObj::Node(_) => 0,
+        OsmObj::Way(w) => (w.nodes.len() * size_of::<osmpbfreader::NodeId>()) as u64,
+        OsmObj::Relation(r) => r
+            .refs
+            .iter()
+            .map(|r| (size_of::<osmpbfreader::Ref>() + r.role.len()) as u64)
+            .sum(),
+    };
+    size_of::<(OsmId, OsmObj)>() as u64 + tags + refs
+}
+
+/// A rough estimation of the memory used by the position of a spilled object
+const SPILLED_POSITION_SIZE: u64 = size_of::<(OsmId, (u64, u32))>() as u64;
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buffer, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buffer = &mut buffer[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The temporary file of the spilled objects, removed when dropped
+struct SpillFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl SpillFile {
+    fn create(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(format!(
+            "cosmogony-{}-{}.osm-objects",
+            std::process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("impossible to create {}", path.display()))?;
+        Ok(SpillFile { path, file, len: 0 })
+    }
+
+    /// Append the serialized objects, and return the position of each one
+    fn append(&mut self, objects: &BTreeMap<OsmId, OsmObj>) -> io::Result<Vec<(u64, u32)>> {
+        let mut buffer = vec![];
+        let mut positions = Vec::with_capacity(objects.len());
+        for obj in objects.values() {
+            let start = buffer.len();
+            serde_json::to_writer(&mut buffer, obj)?;
+            positions.push((self.len + start as u64, (buffer.len() - start) as u32));
+        }
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&buffer)?;
+        self.len += buffer.len() as u64;
+        Ok(positions)
+    }
+
+    fn read(&self, (offset, len): (u64, u32)) -> io::Result<OsmObj> {
+        let mut buffer = vec![0; len as usize];
+        read_exact_at(&self.file, &mut buffer, offset)?;
+        Ok(serde_json::from_slice(&buffer)?)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("impossible to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// An osm objects store spilling the objects to a temporary file above a memory limit
+pub struct SpillingStore {
+    max_memory: u64,
+    memory: u64,
+    in_memory: BTreeMap<OsmId, OsmObj>,
+    spilled: BTreeMap<OsmId, (u64, u32)>,
+    file: SpillFile,
+    /// the first error while spilling the objects, reported by `finish`
+    error: Option<io::Error>,
+    /// the first error while reading the spilled objects, reported by `check`
+    read_error: OnceLock<io::Error>,
+}
+
+impl SpillingStore {
+    /// Create a store keeping at most `max_memory` bytes of objects in memory, the others being
+    /// spilled in a temporary file of `dir`
+    pub fn new(max_memory: u64, dir: &Path) -> Result<Self, Error> {
+        Ok(SpillingStore {
+            max_memory,
+            memory: 0,
+            in_memory: BTreeMap::new(),
+            spilled: BTreeMap::new(),
+            file: SpillFile::create(dir)?,
+            error: None,
+            read_error: OnceLock::new(),
+        })
+    }
+
+    /// Check that all the objects have been stored
+    pub fn finish(mut self) -> Result<Self, Error> {
+        match self.error.take() {
+            Some(e) => Err(Error::from(e).context("impossible to spill the osm objects")),
+            None => {
+                info!(
+                    "{} osm objects in memory, {} spilled on disk",
+                    self.in_memory.len(),
+                    self.spilled.len()
+                );
+                Ok(self)
+            }
+        }
+    }
+
+    /// The memory left to the objects, once the positions of the spilled objects are counted
+    ///
+    /// It is at least a tenth of the limit, so the objects are not spilled one by one when the
+    /// positions alone exceed the limit.
+    fn objects_memory_limit(&self) -> u64 {
+        let positions = self.spilled.len() as u64 * SPILLED_POSITION_SIZE;
+        self.max_memory
+            .saturating_sub(positions)
+            .max(self.max_memory / 10)
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let positions = self.file.append(&self.in_memory)?;
+        debug!(
+            "{} osm objects spilled in {}",
+            positions.len(),
+            self.file.path.display()
+        );
+        let limit = self.objects_memory_limit();
+        let objects = std::mem::take(&mut self.in_memory);
+        self.spilled.extend(objects.into_keys().zip(positions));
+        self.memory = 0;
+        let min_limit = self.max_memory / 10;
+        if limit > min_limit && self.objects_memory_limit() == min_limit {
+            warn!(
+                "the positions of the {} spilled osm objects use most of the memory limit",
+                self.spilled.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// The spilled object at a position, None (and the error kept) if it cannot be read
+    fn read(&self, position: (u64, u32)) -> Option<OsmObj> {
+        match self.file.read(position) {
+            Ok(obj) => Some(obj),
+            Err(e) => {
+                let _ = self.read_error.set(e);
+                None
+            }
+        }
+    }
+}
+
+impl StoreObjs for SpillingStore {
+    fn insert(&mut self, key: OsmId, value: OsmObj) {
+        self.memory += estimated_size(&value);
+        self.in_memory.insert(key, value);
+        if self.memory > self.objects_memory_limit() && self.error.is_none() {
+            if let Err(e) = self.spill() {
+                // the objects stay in memory
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn contains_key(&self, key: &OsmId) -> bool {
+        self.in_memory.contains_key(key) || self.spilled.contains_key(key)
+    }
+}
+
+impl OsmObjects for SpillingStore {
+    fn get(&self, id: &OsmId) -> Option<Cow<'_, OsmObj>> {
+        match self.in_memory.get(id) {
+            Some(obj) => Some(Cow::Borrowed(obj)),
+            None => self
+                .spilled
+                .get(id)
+                .and_then(|p| self.read(*p))
+                .map(Cow::Owned),
+        }
+    }
+
+    fn objects(&self) -> Box<dyn Iterator<Item = Cow<'_, OsmObj>> + '_> {
+        Box::new(
+            self.in_memory
+                .iter()
+                .merge_join_by(self.spilled.iter(), |(a, _), (b, _)| a.cmp(b))
+                .filter_map(|objects| match objects {
+                    EitherOrBoth::Left((_, obj)) | EitherOrBoth::Both((_, obj), _) => {
+                        Some(Cow::Borrowed(obj))
+                    }
+                    EitherOrBoth::Right((_, position)) => self.read(*position).map(Cow::Owned),
+                }),
+        )
+    }
+
+    fn dependencies(&self, obj: &OsmObj) -> Cow<'_, BTreeMap<OsmId, OsmObj>> {
+        let mut dependencies = BTreeMap::new();
+        let add_nodes = |way: &osmpbfreader::Way, deps: &mut BTreeMap<OsmId, OsmObj>| {
+            for node in &way.nodes {
+                if let Entry::Vacant(entry) = deps.entry(OsmId::Node(*node)) {
+                    if let Some(n) = self.get(entry.key()) {
+                        entry.insert(n.into_owned());
+                    }
+                }
+            }
+        };
+        match obj {
+            OsmObj::Way(way) => add_nodes(way, &mut dependencies),
+            OsmObj::Relation(relation) => {
+                for member in &relation.refs {
+                    let Some(member_obj) = self.get(&member.member) else {
+                        continue;
+                    };
+                    if let Some(way) = member_obj.way() {
+                        add_nodes(way, &mut dependencies);
+                    }
+                    dependencies.insert(member.member, member_obj.into_owned());
+                }
+            }
+            OsmObj::Node(_) => {}
+        }
+        Cow::Owned(dependencies)
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        match self.read_error.get() {
+            Some(e) => Err(anyhow!("impossible to read the spilled osm objects: {}", e)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use osmpbfreader::{Node, NodeId, Ref, Relation, RelationId, Way, WayId};
+
+    fn objects() -> Vec<OsmObj> {
+        let node = |id| {
+            OsmObj::Node(Node {
+                id: NodeId(id),
+                tags: Default::default(),
+                decimicro_lat: id as i32,
+                decimicro_lon: -id as i32,
+            })
+        };
+        vec![
+            node(1),
+            node(2),
+            node(3),
+            node(4),
+            OsmObj::Way(Way {
+                id: WayId(10),
+                tags: Default::default(),
+                nodes: vec![NodeId(1), NodeId(2), NodeId(3), NodeId(1)],
+            }),
+            OsmObj::Relation(Relation {
+                id: RelationId(20),
+                tags: [("name".into(), "Somewhere".into())].into_iter().collect(),
+                refs: vec![
+                    Ref {
+                        member: WayId(10).into(),
+                        role: "outer".into(),
+                    },
+                    Ref {
+                        member: NodeId(4).into(),
+                        role: "admin_centre".into(),
+                    },
+                ],
+            }),
+        ]
+    }
+
+    #[test]
+    fn objects_are_spilled_above_the_memory_limit() {
+        let dir = std::env::temp_dir();
+        // about 2 nodes in memory
+        let limit = 2 * estimated_size(&objects()[0]) + 2 * SPILLED_POSITION_SIZE;
+        let mut store = SpillingStore::new(limit, &dir).unwrap();
+        for obj in objects() {
+            store.insert(obj.id(), obj);
+        }
+        let store = store.finish().unwrap();
+        assert!(!store.spilled.is_empty());
+        assert!(store.contains_key(&NodeId(1).into()));
+        assert!(!store.contains_key(&NodeId(5).into()));
+
+        let all: Vec<OsmObj> = store.objects().map(Cow::into_owned).collect();
+        assert_eq!(all, objects());
+        assert_eq!(
+            store.get(&WayId(10).into()).map(Cow::into_owned),
+            Some(objects()[4].clone())
+        );
+
+        let deps = store.dependencies(&objects()[5]);
+        let ids: Vec<OsmId> = deps.keys().copied().collect();
+        let expected: Vec<OsmId> = objects()[..5].iter().map(|o| o.id()).collect();
+        assert_eq!(ids, expected);
+
+        assert!(store.check().is_ok());
+
+        let path = store.file.path.clone();
+        assert!(path.exists());
+        drop(store);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn spilled_read_errors_are_reported() {
+        let mut store = SpillingStore::new(0, &std::env::temp_dir()).unwrap();
+        for obj in objects() {
+            store.insert(obj.id(), obj);
+        }
+        let store = store.finish().unwrap();
+        // the spill file is truncated behind the store's back
+        store.file.file.set_len(0).unwrap();
+        assert!(store.get(&WayId(10).into()).is_none());
+        assert!(store.check().is_err());
+    }
+}
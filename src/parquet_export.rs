@@ -0,0 +1,268 @@
+// Export of the zones as GeoParquet (https://geoparquet.org/releases/v1.1.0/), for the data
+// pipelines loading the zones in Spark, DuckDB, pandas...
+//
+// The zones are the rows of a single table, with their main attributes as columns and their
+// boundary as a WKB `geometry` column. The GeoParquet metadata (the geometry column, its
+// encoding and its extent) is stored in the `geo` key of the file metadata. The bbox of each
+// boundary is also in the `bbox` struct column, declared as the covering of the geometries.
+
+use crate::wkb::write_multipolygon;
+use anyhow::{Context, Error};
+use cosmogony::Zone;
+use geo::prelude::BoundingRect;
+use geo_types::Rect;
+use parquet::basic::Compression;
+use parquet::data_type::{
+    BoolType, ByteArray, ByteArrayType, DataType, DoubleType, Int32Type, Int64Type,
+};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// the columns of the zones table, in the order they are written
+const SCHEMA: &str = "
+message zone {
+  REQUIRED INT64 id;
+  REQUIRED BYTE_ARRAY osm_id (UTF8);
+  OPTIONAL INT32 admin_level;
+  OPTIONAL BYTE_ARRAY zone_type (UTF8);
+  REQUIRED BYTE_ARRAY name (UTF8);
+  REQUIRED BYTE_ARRAY label (UTF8);
+  REQUIRED BYTE_ARRAY zip_codes (UTF8);
+  OPTIONAL BYTE_ARRAY wikidata (UTF8);
+  OPTIONAL BYTE_ARRAY country_code (UTF8);
+  OPTIONAL INT64 parent;
+  OPTIONAL INT64 population;
+  REQUIRED BOOLEAN is_generated;
+  OPTIONAL BYTE_ARRAY origin (UTF8);
+  REQUIRED BOOLEAN disputed;
+  OPTIONAL BYTE_ARRAY geometry;
+  OPTIONAL group bbox {
+    REQUIRED DOUBLE xmin;
+    REQUIRED DOUBLE ymin;
+    REQUIRED DOUBLE xmax;
+    REQUIRED DOUBLE ymax;
+  }
+}
+";
+
+/// the number of zones by row group
+const ROW_GROUP_SIZE: usize = 10_000;
+
+fn string(s: &str) -> ByteArray {
+    ByteArray::from(s.as_bytes().to_vec())
+}
+
+/// Write a column of the row group, the None values being nulls
+fn write_column<T: DataType, W: Write + Send>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    values: impl Iterator<Item = Option<T::T>>,
+) -> Result<(), Error> {
+    let mut column = row_group
+        .next_column()?
+        .context("more columns written than in the schema")?;
+    let mut levels = vec![];
+    let mut non_null_values = vec![];
+    for value in values {
+        levels.push(value.is_some() as i16);
+        non_null_values.extend(value);
+    }
+    let writer = column.typed::<T>();
+    let nullable = writer.get_descriptor().max_def_level() > 0;
+    writer.write_batch(&non_null_values, nullable.then_some(&levels[..]), None)?;
+    column.close()?;
+    Ok(())
+}
+
+fn write_row_group<W: Write + Send>(
+    writer: &mut SerializedFileWriter<W>,
+    zones: &[Zone],
+) -> Result<(), Error> {
+    let mut row_group = writer.next_row_group()?;
+    let rg = &mut row_group;
+    write_column::<Int64Type, _>(rg, zones.iter().map(|z| Some(z.id.index as i64)))?;
+    write_column::<ByteArrayType, _>(rg, zones.iter().map(|z| Some(string(&z.osm_id))))?;
+    write_column::<Int32Type, _>(rg, zones.iter().map(|z| z.admin_level.map(|l| l as i32)))?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        zones
+            .iter()
+            .map(|z| z.zone_type.map(|t| string(t.as_str()))),
+    )?;
+    write_column::<ByteArrayType, _>(rg, zones.iter().map(|z| Some(string(&z.name))))?;
+    write_column::<ByteArrayType, _>(rg, zones.iter().map(|z| Some(string(&z.label))))?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        zones.iter().map(|z| Some(string(&z.zip_codes.join(";")))),
+    )?;
+    write_column::<ByteArrayType, _>(rg, zones.iter().map(|z| z.wikidata.as_deref().map(string)))?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        zones.iter().map(|z| z.country_code.as_deref().map(string)),
+    )?;
+    write_column::<Int64Type, _>(rg, zones.iter().map(|z| z.parent.map(|p| p.index as i64)))?;
+    write_column::<Int64Type, _>(rg, zones.iter().map(|z| z.population.map(|p| p as i64)))?;
+    write_column::<BoolType, _>(rg, zones.iter().map(|z| Some(z.is_generated)))?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        zones.iter().map(|z| z.origin.map(|o| string(o.as_str()))),
+    )?;
+    write_column::<BoolType, _>(rg, zones.iter().map(|z| Some(z.disputed)))?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        zones.iter().map(|z| {
+            z.boundary.as_ref().map(|b| {
+                let mut wkb = vec![];
+                write_multipolygon(&mut wkb, b);
+                ByteArray::from(wkb)
+            })
+        }),
+    )?;
+    let bboxes: Vec<Option<Rect<f64>>> = zones.iter().map(boundary_bbox).collect();
+    write_column::<DoubleType, _>(rg, bboxes.iter().map(|b| b.map(|b| b.min().x)))?;
+    write_column::<DoubleType, _>(rg, bboxes.iter().map(|b| b.map(|b| b.min().y)))?;
+    write_column::<DoubleType, _>(rg, bboxes.iter().map(|b| b.map(|b| b.max().x)))?;
+    write_column::<DoubleType, _>(rg, bboxes.iter().map(|b| b.map(|b| b.max().y)))?;
+    row_group.close()?;
+    Ok(())
+}
+
+/// The bbox of the boundary of a zone (None for the zones without boundary)
+fn boundary_bbox(zone: &Zone) -> Option<Rect<f64>> {
+    let boundary = zone.boundary.as_ref()?;
+    zone.bbox.or_else(|| boundary.bounding_rect())
+}
+
+/// The GeoParquet metadata of the file, with the extent of all the boundaries, and the `bbox`
+/// column covering the geometries (so the readers can filter the rows without decoding them)
+fn geo_metadata(zones: &[Zone]) -> serde_json::Value {
+    let extent = zones.iter().filter_map(boundary_bbox).reduce(|a, b| {
+        Rect::new(
+            (a.min().x.min(b.min().x), a.min().y.min(b.min().y)),
+            (a.max().x.max(b.max().x), a.max().y.max(b.max().y)),
+        )
+    });
+    let mut geometry = serde_json::json!({
+        "encoding": "WKB",
+        "geometry_types": ["MultiPolygon"],
+        "covering": {
+            "bbox": {
+                "xmin": ["bbox", "xmin"],
+                "ymin": ["bbox", "ymin"],
+                "xmax": ["bbox", "xmax"],
+                "ymax": ["bbox", "ymax"],
+            },
+        },
+    });
+    if let Some(e) = extent {
+        geometry["bbox"] = serde_json::json!([e.min().x, e.min().y, e.max().x, e.max().y]);
+    }
+    serde_json::json!({
+        "version": "1.1.0",
+        "primary_column": "geometry",
+        "columns": { "geometry": geometry },
+    })
+}
+
+/// Write the zones in a GeoParquet file, one row by zone
+pub fn write_zones_as_geoparquet(zones: &[Zone], path: &Path) -> Result<(), Error> {
+    let file =
+        File::create(path).with_context(|| format!("impossible to create {}", path.display()))?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            "geo".to_string(),
+            geo_metadata(zones).to_string(),
+        )]))
+        .build();
+    let mut writer = SerializedFileWriter::new(
+        file,
+        Arc::new(parse_message_type(SCHEMA)?),
+        Arc::new(properties),
+    )?;
+    for chunk in zones.chunks(ROW_GROUP_SIZE) {
+        write_row_group(&mut writer, chunk)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::{ZoneIndex, ZoneOrigin, ZoneType};
+    use geo_types::{LineString, MultiPolygon, Polygon};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    #[test]
+    fn zones_written_as_geoparquet() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cosmogony.parquet");
+        let ring = LineString::from(vec![(1., 2.), (3., 2.), (3., 5.), (1., 5.), (1., 2.)]);
+        let zones = vec![
+            Zone {
+                id: ZoneIndex { index: 0 },
+                osm_id: "relation:0".into(),
+                name: "Somewhere".into(),
+                zone_type: Some(ZoneType::State),
+                origin: Some(ZoneOrigin::OsmRelation),
+                boundary: Some(MultiPolygon(vec![Polygon::new(ring, vec![])])),
+                ..Default::default()
+            },
+            Zone {
+                id: ZoneIndex { index: 1 },
+                osm_id: "node:1".into(),
+                name: "Somewhere city".into(),
+                parent: Some(ZoneIndex { index: 0 }),
+                population: Some(1000),
+                ..Default::default()
+            },
+        ];
+        write_zones_as_geoparquet(&zones, &path).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        let geo = metadata
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .find(|kv| kv.key == "geo")
+            .and_then(|kv| kv.value.clone())
+            .unwrap();
+        let geo: serde_json::Value = serde_json::from_str(&geo).unwrap();
+        assert_eq!(geo["primary_column"], "geometry");
+        assert_eq!(
+            geo["columns"]["geometry"]["bbox"],
+            serde_json::json!([1., 2., 3., 5.])
+        );
+
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(rows[0].get_string(1).unwrap(), "relation:0");
+        assert_eq!(rows[0].get_string(3).unwrap(), "state");
+        assert_eq!(rows[0].get_string(12).unwrap(), "osm_relation");
+        // a multipolygon with 1 ring of 5 points
+        assert_eq!(rows[0].get_bytes(14).unwrap().len(), 9 + 9 + 4 + 5 * 16);
+        assert_eq!(rows[1].get_long(9).unwrap(), 0);
+        assert_eq!(rows[1].get_long(10).unwrap(), 1000);
+        assert!(rows[1].get_bytes(14).is_err());
+        let bbox = rows[0].get_group(15).unwrap();
+        let bbox: Vec<f64> = (0..4).map(|i| bbox.get_double(i).unwrap()).collect();
+        assert_eq!(bbox, vec![1., 2., 3., 5.]);
+        assert!(rows[1].get_group(15).is_err());
+        assert_eq!(
+            geo["columns"]["geometry"]["covering"]["bbox"]["xmin"],
+            serde_json::json!(["bbox", "xmin"])
+        );
+    }
+}
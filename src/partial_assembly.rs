@@ -0,0 +1,409 @@
+// When an extract clips a boundary relation, the ways outside the extract are missing and the
+// ways of the relation do not form closed rings: the zone has no boundary and is dropped, so the
+// regions at the border of the extract are silently absent.
+//
+// With the partial assembly, the ways (or pieces of ways, the nodes outside the extract being
+// missing too) are joined in chains, and the chains ending at the border of the extract are
+// closed along the extract's bounds (the bbox of all the nodes read). The dangling ends of the
+// chains split the extract's border in arcs alternately inside and outside the zone, so there
+// are only 2 possible closings, one being the complement of the other in the extract: the one
+// containing the admin_centre (or label) of the zone is chosen, or else the smaller one (the
+// zones clipped by an extract are usually mostly outside of it).
+//
+// The zones assembled this way are flagged as `clipped`.
+
+use crate::additional_zones::convert_to_geo;
+use crate::osm_store::OsmObjects;
+use crate::zone_ext::boundary_centroid;
+use cosmogony::{Zone, ZoneOrigin};
+use geo::prelude::BoundingRect;
+use geo_types::{Coord, LineString, MultiPolygon, Point, Polygon, Rect};
+use geos::{Geom, Geometry};
+use osmpbfreader::{NodeId, OsmId, OsmObj, Relation};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The dangling ends of the chains must be closer to the extract's border than this ratio of
+/// the extract's largest side (the last node inside the extract is not exactly on its border)
+const MAX_DISTANCE_TO_BORDER: f64 = 0.02;
+
+/// A chain of the nodes of the relation's ways, joined by their common nodes
+type Chain = Vec<(NodeId, Coord<f64>)>;
+
+/// The bounds of the extract: the bbox of all the nodes read
+pub fn extract_bounds(pbf: &dyn OsmObjects) -> Option<Rect<f64>> {
+    let mut bounds: Option<Rect<f64>> = None;
+    for obj in pbf.objects() {
+        let Some(node) = obj.node() else { continue };
+        let c = Coord {
+            x: node.lon(),
+            y: node.lat(),
+        };
+        bounds = Some(match bounds {
+            None => Rect::new(c, c),
+            Some(b) => Rect::new(
+                (b.min().x.min(c.x), b.min().y.min(c.y)),
+                (b.max().x.max(c.x), b.max().y.max(c.y)),
+            ),
+        });
+    }
+    bounds
+}
+
+/// The pieces of the relation's ways made of the nodes present in the extract
+fn way_pieces(relation: &Relation, objects: &BTreeMap<OsmId, OsmObj>) -> Vec<Chain> {
+    let mut pieces = vec![];
+    for way in relation
+        .refs
+        .iter()
+        .filter_map(|r| objects.get(&r.member)?.way())
+    {
+        let mut piece = vec![];
+        for id in &way.nodes {
+            match objects.get(&OsmId::Node(*id)).and_then(|o| o.node()) {
+                Some(node) => piece.push((
+                    *id,
+                    Coord {
+                        x: node.lon(),
+                        y: node.lat(),
+                    },
+                )),
+                None => pieces.push(std::mem::take(&mut piece)),
+            }
+        }
+        pieces.push(piece);
+    }
+    pieces.retain(|p| p.len() >= 2);
+    pieces
+}
+
+/// Join the pieces of ways having a common end
+fn join_pieces(mut pieces: Vec<Chain>) -> Vec<Chain> {
+    let mut chains = vec![];
+    while let Some(mut chain) = pieces.pop() {
+        loop {
+            let (first, last) = (chain[0].0, chain[chain.len() - 1].0);
+            if chain.len() > 2 && first == last {
+                break;
+            }
+            let Some(i) = pieces.iter().position(|p| {
+                [first, last].contains(&p[0].0) || [first, last].contains(&p[p.len() - 1].0)
+            }) else {
+                break;
+            };
+            let mut piece = pieces.swap_remove(i);
+            if piece[0].0 == last {
+                chain.extend(piece.drain(1..));
+            } else if piece[piece.len() - 1].0 == last {
+                piece.reverse();
+                chain.extend(piece.drain(1..));
+            } else {
+                if piece[0].0 == first {
+                    piece.reverse();
+                }
+                piece.pop();
+                piece.append(&mut chain);
+                chain = piece;
+            }
+        }
+        chains.push(chain);
+    }
+    chains
+}
+
+/// The position of a point projected on the extract's border, as the distance from the
+/// south-west corner counterclockwise (each side counting for 1), and its distance to the border
+fn border_position(bounds: &Rect<f64>, c: Coord<f64>) -> (f64, f64) {
+    let (min, max) = (bounds.min(), bounds.max());
+    let (width, height) = (
+        bounds.width().max(f64::EPSILON),
+        bounds.height().max(f64::EPSILON),
+    );
+    let sides = [
+        (c.y - min.y, (c.x - min.x) / width),
+        (max.x - c.x, 1. + (c.y - min.y) / height),
+        (max.y - c.y, 2. + (max.x - c.x) / width),
+        (c.x - min.x, 3. + (max.y - c.y) / height),
+    ];
+    let (distance, position) = sides
+        .into_iter()
+        .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+        .expect("a rect has 4 sides");
+    (position.clamp(0., 4.), distance.abs())
+}
+
+/// The point of the extract's border at a position
+fn border_point(bounds: &Rect<f64>, position: f64) -> Coord<f64> {
+    let (min, max) = (bounds.min(), bounds.max());
+    let side = (position.floor() as usize).min(3);
+    let offset = position - side as f64;
+    match side {
+        0 => Coord {
+            x: min.x + offset * bounds.width(),
+            y: min.y,
+        },
+        1 => Coord {
+            x: max.x,
+            y: min.y + offset * bounds.height(),
+        },
+        2 => Coord {
+            x: max.x - offset * bounds.width(),
+            y: max.y,
+        },
+        _ => Coord {
+            x: min.x,
+            y: max.y - offset * bounds.height(),
+        },
+    }
+}
+
+/// The points of the extract's border from a position to another one, its corners included
+fn border_arc(bounds: &Rect<f64>, from: f64, to: f64) -> Vec<Coord<f64>> {
+    let mut arc = vec![border_point(bounds, from)];
+    if from < to {
+        let corners = (from.floor() as i32 + 1)..=(to.ceil() as i32 - 1);
+        arc.extend(corners.map(|k| border_point(bounds, k as f64)));
+    } else {
+        let corners = ((to.floor() as i32 + 1)..=(from.ceil() as i32 - 1)).rev();
+        arc.extend(corners.map(|k| border_point(bounds, k as f64)));
+    }
+    arc.push(border_point(bounds, to));
+    arc
+}
+
+/// Close the open chains along the extract's border, each dangling end being linked to the
+/// next one counterclockwise, alternately (so the rings never cross)
+fn close_chains(bounds: &Rect<f64>, chains: &[&Chain]) -> Option<Vec<LineString<f64>>> {
+    let max_distance = MAX_DISTANCE_TO_BORDER * bounds.width().max(bounds.height());
+    // the ends of the chain `i` are the vertices `2i` (its start) and `2i + 1` (its end)
+    let mut positions = vec![];
+    for chain in chains {
+        for c in [chain[0].1, chain[chain.len() - 1].1] {
+            let (position, distance) = border_position(bounds, c);
+            if distance > max_distance {
+                // the ways are broken inside the extract, not clipped by it
+                return None;
+            }
+            positions.push(position);
+        }
+    }
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by(|a, b| positions[*a].total_cmp(&positions[*b]));
+    let mut linked = vec![0; positions.len()];
+    for pair in order.chunks(2) {
+        linked[pair[0]] = pair[1];
+        linked[pair[1]] = pair[0];
+    }
+
+    let mut rings = vec![];
+    let mut visited = BTreeSet::new();
+    for start in (0..chains.len()).map(|i| 2 * i) {
+        let mut ring = vec![];
+        let mut vertex = start;
+        while visited.insert(vertex / 2) {
+            let chain = chains[vertex / 2];
+            let end = vertex ^ 1;
+            if vertex % 2 == 0 {
+                ring.extend(chain.iter().map(|(_, c)| *c));
+            } else {
+                ring.extend(chain.iter().rev().map(|(_, c)| *c));
+            }
+            let next = linked[end];
+            ring.extend(border_arc(bounds, positions[end], positions[next]));
+            vertex = next;
+        }
+        if ring.len() >= 3 {
+            ring.push(ring[0]);
+            rings.push(LineString::from(ring));
+        }
+    }
+    Some(rings)
+}
+
+/// The symmetric difference of the rings, the area covered by an odd number of rings
+fn rings_area(
+    rings: impl IntoIterator<Item = LineString<f64>>,
+) -> geos::GResult<Geometry<'static>> {
+    let mut area = Geometry::create_empty_polygon()?;
+    for ring in rings {
+        let polygon = Geometry::try_from(&Polygon::new(ring, vec![]))?.buffer(0., 1)?;
+        area = area.sym_difference(&polygon)?;
+    }
+    Ok(area)
+}
+
+/// Assemble the boundary of a relation clipped by the extract, closing its chains along the
+/// extract's bounds
+pub fn assemble_clipped_boundary(
+    relation: &Relation,
+    objects: &BTreeMap<OsmId, OsmObj>,
+    bounds: &Rect<f64>,
+    centre: Option<Point<f64>>,
+) -> Option<MultiPolygon<f64>> {
+    let chains = join_pieces(way_pieces(relation, objects));
+    let (closed, open): (Vec<&Chain>, Vec<&Chain>) = chains
+        .iter()
+        .partition(|c| c.len() > 3 && c[0].0 == c[c.len() - 1].0);
+    if open.is_empty() {
+        return None;
+    }
+    let open_rings = close_chains(bounds, &open)?;
+    let closed_rings = closed
+        .into_iter()
+        .map(|c| LineString::from(c.iter().map(|(_, c)| *c).collect::<Vec<_>>()));
+
+    let boundary = || -> geos::GResult<Geometry<'static>> {
+        let closed_area = rings_area(closed_rings)?;
+        let inside = rings_area(open_rings)?;
+        let extract = Geometry::try_from(&bounds.to_polygon())?;
+        let outside = extract.difference(&inside)?;
+        let candidates = [
+            inside.sym_difference(&closed_area)?,
+            outside.sym_difference(&closed_area)?,
+        ];
+        let contains_centre = |c: &Geometry<'_>| -> geos::GResult<Option<bool>> {
+            centre
+                .map(|p| c.covers(&Geometry::try_from(&p)?))
+                .transpose()
+        };
+        let [a, b] = candidates;
+        let chosen = match contains_centre(&a)? {
+            Some(true) => a,
+            Some(false) if contains_centre(&b)? == Some(true) => b,
+            _ if a.area()? <= b.area()? => a,
+            _ => b,
+        };
+        Ok(chosen)
+    };
+    let boundary = match boundary() {
+        Ok(boundary) => boundary,
+        Err(e) => {
+            warn!(
+                "relation:{}: impossible to assemble the clipped boundary: {}",
+                relation.id.0, e
+            );
+            return None;
+        }
+    };
+    convert_to_geo(boundary)
+        .map_err(|e| {
+            warn!(
+                "relation:{}: impossible to convert the clipped boundary: {}",
+                relation.id.0, e
+            )
+        })
+        .ok()
+        .flatten()
+}
+
+/// Give its clipped boundary to a zone without boundary, flagging it as `clipped`
+///
+/// Returns false if the boundary of the zone cannot be assembled.
+pub fn assemble_clipped_zone(
+    zone: &mut Zone,
+    relation: &Relation,
+    objects: &BTreeMap<OsmId, OsmObj>,
+    bounds: &Rect<f64>,
+) -> bool {
+    let Some(boundary) = assemble_clipped_boundary(relation, objects, bounds, zone.admin_centre)
+    else {
+        return false;
+    };
+    debug!("{}: boundary clipped by the extract", zone.osm_id);
+    zone.center = zone
+        .admin_centre
+        .or_else(|| boundary_centroid(Some(&boundary), &zone.osm_id));
+    zone.bbox = boundary.bounding_rect();
+    zone.boundary = Some(boundary);
+    zone.origin = Some(ZoneOrigin::OsmRelation);
+    zone.clipped = true;
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::prelude::Area;
+    use osmpbfreader::{Node, Ref, RelationId, Way, WayId};
+
+    /// a relation made of ways through the given points (the points outside the extract
+    /// are missing), and the nodes of the ways
+    fn relation(ways: &[&[(f64, f64)]]) -> (Relation, BTreeMap<OsmId, OsmObj>) {
+        let mut objects = BTreeMap::new();
+        let mut refs = vec![];
+        let mut next_node = 0;
+        for (i, points) in ways.iter().enumerate() {
+            let mut nodes = vec![];
+            for (x, y) in points.iter() {
+                // the same point is the same node
+                let existing = objects.values().find_map(|o: &OsmObj| {
+                    let n = o.node()?;
+                    (n.lon() == *x && n.lat() == *y).then_some(n.id)
+                });
+                let id = existing.unwrap_or_else(|| {
+                    next_node += 1;
+                    NodeId(next_node)
+                });
+                nodes.push(id);
+                if (0. ..=10.).contains(x) && (0. ..=10.).contains(y) {
+                    let node = Node {
+                        id,
+                        tags: Default::default(),
+                        decimicro_lat: (y * 1e7) as i32,
+                        decimicro_lon: (x * 1e7) as i32,
+                    };
+                    objects.insert(OsmId::Node(id), OsmObj::Node(node));
+                }
+            }
+            let way = Way {
+                id: WayId(i as i64),
+                tags: Default::default(),
+                nodes,
+            };
+            refs.push(Ref {
+                member: way.id.into(),
+                role: "outer".into(),
+            });
+            objects.insert(OsmId::Way(way.id), OsmObj::Way(way));
+        }
+        let relation = Relation {
+            id: RelationId(1),
+            tags: Default::default(),
+            refs,
+        };
+        (relation, objects)
+    }
+
+    fn area(boundary: &MultiPolygon<f64>) -> f64 {
+        (boundary.unsigned_area() * 1e6).round() / 1e6
+    }
+
+    #[test]
+    fn clipped_boundaries_are_closed_along_the_extract() {
+        let bounds = Rect::new((0., 0.), (10., 10.));
+        // a zone crossing the east border of the extract, split in 2 ways
+        let (rel, objects) = relation(&[
+            &[(12., 2.), (10., 2.), (6., 2.), (6., 8.)],
+            &[(6., 8.), (10., 8.), (12., 8.)],
+        ]);
+        let boundary = assemble_clipped_boundary(&rel, &objects, &bounds, None).unwrap();
+        assert_eq!(area(&boundary), 24.);
+
+        // the zone is the other side if its admin_centre is there
+        let centre = Some(Point::new(1., 1.));
+        let boundary = assemble_clipped_boundary(&rel, &objects, &bounds, centre).unwrap();
+        assert_eq!(area(&boundary), 76.);
+
+        // a zone crossing the south west corner, with a second complete ring in the extract
+        let (rel, objects) = relation(&[
+            &[(-2., 4.), (0., 4.), (4., 4.), (4., 0.), (4., -2.)],
+            &[(7., 7.), (8., 7.), (8., 8.), (7., 8.), (7., 7.)],
+        ]);
+        let boundary = assemble_clipped_boundary(&rel, &objects, &bounds, None).unwrap();
+        assert_eq!(area(&boundary), 17.);
+        assert_eq!(boundary.0.len(), 2);
+
+        // the ways are broken in the middle of the extract, it is not a clipping
+        let (rel, objects) = relation(&[&[(12., 2.), (10., 2.), (6., 2.), (6., 8.)]]);
+        assert!(assemble_clipped_boundary(&rel, &objects, &bounds, None).is_none());
+    }
+}
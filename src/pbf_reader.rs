@@ -0,0 +1,285 @@
+// The osm objects are read from the pbf in several passes: the objects needed first, then their
+// dependencies (the members of the relations and the nodes of the ways), until all of them are
+// read.
+//
+// The reader of osmpbfreader decodes the blobs in parallel, but the objects of all the blobs are
+// then built and filtered one by one by the reading thread, and this is most of the time of the
+// "Reading pbf" phase. Here, the blobs are decoded and their objects filtered on rayon's thread
+// pool, and only the objects kept are sent to the reading thread, to be stored.
+//
+// osmpbfreader does not keep the metadata of the objects (version, changeset, timestamp), they
+// are read from the decoded blocks if asked.
+//
+// The osm changes (eg. the replication diffs since the pbf was written) are applied while
+// reading: the changed objects of the pbf are skipped, and the objects of the changes are read
+// instead, at the same passes as the ones of the pbf.
+
+use crate::options::CancellationToken;
+use crate::osm_change::OsmChange;
+use anyhow::Error;
+use cosmogony::OsmMetadata;
+use osmpbfreader::osmformat::{Info, PrimitiveBlock};
+use osmpbfreader::{
+    blocks, primitive_block_from_blob, NodeId, OsmId, OsmObj, OsmPbfReader, RelationId, StoreObjs,
+    WayId,
+};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Seek};
+use std::sync::mpsc::sync_channel;
+
+/// The blocks decoded waiting to be stored, by thread of the pool
+const PENDING_BLOCKS_BY_THREAD: usize = 4;
+
+/// Read the objects validating a predicate, and all their dependencies, decoding the blobs of
+/// the pbf on rayon's thread pool
+///
+/// The objects read are the same as the ones of `OsmPbfReader::get_objs_and_deps_store`. With
+/// a `metadata` map, the metadata of the objects read are also stored in it (not for the
+/// changed objects). The reading stops with a `Cancelled` error once the cancellation token
+/// is cancelled.
+pub fn read_objs_and_deps<R, F, T>(
+    reader: &mut OsmPbfReader<R>,
+    pred: F,
+    objects: &mut T,
+    mut metadata: Option<&mut BTreeMap<OsmId, OsmMetadata>>,
+    changes: &OsmChange,
+    cancellation: &CancellationToken,
+) -> Result<(), Error>
+where
+    R: Read + Seek + Send,
+    F: Fn(&OsmObj) -> bool + Sync,
+    T: StoreObjs,
+{
+    // the dependencies to read in the next pass, and all the ones already looked for (the
+    // objects missing from the pbf are only looked for once)
+    let mut wanted = HashSet::<OsmId>::new();
+    let mut looked_for = HashSet::<OsmId>::new();
+    let mut first_pass = true;
+    loop {
+        reader.rewind()?;
+        let mut next_wanted = HashSet::new();
+        std::thread::scope(|scope| -> Result<(), Error> {
+            let capacity = PENDING_BLOCKS_BY_THREAD * rayon::current_num_threads();
+            let (sender, receiver) = sync_channel(capacity);
+            let is_read = |obj: &OsmObj| (first_pass && pred(obj)) || wanted.contains(&obj.id());
+            let is_read_from_pbf =
+                move |obj: &OsmObj| changes.get(&obj.id()).is_none() && is_read(obj);
+            let changed: Vec<OsmObj> = changes.objects().filter(|o| is_read(o)).cloned().collect();
+            let with_metadata = metadata.is_some();
+            let blobs = reader.blobs();
+            let decoding = scope.spawn(move || {
+                blobs
+                    .par_bridge()
+                    .try_for_each_with(sender, |sender, blob| {
+                        cancellation.check()?;
+                        let block = primitive_block_from_blob(&blob?)?;
+                        let read: Vec<OsmObj> =
+                            blocks::iter(&block).filter(is_read_from_pbf).collect();
+                        let read_metadata = if with_metadata {
+                            block_metadata(&block, &read)
+                        } else {
+                            vec![]
+                        };
+                        // the reading thread only stops receiving on a panic
+                        let _ = sender.send((read, read_metadata));
+                        Ok::<_, Error>(())
+                    })
+            });
+            let changed = std::iter::once((changed, vec![]));
+            for (read, read_metadata) in changed.chain(receiver) {
+                if let Some(metadata) = metadata.as_deref_mut() {
+                    metadata.extend(read_metadata);
+                }
+                for obj in read {
+                    let dependencies: Vec<OsmId> = match obj {
+                        OsmObj::Relation(ref rel) => rel.refs.iter().map(|r| r.member).collect(),
+                        OsmObj::Way(ref way) => way.nodes.iter().map(|n| (*n).into()).collect(),
+                        OsmObj::Node(_) => vec![],
+                    };
+                    for id in dependencies {
+                        if !objects.contains_key(&id) && looked_for.insert(id) {
+                            next_wanted.insert(id);
+                        }
+                    }
+                    next_wanted.remove(&obj.id());
+                    objects.insert(obj.id(), obj);
+                }
+            }
+            decoding.join().expect("the decoding of the pbf panicked")
+        })?;
+        if next_wanted.is_empty() {
+            return Ok(());
+        }
+        wanted = next_wanted;
+        first_pass = false;
+    }
+}
+
+/// The metadata of the objects read from a block (the blocks have no metadata if the pbf has
+/// been written without them)
+fn block_metadata(block: &PrimitiveBlock, read: &[OsmObj]) -> Vec<(OsmId, OsmMetadata)> {
+    let ids: HashSet<OsmId> = read.iter().map(|o| o.id()).collect();
+    if ids.is_empty() {
+        return vec![];
+    }
+    // the timestamps are in units of `date_granularity` milliseconds
+    let granularity = i64::from(block.get_date_granularity());
+    let metadata = |version: i32, changeset: i64, timestamp: i64| {
+        let timestamp = timestamp * granularity / 1000;
+        OsmMetadata {
+            version: version.max(0) as u32,
+            changeset,
+            timestamp,
+            last_modified: timestamp,
+        }
+    };
+    let mut infos: Vec<(OsmId, &Info)> = vec![];
+    let mut block_metadata = vec![];
+    for group in block.get_primitivegroup() {
+        let nodes = group
+            .get_nodes()
+            .iter()
+            .filter(|n| n.has_info())
+            .map(|n| (OsmId::Node(NodeId(n.get_id())), n.get_info()));
+        let ways = group
+            .get_ways()
+            .iter()
+            .filter(|w| w.has_info())
+            .map(|w| (OsmId::Way(WayId(w.get_id())), w.get_info()));
+        let relations = group
+            .get_relations()
+            .iter()
+            .filter(|r| r.has_info())
+            .map(|r| (OsmId::Relation(RelationId(r.get_id())), r.get_info()));
+        infos.extend(nodes.chain(ways).chain(relations));
+
+        // the ids, timestamps and changesets of the dense nodes are delta coded
+        let dense = group.get_dense();
+        if dense.has_denseinfo() {
+            let info = dense.get_denseinfo();
+            let (mut id, mut timestamp, mut changeset) = (0, 0, 0);
+            for (i, delta_id) in dense.get_id().iter().enumerate() {
+                id += delta_id;
+                timestamp += info.get_timestamp().get(i).copied().unwrap_or_default();
+                changeset += info.get_changeset().get(i).copied().unwrap_or_default();
+                let version = info.get_version().get(i).copied().unwrap_or_default();
+                let id = OsmId::Node(NodeId(id));
+                if ids.contains(&id) && version > 0 {
+                    block_metadata.push((id, metadata(version, changeset, timestamp)));
+                }
+            }
+        }
+    }
+    for (id, info) in infos {
+        if ids.contains(&id) && info.get_version() > 0 {
+            let (version, changeset) = (info.get_version(), info.get_changeset());
+            block_metadata.push((id, metadata(version, changeset, info.get_timestamp())));
+        }
+    }
+    block_metadata
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs::File;
+
+    #[test]
+    fn objects_read_in_parallel() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/luxembourg_filtered.osm.pbf"
+        );
+        let is_needed = |o: &OsmObj| crate::is_admin(o) || crate::is_place(o);
+        let expected = OsmPbfReader::new(File::open(path).unwrap())
+            .get_objs_and_deps(is_needed)
+            .unwrap();
+
+        let mut objects = BTreeMap::new();
+        let mut reader = OsmPbfReader::new(File::open(path).unwrap());
+        read_objs_and_deps(
+            &mut reader,
+            is_needed,
+            &mut objects,
+            None,
+            &OsmChange::default(),
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert!(!objects.is_empty());
+        assert_eq!(objects.len(), expected.len());
+        assert!(objects.keys().eq(expected.keys()));
+    }
+
+    #[test]
+    fn changes_applied_while_reading() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/luxembourg_filtered.osm.pbf"
+        );
+        let is_needed = |o: &OsmObj| crate::is_admin(o);
+        let mut objects = BTreeMap::new();
+        let mut reader = OsmPbfReader::new(File::open(path).unwrap());
+        let none = OsmChange::default();
+        let cancellation = CancellationToken::default();
+        read_objs_and_deps(
+            &mut reader,
+            is_needed,
+            &mut objects,
+            None,
+            &none,
+            &cancellation,
+        )
+        .unwrap();
+        let mut relations = objects.values().filter_map(|o| o.relation());
+        let (deleted, renamed) = (relations.next().unwrap(), relations.next().unwrap());
+        let way = renamed
+            .refs
+            .iter()
+            .filter_map(|r| r.member.way())
+            .find(|w| objects.contains_key(&(*w).into()))
+            .unwrap();
+
+        // a relation deleted, another one renamed, and a new boundary using a way of the pbf
+        let changes = format!(
+            r#"<osmChange>
+              <delete><relation id="{}"/></delete>
+              <modify><relation id="{}">
+                <member type="way" ref="{}" role="outer"/>
+                <tag k="boundary" v="administrative"/>
+                <tag k="admin_level" v="8"/>
+                <tag k="name" v="Renamed"/>
+              </relation></modify>
+              <create><relation id="1">
+                <member type="way" ref="{}" role="outer"/>
+                <tag k="boundary" v="administrative"/>
+                <tag k="admin_level" v="10"/>
+              </relation></create>
+            </osmChange>"#,
+            deleted.id.0, renamed.id.0, way.0, way.0
+        );
+        let changes = OsmChange::from_xml(changes.as_bytes()).unwrap();
+        let mut changed = BTreeMap::new();
+        let mut reader = OsmPbfReader::new(File::open(path).unwrap());
+        read_objs_and_deps(
+            &mut reader,
+            is_needed,
+            &mut changed,
+            None,
+            &changes,
+            &cancellation,
+        )
+        .unwrap();
+
+        assert!(!changed.contains_key(&deleted.id.into()));
+        let renamed = changed[&renamed.id.into()].relation().unwrap();
+        assert_eq!(renamed.tags.get("name").unwrap().as_str(), "Renamed");
+        assert_eq!(renamed.refs.len(), 1);
+        assert!(changed.contains_key(&RelationId(1).into()));
+        // the way and its nodes are read from the pbf
+        let way = changed[&way.into()].way().unwrap();
+        assert!(way.nodes.iter().all(|n| changed.contains_key(&(*n).into())));
+    }
+}
@@ -0,0 +1,1022 @@
+// The cosmogony build is a sequence of stages, each one working on the zones
+// computed by the previous ones.
+
+use crate::additional_zones::compute_additional_places;
+use crate::country_cache::CountryCache;
+use crate::country_finder::read_country_land_areas;
+use crate::country_post_processing::CountryPostProcessors;
+use crate::dedup::remove_duplicate_zones;
+use crate::enclaves::handle_enclaves;
+use crate::extra_zones::{add_extra_zones, resolve_extra_zones_conflicts};
+#[cfg(feature = "h3")]
+use crate::h3_cells::assign_h3_cells;
+use crate::hierarchy_builder::{
+    apply_parent_overrides, build_hierarchy, build_multi_parent_hierarchy, find_inclusions,
+    propagate_population, read_parent_overrides, record_inclusions, restrict_disputed_inclusions,
+    ZonesTree,
+};
+use crate::land_clip::{clip_zones_to_land, LandPolygons};
+use crate::missing_levels::infer_missing_levels;
+use crate::name_collisions::disambiguate_labels;
+use crate::neighbors::find_neighbors;
+use crate::options::{BuildOptions, Cancelled, DEFAULT_SIBLING_OVERLAP_THRESHOLD};
+use crate::osm_store::OsmObjects;
+use crate::precision::round_coordinates;
+use crate::sibling_overlaps::find_sibling_overlaps;
+use crate::simplification::{simplify_zones, snap_borders};
+use crate::timezones::{assign_timezones, TimezoneFinder};
+use crate::transliteration::romanize_names;
+use crate::winding_order::orient_boundaries;
+use crate::zone_ext::ZoneExt;
+use crate::{
+    clean_untagged_zones, compute_all_labels, filter_tags, label_templates, read_zones, trim_names,
+    type_zones,
+};
+use anyhow::{anyhow, Context, Error};
+use cosmogony::{BuildWarning, BuildWarningKind, CosmogonyStats, PhaseDuration, Zone, ZoneIndex};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The data shared by the stages of a pipeline
+pub struct PipelineContext<'a> {
+    pub zones: &'a mut Vec<Zone>,
+    pub stats: &'a mut CosmogonyStats,
+    pub parsed_pbf: &'a dyn OsmObjects,
+    pub options: &'a BuildOptions,
+    /// the recoverable problems found by the stages, reported in the metadata
+    pub warnings: Vec<BuildWarning>,
+    /// the zones read from the extra zones file
+    pub(crate) extra_zones: Range<usize>,
+    /// the fingerprint of the input file, keying the country cache
+    pub(crate) input_fingerprint: Option<String>,
+    /// the durations of the phases of the build (the stats are replaced by the extract stage)
+    pub(crate) phase_durations: Vec<PhaseDuration>,
+    /// the total duration of the inclusions computations, to exclude it from the stages durations
+    inclusions_duration: Duration,
+    /// the inclusions and the rtree are costly to compute, so they are shared by the stages.
+    /// They are only valid for `indexed_zones` zones, as the zones indexes are invalidated
+    /// when zones are added or removed.
+    inclusions: Option<Vec<Vec<ZoneIndex>>>,
+    zones_rtree: Option<ZonesTree>,
+    indexed_zones: usize,
+}
+
+impl<'a> PipelineContext<'a> {
+    pub fn new(
+        zones: &'a mut Vec<Zone>,
+        stats: &'a mut CosmogonyStats,
+        parsed_pbf: &'a dyn OsmObjects,
+        options: &'a BuildOptions,
+    ) -> Self {
+        let nb_zones = zones.len();
+        PipelineContext {
+            zones,
+            stats,
+            parsed_pbf,
+            options,
+            warnings: vec![],
+            extra_zones: nb_zones..nb_zones,
+            input_fingerprint: None,
+            phase_durations: vec![],
+            inclusions_duration: Duration::ZERO,
+            inclusions: None,
+            zones_rtree: None,
+            indexed_zones: 0,
+        }
+    }
+
+    fn indexes_are_valid(&self) -> bool {
+        self.indexed_zones == self.zones.len()
+    }
+
+    fn compute_inclusions_if_needed(&mut self) -> Result<(), Cancelled> {
+        if !self.indexes_are_valid() || self.inclusions.is_none() {
+            let start = Instant::now();
+            let (inclusions, ztree) = find_inclusions(
+                self.zones,
+                self.options.inclusion_threshold,
+                &self.options.cancellation,
+            )?;
+            self.inclusions = Some(inclusions);
+            self.zones_rtree = Some(ztree);
+            self.indexed_zones = self.zones.len();
+            self.inclusions_duration += start.elapsed();
+            self.record_duration("inclusions", start.elapsed());
+        }
+        Ok(())
+    }
+
+    /// add the duration to the phase (the inclusions can be computed several times)
+    pub(crate) fn record_duration(&mut self, phase: &str, duration: Duration) {
+        match self.phase_durations.iter_mut().find(|d| d.phase == phase) {
+            Some(d) => d.seconds += duration.as_secs_f64(),
+            None => self.phase_durations.push(PhaseDuration {
+                phase: phase.to_string(),
+                seconds: duration.as_secs_f64(),
+            }),
+        }
+    }
+
+    fn compute_zones_rtree_if_needed(&mut self) {
+        if !self.indexes_are_valid() {
+            self.inclusions = None;
+            self.zones_rtree = None;
+            self.indexed_zones = self.zones.len();
+        }
+        if self.zones_rtree.is_none() {
+            self.zones_rtree = Some(self.zones.iter().collect());
+        }
+    }
+
+    /// take the inclusions, they will need to be recomputed by the next stages
+    pub(crate) fn take_inclusions(&mut self) -> Result<Vec<Vec<ZoneIndex>>, Cancelled> {
+        self.compute_inclusions_if_needed()?;
+        Ok(self.inclusions.take().unwrap_or_default())
+    }
+
+    /// take the rtree of the zones, it will need to be recomputed by the next stages
+    pub(crate) fn take_zones_rtree(&mut self) -> ZonesTree {
+        self.compute_zones_rtree_if_needed();
+        self.zones_rtree
+            .take()
+            .unwrap_or_else(|| self.zones.iter().collect())
+    }
+}
+
+/// A step of the cosmogony build
+pub trait Stage {
+    /// name of the stage, used to omit the stage or insert another stage relatively to this one
+    fn name(&self) -> &'static str;
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error>;
+}
+
+/// Read the zones from the osm objects
+pub struct Extract;
+
+impl Stage for Extract {
+    fn name(&self) -> &'static str {
+        "extract"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        let (zones, stats) = read_zones(ctx.parsed_pbf, ctx.options, &mut ctx.warnings)?;
+        *ctx.zones = zones;
+        *ctx.stats = stats;
+        ctx.extra_zones = ctx.zones.len()..ctx.zones.len();
+        Ok(())
+    }
+}
+
+/// Remove the zones duplicating another zone (eg. a way mapped along its relation)
+pub struct Dedup;
+
+impl Stage for Dedup {
+    fn name(&self) -> &'static str {
+        "dedup"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        remove_duplicate_zones(ctx.zones, ctx.stats);
+        let duplicates = ctx.stats.duplicate_zones.iter();
+        ctx.warnings
+            .extend(duplicates.map(|(duplicate, kept)| BuildWarning {
+                kind: BuildWarningKind::DroppedDuplicate,
+                osm_id: duplicate.clone(),
+                message: format!("duplicate of {}, removed", kept),
+            }));
+        ctx.extra_zones = ctx.zones.len()..ctx.zones.len();
+        Ok(())
+    }
+}
+
+/// Add the zones of the extra zones file, if any
+pub struct ExtraZones;
+
+impl Stage for ExtraZones {
+    fn name(&self) -> &'static str {
+        "extra_zones"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if let Some(ref path) = ctx.options.extra_zones {
+            ctx.extra_zones = add_extra_zones(ctx.zones, path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Detect the enclaves, and attach them to their own country
+pub struct Enclaves;
+
+impl Stage for Enclaves {
+    fn name(&self) -> &'static str {
+        "enclaves"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        ctx.compute_inclusions_if_needed()?;
+        if let (Some(inclusions), Some(ztree)) = (&mut ctx.inclusions, &ctx.zones_rtree) {
+            handle_enclaves(ctx.zones, inclusions, ztree, ctx.stats);
+        }
+        Ok(())
+    }
+}
+
+/// Infer the admin_level of the administrative zones without admin_level, if they are kept
+pub struct MissingLevels;
+
+impl Stage for MissingLevels {
+    fn name(&self) -> &'static str {
+        "missing_levels"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if ctx.options.infer_missing_levels {
+            ctx.compute_inclusions_if_needed()?;
+            let inclusions = ctx.inclusions.as_deref().unwrap_or_default();
+            ctx.stats.levels_inferred = infer_missing_levels(ctx.zones, inclusions);
+        }
+        Ok(())
+    }
+}
+
+/// Find the type and the country of the zones
+pub struct Type;
+
+impl Stage for Type {
+    fn name(&self) -> &'static str {
+        "type"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        ctx.compute_inclusions_if_needed()?;
+        ctx.compute_zones_rtree_if_needed();
+        let inclusions = ctx.inclusions.as_deref().unwrap_or_default();
+        let land_areas = if ctx.options.country_land_areas {
+            read_country_land_areas(ctx.parsed_pbf)
+        } else {
+            BTreeMap::new()
+        };
+        let cache = match (&ctx.options.country_cache, &ctx.input_fingerprint) {
+            (Some(path), Some(fingerprint)) => {
+                let cache = CountryCache::new(path, fingerprint, ctx.zones, ctx.options)?;
+                if cache.is_none() {
+                    info!("the osm objects are filtered, the country cache is not used");
+                }
+                cache
+            }
+            (Some(_), None) => {
+                info!("no input file fingerprint, the country cache is not used");
+                None
+            }
+            _ => None,
+        };
+        type_zones(
+            ctx.zones,
+            ctx.stats,
+            ctx.options,
+            inclusions,
+            land_areas,
+            cache.as_ref(),
+        )?;
+        if let Some(ref ztree) = ctx.zones_rtree {
+            resolve_extra_zones_conflicts(
+                ctx.zones,
+                ctx.extra_zones.clone(),
+                ztree,
+                ctx.options.extra_zones_policy,
+                ctx.stats,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Fix the known oddities of some countries, once the zones are typed
+#[derive(Default)]
+pub struct CountryPostProcessing(pub CountryPostProcessors);
+
+impl Stage for CountryPostProcessing {
+    fn name(&self) -> &'static str {
+        "country_post_processing"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        info!("running the country post-processors");
+        self.0.process(ctx.zones);
+        Ok(())
+    }
+}
+
+/// Attach each zone to its parent
+pub struct Hierarchy;
+
+impl Stage for Hierarchy {
+    fn name(&self) -> &'static str {
+        "hierarchy"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        let strategy = ctx.options.disputed_strategy;
+        let mut inclusions = ctx.take_inclusions()?;
+        // all the inclusions are recorded, even the ones excluded by the disputed strategy
+        let kept_inclusions = ctx.options.keep_inclusions.then(|| inclusions.clone());
+        restrict_disputed_inclusions(ctx.zones, &mut inclusions, strategy);
+        let warnings = if ctx.options.multi_parents {
+            build_multi_parent_hierarchy(ctx.zones, inclusions, &ctx.options.cancellation)?
+        } else {
+            build_hierarchy(ctx.zones, inclusions, &ctx.options.cancellation)?
+        };
+        ctx.warnings.extend(warnings);
+        if let Some(ref inclusions) = kept_inclusions {
+            record_inclusions(ctx.zones, inclusions);
+        }
+        if let Some(ref path) = ctx.options.parent_overrides {
+            info!("reading parent overrides from {}", path.display());
+            let overrides = read_parent_overrides(path)?;
+            let nb_applied = apply_parent_overrides(ctx.zones, &overrides);
+            info!("{} parent overrides applied", nb_applied);
+        }
+        Ok(())
+    }
+}
+
+/// Report the overlapping siblings in the stats
+pub struct SiblingOverlaps;
+
+impl Stage for SiblingOverlaps {
+    fn name(&self) -> &'static str {
+        "sibling_overlaps"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        let threshold =
+            (ctx.options.sibling_overlap_threshold).unwrap_or(DEFAULT_SIBLING_OVERLAP_THRESHOLD);
+        ctx.stats.sibling_overlaps = find_sibling_overlaps(ctx.zones, threshold);
+        Ok(())
+    }
+}
+
+/// Build zones (with voronoi) for the places without boundary
+pub struct AdditionalZones;
+
+impl Stage for AdditionalZones {
+    fn name(&self) -> &'static str {
+        "additional_zones"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if ctx.options.disable_voronoi {
+            return Ok(());
+        }
+        let ztree = ctx.take_zones_rtree();
+        compute_additional_places(ctx.zones, ctx.parsed_pbf, ztree, ctx.options)
+    }
+}
+
+/// Give a timezone to the zones, if a timezones file is given
+pub struct Timezone;
+
+impl Stage for Timezone {
+    fn name(&self) -> &'static str {
+        "timezone"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if let Some(ref path) = ctx.options.timezones {
+            let finder = TimezoneFinder::from_file(path)?;
+            assign_timezones(ctx.zones, &finder);
+        }
+        Ok(())
+    }
+}
+
+/// Give to the zones the h3 cells covering them, if a resolution is given
+#[cfg(feature = "h3")]
+pub struct H3Cells;
+
+#[cfg(feature = "h3")]
+impl Stage for H3Cells {
+    fn name(&self) -> &'static str {
+        "h3_cells"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        match ctx.options.h3_resolution {
+            Some(resolution) => assign_h3_cells(ctx.zones, resolution),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Aggregate the population of the children zones, if enabled
+pub struct Population;
+
+impl Stage for Population {
+    fn name(&self) -> &'static str {
+        "population"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if ctx.options.propagate_population {
+            propagate_population(ctx.zones);
+        }
+        Ok(())
+    }
+}
+
+/// Compute the names of the zones
+pub struct Names;
+
+impl Stage for Names {
+    fn name(&self) -> &'static str {
+        "names"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        let reject_invalid_langs = ctx.options.reject_invalid_lang_tags;
+        ctx.zones
+            .iter_mut()
+            .for_each(|z| z.compute_names(reject_invalid_langs));
+        if ctx.options.alternate_names {
+            ctx.zones
+                .iter_mut()
+                .for_each(|z| z.compute_alternate_names(reject_invalid_langs));
+        }
+        if ctx.options.romanize_names {
+            romanize_names(ctx.zones);
+        }
+        Ok(())
+    }
+}
+
+/// Compute the labels of the zones
+pub struct Labels;
+
+impl Stage for Labels {
+    fn name(&self) -> &'static str {
+        "labels"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        let templates = label_templates(ctx.options)?;
+        compute_all_labels(ctx.zones, ctx.options, &templates, None);
+        Ok(())
+    }
+}
+
+/// Add a suffix to the labels of the siblings with the same label
+pub struct Disambiguation;
+
+impl Stage for Disambiguation {
+    fn name(&self) -> &'static str {
+        "disambiguation"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        // the labels of the descendants of the suffixed zones are built again
+        let templates = label_templates(ctx.options)?;
+        ctx.stats.name_collisions = disambiguate_labels(ctx.zones, |zones, only| {
+            compute_all_labels(zones, ctx.options, &templates, Some(only))
+        });
+        Ok(())
+    }
+}
+
+/// Remove the zones without type, and the tags and names not kept
+pub struct Clean;
+
+impl Stage for Clean {
+    fn name(&self) -> &'static str {
+        "clean"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        // We remove the useless zones from cosmogony.
+        //
+        // WARNING: this invalidates the different indexes  (we can no longer lookup a Zone by it's id
+        // in the zones's vector) this should be removed later on (and switch to a map by osm_id ?) as
+        // it's not elegant, but for the moment it'll do.
+        clean_untagged_zones(ctx.zones);
+        filter_tags(ctx.zones, &ctx.options.kept_tags);
+        trim_names(ctx.zones, &ctx.options.filter_langs);
+        ctx.inclusions = None;
+        ctx.zones_rtree = None;
+        Ok(())
+    }
+}
+
+/// Clip the boundaries to the land, if a land polygons file is given
+pub struct LandClip;
+
+impl Stage for LandClip {
+    fn name(&self) -> &'static str {
+        "land_clip"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if let Some(ref path) = ctx.options.land_polygons {
+            let land = LandPolygons::from_file(path)?;
+            let nb_clipped = clip_zones_to_land(ctx.zones, &land);
+            info!("{} zones clipped to the land", nb_clipped);
+        }
+        Ok(())
+    }
+}
+
+/// Snap the borders of the adjacent zones together, if a tolerance is given
+pub struct SnapBorders;
+
+impl Stage for SnapBorders {
+    fn name(&self) -> &'static str {
+        "snap_borders"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if let Some(tolerance) = ctx.options.snap_tolerance {
+            snap_borders(ctx.zones, tolerance);
+        }
+        Ok(())
+    }
+}
+
+/// Simplify the boundaries, if a tolerance is given
+pub struct Simplify;
+
+impl Stage for Simplify {
+    fn name(&self) -> &'static str {
+        "simplify"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if let Some(ref tolerances) = ctx.options.simplify {
+            simplify_zones(ctx.zones, tolerances, &ctx.options.cancellation)?;
+            // the area and the perimeter are the ones of the simplified boundaries
+            ctx.zones.par_iter_mut().for_each(|z| z.compute_measures());
+        }
+        Ok(())
+    }
+}
+
+/// Find the zones of the same type sharing a border, if asked
+pub struct Neighbors;
+
+impl Stage for Neighbors {
+    fn name(&self) -> &'static str {
+        "neighbors"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if ctx.options.neighbors {
+            find_neighbors(ctx.zones);
+        }
+        Ok(())
+    }
+}
+
+/// Round the coordinates, if a precision is given
+pub struct Precision;
+
+impl Stage for Precision {
+    fn name(&self) -> &'static str {
+        "precision"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        if let Some(decimals) = ctx.options.coordinates_precision {
+            round_coordinates(ctx.zones, decimals);
+        }
+        Ok(())
+    }
+}
+
+/// Orient the rings of the boundaries
+pub struct Orientation;
+
+impl Stage for Orientation {
+    fn name(&self) -> &'static str {
+        "orientation"
+    }
+
+    fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        orient_boundaries(ctx.zones, ctx.options.winding_order);
+        Ok(())
+    }
+}
+
+/// An ordered list of stages
+///
+/// The default pipeline runs all the stages, but it is possible to omit, reorder or insert
+/// custom stages (eg. to enrich the zones with an external dataset):
+///
+/// ```no_run
+/// use anyhow::Error;
+/// use cosmogony_builder::pipeline::{Pipeline, PipelineContext, Stage};
+///
+/// struct Uppercase;
+///
+/// impl Stage for Uppercase {
+///     fn name(&self) -> &'static str {
+///         "uppercase"
+///     }
+///     fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+///         ctx.zones.iter_mut().for_each(|z| z.name = z.name.to_uppercase());
+///         Ok(())
+///     }
+/// }
+///
+/// let pipeline = Pipeline::default()
+///     .without_stage("additional_zones")
+///     .with_stage_after("names", Uppercase);
+/// ```
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage + Send + Sync>>,
+}
+
+impl Default for Pipeline {
+    /// all the stages, from the osm objects to the cosmogony zones
+    fn default() -> Self {
+        Pipeline::ontology().with_stage_at(0, Extract)
+    }
+}
+
+impl Pipeline {
+    /// a pipeline without any stage
+    pub fn empty() -> Self {
+        Pipeline { stages: vec![] }
+    }
+
+    /// the stages building the ontology from already extracted zones
+    pub fn ontology() -> Self {
+        let pipeline = Pipeline::empty()
+            .with_stage(Dedup)
+            .with_stage(ExtraZones)
+            .with_stage(Enclaves)
+            .with_stage(MissingLevels)
+            .with_stage(Type)
+            .with_stage(CountryPostProcessing::default())
+            .with_stage(Hierarchy)
+            .with_stage(SiblingOverlaps)
+            .with_stage(AdditionalZones)
+            .with_stage(Timezone)
+            .with_stage(Population)
+            .with_stage(Names)
+            .with_stage(Labels)
+            .with_stage(Disambiguation)
+            .with_stage(Clean)
+            .with_stage(LandClip);
+        // the cells are computed with the full precision boundaries
+        #[cfg(feature = "h3")]
+        let pipeline = pipeline.with_stage(H3Cells);
+        pipeline
+            .with_stage(SnapBorders)
+            // the snapped borders are exactly shared, and not simplified yet
+            .with_stage(Neighbors)
+            .with_stage(Simplify)
+            .with_stage(Precision)
+            .with_stage(Orientation)
+    }
+
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|s| s.name()).collect()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.stages.iter().position(|s| s.name() == name)
+    }
+
+    /// add a stage at the end of the pipeline
+    pub fn with_stage(mut self, stage: impl Stage + Send + Sync + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    fn with_stage_at(mut self, idx: usize, stage: impl Stage + Send + Sync + 'static) -> Self {
+        self.stages.insert(idx, Box::new(stage));
+        self
+    }
+
+    /// insert a stage before the stage `name` (or at the end if there is no such stage)
+    pub fn with_stage_before(self, name: &str, stage: impl Stage + Send + Sync + 'static) -> Self {
+        let idx = self.position(name).unwrap_or(self.stages.len());
+        self.with_stage_at(idx, stage)
+    }
+
+    /// insert a stage after the stage `name` (or at the end if there is no such stage)
+    pub fn with_stage_after(self, name: &str, stage: impl Stage + Send + Sync + 'static) -> Self {
+        let idx = self.position(name).map_or(self.stages.len(), |idx| idx + 1);
+        self.with_stage_at(idx, stage)
+    }
+
+    /// replace the country post-processors of the pipeline (eg. to register custom processors)
+    pub fn with_country_post_processors(self, processors: CountryPostProcessors) -> Self {
+        let name = CountryPostProcessing::default().name();
+        let idx = self
+            .position(name)
+            .or_else(|| self.position("type").map(|idx| idx + 1));
+        let pipeline = self.without_stage(name);
+        match idx {
+            Some(idx) => pipeline.with_stage_at(idx, CountryPostProcessing(processors)),
+            None => pipeline.with_stage(CountryPostProcessing(processors)),
+        }
+    }
+
+    /// remove the stage `name`
+    pub fn without_stage(mut self, name: &str) -> Self {
+        self.stages.retain(|s| s.name() != name);
+        self
+    }
+
+    fn check_stage_names(&self, options: &BuildOptions) -> Result<(), Error> {
+        let names = self.stage_names();
+        for name in &options.skipped_stages {
+            if !names.contains(&name.as_str()) {
+                warn!("cannot skip stage {}, there is no such stage", name);
+            }
+        }
+        for name in options
+            .dump_after
+            .iter()
+            .map(|(name, _)| name)
+            .chain(options.stop_after.iter())
+        {
+            if !names.contains(&name.as_str()) {
+                return Err(anyhow!(
+                    "unknown stage '{}', the stages are: {}",
+                    name,
+                    names.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run all the stages, except the ones skipped in the options
+    ///
+    /// For debugging, the zones can be dumped after some stages, and the pipeline can
+    /// be stopped after a given stage.
+    pub fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+        self.check_stage_names(ctx.options)?;
+        for stage in &self.stages {
+            if ctx.options.skipped_stages.iter().any(|s| s == stage.name()) {
+                info!("skipping stage {}", stage.name());
+                continue;
+            }
+            ctx.options.cancellation.check()?;
+            info!("running stage {}", stage.name());
+            let start = Instant::now();
+            let inclusions_duration = ctx.inclusions_duration;
+            stage
+                .run(ctx)
+                .and_then(|_| ctx.parsed_pbf.check())
+                .with_context(|| format!("stage {} failed", stage.name()))?;
+            let duration = start
+                .elapsed()
+                .saturating_sub(ctx.inclusions_duration - inclusions_duration);
+            ctx.record_duration(stage.name(), duration);
+
+            for (_, dir) in ctx
+                .options
+                .dump_after
+                .iter()
+                .filter(|(s, _)| s == stage.name())
+            {
+                dump_zones(ctx.zones, stage.name(), dir)?;
+            }
+            if ctx.options.stop_after.as_deref() == Some(stage.name()) {
+                info!("stopping the pipeline after stage {}", stage.name());
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// write the zones in `<dir>/<stage>.jsonl`, one zone per line
+fn dump_zones(zones: &[Zone], stage: &str, dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir).with_context(|| format!("impossible to create {}", dir.display()))?;
+    let path = dir.join(format!("{}.jsonl", stage));
+    info!("dumping {} zones in {}", zones.len(), path.display());
+    let file =
+        File::create(&path).with_context(|| format!("impossible to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    for z in zones {
+        serde_json::to_writer(&mut writer, z)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::options::Cancelled;
+    use osmpbfreader::{OsmId, OsmObj};
+    use std::collections::BTreeMap;
+
+    struct Rename(&'static str);
+
+    impl Stage for Rename {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+            ctx.zones.iter_mut().for_each(|z| z.name.push_str(self.0));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pipeline_stages_edition() {
+        let mut stages = vec![
+            "extract",
+            "dedup",
+            "extra_zones",
+            "enclaves",
+            "missing_levels",
+            "type",
+            "country_post_processing",
+            "hierarchy",
+            "sibling_overlaps",
+            "additional_zones",
+            "timezone",
+            "population",
+            "names",
+            "labels",
+            "disambiguation",
+            "clean",
+            "land_clip",
+            "snap_borders",
+            "neighbors",
+            "simplify",
+            "precision",
+            "orientation",
+        ];
+        if cfg!(feature = "h3") {
+            let land_clip = stages.iter().position(|s| *s == "land_clip").unwrap();
+            stages.insert(land_clip + 1, "h3_cells");
+        }
+        assert_eq!(Pipeline::default().stage_names(), stages);
+        let pipeline = Pipeline::ontology()
+            .without_stage("additional_zones")
+            .without_stage("timezone")
+            .without_stage("h3_cells")
+            .without_stage("simplify")
+            .with_stage_after("names", Rename("a"))
+            .with_stage_before("type", Rename("b"));
+        assert_eq!(
+            pipeline.stage_names(),
+            vec![
+                "dedup",
+                "extra_zones",
+                "enclaves",
+                "missing_levels",
+                "b",
+                "type",
+                "country_post_processing",
+                "hierarchy",
+                "sibling_overlaps",
+                "population",
+                "names",
+                "a",
+                "labels",
+                "disambiguation",
+                "clean",
+                "land_clip",
+                "snap_borders",
+                "neighbors",
+                "precision",
+                "orientation"
+            ]
+        );
+    }
+
+    #[test]
+    fn pipeline_skipped_stages() {
+        let mut zones = vec![Zone::default()];
+        let mut stats = CosmogonyStats::default();
+        let parsed_pbf = BTreeMap::<OsmId, OsmObj>::new();
+        let options = BuildOptions {
+            skipped_stages: vec!["b".into()],
+            ..Default::default()
+        };
+        let mut ctx = PipelineContext::new(&mut zones, &mut stats, &parsed_pbf, &options);
+        Pipeline::empty()
+            .with_stage(Rename("a"))
+            .with_stage(Rename("b"))
+            .with_stage(Rename("c"))
+            .run(&mut ctx)
+            .unwrap();
+        let phases: Vec<_> = ctx.phase_durations.iter().map(|d| &d.phase).collect();
+        assert_eq!(phases, ["a", "c"]);
+        assert_eq!(zones[0].name, "ac");
+    }
+
+    #[test]
+    fn simplified_zones_measures() {
+        let mut zones = vec![Zone {
+            boundary: Some(crate::test_utils::square((0., 0.), (1., 1.))),
+            ..Default::default()
+        }];
+        // a point in the middle of a side, removed by the simplification
+        let ring = &mut zones[0].boundary.as_mut().unwrap().0[0];
+        let mut coords = ring.exterior().0.clone();
+        coords.insert(1, (0.5, -0.001).into());
+        *ring = geo_types::Polygon::new(coords.into(), vec![]);
+        zones[0].compute_measures();
+        let perimeter = zones[0].perimeter.unwrap();
+
+        let mut stats = CosmogonyStats::default();
+        let parsed_pbf = BTreeMap::<OsmId, OsmObj>::new();
+        let options = BuildOptions {
+            simplify: Some("0.01".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut ctx = PipelineContext::new(&mut zones, &mut stats, &parsed_pbf, &options);
+        Simplify.run(&mut ctx).unwrap();
+        assert_eq!(
+            zones[0].boundary.as_ref().unwrap().0[0].exterior().0.len(),
+            5
+        );
+        assert!(zones[0].perimeter.unwrap() < perimeter);
+    }
+
+    #[test]
+    fn pipeline_dump_and_stop() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dump_dir = tmp.path();
+        let mut zones = vec![Zone::default()];
+        let mut stats = CosmogonyStats::default();
+        let parsed_pbf = BTreeMap::<OsmId, OsmObj>::new();
+        let options = BuildOptions {
+            dump_after: vec![("a".into(), dump_dir.to_path_buf())],
+            stop_after: Some("b".into()),
+            ..Default::default()
+        };
+        let mut ctx = PipelineContext::new(&mut zones, &mut stats, &parsed_pbf, &options);
+        Pipeline::empty()
+            .with_stage(Rename("a"))
+            .with_stage(Rename("b"))
+            .with_stage(Rename("c"))
+            .run(&mut ctx)
+            .unwrap();
+        assert_eq!(zones[0].name, "ab");
+
+        let dump = fs::read_to_string(dump_dir.join("a.jsonl")).unwrap();
+        let dumped_zone: Zone = serde_json::from_str(dump.trim()).unwrap();
+        assert_eq!(dumped_zone.name, "a");
+    }
+
+    /// a stage cancelling the build, like another thread would do
+    struct Cancel;
+
+    impl Stage for Cancel {
+        fn name(&self) -> &'static str {
+            "cancel"
+        }
+
+        fn run(&self, ctx: &mut PipelineContext<'_>) -> Result<(), Error> {
+            ctx.options.cancellation.cancel();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pipeline_cancellation() {
+        let mut zones = vec![Zone::default()];
+        let mut stats = CosmogonyStats::default();
+        let parsed_pbf = BTreeMap::<OsmId, OsmObj>::new();
+        let options = BuildOptions::default();
+        let mut ctx = PipelineContext::new(&mut zones, &mut stats, &parsed_pbf, &options);
+        let error = Pipeline::empty()
+            .with_stage(Rename("a"))
+            .with_stage(Cancel)
+            .with_stage(Rename("b"))
+            .run(&mut ctx)
+            .unwrap_err();
+        assert!(error.downcast_ref::<Cancelled>().is_some());
+        assert_eq!(zones[0].name, "a");
+        assert!(options.cancellation.is_cancelled());
+    }
+
+    #[test]
+    fn pipeline_unknown_stage() {
+        let mut zones = vec![];
+        let mut stats = CosmogonyStats::default();
+        let parsed_pbf = BTreeMap::<OsmId, OsmObj>::new();
+        let options = BuildOptions {
+            stop_after: Some("type_zones".into()),
+            ..Default::default()
+        };
+        let mut ctx = PipelineContext::new(&mut zones, &mut stats, &parsed_pbf, &options);
+        assert!(Pipeline::ontology().run(&mut ctx).is_err());
+    }
+}
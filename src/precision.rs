@@ -0,0 +1,113 @@
+// The osm coordinates have 7 decimals, but their json serialization has up to 17 digits and
+// the geometries computed by cosmogony (voronoi cells, repaired boundaries) have full precision.
+// Rounding the coordinates to 6 decimals (about 10 cm) is enough for most uses, and makes
+// the output much smaller.
+//
+// The borders shared by adjacent zones are rounded the same way, so they stay edge-matched.
+
+use cosmogony::Zone;
+use geo_types::{Coord, LineString, MultiPolygon, Point, Polygon, Rect};
+
+fn round(v: f64, factor: f64) -> f64 {
+    (v * factor).round() / factor
+}
+
+fn round_coord(c: Coord<f64>, factor: f64) -> Coord<f64> {
+    Coord {
+        x: round(c.x, factor),
+        y: round(c.y, factor),
+    }
+}
+
+/// the rounded ring, without its consecutive duplicated points, None if it is degenerated
+fn round_ring(ring: &LineString<f64>, factor: f64) -> Option<LineString<f64>> {
+    let mut coords: Vec<_> = ring.0.iter().map(|c| round_coord(*c, factor)).collect();
+    coords.dedup();
+    (coords.len() >= 4).then_some(LineString(coords))
+}
+
+fn round_boundary(boundary: &MultiPolygon<f64>, factor: f64) -> MultiPolygon<f64> {
+    boundary
+        .iter()
+        .filter_map(|p| {
+            let exterior = round_ring(p.exterior(), factor)?;
+            let interiors = p
+                .interiors()
+                .iter()
+                .filter_map(|r| round_ring(r, factor))
+                .collect();
+            Some(Polygon::new(exterior, interiors))
+        })
+        .collect()
+}
+
+/// Round the coordinates of the zones' boundaries, centers and bboxes to `decimals` decimals
+///
+/// The polygons (or holes) too small for this precision are removed, but a boundary
+/// is never removed entirely (it is kept unrounded if all its polygons are too small).
+pub fn round_coordinates(zones: &mut [Zone], decimals: u32) {
+    info!("rounding the coordinates to {} decimals", decimals);
+    let factor = 10f64.powi(decimals as i32);
+    for zone in zones {
+        if let Some(ref boundary) = zone.boundary {
+            let rounded = round_boundary(boundary, factor);
+            if rounded.0.is_empty() {
+                debug!(
+                    "the boundary of {} is too small to be rounded, it is kept as is",
+                    zone.osm_id
+                );
+            } else {
+                zone.boundary = Some(rounded);
+            }
+        }
+        zone.center = zone
+            .center
+            .map(|c| Point::from(round_coord(c.into(), factor)));
+        zone.bbox = zone
+            .bbox
+            .map(|b| Rect::new(round_coord(b.min(), factor), round_coord(b.max(), factor)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn coordinates_are_rounded() {
+        let ring = LineString::from(vec![
+            (0., 0.),
+            (0.123_456_789, 0.),
+            (0.123_456_7, 0.000_000_1), // a duplicate once rounded
+            (0.5, 0.5),
+            (0., 0.),
+        ]);
+        let island = LineString::from(vec![
+            (2., 2.),
+            (2.000_000_1, 2.),
+            (2., 2.000_000_1),
+            (2., 2.),
+        ]);
+        let mut zones = vec![Zone {
+            boundary: Some(MultiPolygon(vec![
+                Polygon::new(ring, vec![]),
+                Polygon::new(island, vec![]),
+            ])),
+            center: Some(Point::new(0.111_111_1, 0.222_222_2)),
+            ..Default::default()
+        }];
+        round_coordinates(&mut zones, 6);
+
+        let boundary = zones[0].boundary.as_ref().unwrap();
+        assert_eq!(
+            boundary.0.len(),
+            1,
+            "the island is too small for this precision"
+        );
+        assert_eq!(
+            boundary.0[0].exterior(),
+            &LineString::from(vec![(0., 0.), (0.123_457, 0.), (0.5, 0.5), (0., 0.)])
+        );
+        assert_eq!(zones[0].center, Some(Point::new(0.111_111, 0.222_222)));
+    }
+}
@@ -0,0 +1,263 @@
+// The scheduled builds are fed with an extract kept up to date with the replication diffs of an
+// Osmosis replication server (eg. `https://planet.openstreetmap.org/replication/minute`). The
+// server publishes its current state in `state.txt`, and for each sequence number a diff
+// (`000/001/234.osc.gz`) with the state at its end (`000/001/234.state.txt`).
+//
+// A replication directory has the state of the extract (`state.txt`) and the diffs published
+// since (`<sequence number>.osc.gz`), downloaded from the server with the `replication` feature
+// (the diffs already downloaded are kept). The diffs are applied, in order, to the osm objects
+// read from the extract during the build, so the extract itself is not rewritten: it is
+// replaced (with its new state) when the diffs to apply become too many.
+
+use anyhow::{anyhow, Context, Error};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The state of the extract in a replication directory
+const STATE_FILE: &str = "state.txt";
+
+/// The state of a replication server, or of an extract updated from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationState {
+    /// the sequence number of the last diff
+    pub sequence_number: u64,
+    /// the timestamp of the osm data at the end of the last diff (eg. `2023-01-01T00:00:00Z`)
+    pub timestamp: String,
+}
+
+impl FromStr for ReplicationState {
+    type Err = Error;
+
+    /// Parse a `state.txt` file, in the java properties format of Osmosis
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sequence_number = None;
+        let mut timestamp = None;
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(anyhow!("invalid replication state line '{}'", line));
+            };
+            // the colons are escaped in the properties files
+            let value = value.trim().replace("\\:", ":");
+            match key.trim() {
+                "sequenceNumber" => {
+                    sequence_number = Some(value.parse().with_context(|| {
+                        format!("invalid replication sequence number '{}'", value)
+                    })?)
+                }
+                "timestamp" => timestamp = Some(value),
+                _ => {}
+            }
+        }
+        Ok(ReplicationState {
+            sequence_number: sequence_number
+                .ok_or_else(|| anyhow!("no sequenceNumber in the replication state"))?,
+            timestamp: timestamp.unwrap_or_default(),
+        })
+    }
+}
+
+impl fmt::Display for ReplicationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "sequenceNumber={}", self.sequence_number)?;
+        writeln!(f, "timestamp={}", self.timestamp.replace(':', "\\:"))
+    }
+}
+
+impl ReplicationState {
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("impossible to read {}", path.display()))?;
+        contents
+            .parse()
+            .with_context(|| format!("invalid replication state file {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("impossible to write {}", path.display()))
+    }
+}
+
+/// The path of the files of a sequence number on the server, without extension
+/// (eg. `001/234/567` for 1234567)
+fn sequence_path(sequence_number: u64) -> String {
+    format!(
+        "{:03}/{:03}/{:03}",
+        sequence_number / 1_000_000,
+        sequence_number / 1000 % 1000,
+        sequence_number % 1000
+    )
+}
+
+/// The url of the current state of a replication server
+pub fn server_state_url(server: &str) -> String {
+    format!("{}/state.txt", server.trim_end_matches('/'))
+}
+
+/// The url of the diff of a sequence number
+pub fn diff_url(server: &str, sequence_number: u64) -> String {
+    format!(
+        "{}/{}.osc.gz",
+        server.trim_end_matches('/'),
+        sequence_path(sequence_number)
+    )
+}
+
+/// The url of the state at the end of the diff of a sequence number
+pub fn state_url(server: &str, sequence_number: u64) -> String {
+    format!(
+        "{}/{}.state.txt",
+        server.trim_end_matches('/'),
+        sequence_path(sequence_number)
+    )
+}
+
+/// The urls of the diffs to apply, in order, to bring an extract at the local state up to the
+/// state of the server
+pub fn pending_diffs(
+    server: &str,
+    local: &ReplicationState,
+    remote: &ReplicationState,
+) -> Vec<String> {
+    (local.sequence_number + 1..=remote.sequence_number)
+        .map(|sequence_number| diff_url(server, sequence_number))
+        .collect()
+}
+
+/// The file of a diff in a replication directory
+fn diff_file(dir: &Path, sequence_number: u64) -> PathBuf {
+    dir.join(format!("{}.osc.gz", sequence_number))
+}
+
+/// The diffs of a replication directory to apply, in order, to its extract
+///
+/// They are the diffs following the state of the extract, up to the first missing one.
+pub fn downloaded_diffs(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let local = ReplicationState::read(&dir.join(STATE_FILE))?;
+    let diffs: Vec<PathBuf> = (local.sequence_number + 1..)
+        .map(|sequence_number| diff_file(dir, sequence_number))
+        .take_while(|path| path.exists())
+        .collect();
+    Ok(diffs)
+}
+
+#[cfg(feature = "replication")]
+fn download(url: &str) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+    let response = ureq::get(url)
+        .call()
+        .with_context(|| format!("impossible to download {}", url))?;
+    let mut body = vec![];
+    response
+        .into_reader()
+        .read_to_end(&mut body)
+        .with_context(|| format!("impossible to download {}", url))?;
+    Ok(body)
+}
+
+/// The current state of a replication server
+#[cfg(feature = "replication")]
+pub fn fetch_server_state(server: &str) -> Result<ReplicationState, Error> {
+    let url = server_state_url(server);
+    String::from_utf8_lossy(&download(&url)?)
+        .parse()
+        .with_context(|| format!("invalid replication state {}", url))
+}
+
+/// Download in a replication directory the diffs published by a server since the state of its
+/// extract (and not downloaded yet), and return all the diffs to apply to the extract
+#[cfg(feature = "replication")]
+pub fn download_pending_diffs(server: &str, dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let local = ReplicationState::read(&dir.join(STATE_FILE))?;
+    let remote = fetch_server_state(server)?;
+    let missing: Vec<u64> = (local.sequence_number + 1..=remote.sequence_number)
+        .filter(|sequence_number| !diff_file(dir, *sequence_number).exists())
+        .collect();
+    info!(
+        "the extract is at the state {} ({}), the server at {} ({}), {} diffs to download",
+        local.sequence_number,
+        local.timestamp,
+        remote.sequence_number,
+        remote.timestamp,
+        missing.len()
+    );
+    for sequence_number in missing {
+        let body = download(&diff_url(server, sequence_number))?;
+        // an interrupted download does not leave an incomplete diff
+        let path = diff_file(dir, sequence_number);
+        let partial = path.with_extension("part");
+        std::fs::write(&partial, body)
+            .and_then(|_| std::fs::rename(&partial, &path))
+            .with_context(|| format!("impossible to write {}", path.display()))?;
+    }
+    downloaded_diffs(dir)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replication_diffs() {
+        let local: ReplicationState = "#Sun Jan 01 00:00:00 UTC 2023\n\
+             sequenceNumber=5432998\n\
+             timestamp=2023-01-01T00\\:00\\:00Z\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            local,
+            ReplicationState {
+                sequence_number: 5_432_998,
+                timestamp: "2023-01-01T00:00:00Z".into(),
+            }
+        );
+        assert_eq!(
+            local.to_string().parse::<ReplicationState>().unwrap(),
+            local
+        );
+        assert!("timestamp=2023-01-01T00\\:00\\:00Z"
+            .parse::<ReplicationState>()
+            .is_err());
+
+        let server = "https://planet.openstreetmap.org/replication/minute/";
+        let remote = ReplicationState {
+            sequence_number: 5_433_000,
+            timestamp: "2023-01-01T00:02:00Z".into(),
+        };
+        assert_eq!(
+            pending_diffs(server, &local, &remote),
+            vec![
+                "https://planet.openstreetmap.org/replication/minute/005/432/999.osc.gz",
+                "https://planet.openstreetmap.org/replication/minute/005/433/000.osc.gz",
+            ]
+        );
+        assert_eq!(
+            state_url(server, 5_433_000),
+            "https://planet.openstreetmap.org/replication/minute/005/433/000.state.txt"
+        );
+        assert!(pending_diffs(server, &remote, &local).is_empty());
+    }
+
+    #[test]
+    fn replication_dir_diffs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let state = ReplicationState {
+            sequence_number: 41,
+            timestamp: "2023-01-01T00:00:00Z".into(),
+        };
+        state.write(&dir.join(STATE_FILE)).unwrap();
+        for sequence_number in [40, 42, 43, 45] {
+            std::fs::write(diff_file(dir, sequence_number), b"").unwrap();
+        }
+        // the diff 40 is already in the extract, and the 44 is missing
+        assert_eq!(
+            downloaded_diffs(dir).unwrap(),
+            vec![dir.join("42.osc.gz"), dir.join("43.osc.gz")]
+        );
+    }
+}
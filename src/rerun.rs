@@ -0,0 +1,172 @@
+// Some stages of the pipeline can be run again on an existing cosmogony (eg. loaded with
+// `Cosmogony::load`), to experiment with one stage (new typing rules, another disputed
+// strategy, another voronoi clip level...) without reading the pbf and building the
+// boundaries again.
+//
+// The results of the rerun stages are first removed from the zones, so the stages compute them
+// again: the types and countries of the osm zones for `type`, the parents for `hierarchy` and
+// the voronoi boundaries of the places for `additional_zones` (the places become seeds again).
+// The stages reading the osm objects (`extract`, and the residential landuses of
+// `additional_zones`) have no objects to read.
+
+use crate::merger::recount_zones;
+use crate::options::BuildOptions;
+use crate::pipeline::{Pipeline, PipelineContext};
+use crate::renumber_zones;
+use anyhow::{anyhow, Error};
+use cosmogony::{Cosmogony, ZoneOrigin};
+use geo_types::Rect;
+use osmpbfreader::{OsmId, OsmObj};
+use std::collections::BTreeMap;
+
+/// Remove the results of the stages of the pipeline from the zones
+fn reset_stages_results(cosmogony: &mut Cosmogony, stages: &[&str]) {
+    let zones = &mut cosmogony.zones;
+    if stages.contains(&"type") {
+        // the generated zones (the places, the extra zones) are typed when they are created
+        for z in zones.iter_mut().filter(|z| !z.is_generated) {
+            let is_ceremonial = z
+                .tags
+                .get("boundary")
+                .is_some_and(|b| b == "ceremonial" || b == "traditional");
+            if !is_ceremonial {
+                z.zone_type = None;
+            }
+            z.country_code = None;
+        }
+    }
+    if stages.contains(&"hierarchy") {
+        for z in zones.iter_mut() {
+            z.parent = None;
+            z.parents.clear();
+            z.included_in.clear();
+        }
+    }
+    if stages.contains(&"additional_zones") {
+        let is_computed = |o: Option<ZoneOrigin>| {
+            matches!(
+                o,
+                Some(ZoneOrigin::Voronoi | ZoneOrigin::ResidentialLanduse)
+            )
+        };
+        for z in zones.iter_mut().filter(|z| is_computed(z.origin)) {
+            // the center of the place is the seed of its voronoi cell
+            let Some(center) = z.admin_centre.or(z.center) else {
+                continue;
+            };
+            z.admin_centre = Some(center);
+            z.boundary = None;
+            z.bbox = Some(Rect::new(center.0, center.0));
+            z.origin = None;
+            z.area = None;
+            z.perimeter = None;
+        }
+    }
+}
+
+/// Run some stages of a pipeline again on the zones of a cosmogony
+///
+/// The zones are renumbered (their ids become their positions), the stats are counted again and
+/// the warnings of the stages are added to the metadata. The `extract` stage cannot be run, the
+/// osm objects are not there.
+///
+/// ```no_run
+/// # use cosmogony::Cosmogony;
+/// # use cosmogony_builder::{rerun_stages, BuildOptions};
+/// # use cosmogony_builder::pipeline::{Hierarchy, Pipeline, Type};
+/// let mut cosmogony = Cosmogony::load("cosmogony.json").unwrap();
+/// let options = BuildOptions {
+///     admin_level_overrides: Some("my_rules.yaml".into()),
+///     ..Default::default()
+/// };
+/// let pipeline = Pipeline::empty().with_stage(Type).with_stage(Hierarchy);
+/// rerun_stages(&mut cosmogony, &pipeline, &options).unwrap();
+/// ```
+pub fn rerun_stages(
+    cosmogony: &mut Cosmogony,
+    pipeline: &Pipeline,
+    options: &BuildOptions,
+) -> Result<(), Error> {
+    let stages = pipeline.stage_names();
+    if stages.contains(&"extract") {
+        return Err(anyhow!(
+            "the extract stage cannot be run again without the osm file"
+        ));
+    }
+    info!(
+        "running the stages {} again on {} zones",
+        stages.join(", "),
+        cosmogony.zones.len()
+    );
+    renumber_zones(&mut cosmogony.zones);
+    reset_stages_results(cosmogony, &stages);
+
+    let no_objects = BTreeMap::<OsmId, OsmObj>::new();
+    let mut ctx = PipelineContext::new(
+        &mut cosmogony.zones,
+        &mut cosmogony.meta.stats,
+        &no_objects,
+        options,
+    );
+    pipeline.run(&mut ctx)?;
+    let warnings = std::mem::take(&mut ctx.warnings);
+    cosmogony.meta.warnings.extend(warnings);
+    // the zones can have been added or removed
+    renumber_zones(&mut cosmogony.zones);
+    recount_zones(&mut cosmogony.meta.stats, &cosmogony.zones);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::{Hierarchy, Type};
+    use cosmogony::{Zone, ZoneIndex, ZoneType};
+    use geo::prelude::BoundingRect;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+
+    fn zone(index: usize, admin_level: u32, min: f64, max: f64) -> Zone {
+        let ring = LineString::from(vec![(min, min), (min, max), (max, max), (max, min)]);
+        let boundary = MultiPolygon(vec![Polygon::new(ring, vec![])]);
+        Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            name: format!("zone {}", index),
+            admin_level: Some(admin_level),
+            is_generated: false,
+            bbox: boundary.bounding_rect(),
+            boundary: Some(boundary),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stages_are_run_again() {
+        // the ids are not the positions, like in a cleaned cosmogony
+        let mut country = zone(4, 2, 0., 10.);
+        country.zone_type = Some(ZoneType::Country);
+        let mut city = zone(9, 8, 1., 2.);
+        // a previous typing, replaced by the new one
+        city.zone_type = Some(ZoneType::State);
+        city.parent = Some(ZoneIndex { index: 4 });
+        let mut cosmogony = Cosmogony {
+            zones: vec![country, city],
+            ..Default::default()
+        };
+        let options = BuildOptions {
+            no_country: true,
+            ..Default::default()
+        };
+        let pipeline = Pipeline::empty().with_stage(Type).with_stage(Hierarchy);
+        rerun_stages(&mut cosmogony, &pipeline, &options).unwrap();
+
+        let zones = &cosmogony.zones;
+        assert_eq!(zones[0].id, ZoneIndex { index: 0 });
+        assert_eq!(zones[1].zone_type, Some(ZoneType::City));
+        assert_eq!(zones[1].parent, Some(ZoneIndex { index: 0 }));
+        assert_eq!(cosmogony.meta.stats.zone_type_counts.get("City"), Some(&1));
+
+        let pipeline = Pipeline::default();
+        assert!(rerun_stages(&mut cosmogony, &pipeline, &options).is_err());
+    }
+}
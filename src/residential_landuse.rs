@@ -0,0 +1,253 @@
+// In the sparsely mapped regions, the voronoi cells of the places without boundary are very
+// large: a village gets all the land up to the next village.
+//
+// With the `landuse` strategy, the boundary of a place is instead the residential landuses
+// (`landuse=residential`) of its voronoi cell around it, merged with a closing (a buffer followed
+// by a negative buffer, a rough concave hull of the landuses) so the houses of a same town make
+// a single area. This is much closer to the real shape of the towns.
+//
+// The voronoi cells are still used to share the landuses between the neighbouring places. The
+// places without residential landuse nearby keep their voronoi cell.
+
+use crate::additional_zones::convert_to_geo;
+use crate::osm_store::OsmObjects;
+use cosmogony::{Zone, ZoneOrigin};
+use geo::prelude::BoundingRect;
+use geo_types::{LineString, MultiPolygon, Point, Polygon, Rect};
+use geos::{Geom, Geometry};
+use osm_boundaries_utils::build_boundary;
+use osmpbfreader::{OsmId, OsmObj};
+use rstar::{RTree, RTreeObject, AABB};
+
+/// The landuses closer than this distance (in degrees, about 200m) are merged in a single area
+pub const LANDUSE_MERGE_DISTANCE: f64 = 0.002;
+
+/// The maximum distance (in degrees, about 2km) between a place and its residential landuses
+pub const LANDUSE_MAX_DISTANCE: f64 = 0.02;
+
+/// The segments used by the buffers to approximate a quarter circle
+const BUFFER_QUADRANT_SEGMENTS: i32 = 4;
+
+/// check if the object is a residential landuse area (a closed way or a multipolygon)
+pub fn is_residential_landuse(obj: &OsmObj) -> bool {
+    match *obj {
+        OsmObj::Way(ref way) => way.is_closed() && way.tags.contains("landuse", "residential"),
+        OsmObj::Relation(ref rel) => rel.tags.contains("landuse", "residential"),
+        _ => false,
+    }
+}
+
+fn landuse_polygon(obj: &OsmObj, pbf: &dyn OsmObjects) -> Option<MultiPolygon<f64>> {
+    match *obj {
+        OsmObj::Way(ref way) => {
+            let dependencies = pbf.dependencies(obj);
+            let ring: Option<Vec<_>> = way
+                .nodes
+                .iter()
+                .map(|n| {
+                    let node = dependencies.get(&OsmId::Node(*n))?.node()?;
+                    Some((node.lon(), node.lat()))
+                })
+                .collect();
+            let ring = ring.filter(|r| r.len() >= 4)?;
+            Some(MultiPolygon(vec![Polygon::new(
+                LineString::from(ring),
+                vec![],
+            )]))
+        }
+        OsmObj::Relation(ref rel) => build_boundary(rel, &pbf.dependencies(obj)),
+        _ => None,
+    }
+}
+
+struct LanduseBbox {
+    index: usize,
+    bbox: AABB<Point<f64>>,
+}
+
+impl RTreeObject for LanduseBbox {
+    type Envelope = AABB<Point<f64>>;
+    fn envelope(&self) -> Self::Envelope {
+        self.bbox
+    }
+}
+
+fn envelope(bbox: &Rect<f64>) -> AABB<Point<f64>> {
+    AABB::from_corners(bbox.min().into(), bbox.max().into())
+}
+
+/// The residential landuses read from the osm objects
+pub struct ResidentialAreas {
+    polygons: Vec<MultiPolygon<f64>>,
+    tree: RTree<LanduseBbox>,
+}
+
+impl ResidentialAreas {
+    pub fn read(pbf: &dyn OsmObjects) -> Self {
+        let polygons: Vec<_> = pbf
+            .objects()
+            .filter(|o| is_residential_landuse(o))
+            .filter_map(|o| landuse_polygon(&o, pbf))
+            .collect();
+        info!("{} residential landuses read", polygons.len());
+        let bboxes = polygons
+            .iter()
+            .enumerate()
+            .filter_map(|(index, p)| {
+                Some(LanduseBbox {
+                    index,
+                    bbox: envelope(&p.bounding_rect()?),
+                })
+            })
+            .collect();
+        ResidentialAreas {
+            polygons,
+            tree: RTree::bulk_load(bboxes),
+        }
+    }
+
+    /// the landuses whose bbox is closer than `LANDUSE_MAX_DISTANCE` of a point
+    fn near(&self, center: Point<f64>) -> impl Iterator<Item = &MultiPolygon<f64>> {
+        let d = LANDUSE_MAX_DISTANCE;
+        let area = AABB::from_corners(
+            Point::new(center.x() - d, center.y() - d),
+            Point::new(center.x() + d, center.y() + d),
+        );
+        self.tree
+            .locate_in_envelope_intersecting(&area)
+            .map(|l| &self.polygons[l.index])
+    }
+
+    /// Restrict the boundary of a place (its voronoi cell) to its residential landuses,
+    /// keeping the suburbs given to the place
+    ///
+    /// Returns false if the place has no residential landuse, its boundary is not changed.
+    pub fn restrict_to_landuses(&self, place: &mut Zone, suburbs: &[&Zone]) -> bool {
+        let (Some(boundary), Some(center)) = (&place.boundary, place.admin_centre.or(place.center))
+        else {
+            return false;
+        };
+        let shape = || -> geos::GResult<Option<Geometry<'static>>> {
+            let cell = Geometry::try_from(boundary)?;
+            let geos_center = Geometry::try_from(&center)?;
+            let mut landuses = vec![];
+            for polygon in self.near(center) {
+                let landuse = Geometry::try_from(polygon)?;
+                if landuse.distance(&geos_center)? <= LANDUSE_MAX_DISTANCE
+                    && landuse.intersects(&cell)?
+                {
+                    landuses.push(landuse);
+                }
+            }
+            if landuses.is_empty() {
+                return Ok(None);
+            }
+            let mut shape = Geometry::create_geometry_collection(landuses)?
+                .unary_union()?
+                .buffer(LANDUSE_MERGE_DISTANCE, BUFFER_QUADRANT_SEGMENTS)?
+                .buffer(-LANDUSE_MERGE_DISTANCE, BUFFER_QUADRANT_SEGMENTS)?
+                .intersection(&cell)?;
+            for suburb in suburbs.iter().filter_map(|s| s.boundary.as_ref()) {
+                shape = shape.union(&Geometry::try_from(suburb)?)?;
+            }
+            Ok(Some(shape))
+        };
+        let shape = match shape() {
+            Ok(Some(shape)) => shape,
+            Ok(None) => return false,
+            Err(e) => {
+                warn!(
+                    "impossible to restrict {} to its residential landuses: {}",
+                    place.osm_id, e
+                );
+                return false;
+            }
+        };
+        match convert_to_geo(shape) {
+            Ok(Some(shape)) => {
+                place.bbox = shape.bounding_rect();
+                place.boundary = Some(shape);
+                place.origin = Some(ZoneOrigin::ResidentialLanduse);
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                warn!(
+                    "impossible to convert the landuses of {} to geo: {}",
+                    place.osm_id, e
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::square;
+    use cosmogony::ZoneIndex;
+    use osmpbfreader::{Node, NodeId, Way, WayId};
+    use std::collections::BTreeMap;
+
+    fn residential_areas(squares: &[(f64, f64)]) -> ResidentialAreas {
+        let mut pbf = BTreeMap::new();
+        for (i, (x, y)) in squares.iter().enumerate() {
+            let square = square((*x, *y), (x + 0.001, y + 0.001));
+            let mut nodes = vec![];
+            for (j, c) in square.0[0].exterior().coords().take(4).enumerate() {
+                let id = NodeId((i * 10 + j) as i64);
+                nodes.push(id);
+                let node = Node {
+                    id,
+                    tags: Default::default(),
+                    decimicro_lat: (c.y * 1e7).round() as i32,
+                    decimicro_lon: (c.x * 1e7).round() as i32,
+                };
+                pbf.insert(OsmId::Node(id), OsmObj::Node(node));
+            }
+            nodes.push(nodes[0]);
+            let way = Way {
+                id: WayId(i as i64),
+                tags: [("landuse".into(), "residential".into())]
+                    .into_iter()
+                    .collect(),
+                nodes,
+            };
+            pbf.insert(OsmId::Way(way.id), OsmObj::Way(way));
+        }
+        ResidentialAreas::read(&pbf)
+    }
+
+    #[test]
+    fn places_are_restricted_to_their_landuses() {
+        // 2 close houses blocks, and a far away one
+        let areas = residential_areas(&[(0.5, 0.5), (0.5015, 0.5), (0.6, 0.6)]);
+        let mut place = Zone {
+            id: ZoneIndex { index: 0 },
+            osm_id: "node:1".into(),
+            center: Some(Point::new(0.501, 0.501)),
+            boundary: Some(square((0., 0.), (1., 1.))),
+            ..Default::default()
+        };
+
+        assert!(areas.restrict_to_landuses(&mut place, &[]));
+        assert_eq!(place.origin, Some(ZoneOrigin::ResidentialLanduse));
+        let boundary = place.boundary.as_ref().unwrap();
+        // the 2 blocks are merged, with the gap between them
+        assert_eq!(boundary.0.len(), 1);
+        let bbox = place.bbox.unwrap();
+        assert!((bbox.min().x - 0.5).abs() < 1e-6);
+        assert!((bbox.max().x - 0.5025).abs() < 1e-6);
+        assert!(bbox.max().y < 0.502);
+
+        // no landuse near this place, it keeps its cell
+        let mut place = Zone {
+            center: Some(Point::new(0.2, 0.2)),
+            boundary: Some(square((0., 0.), (0.4, 0.4))),
+            ..Default::default()
+        };
+        assert!(!areas.restrict_to_landuses(&mut place, &[]));
+        assert_eq!(place.boundary, Some(square((0., 0.), (0.4, 0.4))));
+    }
+}
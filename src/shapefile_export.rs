@@ -0,0 +1,246 @@
+// Export of the zones as ESRI Shapefiles, as many GIS tools can only ingest this format.
+//
+// The attributes are stored in a dBase file, which has some strong limitations:
+// the fields names cannot exceed 10 characters and the text values 254 bytes.
+
+use anyhow::{anyhow, Context, Error};
+use cosmogony::{Zone, ZoneOrigin, ZoneType};
+use shapefile::dbase::{FieldName, FieldValue, Record, TableWriterBuilder};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// max length of a dBase field name
+const MAX_FIELD_NAME_LEN: usize = 10;
+/// max length (in bytes) of a dBase text value
+const MAX_TEXT_LEN: usize = 254;
+
+/// How the zones are split in shapefiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShapefileLayout {
+    /// all the zones in one shapefile, with a `zone_type` attribute
+    #[default]
+    Combined,
+    /// one shapefile by zone type, named `<name>_<zone_type>.shp`
+    PerZoneType,
+}
+
+#[derive(Clone, Copy)]
+enum FieldKind {
+    Text,
+    Number,
+    Boolean,
+}
+
+/// the exported attributes, with their full name
+const FIELDS: [(&str, FieldKind); 14] = [
+    ("id", FieldKind::Number),
+    ("osm_id", FieldKind::Text),
+    ("admin_level", FieldKind::Number),
+    ("zone_type", FieldKind::Text),
+    ("name", FieldKind::Text),
+    ("label", FieldKind::Text),
+    ("zip_codes", FieldKind::Text),
+    ("wikidata", FieldKind::Text),
+    ("country_code", FieldKind::Text),
+    ("parent", FieldKind::Number),
+    ("population", FieldKind::Number),
+    ("is_generated", FieldKind::Boolean),
+    ("origin", FieldKind::Text),
+    ("disputed", FieldKind::Boolean),
+];
+
+/// Truncate the field names to the dBase limit, keeping them unique.
+///
+/// When 2 names have the same truncated form, the following ones get a numeric suffix
+/// (eg. `country_code` and `country_color` give `country_co` and `country__1`).
+fn dbf_field_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut dbf_names: Vec<String> = vec![];
+    for name in names {
+        let truncated: String = name.chars().take(MAX_FIELD_NAME_LEN).collect();
+        let mut dbf_name = truncated.clone();
+        let mut suffix_idx = 1;
+        while dbf_names.contains(&dbf_name) {
+            let suffix = format!("_{}", suffix_idx);
+            let prefix: String = truncated
+                .chars()
+                .take(MAX_FIELD_NAME_LEN - suffix.len())
+                .collect();
+            dbf_name = format!("{}{}", prefix, suffix);
+            suffix_idx += 1;
+        }
+        dbf_names.push(dbf_name);
+    }
+    dbf_names
+}
+
+/// Truncate a text value to the dBase limit, without splitting a character
+fn truncate_text(value: &str) -> String {
+    if value.len() <= MAX_TEXT_LEN {
+        return value.to_string();
+    }
+    let mut end = MAX_TEXT_LEN;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+fn field_value(zone: &Zone, field: &str) -> FieldValue {
+    let text = |s: Option<&str>| FieldValue::Character(s.map(truncate_text));
+    let number = |n: Option<f64>| FieldValue::Numeric(n);
+    match field {
+        "id" => number(Some(zone.id.index as f64)),
+        "osm_id" => text(Some(&zone.osm_id)),
+        "admin_level" => number(zone.admin_level.map(f64::from)),
+        "zone_type" => text(zone.zone_type.as_ref().map(ZoneType::as_str)),
+        "name" => text(Some(&zone.name)),
+        "label" => text(Some(&zone.label)),
+        "zip_codes" => text(Some(&zone.zip_codes.join(";"))),
+        "wikidata" => text(zone.wikidata.as_deref()),
+        "country_code" => text(zone.country_code.as_deref()),
+        "parent" => number(zone.parent.map(|p| p.index as f64)),
+        "population" => number(zone.population.map(|p| p as f64)),
+        "is_generated" => FieldValue::Logical(Some(zone.is_generated)),
+        "origin" => text(zone.origin.as_ref().map(ZoneOrigin::as_str)),
+        "disputed" => FieldValue::Logical(Some(zone.disputed)),
+        _ => FieldValue::Character(None),
+    }
+}
+
+fn write_shapefile(path: &Path, zones: &[&Zone]) -> Result<(), Error> {
+    info!("writing {} zones in {}", zones.len(), path.display());
+    let dbf_names = dbf_field_names(FIELDS.iter().map(|(name, _)| *name));
+    let mut table_builder = TableWriterBuilder::new();
+    for ((_, kind), dbf_name) in FIELDS.iter().zip(&dbf_names) {
+        let field_name =
+            FieldName::try_from(dbf_name.as_str()).map_err(|e| anyhow!("{}: {}", dbf_name, e))?;
+        table_builder = match kind {
+            FieldKind::Text => table_builder.add_character_field(field_name, MAX_TEXT_LEN as u8),
+            FieldKind::Number => table_builder.add_numeric_field(field_name, 20, 0),
+            FieldKind::Boolean => table_builder.add_logical_field(field_name),
+        };
+    }
+
+    let mut writer = shapefile::Writer::from_path(path, table_builder)
+        .with_context(|| format!("impossible to create {}", path.display()))?;
+    for (zone, boundary) in zones
+        .iter()
+        .filter_map(|z| z.boundary.as_ref().map(|b| (z, b)))
+    {
+        let shape = shapefile::Polygon::from(boundary.clone());
+        let mut record = Record::default();
+        for ((name, _), dbf_name) in FIELDS.iter().zip(&dbf_names) {
+            record.insert(dbf_name.clone(), field_value(zone, name));
+        }
+        writer
+            .write_shape_and_record(&shape, &record)
+            .with_context(|| format!("impossible to write zone {}", zone.osm_id))?;
+    }
+    // the text values are written in utf-8, we tell it to the GIS tools
+    std::fs::write(path.with_extension("cpg"), "UTF-8")?;
+    Ok(())
+}
+
+fn per_zone_type_path(path: &Path, zone_type: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("cosmogony");
+    path.with_file_name(format!("{}_{}.shp", stem, zone_type))
+}
+
+/// Write the zones (with a boundary) in shapefiles
+///
+/// Besides the `.shp`, each shapefile is made of a `.shx` (the index),
+/// a `.dbf` (the attributes) and a `.cpg` (the attributes encoding) file.
+pub fn write_zones_as_shapefile(
+    zones: &[Zone],
+    path: &Path,
+    layout: ShapefileLayout,
+) -> Result<(), Error> {
+    let nb_zones = zones.len();
+    let zones: Vec<&Zone> = zones.iter().filter(|z| z.boundary.is_some()).collect();
+    if zones.len() < nb_zones {
+        warn!(
+            "{} zones without boundary are not exported in the shapefile",
+            nb_zones - zones.len()
+        );
+    }
+    match layout {
+        ShapefileLayout::Combined => write_shapefile(path, &zones),
+        ShapefileLayout::PerZoneType => {
+            let mut zones_by_type = BTreeMap::<&str, Vec<&Zone>>::new();
+            for zone in zones {
+                let zone_type = zone.zone_type.as_ref().map_or("untyped", ZoneType::as_str);
+                zones_by_type.entry(zone_type).or_default().push(zone);
+            }
+            for (zone_type, zones) in zones_by_type {
+                write_shapefile(&per_zone_type_path(path, zone_type), &zones)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+
+    #[test]
+    fn field_names_are_truncated() {
+        assert_eq!(
+            dbf_field_names(vec!["id", "country_code", "country_color", "country_coast"]),
+            vec!["id", "country_co", "country__1", "country__2"]
+        );
+    }
+
+    #[test]
+    fn text_values_are_truncated() {
+        assert_eq!(truncate_text("bob"), "bob");
+        let long_text = "é".repeat(200);
+        let truncated = truncate_text(&long_text);
+        assert_eq!(truncated.len(), 254);
+        assert!(long_text.starts_with(&truncated));
+    }
+
+    #[test]
+    fn write_and_read_shapefile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let ring = LineString::from(vec![(0., 0.), (0., 1.), (1., 1.), (1., 0.), (0., 0.)]);
+        let zone = Zone {
+            osm_id: "relation:42".into(),
+            name: "Bobville".into(),
+            zone_type: Some(ZoneType::City),
+            country_code: Some("FR".into()),
+            population: Some(1234),
+            boundary: Some(MultiPolygon(vec![Polygon::new(ring, vec![])])),
+            ..Default::default()
+        };
+        let zones = vec![zone, Zone::default()];
+
+        let path = dir.join("zones.shp");
+        write_zones_as_shapefile(&zones, &path, ShapefileLayout::PerZoneType).unwrap();
+
+        let city_path = dir.join("zones_city.shp");
+        let shapes_and_records =
+            shapefile::read_as::<_, shapefile::Polygon, Record>(&city_path).unwrap();
+        assert_eq!(shapes_and_records.len(), 1);
+        let (_, record) = &shapes_and_records[0];
+        assert_eq!(
+            record.get("name"),
+            Some(&FieldValue::Character(Some("Bobville".into())))
+        );
+        assert_eq!(
+            record.get("country_co"),
+            Some(&FieldValue::Character(Some("FR".into())))
+        );
+        assert_eq!(
+            record.get("population"),
+            Some(&FieldValue::Numeric(Some(1234.)))
+        );
+        // the zone without boundary has not been exported
+        assert!(!dir.join("zones_untyped.shp").exists());
+    }
+}
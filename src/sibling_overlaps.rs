@@ -0,0 +1,149 @@
+// The zones with the same parent and the same type should partition their parent.
+// When 2 of those siblings overlap significantly, it is often an osm editing mistake
+// (like a boundary way shared by the wrong relations), worth reporting to fix it upstream.
+
+use crate::additional_zones::convert_to_geo;
+use cosmogony::{SiblingOverlap, Zone, ZoneIndex, ZoneType};
+use geo::prelude::{ChamberlainDuquetteArea, Intersects};
+use geos::{Geom, Geometry};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+/// geodesic area of a geos geometry, in km²
+fn area_km2(geom: Geometry<'_>) -> f64 {
+    match convert_to_geo(geom) {
+        Ok(Some(mpoly)) => mpoly.chamberlain_duquette_unsigned_area() / 1e6,
+        _ => 0.,
+    }
+}
+
+fn group_overlaps(zones: &[&Zone], threshold: f64) -> Vec<SiblingOverlap> {
+    let geoms: Vec<(&Zone, Geometry<'_>, f64)> = zones
+        .iter()
+        .filter_map(|z| {
+            let boundary = z.boundary.as_ref()?;
+            let geom = Geometry::try_from(boundary)
+                .map_err(|e| warn!("failed to convert {} to geos: {}", z.osm_id, e))
+                .ok()?;
+            Some((
+                *z,
+                geom,
+                boundary.chamberlain_duquette_unsigned_area() / 1e6,
+            ))
+        })
+        .collect();
+
+    let mut overlaps = vec![];
+    for (i, (zone, geom, area)) in geoms.iter().enumerate() {
+        for (other, other_geom, other_area) in &geoms[i + 1..] {
+            let bboxes_intersect = match (zone.bbox, other.bbox) {
+                (Some(bbox), Some(other_bbox)) => bbox.intersects(&other_bbox),
+                _ => false,
+            };
+            if !bboxes_intersect {
+                continue;
+            }
+            let overlap_area = match geom.intersection(other_geom) {
+                Ok(overlap) => area_km2(overlap),
+                Err(e) => {
+                    warn!(
+                        "failed to compute the overlap of {} and {}: {}",
+                        zone.osm_id, other.osm_id, e
+                    );
+                    continue;
+                }
+            };
+            let smallest_area = area.min(*other_area);
+            if smallest_area <= 0. {
+                continue;
+            }
+            let ratio = overlap_area / smallest_area;
+            if ratio > threshold {
+                overlaps.push(SiblingOverlap {
+                    osm_ids: [zone.osm_id.clone(), other.osm_id.clone()],
+                    zone_type: zone.zone_type,
+                    area: overlap_area,
+                    ratio,
+                });
+            }
+        }
+    }
+    overlaps
+}
+
+/// Find the siblings (zones with the same parent and the same type) that overlap each other
+///
+/// Only the overlaps larger than `threshold` (as a ratio of the smallest zone of the pair)
+/// are returned.
+pub fn find_sibling_overlaps(zones: &[Zone], threshold: f64) -> Vec<SiblingOverlap> {
+    info!("looking for overlapping siblings");
+    let mut siblings = BTreeMap::<(Option<ZoneIndex>, ZoneType), Vec<&Zone>>::new();
+    for z in zones.iter().filter(|z| !z.is_generated) {
+        if let Some(zone_type) = z.zone_type {
+            siblings.entry((z.parent, zone_type)).or_default().push(z);
+        }
+    }
+
+    let mut overlaps: Vec<_> = siblings
+        .into_par_iter()
+        .filter(|(_, zones)| zones.len() > 1)
+        .flat_map(|(_, zones)| group_overlaps(&zones, threshold))
+        .collect();
+    overlaps.sort_by(|a, b| a.osm_ids.cmp(&b.osm_ids));
+    if !overlaps.is_empty() {
+        warn!("{} pairs of overlapping siblings found", overlaps.len());
+    }
+    overlaps
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::prelude::BoundingRect;
+    use geo_types::{LineString, MultiPolygon, Polygon};
+
+    fn zone(osm_id: &str, parent: usize, min_x: f64, max_x: f64) -> Zone {
+        let ring = LineString::from(vec![
+            (min_x, 0.),
+            (min_x, 0.1),
+            (max_x, 0.1),
+            (max_x, 0.),
+            (min_x, 0.),
+        ]);
+        let boundary = MultiPolygon(vec![Polygon::new(ring, vec![])]);
+        Zone {
+            osm_id: osm_id.into(),
+            zone_type: Some(ZoneType::City),
+            parent: Some(ZoneIndex { index: parent }),
+            bbox: boundary.bounding_rect(),
+            boundary: Some(boundary),
+            is_generated: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn overlapping_siblings_are_reported() {
+        let zones = vec![
+            zone("relation:1", 0, 0., 0.1),
+            // overlaps half of relation:1
+            zone("relation:2", 0, 0.05, 0.15),
+            // only touches relation:2
+            zone("relation:3", 0, 0.15, 0.25),
+            // overlaps relation:3 but is not a sibling
+            zone("relation:4", 1, 0.2, 0.3),
+            // overlaps a tiny part of relation:3
+            zone("relation:5", 0, 0.249, 0.35),
+        ];
+        let overlaps = find_sibling_overlaps(&zones, 0.05);
+
+        assert_eq!(overlaps.len(), 1);
+        let overlap = &overlaps[0];
+        assert_eq!(overlap.osm_ids, ["relation:1", "relation:2"]);
+        assert!((overlap.ratio - 0.5).abs() < 0.01);
+        // each zone is about 11km x 11km
+        assert!((overlap.area - 61.5).abs() < 1.);
+
+        assert_eq!(find_sibling_overlaps(&zones, 0.).len(), 2);
+    }
+}
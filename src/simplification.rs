@@ -0,0 +1,572 @@
+// Simplifying each boundary independently makes the borders of adjacent zones diverge,
+// creating gaps and overlaps between the neighbours.
+// To prevent this, the rings are split in arcs at the junctions (the points where the borders
+// of several zones meet) and each arc is simplified only once, so all the zones sharing a border
+// get the same simplified border.
+//
+// This needs the shared borders to have exactly the same points, which is not always the case:
+// the repaired geometries get new points where they self-intersect, and the coordinates of the
+// voronoi cells are computed. The borders can first be snapped together: the points closer
+// than a tolerance are merged, and the points of a border near a segment of the other border
+// are inserted in it.
+
+use crate::options::{CancellationToken, Cancelled, SimplifyTolerances};
+use cosmogony::Zone;
+use geo::prelude::BoundingRect;
+use geo::Simplify;
+use geo_types::{Coord, LineString, MultiPolygon, Polygon};
+use geos::{Geom, Geometry};
+use rstar::{RTree, AABB};
+use std::collections::{HashMap, HashSet};
+
+pub(crate) type PointKey = (u64, u64);
+
+fn key(c: &Coord<f64>) -> PointKey {
+    (c.x.to_bits(), c.y.to_bits())
+}
+
+/// the points of a ring, without its closing point
+pub(crate) fn open_ring(ring: &LineString<f64>) -> &[Coord<f64>] {
+    match ring.0.split_last() {
+        Some((_, others)) if ring.is_closed() && !others.is_empty() => others,
+        _ => &ring.0[..],
+    }
+}
+
+fn rings(boundary: &MultiPolygon<f64>) -> impl Iterator<Item = &[Coord<f64>]> {
+    boundary
+        .iter()
+        .flat_map(|p| std::iter::once(p.exterior()).chain(p.interiors()))
+        .map(open_ring)
+}
+
+/// The junctions are the points that are not linked to exactly 2 other points
+/// (eg. where 3 zones meet, or where 2 zones stop sharing their border).
+pub(crate) fn find_junctions(zones: &[Zone]) -> HashSet<PointKey> {
+    let mut neighbours: HashMap<PointKey, Vec<PointKey>> = HashMap::new();
+    for ring in zones
+        .iter()
+        .filter_map(|z| z.boundary.as_ref())
+        .flat_map(rings)
+    {
+        let n = ring.len();
+        for (i, c) in ring.iter().enumerate() {
+            let point_neighbours = neighbours.entry(key(c)).or_default();
+            for neighbour in [&ring[(i + n - 1) % n], &ring[(i + 1) % n]] {
+                let neighbour = key(neighbour);
+                // no need to go further, 3 neighbours are enough to make a junction
+                if point_neighbours.len() < 3 && !point_neighbours.contains(&neighbour) {
+                    point_neighbours.push(neighbour);
+                }
+            }
+        }
+    }
+    neighbours
+        .into_iter()
+        .filter(|(_, n)| n.len() != 2)
+        .map(|(k, _)| k)
+        .collect()
+}
+
+/// Split a ring in arcs going from a junction to another.
+///
+/// A ring without junction is not shared with another zone (or it is shared entirely),
+/// so it makes one arc, starting at its smallest point so that every zone sharing it
+/// gets the same arc.
+pub(crate) fn split_ring(
+    ring: &[Coord<f64>],
+    junctions: &HashSet<PointKey>,
+) -> Vec<Vec<Coord<f64>>> {
+    let start = ring
+        .iter()
+        .position(|c| junctions.contains(&key(c)))
+        .or_else(|| (0..ring.len()).min_by_key(|i| key(&ring[*i])))
+        .unwrap_or(0);
+    let mut arcs = vec![];
+    let mut arc = vec![ring[start]];
+    for (i, c) in ring
+        .iter()
+        .cycle()
+        .skip(start + 1)
+        .take(ring.len())
+        .enumerate()
+    {
+        arc.push(*c);
+        if junctions.contains(&key(c)) || i + 1 == ring.len() {
+            arcs.push(std::mem::replace(&mut arc, vec![*c]));
+        }
+    }
+    arcs
+}
+
+/// An arc and its reverse are the same border, we use the smallest of the two as a key.
+/// Returns the key and whether the arc has been reversed.
+pub(crate) fn arc_key(arc: &[Coord<f64>]) -> (Vec<PointKey>, bool) {
+    let keys: Vec<_> = arc.iter().map(key).collect();
+    let reversed_keys: Vec<_> = keys.iter().rev().cloned().collect();
+    if reversed_keys < keys {
+        (reversed_keys, true)
+    } else {
+        (keys, false)
+    }
+}
+
+fn simplify_ring(
+    ring: &[Coord<f64>],
+    junctions: &HashSet<PointKey>,
+    arc_tolerances: &HashMap<Vec<PointKey>, f64>,
+    simplified_arcs: &mut HashMap<Vec<PointKey>, Vec<Coord<f64>>>,
+) -> LineString<f64> {
+    if ring.is_empty() {
+        return LineString::from(ring.to_vec());
+    }
+    let mut coords = vec![];
+    for arc in split_ring(ring, junctions) {
+        let (k, reversed) = arc_key(&arc);
+        let tolerance = arc_tolerances.get(&k).cloned().unwrap_or(0.);
+        let simplified = simplified_arcs.entry(k).or_insert_with(|| {
+            let mut arc = arc.clone();
+            if reversed {
+                arc.reverse();
+            }
+            LineString::from(arc).simplify(&tolerance).0
+        });
+        // each arc begins at the end of the previous one, so we skip the last point of each arc
+        let nb_points = simplified.len().saturating_sub(1);
+        if reversed {
+            coords.extend(simplified.iter().rev().take(nb_points));
+        } else {
+            coords.extend(simplified.iter().take(nb_points));
+        }
+    }
+    if coords.len() < 3 {
+        // the ring is too small for the tolerance, we keep it as is
+        coords = ring.to_vec();
+    }
+    let mut ring = LineString::from(coords);
+    ring.close();
+    ring
+}
+
+/// Simplify the boundaries of the zones, with a tolerance depending on the zone type.
+///
+/// The borders shared by several zones are simplified with the smallest tolerance of those zones,
+/// so the neighbours stay edge-matched.
+///
+/// Fails if the build is cancelled.
+pub fn simplify_zones(
+    zones: &mut [Zone],
+    tolerances: &SimplifyTolerances,
+    cancellation: &CancellationToken,
+) -> Result<(), Cancelled> {
+    info!("simplifying the boundaries");
+    let junctions = find_junctions(zones);
+
+    let mut arc_tolerances: HashMap<Vec<PointKey>, f64> = HashMap::new();
+    for zone in zones.iter() {
+        cancellation.check()?;
+        let tolerance = tolerances.tolerance(zone.zone_type);
+        for ring in zone.boundary.iter().flat_map(rings) {
+            for arc in split_ring(ring, &junctions) {
+                let arc_tolerance = arc_tolerances.entry(arc_key(&arc).0).or_insert(tolerance);
+                *arc_tolerance = arc_tolerance.min(tolerance);
+            }
+        }
+    }
+
+    let mut simplified_arcs = HashMap::new();
+    for zone in zones.iter_mut() {
+        cancellation.check()?;
+        let boundary = match zone.boundary {
+            Some(ref b) => b,
+            None => continue,
+        };
+        let mut simplify = |ring: &LineString<f64>| {
+            simplify_ring(
+                open_ring(ring),
+                &junctions,
+                &arc_tolerances,
+                &mut simplified_arcs,
+            )
+        };
+        let simplified: MultiPolygon<f64> = boundary
+            .iter()
+            .map(|p| {
+                Polygon::new(
+                    simplify(p.exterior()),
+                    p.interiors().iter().map(&mut simplify).collect(),
+                )
+            })
+            .collect();
+        zone.bbox = simplified.bounding_rect();
+        zone.boundary = Some(simplified);
+    }
+    Ok(())
+}
+
+fn distance(a: &Coord<f64>, b: &Coord<f64>) -> f64 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+/// The points closer than a tolerance, merged in the lowest one (by x, then y)
+///
+/// The points are merged in sorted order, so the snapped points do not depend on the order of
+/// the zones.
+struct SnapGrid {
+    tolerance: f64,
+    /// the points kept, by cell of the grid
+    cells: HashMap<(i64, i64), Vec<Coord<f64>>>,
+    /// the point kept for each point
+    snapped: HashMap<PointKey, Coord<f64>>,
+}
+
+impl SnapGrid {
+    fn new(tolerance: f64, points: impl Iterator<Item = Coord<f64>>) -> Self {
+        let mut points: Vec<Coord<f64>> = points.collect();
+        points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+        points.dedup();
+        let mut grid = SnapGrid {
+            tolerance,
+            cells: HashMap::new(),
+            snapped: HashMap::new(),
+        };
+        for c in points {
+            let (x, y) = grid.cell(&c);
+            let snapped = (x - 1..=x + 1)
+                .flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y)))
+                .filter_map(|cell| grid.cells.get(&cell))
+                .flatten()
+                .filter(|p| distance(p, &c) <= tolerance)
+                .min_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)))
+                .copied();
+            let snapped = snapped.unwrap_or_else(|| {
+                grid.cells.entry((x, y)).or_default().push(c);
+                c
+            });
+            grid.snapped.insert(key(&c), snapped);
+        }
+        grid
+    }
+
+    fn cell(&self, c: &Coord<f64>) -> (i64, i64) {
+        (
+            (c.x / self.tolerance).floor() as i64,
+            (c.y / self.tolerance).floor() as i64,
+        )
+    }
+
+    fn snap(&self, c: Coord<f64>) -> Coord<f64> {
+        self.snapped.get(&key(&c)).copied().unwrap_or(c)
+    }
+
+    fn points(&self) -> impl Iterator<Item = &Coord<f64>> {
+        self.cells.values().flatten()
+    }
+}
+
+/// the position of the projection of `p` on the segment `[a, b]` (between 0 and 1)
+/// if it is strictly inside the segment and closer than `tolerance` to `p`
+fn projection_on_segment(
+    p: &Coord<f64>,
+    a: &Coord<f64>,
+    b: &Coord<f64>,
+    tolerance: f64,
+) -> Option<f64> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length2 = dx * dx + dy * dy;
+    if length2 == 0. {
+        return None;
+    }
+    let t = ((p.x - a.x) * dx + (p.y - a.y) * dy) / length2;
+    let projection = Coord {
+        x: a.x + t * dx,
+        y: a.y + t * dy,
+    };
+    (t > 0. && t < 1. && distance(p, &projection) <= tolerance).then_some(t)
+}
+
+/// the ring with the snapped points, and with the points of `points_tree` near its segments
+fn snap_ring(
+    ring: &LineString<f64>,
+    tolerance: f64,
+    points_tree: &RTree<[f64; 2]>,
+) -> Option<LineString<f64>> {
+    let mut coords: Vec<Coord<f64>> = vec![];
+    for (a, b) in ring.lines().map(|l| (l.start, l.end)) {
+        coords.push(a);
+        let envelope = AABB::from_corners(
+            [a.x.min(b.x) - tolerance, a.y.min(b.y) - tolerance],
+            [a.x.max(b.x) + tolerance, a.y.max(b.y) + tolerance],
+        );
+        let mut inserted: Vec<(f64, Coord<f64>)> = points_tree
+            .locate_in_envelope(&envelope)
+            .map(|p| Coord { x: p[0], y: p[1] })
+            .filter(|p| *p != a && *p != b)
+            .filter_map(|p| projection_on_segment(&p, &a, &b, tolerance).map(|t| (t, p)))
+            .collect();
+        inserted.sort_by(|(t1, _), (t2, _)| t1.total_cmp(t2));
+        coords.extend(inserted.into_iter().map(|(_, p)| p));
+    }
+    coords.extend(ring.0.last());
+    coords.dedup();
+    (coords.len() >= 4).then_some(LineString(coords))
+}
+
+/// Snap the borders of the zones closer than `tolerance` (in degrees), so that the borders
+/// shared by adjacent zones have exactly the same points (and are simplified the same way).
+///
+/// The points are snapped to the lowest point closer than `tolerance`. The rings degenerated by
+/// the snapping are kept as they were, and the boundaries made invalid are not snapped.
+/// Returns the number of modified boundaries.
+pub fn snap_borders(zones: &mut [Zone], tolerance: f64) -> usize {
+    info!("snapping the borders closer than {}", tolerance);
+    let grid = SnapGrid::new(
+        tolerance,
+        (zones.iter())
+            .filter_map(|z| z.boundary.as_ref())
+            .flat_map(|b| b.iter())
+            .flat_map(|p| std::iter::once(p.exterior()).chain(p.interiors()))
+            .flat_map(|r| r.0.iter().copied()),
+    );
+    let snapped_zones: Vec<Option<MultiPolygon<f64>>> = zones
+        .iter()
+        .map(|z| {
+            let boundary = z.boundary.as_ref()?;
+            let snap = |ring: &LineString<f64>| -> LineString<f64> {
+                ring.0.iter().map(|c| grid.snap(*c)).collect()
+            };
+            Some(
+                boundary
+                    .iter()
+                    .map(|p| {
+                        Polygon::new(snap(p.exterior()), p.interiors().iter().map(snap).collect())
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let points_tree = RTree::bulk_load(grid.points().map(|c| [c.x, c.y]).collect());
+    let mut nb_snapped = 0;
+    let mut nb_invalid = 0;
+    for (zone, snapped) in zones.iter_mut().zip(snapped_zones) {
+        let (Some(boundary), Some(snapped)) = (zone.boundary.as_ref(), snapped) else {
+            continue;
+        };
+        let snap = |snapped: &LineString<f64>, original: &LineString<f64>| {
+            snap_ring(snapped, tolerance, &points_tree).unwrap_or_else(|| original.clone())
+        };
+        let new_boundary: MultiPolygon<f64> = snapped
+            .iter()
+            .zip(boundary.iter())
+            .map(|(snapped, original)| {
+                Polygon::new(
+                    snap(snapped.exterior(), original.exterior()),
+                    (snapped.interiors().iter())
+                        .zip(original.interiors())
+                        .map(|(s, o)| snap(s, o))
+                        .collect(),
+                )
+            })
+            .collect();
+        if &new_boundary == boundary {
+            continue;
+        }
+        // merging some close points of a zone can make its boundary self-intersect
+        let is_valid = Geometry::try_from(&new_boundary).is_ok_and(|g| g.is_valid());
+        if !is_valid {
+            debug!(
+                "the snapped boundary of zone {} is invalid, it is not snapped",
+                zone.osm_id
+            );
+            nb_invalid += 1;
+        } else {
+            nb_snapped += 1;
+            zone.bbox = new_boundary.bounding_rect();
+            zone.boundary = Some(new_boundary);
+        }
+    }
+    info!(
+        "{} boundaries snapped, {} not snapped as they would be invalid",
+        nb_snapped, nb_invalid
+    );
+    nb_snapped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::ZoneType;
+
+    fn make_zone(zone_type: ZoneType, ring: Vec<(f64, f64)>) -> Zone {
+        Zone {
+            zone_type: Some(zone_type),
+            boundary: Some(MultiPolygon(vec![Polygon::new(
+                LineString::from(ring),
+                vec![],
+            )])),
+            ..Default::default()
+        }
+    }
+
+    /// 2 adjacent squares, sharing a wiggly border on x=2
+    fn adjacent_zones(left_type: ZoneType, right_type: ZoneType) -> Vec<Zone> {
+        let border = [(2., 0.), (2.01, 0.5), (1.99, 1.), (2.01, 1.5), (2., 2.)];
+        let mut left = vec![(0., 0.)];
+        left.extend(border.iter().cloned());
+        left.push((0., 2.));
+        let mut right = vec![(4., 0.), (4., 2.)];
+        right.extend(border.iter().rev().cloned());
+        vec![make_zone(left_type, left), make_zone(right_type, right)]
+    }
+
+    fn border_points(zone: &Zone) -> Vec<(f64, f64)> {
+        let mut points: Vec<_> = zone.boundary.as_ref().unwrap().0[0]
+            .exterior()
+            .points()
+            .map(|p| (p.x(), p.y()))
+            .filter(|(x, _)| (x - 2.).abs() < 0.1)
+            .collect();
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        points.dedup();
+        points
+    }
+
+    #[test]
+    fn shared_border_is_simplified_once() {
+        let mut zones = adjacent_zones(ZoneType::City, ZoneType::City);
+        simplify_zones(
+            &mut zones,
+            &SimplifyTolerances {
+                default: 0.1,
+                by_zone_type: vec![],
+            },
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert_eq!(border_points(&zones[0]), vec![(2., 0.), (2., 2.)]);
+        assert_eq!(border_points(&zones[0]), border_points(&zones[1]));
+        // the corners are kept
+        assert_eq!(
+            zones[0].boundary.as_ref().unwrap().0[0].exterior().0.len(),
+            5
+        );
+        assert_eq!(
+            zones[1].boundary.as_ref().unwrap().0[0].exterior().0.len(),
+            5
+        );
+    }
+
+    #[test]
+    fn shared_border_uses_the_smallest_tolerance() {
+        let mut zones = adjacent_zones(ZoneType::Country, ZoneType::City);
+        simplify_zones(
+            &mut zones,
+            &SimplifyTolerances {
+                default: 0.,
+                by_zone_type: vec![(ZoneType::Country, 0.1)],
+            },
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        assert_eq!(border_points(&zones[0]).len(), 5);
+        assert_eq!(border_points(&zones[0]), border_points(&zones[1]));
+    }
+
+    #[test]
+    fn close_borders_are_snapped() {
+        let mut zones = adjacent_zones(ZoneType::City, ZoneType::City);
+        // the left zone's border has an additional point, on a segment of the right zone's border
+        let left = zones[0].boundary.as_mut().unwrap();
+        let mut ring = left.0[0].exterior().0.clone();
+        ring.insert(4, Coord { x: 2., y: 1.25 });
+        left.0[0] = Polygon::new(LineString(ring), vec![]);
+        // and the right zone's border is slightly shifted
+        let right = zones[1].boundary.as_mut().unwrap();
+        let ring: Vec<_> = right.0[0]
+            .exterior()
+            .points()
+            .map(|p| (if p.x() < 3. { p.x() + 1e-9 } else { p.x() }, p.y()))
+            .collect();
+        right.0[0] = Polygon::new(LineString::from(ring), vec![]);
+        assert_ne!(border_points(&zones[0]), border_points(&zones[1]));
+
+        assert_eq!(snap_borders(&mut zones, 1e-7), 1);
+        assert_eq!(border_points(&zones[1]).len(), 6);
+        assert_eq!(border_points(&zones[0]), border_points(&zones[1]));
+    }
+
+    #[test]
+    fn snapping_does_not_depend_on_the_zones_order() {
+        let shifted = |zone: &mut Zone, dx: f64| {
+            let boundary = zone.boundary.as_mut().unwrap();
+            let ring: Vec<_> = (boundary.0[0].exterior().points())
+                .map(|p| {
+                    (
+                        if p.x() < 3. && p.x() > 1. {
+                            p.x() + dx
+                        } else {
+                            p.x()
+                        },
+                        p.y(),
+                    )
+                })
+                .collect();
+            boundary.0[0] = Polygon::new(LineString::from(ring), vec![]);
+        };
+        let mut zones = adjacent_zones(ZoneType::City, ZoneType::City);
+        shifted(&mut zones[0], 4e-8);
+        shifted(&mut zones[1], -4e-8);
+        let mut reversed: Vec<Zone> = zones.iter().rev().cloned().collect();
+
+        assert_eq!(snap_borders(&mut zones, 1e-7), 1);
+        assert_eq!(snap_borders(&mut reversed, 1e-7), 1);
+        reversed.reverse();
+        assert_eq!(zones[0].boundary, reversed[0].boundary);
+        assert_eq!(zones[1].boundary, reversed[1].boundary);
+        // the lowest points are kept, ie. the ones of the right zone
+        assert!(border_points(&zones[0]).contains(&(2. - 4e-8, 0.)));
+    }
+
+    #[test]
+    fn invalid_snapped_boundary_is_kept() {
+        // 2 triangles joined by a narrow neck, whose sides would touch once snapped
+        let ring = vec![
+            (0., 0.),
+            (4., 0.),
+            (2., 2. - 1e-8),
+            (4., 4.),
+            (0., 4.),
+            (2., 2. + 1e-8),
+            (0., 0.),
+        ];
+        let mut zones = vec![make_zone(ZoneType::City, ring)];
+        let boundary = zones[0].boundary.clone();
+        assert!(Geometry::try_from(boundary.as_ref().unwrap())
+            .unwrap()
+            .is_valid());
+
+        assert_eq!(snap_borders(&mut zones, 1e-7), 0);
+        assert_eq!(zones[0].boundary, boundary);
+    }
+
+    #[test]
+    fn isolated_ring_is_simplified() {
+        let mut zones = vec![make_zone(
+            ZoneType::City,
+            vec![(0., 0.), (0., 1.), (0., 2.), (2., 2.), (2., 0.), (1., 0.)],
+        )];
+        simplify_zones(
+            &mut zones,
+            &SimplifyTolerances {
+                default: 0.1,
+                by_zone_type: vec![],
+            },
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        let ring = &zones[0].boundary.as_ref().unwrap().0[0].exterior().0;
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring.first(), ring.last());
+    }
+}
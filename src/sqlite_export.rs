@@ -0,0 +1,290 @@
+// Export of the zones in a SQLite database, a single queryable file for the mobile and offline
+// consumers.
+//
+// The `zone` table has the zones with their boundary as a WKB blob, the `zone_names` table
+// their names and labels in each lang, and the `zone_hierarchy` table all the ancestors of each
+// zone (the closure of the parent relation), so the ancestors or the descendants of a zone are
+// found with a single indexed query. The bounding boxes of the zones are in a `zone_bbox` rtree.
+
+use crate::wkb::write_multipolygon;
+use crate::zone_ext::get_international_names;
+use anyhow::{Context, Error};
+use cosmogony::{Zone, ZoneIndex, ZoneType};
+use rusqlite::{params, Transaction};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE zone (
+  id INTEGER PRIMARY KEY NOT NULL,
+  osm_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  label TEXT NOT NULL,
+  admin_level INTEGER,
+  zone_type TEXT,
+  country_code TEXT,
+  parent INTEGER REFERENCES zone(id),
+  wikidata TEXT,
+  zip_codes TEXT,
+  population INTEGER,
+  is_generated BOOLEAN NOT NULL,
+  disputed BOOLEAN NOT NULL,
+  center_lon DOUBLE,
+  center_lat DOUBLE,
+  geometry BLOB
+);
+CREATE TABLE zone_names (
+  zone_id INTEGER NOT NULL REFERENCES zone(id),
+  lang TEXT NOT NULL,
+  name TEXT,
+  label TEXT,
+  PRIMARY KEY (zone_id, lang)
+);
+CREATE TABLE zone_hierarchy (
+  zone_id INTEGER NOT NULL REFERENCES zone(id),
+  ancestor_id INTEGER NOT NULL REFERENCES zone(id),
+  depth INTEGER NOT NULL,
+  PRIMARY KEY (zone_id, ancestor_id)
+);
+CREATE VIRTUAL TABLE zone_bbox USING rtree(id, min_x, max_x, min_y, max_y);
+";
+
+/// the indexes are created after the zones are inserted, which is faster
+const INDEXES: &str = "
+CREATE UNIQUE INDEX zone_osm_id ON zone (osm_id);
+CREATE INDEX zone_parent ON zone (parent);
+CREATE INDEX zone_country_type ON zone (country_code, zone_type);
+CREATE INDEX zone_name ON zone (name);
+CREATE INDEX zone_names_lang_name ON zone_names (lang, name);
+CREATE INDEX zone_hierarchy_ancestor ON zone_hierarchy (ancestor_id, depth);
+";
+
+/// The names of a zone by lang
+///
+/// The international names are not serialized, so the names of the zones read from a cosmogony
+/// file are the `name:<lang>` tags of the langs of their labels (the langs kept by the build).
+fn zone_names(zone: &Zone) -> BTreeMap<String, String> {
+    if !zone.international_names.is_empty() {
+        return zone.international_names.clone();
+    }
+    let mut names = get_international_names(&zone.tags, &zone.name, false);
+    names.retain(|lang, _| zone.international_labels.contains_key(lang));
+    names
+}
+
+fn insert_zone(transaction: &Transaction<'_>, zone: &Zone) -> Result<(), Error> {
+    let geometry = zone.boundary.as_ref().map(|b| {
+        let mut wkb = vec![];
+        write_multipolygon(&mut wkb, b);
+        wkb
+    });
+    // the statements are prepared once, and reused for all the zones
+    transaction
+        .prepare_cached(
+            "INSERT INTO zone (id, osm_id, name, label, admin_level, zone_type, country_code,
+               parent, wikidata, zip_codes, population, is_generated, disputed, center_lon,
+               center_lat, geometry)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+        )?
+        .execute(params![
+            zone.id.index as i64,
+            zone.osm_id,
+            zone.name,
+            zone.label,
+            zone.admin_level,
+            zone.zone_type.as_ref().map(ZoneType::as_str),
+            zone.country_code,
+            zone.parent.map(|p| p.index as i64),
+            zone.wikidata,
+            zone.zip_codes.join(";"),
+            zone.population.map(|p| p as i64),
+            zone.is_generated,
+            zone.disputed,
+            zone.center.map(|c| c.x()),
+            zone.center.map(|c| c.y()),
+            geometry,
+        ])?;
+    if let Some(bbox) = zone.bbox {
+        transaction
+            .prepare_cached("INSERT INTO zone_bbox VALUES (?1, ?2, ?3, ?4, ?5)")?
+            .execute(params![
+                zone.id.index as i64,
+                bbox.min().x,
+                bbox.max().x,
+                bbox.min().y,
+                bbox.max().y
+            ])?;
+    }
+
+    let names = zone_names(zone);
+    let langs: BTreeSet<&String> = names
+        .keys()
+        .chain(zone.international_labels.keys())
+        .collect();
+    let mut insert =
+        transaction.prepare_cached("INSERT INTO zone_names VALUES (?1, ?2, ?3, ?4)")?;
+    for lang in langs {
+        insert.execute(params![
+            zone.id.index as i64,
+            lang,
+            names.get(lang),
+            zone.international_labels.get(lang),
+        ])?;
+    }
+    Ok(())
+}
+
+/// Insert the ancestors of a zone, from its parent (depth 1) up to the root of its hierarchy
+fn insert_ancestors(
+    transaction: &Transaction<'_>,
+    zone: &Zone,
+    parents: &BTreeMap<ZoneIndex, Option<ZoneIndex>>,
+) -> Result<(), Error> {
+    let mut visited = BTreeSet::from([zone.id]);
+    let mut ancestor = zone.parent;
+    let mut depth = 1;
+    // the visited zones stop the loops of a broken hierarchy
+    while let Some(id) = ancestor.filter(|id| visited.insert(*id)) {
+        transaction
+            .prepare_cached("INSERT INTO zone_hierarchy VALUES (?1, ?2, ?3)")?
+            .execute(params![zone.id.index as i64, id.index as i64, depth])?;
+        ancestor = parents.get(&id).copied().flatten();
+        depth += 1;
+    }
+    Ok(())
+}
+
+/// Write the zones in a SQLite database, with their names and their hierarchy
+pub fn write_zones_as_sqlite(zones: &[Zone], path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("impossible to remove {}", path.display()))?;
+    }
+    let mut db = rusqlite::Connection::open(path)
+        .with_context(|| format!("impossible to create {}", path.display()))?;
+
+    let transaction = db.transaction()?;
+    // the parents can be written after their children
+    transaction.execute_batch("PRAGMA defer_foreign_keys = ON;")?;
+    transaction.execute_batch(SCHEMA)?;
+    info!("writing {} zones in {}", zones.len(), path.display());
+    let parents: BTreeMap<_, _> = zones.iter().map(|z| (z.id, z.parent)).collect();
+    for zone in zones {
+        insert_zone(&transaction, zone)?;
+        insert_ancestors(&transaction, zone, &parents)?;
+    }
+    transaction.execute_batch(INDEXES)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo_types::{LineString, MultiPolygon, Polygon, Rect};
+
+    fn zone(id: usize, parent: Option<usize>, zone_type: ZoneType) -> Zone {
+        let (min, max) = ((id as f64, 0.), (id as f64 + 1., 1.));
+        let ring = LineString::from(vec![min, (max.0, min.1), max, (min.0, max.1), min]);
+        Zone {
+            id: ZoneIndex { index: id },
+            osm_id: format!("relation:{}", id),
+            name: format!("zone {}", id),
+            zone_type: Some(zone_type),
+            parent: parent.map(|index| ZoneIndex { index }),
+            boundary: Some(MultiPolygon(vec![Polygon::new(ring, vec![])])),
+            bbox: Some(Rect::new(min, max)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zones_written_as_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cosmogony.sqlite");
+        let mut zones = vec![
+            zone(0, None, ZoneType::Country),
+            zone(1, Some(0), ZoneType::State),
+            zone(2, Some(1), ZoneType::City),
+            zone(3, Some(1), ZoneType::City),
+        ];
+        zones[2].international_names = BTreeMap::from([("fr".into(), "la zone 2".into())]);
+        zones[2].international_labels = BTreeMap::from([
+            ("de".into(), "Zone 2, Zone 1".into()),
+            ("fr".into(), "la zone 2, la zone 1".into()),
+        ]);
+        // a zone read from a cosmogony file, without its international names
+        zones[3].tags = [("name:de", "Zone 3"), ("name:it", "zona 3")]
+            .into_iter()
+            .collect();
+        zones[3].international_labels = BTreeMap::from([("de".into(), "Zone 3, Zone 1".into())]);
+        // the children are written before their parents
+        zones.reverse();
+        write_zones_as_sqlite(&zones, &path).unwrap();
+
+        let db = rusqlite::Connection::open(&path).unwrap();
+        let query = |sql: &str| -> Vec<(i64, i64)> {
+            db.prepare(sql)
+                .unwrap()
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        // the ancestors of a city, and the descendants of the country
+        assert_eq!(
+            query("SELECT ancestor_id, depth FROM zone_hierarchy WHERE zone_id = 2 ORDER BY depth"),
+            vec![(1, 1), (0, 2)]
+        );
+        assert_eq!(
+            query(
+                "SELECT zone_id, depth FROM zone_hierarchy WHERE ancestor_id = 0 ORDER BY zone_id"
+            ),
+            vec![(1, 1), (2, 2), (3, 2)]
+        );
+        // the zone 3 is the only one intersecting this bbox
+        assert_eq!(
+            query("SELECT id, id FROM zone_bbox WHERE max_x >= 3.5 AND min_x <= 3.7"),
+            vec![(3, 3)]
+        );
+
+        let names = |id: i64| -> Vec<(String, Option<String>, Option<String>)> {
+            db.prepare("SELECT lang, name, label FROM zone_names WHERE zone_id = ?1 ORDER BY lang")
+                .unwrap()
+                .query_map([id], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(
+            names(2),
+            vec![
+                ("de".into(), None, Some("Zone 2, Zone 1".into())),
+                (
+                    "fr".into(),
+                    Some("la zone 2".into()),
+                    Some("la zone 2, la zone 1".into())
+                ),
+            ]
+        );
+        assert_eq!(
+            names(3),
+            vec![(
+                "de".into(),
+                Some("Zone 3".into()),
+                Some("Zone 3, Zone 1".into())
+            )]
+        );
+
+        let (zone_type, blob): (String, Vec<u8>) = db
+            .query_row(
+                "SELECT zone_type, geometry FROM zone WHERE osm_id = 'relation:1'",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(zone_type, "state");
+        // multipolygon with 1 ring of 5 points
+        assert_eq!(blob.len(), 9 + 9 + 4 + 5 * 16);
+    }
+}
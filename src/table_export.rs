@@ -0,0 +1,198 @@
+// Export of the zones as a flat table (CSV or TSV), without geometry, for the analysts
+// who want to load the hierarchy in a spreadsheet or a dataframe.
+//
+// Each row is a zone, with its ancestors names in one column by zone type
+// (the `country`, `state`, ... columns), so the hierarchy can be used without joins, and its
+// bbox in the last columns.
+
+use anyhow::Error;
+use cosmogony::{Zone, ZoneIndex, ZoneType};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// The separator of the table columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    /// comma separated, the values are quoted if needed (RFC 4180)
+    Csv,
+    /// tab separated, the tabs and new lines in the values are replaced by spaces
+    Tsv,
+}
+
+/// the zone types of the ancestors columns, from the largest to the smallest
+pub(crate) const HIERARCHY_TYPES: [ZoneType; 8] = [
+    ZoneType::Country,
+    ZoneType::CountryRegion,
+    ZoneType::State,
+    ZoneType::StateDistrict,
+    ZoneType::City,
+    ZoneType::Borough,
+    ZoneType::CityDistrict,
+    ZoneType::Suburb,
+];
+
+const ATTRIBUTES: [&str; 8] = [
+    "id",
+    "osm_id",
+    "name",
+    "zone_type",
+    "admin_level",
+    "country_code",
+    "parent",
+    "parent_osm_id",
+];
+
+/// the columns of the bbox of the zone, after the ancestors columns
+const BBOX_COLUMNS: [&str; 4] = ["min_lon", "min_lat", "max_lon", "max_lat"];
+
+fn escape(value: &str, format: TableFormat) -> String {
+    match format {
+        TableFormat::Csv if value.contains([',', '"', '\n', '\r']) => {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        }
+        TableFormat::Csv => value.to_string(),
+        TableFormat::Tsv => value.replace(['\t', '\n', '\r'], " "),
+    }
+}
+
+fn write_row(
+    writer: &mut impl Write,
+    values: impl IntoIterator<Item = String>,
+    format: TableFormat,
+) -> Result<(), Error> {
+    let separator = match format {
+        TableFormat::Csv => ",",
+        TableFormat::Tsv => "\t",
+    };
+    let row: Vec<String> = values.into_iter().map(|v| escape(&v, format)).collect();
+    writeln!(writer, "{}", row.join(separator))?;
+    Ok(())
+}
+
+/// The names of the ancestors of a zone, by zone type (the closest ancestor of each type)
+pub(crate) fn ancestors_names<'a>(
+    zone: &Zone,
+    zones_by_id: &BTreeMap<ZoneIndex, &'a Zone>,
+) -> BTreeMap<ZoneType, &'a str> {
+    let mut ancestors = BTreeMap::new();
+    let mut ancestor = zone.parent.and_then(|p| zones_by_id.get(&p));
+    // the number of ancestors is bounded, in case of a cycle in the hierarchy
+    for _ in 0..zones_by_id.len() {
+        let Some(a) = ancestor else { break };
+        if let Some(zone_type) = a.zone_type {
+            ancestors.entry(zone_type).or_insert(a.name.as_str());
+        }
+        ancestor = a.parent.and_then(|p| zones_by_id.get(&p));
+    }
+    ancestors
+}
+
+/// Write one row by zone, with its main attributes and the names of its ancestors
+pub fn write_zones_as_table(
+    zones: &[Zone],
+    mut writer: impl Write,
+    format: TableFormat,
+) -> Result<(), Error> {
+    // the zones ids are not their position once the zones have been cleaned
+    let zones_by_id: BTreeMap<ZoneIndex, &Zone> = zones.iter().map(|z| (z.id, z)).collect();
+
+    let header = ATTRIBUTES
+        .iter()
+        .map(|a| a.to_string())
+        .chain(HIERARCHY_TYPES.iter().map(|t| t.as_str().to_string()))
+        .chain(BBOX_COLUMNS.iter().map(|c| c.to_string()));
+    write_row(&mut writer, header, format)?;
+
+    for zone in zones {
+        let parent = zone.parent.and_then(|p| zones_by_id.get(&p));
+        let ancestors = ancestors_names(zone, &zones_by_id);
+
+        let attributes = [
+            zone.id.index.to_string(),
+            zone.osm_id.clone(),
+            zone.name.clone(),
+            zone.zone_type.map_or("", |t| t.as_str()).to_string(),
+            zone.admin_level.map_or(String::new(), |l| l.to_string()),
+            zone.country_code.clone().unwrap_or_default(),
+            zone.parent.map_or(String::new(), |p| p.index.to_string()),
+            parent.map_or(String::new(), |p| p.osm_id.clone()),
+        ];
+        let hierarchy = HIERARCHY_TYPES
+            .iter()
+            .map(|t| ancestors.get(t).unwrap_or(&"").to_string());
+        let bbox = zone.bbox.map_or([None; 4], |b| {
+            [b.min().x, b.min().y, b.max().x, b.max().y].map(Some)
+        });
+        let bbox = bbox.map(|c| c.map_or(String::new(), |c| c.to_string()));
+        let row = attributes.into_iter().chain(hierarchy).chain(bbox);
+        write_row(&mut writer, row, format)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn zone(id: usize, name: &str, zone_type: ZoneType, parent: Option<usize>) -> Zone {
+        Zone {
+            id: ZoneIndex { index: id },
+            osm_id: format!("relation:{}", id),
+            name: name.into(),
+            zone_type: Some(zone_type),
+            country_code: Some("FR".into()),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        }
+    }
+
+    fn zones() -> Vec<Zone> {
+        // the ids are not the zones positions
+        vec![
+            Zone {
+                bbox: Some(geo_types::Rect::new((-5.1, 41.3), (9.6, 51.1))),
+                ..zone(3, "France", ZoneType::Country, None)
+            },
+            zone(5, "Île-de-France", ZoneType::State, Some(3)),
+            zone(1, "Paris, \"la capitale\"", ZoneType::City, Some(5)),
+        ]
+    }
+
+    #[test]
+    fn csv_export() {
+        let mut output = vec![];
+        write_zones_as_table(&zones(), &mut output, TableFormat::Csv).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            "id,osm_id,name,zone_type,admin_level,country_code,parent,parent_osm_id,\
+             country,country_region,state,state_district,city,borough,city_district,suburb,\
+             min_lon,min_lat,max_lon,max_lat"
+        );
+        assert_eq!(
+            lines[1],
+            "3,relation:3,France,country,,FR,,,,,,,,,,,-5.1,41.3,9.6,51.1"
+        );
+        assert_eq!(
+            lines[3],
+            "1,relation:1,\"Paris, \"\"la capitale\"\"\",city,,FR,5,relation:5,\
+             France,,Île-de-France,,,,,,,,,"
+        );
+    }
+
+    #[test]
+    fn tsv_export() {
+        let mut output = vec![];
+        write_zones_as_table(&zones(), &mut output, TableFormat::Tsv).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let row: Vec<_> = output.lines().nth(2).unwrap().split('\t').collect();
+        assert_eq!(
+            row.len(),
+            ATTRIBUTES.len() + HIERARCHY_TYPES.len() + BBOX_COLUMNS.len()
+        );
+        assert_eq!(row[2], "Île-de-France");
+        assert_eq!(row[8], "France");
+    }
+}
@@ -0,0 +1,31 @@
+// The fixtures shared by the unit tests of several modules.
+
+use cosmogony::Zone;
+use geo::prelude::BoundingRect;
+use geo_types::{LineString, MultiPolygon, Polygon};
+
+/// the closed ring of the rectangle from `min` to `max`, counterclockwise
+pub(crate) fn square_ring(min: (f64, f64), max: (f64, f64)) -> LineString<f64> {
+    LineString::from(vec![
+        (min.0, min.1),
+        (max.0, min.1),
+        (max.0, max.1),
+        (min.0, max.1),
+        (min.0, min.1),
+    ])
+}
+
+/// the rectangle from `min` to `max`, as a boundary
+pub(crate) fn square(min: (f64, f64), max: (f64, f64)) -> MultiPolygon<f64> {
+    MultiPolygon(vec![Polygon::new(square_ring(min, max), vec![])])
+}
+
+/// a zone whose boundary is the rectangle from `min` to `max`
+pub(crate) fn square_zone(min: (f64, f64), max: (f64, f64)) -> Zone {
+    let boundary = square(min, max);
+    Zone {
+        bbox: boundary.bounding_rect(),
+        boundary: Some(boundary),
+        ..Default::default()
+    }
+}
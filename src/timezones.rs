@@ -0,0 +1,90 @@
+// The IANA timezone of the zones, found with the timezones boundaries of the
+// timezone-boundary-builder project (https://github.com/evansiroky/timezone-boundary-builder):
+// a geojson file whose features are the timezones, with their name in the `tzid` property.
+
+use crate::named_polygons::NamedPolygons;
+use anyhow::{Context, Error};
+use cosmogony::Zone;
+use geo_types::Point;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+pub struct TimezoneFinder(NamedPolygons);
+
+impl TimezoneFinder {
+    pub fn new(timezones: NamedPolygons) -> Self {
+        TimezoneFinder(timezones)
+    }
+
+    /// Read the timezones from a geojson file
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        info!("reading timezones from {}", path.display());
+        let file = File::open(path)
+            .with_context(|| format!("impossible to open timezones file {}", path.display()))?;
+        let timezones = NamedPolygons::from_geojson(BufReader::new(file), "tzid")
+            .with_context(|| format!("invalid timezones file {}", path.display()))?;
+        info!("{} timezones read", timezones.len());
+        Ok(Self::new(timezones))
+    }
+
+    /// the timezone containing a point
+    pub fn find(&self, point: &Point<f64>) -> Option<&str> {
+        self.0.find(point)
+    }
+}
+
+/// Give to each zone the timezone containing its center
+pub fn assign_timezones(zones: &mut [Zone], finder: &TimezoneFinder) {
+    info!("assigning the zones' timezones");
+    zones.par_iter_mut().for_each(|z| {
+        let center = z.center.or_else(|| z.bbox.map(|b| b.center().into()));
+        z.timezone = center
+            .and_then(|c| finder.find(&c))
+            .map(|tzid| tzid.to_string());
+    });
+    let nb_without_timezone = zones.iter().filter(|z| z.timezone.is_none()).count();
+    if nb_without_timezone > 0 {
+        info!("{} zones without timezone", nb_without_timezone);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+    use geo::prelude::BoundingRect;
+    use geo_types::MultiPolygon;
+
+    fn square(min_x: f64, max_x: f64) -> MultiPolygon<f64> {
+        test_utils::square((min_x, 0.), (max_x, 10.))
+    }
+
+    #[test]
+    fn zones_timezones() {
+        let finder = TimezoneFinder::new(NamedPolygons::new(vec![
+            ("Europe/Paris".into(), square(0., 10.)),
+            ("Europe/Berlin".into(), square(10., 20.)),
+        ]));
+        let mut zones = vec![
+            Zone {
+                center: Some(Point::new(5., 5.)),
+                ..Default::default()
+            },
+            Zone {
+                bbox: Some(square(12., 14.).bounding_rect().unwrap()),
+                ..Default::default()
+            },
+            Zone {
+                center: Some(Point::new(25., 5.)),
+                ..Default::default()
+            },
+        ];
+        assign_timezones(&mut zones, &finder);
+
+        assert_eq!(zones[0].timezone.as_deref(), Some("Europe/Paris"));
+        assert_eq!(zones[1].timezone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(zones[2].timezone, None);
+    }
+}
@@ -0,0 +1,296 @@
+// Export of the zones as TopoJSON (https://github.com/topojson/topojson-specification),
+// for the web maps.
+//
+// The borders shared by several zones are stored only once, as arcs referenced by the zones
+// geometries, which makes the file much smaller than the GeoJSON of the same zones. Since the
+// neighbours share the same arcs, the clients can simplify them without creating gaps
+// between the zones (like with `topojson-simplify`).
+//
+// The arcs are split at the same junctions as for the simplification of the boundaries (see
+// the `simplification` module), and they are quantized (with the osm precision) and delta-encoded.
+//
+// Each zone is a geometry of the `zones` object, with its hierarchy in its properties: its
+// parent and the names of its ancestors by zone type (like the columns of the table export).
+
+use crate::simplification::{arc_key, find_junctions, open_ring, split_ring, PointKey};
+use crate::table_export::{ancestors_names, HIERARCHY_TYPES};
+use anyhow::Error;
+use cosmogony::{Zone, ZoneIndex};
+use geo_types::{Coord, LineString};
+use serde_derive::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+/// the name of the TopoJSON object holding the zones
+pub const ZONES_OBJECT: &str = "zones";
+
+/// the size of the quantization grid, in degrees (the precision of the osm coordinates)
+const QUANTIZATION_SCALE: f64 = 1e-7;
+
+#[derive(Serialize)]
+struct Transform {
+    scale: [f64; 2],
+    translate: [f64; 2],
+}
+
+#[derive(Serialize)]
+struct Geometry {
+    #[serde(rename = "type")]
+    geometry_type: Option<&'static str>,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arcs: Option<Vec<Vec<Vec<i64>>>>,
+    properties: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct GeometryCollection {
+    #[serde(rename = "type")]
+    collection_type: &'static str,
+    geometries: Vec<Geometry>,
+}
+
+#[derive(Serialize)]
+struct Topology {
+    #[serde(rename = "type")]
+    topology_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bbox: Option<[f64; 4]>,
+    transform: Transform,
+    objects: BTreeMap<&'static str, GeometryCollection>,
+    arcs: Vec<Vec<[i64; 2]>>,
+}
+
+/// The arcs shared by the zones rings
+struct Arcs {
+    junctions: HashSet<PointKey>,
+    index: HashMap<Vec<PointKey>, usize>,
+    arcs: Vec<Vec<Coord<f64>>>,
+}
+
+impl Arcs {
+    fn new(zones: &[Zone]) -> Self {
+        Arcs {
+            junctions: find_junctions(zones),
+            index: HashMap::new(),
+            arcs: vec![],
+        }
+    }
+
+    /// The indexes of the arcs of a ring, a reversed arc `i` being referenced as `!i`
+    fn ring(&mut self, ring: &LineString<f64>) -> Vec<i64> {
+        let ring = open_ring(ring);
+        if ring.is_empty() {
+            return vec![];
+        }
+        split_ring(ring, &self.junctions)
+            .into_iter()
+            .map(|mut arc| {
+                let (k, reversed) = arc_key(&arc);
+                let arcs = &mut self.arcs;
+                let idx = *self.index.entry(k).or_insert_with(|| {
+                    if reversed {
+                        arc.reverse();
+                    }
+                    arcs.push(arc);
+                    arcs.len() - 1
+                }) as i64;
+                if reversed {
+                    !idx
+                } else {
+                    idx
+                }
+            })
+            .collect()
+    }
+
+    fn bbox(&self) -> Option<[f64; 4]> {
+        let mut coords = self.arcs.iter().flatten();
+        let first = coords.next()?;
+        Some(coords.fold([first.x, first.y, first.x, first.y], |b, c| {
+            [b[0].min(c.x), b[1].min(c.y), b[2].max(c.x), b[3].max(c.y)]
+        }))
+    }
+
+    /// the quantized and delta-encoded arcs
+    fn encode(&self, translate: [f64; 2]) -> Vec<Vec<[i64; 2]>> {
+        let quantize = |c: &Coord<f64>| {
+            [
+                ((c.x - translate[0]) / QUANTIZATION_SCALE).round() as i64,
+                ((c.y - translate[1]) / QUANTIZATION_SCALE).round() as i64,
+            ]
+        };
+        self.arcs
+            .iter()
+            .map(|arc| {
+                let mut previous = [0, 0];
+                let mut encoded = vec![];
+                for (i, c) in arc.iter().enumerate() {
+                    let point = quantize(c);
+                    let delta = [point[0] - previous[0], point[1] - previous[1]];
+                    // the points merged by the quantization are skipped,
+                    // but an arc needs at least 2 points
+                    if i == 0 || delta != [0, 0] || (i + 1 == arc.len() && encoded.len() < 2) {
+                        encoded.push(delta);
+                        previous = point;
+                    }
+                }
+                encoded
+            })
+            .collect()
+    }
+}
+
+fn properties(zone: &Zone, zones_by_id: &BTreeMap<ZoneIndex, &Zone>) -> Map<String, Value> {
+    let parent = zone.parent.and_then(|p| zones_by_id.get(&p));
+    let mut properties = Map::new();
+    properties.insert("id".into(), zone.id.index.into());
+    properties.insert("osm_id".into(), zone.osm_id.clone().into());
+    properties.insert("name".into(), zone.name.clone().into());
+    properties.insert("label".into(), zone.label.clone().into());
+    properties.insert(
+        "zone_type".into(),
+        zone.zone_type.map(|t| t.as_str()).into(),
+    );
+    properties.insert("admin_level".into(), zone.admin_level.into());
+    properties.insert("country_code".into(), zone.country_code.clone().into());
+    properties.insert("parent".into(), zone.parent.map(|p| p.index).into());
+    properties.insert(
+        "parent_osm_id".into(),
+        parent.map(|p| p.osm_id.clone()).into(),
+    );
+    let ancestors = ancestors_names(zone, zones_by_id);
+    for zone_type in HIERARCHY_TYPES {
+        if let Some(name) = ancestors.get(&zone_type) {
+            properties.insert(zone_type.as_str().into(), name.to_string().into());
+        }
+    }
+    properties
+}
+
+/// Write the zones as a TopoJSON topology, with one geometry by zone in the `zones` object
+///
+/// The zones without boundary have a null geometry.
+pub fn write_zones_as_topojson(zones: &[Zone], mut writer: impl Write) -> Result<(), Error> {
+    info!("writing the zones as topojson");
+    // the zones ids are not their position once the zones have been cleaned
+    let zones_by_id: BTreeMap<ZoneIndex, &Zone> = zones.iter().map(|z| (z.id, z)).collect();
+
+    let mut arcs = Arcs::new(zones);
+    let geometries = zones
+        .iter()
+        .map(|zone| {
+            let polygons = zone.boundary.as_ref().map(|b| {
+                b.iter()
+                    .map(|p| {
+                        std::iter::once(p.exterior())
+                            .chain(p.interiors())
+                            .map(|r| arcs.ring(r))
+                            .filter(|r| !r.is_empty())
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|p| !p.is_empty())
+                    .collect::<Vec<_>>()
+            });
+            Geometry {
+                geometry_type: polygons.as_ref().map(|_| "MultiPolygon"),
+                id: zone.osm_id.clone(),
+                arcs: polygons,
+                properties: properties(zone, &zones_by_id),
+            }
+        })
+        .collect();
+
+    let bbox = arcs.bbox();
+    let translate = bbox.map_or([0., 0.], |b| [b[0], b[1]]);
+    let topology = Topology {
+        topology_type: "Topology",
+        bbox,
+        transform: Transform {
+            scale: [QUANTIZATION_SCALE, QUANTIZATION_SCALE],
+            translate,
+        },
+        objects: [(
+            ZONES_OBJECT,
+            GeometryCollection {
+                collection_type: "GeometryCollection",
+                geometries,
+            },
+        )]
+        .into_iter()
+        .collect(),
+        arcs: arcs.encode(translate),
+    };
+    serde_json::to_writer(&mut writer, &topology)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+    use cosmogony::ZoneType;
+    use geo_types::MultiPolygon;
+
+    fn zone(id: usize, name: &str, zone_type: ZoneType, parent: Option<usize>) -> Zone {
+        Zone {
+            id: ZoneIndex { index: id },
+            osm_id: format!("relation:{}", id),
+            name: name.into(),
+            zone_type: Some(zone_type),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        }
+    }
+
+    fn square(x: f64) -> MultiPolygon<f64> {
+        test_utils::square((x, 0.), (x + 1., 1.))
+    }
+
+    #[test]
+    fn shared_borders_are_shared_arcs() {
+        let mut zones = vec![
+            zone(0, "Country", ZoneType::Country, None),
+            zone(1, "West", ZoneType::State, Some(0)),
+            zone(2, "East", ZoneType::State, Some(0)),
+        ];
+        zones[1].boundary = Some(square(5.));
+        zones[2].boundary = Some(square(6.));
+
+        let mut output = vec![];
+        write_zones_as_topojson(&zones, &mut output).unwrap();
+        let topology: Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(topology["type"], "Topology");
+        assert_eq!(topology["bbox"], serde_json::json!([5., 0., 7., 1.]));
+        // the border between West and East is stored once
+        assert_eq!(topology["arcs"].as_array().unwrap().len(), 3);
+        let geometries = topology["objects"]["zones"]["geometries"]
+            .as_array()
+            .unwrap();
+        assert_eq!(geometries[0]["type"], Value::Null);
+        let ring_arcs = |g: &Value| -> Vec<i64> {
+            g["arcs"][0][0]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|a| a.as_i64().unwrap())
+                .collect()
+        };
+        let west = ring_arcs(&geometries[1]);
+        let east = ring_arcs(&geometries[2]);
+        assert_eq!(west.len(), 2);
+        assert_eq!(east.len(), 2);
+        let shared: Vec<_> = west.iter().filter(|a| east.contains(&!**a)).collect();
+        assert_eq!(shared.len(), 1);
+
+        let east_properties = &geometries[2]["properties"];
+        assert_eq!(geometries[2]["id"], "relation:2");
+        assert_eq!(east_properties["zone_type"], "state");
+        assert_eq!(east_properties["parent"], 0);
+        assert_eq!(east_properties["parent_osm_id"], "relation:0");
+        assert_eq!(east_properties["country"], "Country");
+    }
+}
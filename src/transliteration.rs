@@ -0,0 +1,109 @@
+// Many zones only have a name in their local script, and the osm `int_name` tag is rarely set,
+// so the international geocoders have no name they can display to everyone.
+//
+// A romanized name is added (as a `latin` name) to the zones whose default name is not in the
+// latin script: the `int_name` tag if there is one, else a transliteration of the name.
+// The `latin` labels are then built from these names like the other international labels.
+
+use cosmogony::Zone;
+use deunicode::deunicode;
+use log::info;
+
+/// the language of the romanized names
+pub const LATIN: &str = "latin";
+
+/// check if a letter is in the latin script (with its diacritics and extensions)
+fn is_latin(c: char) -> bool {
+    matches!(c,
+        'A'..='Z' | 'a'..='z'
+        // latin-1 supplement and latin extended A and B, IPA
+        | '\u{00C0}'..='\u{02AF}'
+        // latin extended additional
+        | '\u{1E00}'..='\u{1EFF}'
+        // latin extended C, D and E
+        | '\u{2C60}'..='\u{2C7F}' | '\u{A720}'..='\u{A7FF}' | '\u{AB30}'..='\u{AB6F}'
+        // fullwidth latin letters
+        | '\u{FF21}'..='\u{FF3A}' | '\u{FF41}'..='\u{FF5A}'
+    ) && c != '\u{00D7}'
+        && c != '\u{00F7}'
+}
+
+/// check if a name has some letters that are not in the latin script
+pub fn is_non_latin(name: &str) -> bool {
+    name.chars().any(|c| c.is_alphabetic() && !is_latin(c))
+}
+
+/// the romanized name of a zone, None if its name is already in the latin script
+fn romanized_name(zone: &Zone) -> Option<String> {
+    if !is_non_latin(&zone.name) {
+        return None;
+    }
+    let int_name = zone
+        .tags
+        .get("int_name")
+        .filter(|n| !n.is_empty() && !is_non_latin(n));
+    let name = match int_name {
+        Some(n) => n.to_string(),
+        None => deunicode(&zone.name).trim().to_string(),
+    };
+    (!name.is_empty()).then_some(name)
+}
+
+/// Add a `latin` name to the zones whose name is not in the latin script
+///
+/// The existing `name:latin` names are kept. Returns the number of romanized zones.
+pub fn romanize_names(zones: &mut [Zone]) -> usize {
+    info!("romanizing the non latin names");
+    let mut nb_romanized = 0;
+    for zone in zones.iter_mut() {
+        if zone.international_names.contains_key(LATIN) {
+            continue;
+        }
+        if let Some(name) = romanized_name(zone) {
+            zone.international_names.insert(LATIN.to_string(), name);
+            nb_romanized += 1;
+        }
+    }
+    info!("{} zones with a romanized name", nb_romanized);
+    nb_romanized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmogony::ZoneTags;
+
+    fn zone(name: &str, tags: &[(&str, &str)]) -> Zone {
+        let mut zone_tags = ZoneTags::new();
+        for (k, v) in tags {
+            zone_tags.insert_if_absent(k, v);
+        }
+        Zone {
+            name: name.into(),
+            tags: zone_tags,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn non_latin_names_are_romanized() {
+        let mut zones = vec![
+            zone("Москва", &[]),
+            zone("Ελλάδα", &[("int_name", "Elláda")]),
+            zone("Île-de-France", &[]),
+            zone("Łódź", &[]),
+            zone("Αθήνα", &[("int_name", "Αθήνα")]), // not a latin int_name
+        ];
+        zones[4]
+            .international_names
+            .insert(LATIN.into(), "Athens".into());
+
+        assert_eq!(romanize_names(&mut zones), 2);
+        let latin = |idx: usize| zones[idx].international_names.get(LATIN).cloned();
+        assert_eq!(latin(0).as_deref(), Some("Moskva"));
+        assert_eq!(latin(1).as_deref(), Some("Elláda"));
+        assert_eq!(latin(2), None);
+        assert_eq!(latin(3), None);
+        assert_eq!(latin(4).as_deref(), Some("Athens"));
+    }
+}
@@ -0,0 +1,225 @@
+// Export of the hierarchy only (the ids, names, types, bboxes and parent links of the zones),
+// without geometry, for the consumers only needing the tree (autocompletes, admin pickers...)
+// instead of stripping the boundaries of the whole cosmogony.
+//
+// The `.tree.json` output is a nested tree: the zones without parent are the roots, with their
+// children in a `children` field. The `.tree.jsonl` output has one zone by line, with the id of
+// its parent, so it can be read as a stream.
+
+use anyhow::Error;
+use cosmogony::{Zone, ZoneIndex, ZoneType};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+/// The layout of the tree file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeFormat {
+    /// a json document with the root zones, each one with its children
+    Nested,
+    /// one zone by line, with the id of its parent
+    Lines,
+}
+
+#[derive(Serialize)]
+struct TreeNode<'a> {
+    id: usize,
+    osm_id: &'a str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zone_type: Option<ZoneType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    admin_level: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country_code: Option<&'a str>,
+    /// `[min_lon, min_lat, max_lon, max_lat]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bbox: Option<[f64; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<TreeNode<'a>>,
+}
+
+impl<'a> TreeNode<'a> {
+    fn new(zone: &'a Zone) -> Self {
+        TreeNode {
+            id: zone.id.index,
+            osm_id: &zone.osm_id,
+            name: &zone.name,
+            zone_type: zone.zone_type,
+            admin_level: zone.admin_level,
+            country_code: zone.country_code.as_deref(),
+            bbox: zone
+                .bbox
+                .map(|b| [b.min().x, b.min().y, b.max().x, b.max().y]),
+            parent: None,
+            children: vec![],
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Tree<'a> {
+    roots: Vec<TreeNode<'a>>,
+}
+
+/// The positions of the children of each zone, and the positions of the zones without parent
+/// (or whose parent is not in the zones)
+fn children_by_zone(zones: &[Zone]) -> (BTreeMap<usize, Vec<usize>>, Vec<usize>) {
+    // the zones ids are not their position once the zones have been cleaned
+    let positions: BTreeMap<ZoneIndex, usize> =
+        zones.iter().enumerate().map(|(i, z)| (z.id, i)).collect();
+    let mut children = BTreeMap::<usize, Vec<usize>>::new();
+    let mut roots = vec![];
+    for (i, zone) in zones.iter().enumerate() {
+        match zone.parent.and_then(|p| positions.get(&p)) {
+            Some(parent) => children.entry(*parent).or_default().push(i),
+            None => roots.push(i),
+        }
+    }
+    (children, roots)
+}
+
+fn nested_node<'a>(
+    zones: &'a [Zone],
+    position: usize,
+    children: &BTreeMap<usize, Vec<usize>>,
+    visited: &mut BTreeSet<usize>,
+) -> TreeNode<'a> {
+    visited.insert(position);
+    let mut node = TreeNode::new(&zones[position]);
+    for child in children.get(&position).into_iter().flatten() {
+        if !visited.contains(child) {
+            node.children
+                .push(nested_node(zones, *child, children, visited));
+        }
+    }
+    node
+}
+
+/// Write the hierarchy of the zones, without their geometries
+pub fn write_zones_as_tree(
+    zones: &[Zone],
+    mut writer: impl Write,
+    format: TreeFormat,
+) -> Result<(), Error> {
+    match format {
+        TreeFormat::Lines => {
+            let ids: BTreeSet<ZoneIndex> = zones.iter().map(|z| z.id).collect();
+            for zone in zones {
+                let node = TreeNode {
+                    parent: zone.parent.filter(|p| ids.contains(p)).map(|p| p.index),
+                    ..TreeNode::new(zone)
+                };
+                serde_json::to_writer(&mut writer, &node)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        TreeFormat::Nested => {
+            let (children, roots) = children_by_zone(zones);
+            let mut visited = BTreeSet::new();
+            let mut tree = Tree {
+                roots: roots
+                    .into_iter()
+                    .map(|r| nested_node(zones, r, &children, &mut visited))
+                    .collect(),
+            };
+            // the zones of a cycle in the hierarchy cannot be reached from the roots
+            for position in 0..zones.len() {
+                if !visited.contains(&position) {
+                    warn!(
+                        "{} is in a cycle of the hierarchy, written as a root of the tree",
+                        zones[position].osm_id
+                    );
+                    let node = nested_node(zones, position, &children, &mut visited);
+                    tree.roots.push(node);
+                }
+            }
+            serde_json::to_writer(&mut writer, &tree)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn zone(index: usize, name: &str, parent: Option<usize>) -> Zone {
+        Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            name: name.into(),
+            zone_type: Some(ZoneType::City),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zones_written_as_tree() {
+        let zones = vec![
+            Zone {
+                zone_type: Some(ZoneType::Country),
+                admin_level: Some(2),
+                country_code: Some("LU".into()),
+                bbox: Some(geo_types::Rect::new((5.7, 49.4), (6.5, 50.2))),
+                ..zone(10, "Lëtzebuerg", None)
+            },
+            zone(11, "Diekirch", Some(10)),
+            zone(12, "Ettelbruck", Some(11)),
+            // the parent of this zone has been removed
+            zone(13, "Somewhere", Some(42)),
+            // a cycle in the hierarchy
+            zone(14, "A", Some(15)),
+            zone(15, "B", Some(14)),
+        ];
+
+        let mut nested = vec![];
+        write_zones_as_tree(&zones, &mut nested, TreeFormat::Nested).unwrap();
+        let nested: serde_json::Value = serde_json::from_slice(&nested).unwrap();
+        let roots = nested["roots"].as_array().unwrap();
+        let names: Vec<_> = roots.iter().map(|r| r["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["Lëtzebuerg", "Somewhere", "A"]);
+        assert_eq!(
+            roots[0],
+            serde_json::json!({
+                "id": 10,
+                "osm_id": "relation:10",
+                "name": "Lëtzebuerg",
+                "zone_type": "country",
+                "admin_level": 2,
+                "country_code": "LU",
+                "bbox": [5.7, 49.4, 6.5, 50.2],
+                "children": [{
+                    "id": 11,
+                    "osm_id": "relation:11",
+                    "name": "Diekirch",
+                    "zone_type": "city",
+                    "children": [{
+                        "id": 12,
+                        "osm_id": "relation:12",
+                        "name": "Ettelbruck",
+                        "zone_type": "city",
+                    }],
+                }],
+            })
+        );
+        assert_eq!(roots[2]["children"][0]["name"], "B");
+
+        let mut lines = vec![];
+        write_zones_as_tree(&zones, &mut lines, TreeFormat::Lines).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(lines)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0].get("parent"), None);
+        assert_eq!(lines[2]["parent"], 11);
+        assert_eq!(lines[3].get("parent"), None);
+        assert!(lines.iter().all(|l| l.get("children").is_none()));
+    }
+}
@@ -0,0 +1,209 @@
+// The parts of a zone not covered by any of its children (eg. the unincorporated areas of a
+// county, or the parts of a state outside of its municipalities), for the renderings that
+// highlight them.
+//
+// They are a derived layer, written apart from the zones as a GeoJSON FeatureCollection, with
+// one feature by zone with children: its boundary minus the union of the boundaries of its
+// children. As the children boundaries rarely follow exactly the boundary of their parent, the
+// tiny polygons left along the borders are dropped.
+
+use crate::additional_zones::convert_to_geo;
+use crate::options::WindingOrder;
+use crate::winding_order::orient_boundary;
+use anyhow::Error;
+use cosmogony::{Zone, ZoneIndex, ZoneType};
+use geo::prelude::Area;
+use geo_types::MultiPolygon;
+use geos::{Geom, Geometry};
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// The polygons of the uncovered area smaller than this ratio of the zone's area are the slivers
+/// between the zone's boundary and its children ones, they are dropped
+pub const MIN_UNCOVERED_AREA_RATIO: f64 = 1e-4;
+
+/// The part of a zone not covered by its children
+#[derive(Debug, Clone)]
+pub struct UncoveredArea {
+    pub id: ZoneIndex,
+    pub osm_id: String,
+    pub name: String,
+    pub zone_type: Option<ZoneType>,
+    pub admin_level: Option<u32>,
+    pub country_code: Option<String>,
+    /// the number of children of the zone with a boundary
+    pub nb_children: usize,
+    pub boundary: MultiPolygon<f64>,
+}
+
+fn uncovered_boundary(zone: &Zone, children: &[&Zone]) -> geos::GResult<Option<Geometry<'static>>> {
+    let Some(boundary) = &zone.boundary else {
+        return Ok(None);
+    };
+    let boundary = Geometry::try_from(boundary)?;
+    let children = children
+        .iter()
+        .filter_map(|c| c.boundary.as_ref())
+        .map(Geometry::try_from)
+        .collect::<geos::GResult<Vec<_>>>()?;
+    let covered = Geometry::create_geometry_collection(children)?.unary_union()?;
+    let uncovered = boundary.difference(&covered)?;
+    Ok((!uncovered.is_empty()?).then_some(uncovered))
+}
+
+/// The polygons of a boundary larger than a ratio of an area
+fn without_slivers(boundary: MultiPolygon<f64>, area: f64) -> MultiPolygon<f64> {
+    let min_area = area * MIN_UNCOVERED_AREA_RATIO;
+    MultiPolygon(
+        boundary
+            .into_iter()
+            .filter(|p| p.unsigned_area() >= min_area)
+            .collect(),
+    )
+}
+
+/// The parts of the zones not covered by their children, for the zones with children, oriented
+/// like the zones' boundaries
+pub fn uncovered_areas(zones: &[Zone], winding_order: WindingOrder) -> Vec<UncoveredArea> {
+    let mut children = BTreeMap::<ZoneIndex, Vec<&Zone>>::new();
+    for zone in zones.iter().filter(|z| z.boundary.is_some()) {
+        if let Some(parent) = zone.parent {
+            children.entry(parent).or_default().push(zone);
+        }
+    }
+    let areas: Vec<_> = zones
+        .par_iter()
+        .filter_map(|zone| {
+            let children = children.get(&zone.id)?;
+            let uncovered = match uncovered_boundary(zone, children) {
+                Ok(uncovered) => uncovered?,
+                Err(e) => {
+                    warn!(
+                        "impossible to compute the uncovered area of {}: {}",
+                        zone.osm_id, e
+                    );
+                    return None;
+                }
+            };
+            let uncovered = match convert_to_geo(uncovered) {
+                Ok(uncovered) => uncovered?,
+                Err(e) => {
+                    warn!(
+                        "impossible to convert the uncovered area of {}: {}",
+                        zone.osm_id, e
+                    );
+                    return None;
+                }
+            };
+            let zone_area = zone.boundary.as_ref()?.unsigned_area();
+            let boundary = without_slivers(uncovered, zone_area);
+            if boundary.0.is_empty() {
+                return None;
+            }
+            let boundary = orient_boundary(&boundary, winding_order);
+            Some(UncoveredArea {
+                id: zone.id,
+                osm_id: zone.osm_id.clone(),
+                name: zone.name.clone(),
+                zone_type: zone.zone_type,
+                admin_level: zone.admin_level,
+                country_code: zone.country_code.clone(),
+                nb_children: children.len(),
+                boundary,
+            })
+        })
+        .collect();
+    info!(
+        "{} zones with an area not covered by their children",
+        areas.len()
+    );
+    areas
+}
+
+/// Write the parts of the zones not covered by their children as a GeoJSON FeatureCollection
+pub fn write_uncovered_areas(
+    zones: &[Zone],
+    winding_order: WindingOrder,
+    mut writer: impl Write,
+) -> Result<(), Error> {
+    let features: Vec<_> = uncovered_areas(zones, winding_order)
+        .into_iter()
+        .map(|area| {
+            json!({
+                "type": "Feature",
+                "geometry": geojson::Geometry::new(geojson::Value::from(&area.boundary)),
+                "properties": {
+                    "id": area.id.index,
+                    "osm_id": area.osm_id,
+                    "name": area.name,
+                    "zone_type": area.zone_type,
+                    "admin_level": area.admin_level,
+                    "country_code": area.country_code,
+                    "nb_children": area.nb_children,
+                },
+            })
+        })
+        .collect();
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    serde_json::to_writer(&mut writer, &collection)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+    use geo::algorithm::winding_order::Winding;
+
+    fn square(min_x: f64, min_y: f64, size: f64) -> MultiPolygon<f64> {
+        test_utils::square((min_x, min_y), (min_x + size, min_y + size))
+    }
+
+    fn zone(index: usize, boundary: MultiPolygon<f64>, parent: Option<usize>) -> Zone {
+        Zone {
+            id: ZoneIndex { index },
+            osm_id: format!("relation:{}", index),
+            name: format!("zone {}", index),
+            boundary: Some(boundary),
+            parent: parent.map(|index| ZoneIndex { index }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn uncovered_areas_of_the_zones() {
+        let zones = vec![
+            zone(0, square(0., 0., 10.), None),
+            // the children cover the left half of their parent
+            zone(1, square(0., 0., 5.), Some(0)),
+            zone(2, square(0., 5., 5.), Some(0)),
+            // a child covering all its parent (but a sliver)
+            zone(3, square(20., 0., 10.), None),
+            zone(4, square(20., 0.0001, 10.), Some(3)),
+            zone(5, square(40., 0., 1.), None),
+        ];
+        let areas = uncovered_areas(&zones, WindingOrder::Rfc7946);
+        assert_eq!(areas.len(), 1);
+        assert_eq!(areas[0].osm_id, "relation:0");
+        assert_eq!(areas[0].nb_children, 2);
+        assert!((areas[0].boundary.unsigned_area() - 50.).abs() < 1e-9);
+        assert!(areas[0].boundary.0[0].exterior().is_ccw());
+        let areas = uncovered_areas(&zones, WindingOrder::Clockwise);
+        assert!(areas[0].boundary.0[0].exterior().is_cw());
+
+        let mut output = vec![];
+        write_uncovered_areas(&zones, WindingOrder::default(), &mut output).unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output["type"], "FeatureCollection");
+        let feature = &output["features"][0];
+        assert_eq!(feature["geometry"]["type"], "MultiPolygon");
+        assert_eq!(feature["properties"]["osm_id"], "relation:0");
+        assert_eq!(feature["properties"]["nb_children"], 2);
+    }
+}
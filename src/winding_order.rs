@@ -0,0 +1,77 @@
+// The rings of the boundaries have the orientation of the osm ways they are built with, or of
+// the geometry operations computing them (voronoi cells, clips, repairs), so some exteriors are
+// clockwise. The GeoJSON RFC 7946 requires counterclockwise exteriors and clockwise holes, and
+// some validators reject the other geometries.
+//
+// The rings are oriented once all the geometries are computed (the simplification and the
+// rounding keep the orientation). The geometries computed after the build (the fused zones of a
+// merge, the uncovered areas) are oriented the same way. Some legacy consumers expect the opposite order (like the
+// shapefiles), so the orientation can be reversed.
+
+use crate::options::WindingOrder;
+use cosmogony::Zone;
+use geo::algorithm::orient::{Direction, Orient};
+use geo::algorithm::winding_order::Winding;
+use geo_types::MultiPolygon;
+
+/// Orient the rings of a boundary
+pub fn orient_boundary(
+    boundary: &MultiPolygon<f64>,
+    winding_order: WindingOrder,
+) -> MultiPolygon<f64> {
+    let direction = match winding_order {
+        WindingOrder::Rfc7946 => Direction::Default,
+        WindingOrder::Clockwise => Direction::Reversed,
+    };
+    boundary.orient(direction)
+}
+
+/// Orient the rings of the zones' boundaries
+pub fn orient_boundaries(zones: &mut [Zone], winding_order: WindingOrder) {
+    for boundary in zones.iter_mut().filter_map(|z| z.boundary.as_mut()) {
+        *boundary = orient_boundary(boundary, winding_order);
+    }
+}
+
+/// The winding order of an oriented boundary, given by its first exterior ring (None for an
+/// empty boundary)
+pub fn winding_order_of(boundary: &MultiPolygon<f64>) -> Option<WindingOrder> {
+    let exterior = boundary.0.first()?.exterior();
+    if exterior.is_cw() {
+        Some(WindingOrder::Clockwise)
+    } else if exterior.is_ccw() {
+        Some(WindingOrder::Rfc7946)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo_types::{LineString, Polygon};
+
+    #[test]
+    fn boundaries_are_oriented() {
+        // a clockwise exterior with a counterclockwise hole
+        let exterior = LineString::from(vec![(0., 0.), (0., 4.), (4., 4.), (4., 0.), (0., 0.)]);
+        let hole = LineString::from(vec![(1., 1.), (2., 1.), (2., 2.), (1., 2.), (1., 1.)]);
+        let mut zones = vec![Zone {
+            boundary: Some(MultiPolygon(vec![Polygon::new(exterior, vec![hole])])),
+            ..Default::default()
+        }];
+
+        orient_boundaries(&mut zones, WindingOrder::Rfc7946);
+        let polygon = &zones[0].boundary.as_ref().unwrap().0[0];
+        assert!(polygon.exterior().is_ccw());
+        assert!(polygon.interiors()[0].is_cw());
+
+        orient_boundaries(&mut zones, WindingOrder::Clockwise);
+        let polygon = &zones[0].boundary.as_ref().unwrap().0[0];
+        assert!(polygon.exterior().is_cw());
+        assert!(polygon.interiors()[0].is_ccw());
+        let boundary = zones[0].boundary.as_ref().unwrap();
+        assert_eq!(winding_order_of(boundary), Some(WindingOrder::Clockwise));
+        assert_eq!(winding_order_of(&MultiPolygon(vec![])), None);
+    }
+}
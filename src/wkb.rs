@@ -0,0 +1,28 @@
+// The Well-Known Binary (little endian) encoding of the boundaries, used by the binary
+// outputs (GeoPackage, GeoParquet and SQLite).
+
+use geo_types::{LineString, MultiPolygon};
+
+fn write_ring(wkb: &mut Vec<u8>, ring: &LineString<f64>) {
+    wkb.extend((ring.0.len() as u32).to_le_bytes());
+    for c in &ring.0 {
+        wkb.extend(c.x.to_le_bytes());
+        wkb.extend(c.y.to_le_bytes());
+    }
+}
+
+/// Append the WKB encoding of a boundary
+pub(crate) fn write_multipolygon(wkb: &mut Vec<u8>, boundary: &MultiPolygon<f64>) {
+    wkb.push(1);
+    wkb.extend(6u32.to_le_bytes()); // MultiPolygon
+    wkb.extend((boundary.0.len() as u32).to_le_bytes());
+    for polygon in boundary {
+        wkb.push(1);
+        wkb.extend(3u32.to_le_bytes()); // Polygon
+        wkb.extend((1 + polygon.interiors().len() as u32).to_le_bytes());
+        write_ring(wkb, polygon.exterior());
+        for interior in polygon.interiors() {
+            write_ring(wkb, interior);
+        }
+    }
+}
@@ -2,14 +2,17 @@
 // The Zone's capabilities have been split in order to hide some functions specific to cosmogony
 // and that we do not want to expose in the model
 
-use cosmogony::{mutable_slice::MutableSlice, Coord, Zone, ZoneIndex, ZoneType};
+use crate::label_templates::LabelTemplate;
+use crate::lang_tags::{canonical_lang_tag, LangFilter};
+use crate::lifecycle::is_historic;
+use crate::options::SecondaryLabelFormat;
+use cosmogony::{AlternateNames, Coord, Zone, ZoneIndex, ZoneOrigin, ZoneTags, ZoneType};
 use geo::algorithm::bounding_rect::BoundingRect;
-use geo::prelude::{Contains, Intersects};
-use geos::Geom;
-use geos::Geometry;
+use geo::prelude::{ChamberlainDuquetteArea, Contains, GeodesicLength, Intersects};
+use geo_types::{LineString, MultiPolygon, Polygon};
 use itertools::Itertools;
 use osm_boundaries_utils::build_boundary;
-use osmpbfreader::objects::{Node, OsmId, OsmObj, Relation, Tags};
+use osmpbfreader::objects::{Node, OsmId, OsmObj, Relation, Tags, Way};
 use regex::Regex;
 use std::collections::{BTreeMap, BTreeSet};
 
@@ -24,8 +27,9 @@ pub trait ZoneExt {
         index: ZoneIndex,
     ) -> Option<Zone>;
 
-    /// check is a zone contains another zone
-    fn contains(&self, other: &Zone) -> bool;
+    /// create a zone from a closed osm way
+    fn from_osm_way(way: &Way, objects: &BTreeMap<OsmId, OsmObj>, index: ZoneIndex)
+        -> Option<Zone>;
 
     /// check if a zone contains another zone's center
     fn contains_center(&self, other: &Zone) -> bool;
@@ -33,11 +37,41 @@ pub trait ZoneExt {
     /// check if a zone intersects another zone
     fn intersects(&self, other: &Zone) -> bool;
 
-    /// compute the labels of a zone
-    fn compute_labels(&mut self, all_zones: &MutableSlice<'_>, filter_langs: &[String]);
+    /// build the label and the international labels of a zone
+    ///
+    /// The zones are only read (the ids of the zones must be their position in `all_zones`),
+    /// so the labels of all the zones can be built in parallel. The labels follow the template
+    /// if any, else they are the names of the zone and its parents joined by commas.
+    fn build_labels(
+        &self,
+        all_zones: &[Zone],
+        lang_filter: &LangFilter,
+        template: Option<&LabelTemplate>,
+    ) -> (String, BTreeMap<String, String>);
+
+    /// build a label with the names in `lang` next to the local name of the zone (eg.
+    /// "東京都, Tokyo, Japan"), with a format
+    ///
+    /// The label in `lang` is used alone when the zone has the same name in `lang`.
+    fn build_secondary_label(
+        &self,
+        all_zones: &[Zone],
+        lang: &str,
+        format: &SecondaryLabelFormat,
+        template: Option<&LabelTemplate>,
+    ) -> String;
 
     /// compute the names of a zone
-    fn compute_names(&mut self);
+    ///
+    /// The names whose language is not a valid language tag are discarded if `reject_invalid_langs`
+    fn compute_names(&mut self, reject_invalid_langs: bool);
+
+    /// extract the `official_name`, `short_name`, `alt_name` and `old_name` tags of a zone, by
+    /// language
+    fn compute_alternate_names(&mut self, reject_invalid_langs: bool);
+
+    /// compute the geodesic area and perimeter of the zone's boundary
+    fn compute_measures(&mut self);
 
     /// a zone can be a child of another zone z if:
     /// z is an admin (we don't want to have non administrative zones as parent)
@@ -53,7 +87,7 @@ impl ZoneExt for Zone {
             OsmId::Relation(r) => format!("relation:{}", r.0),
             OsmId::Way(r) => format!("way:{}", r.0),
         };
-        let tags = &node.tags;
+        let tags = ZoneTags::from(&node.tags);
         let name = match tags.get("name") {
             Some(val) => val,
             None => {
@@ -68,7 +102,7 @@ impl ZoneExt for Zone {
         let zip_code = tags
             .get("addr:postcode")
             .or_else(|| tags.get("postal_code"))
-            .map_or("", |val| &val[..]);
+            .unwrap_or("");
         let zip_codes = zip_code
             .split(';')
             .filter(|s| !s.is_empty())
@@ -85,12 +119,10 @@ impl ZoneExt for Zone {
             .map(|s| s.to_string())
             .unwrap_or_else(|| "".to_string());
 
-        let zone_type = tags
-            .get("place")
-            .map(|s| s.as_str())
-            .and_then(ZoneType::parse);
+        let zone_type = tags.get("place").and_then(ZoneType::parse);
 
-        let international_names = get_international_names(tags, name);
+        let international_names = get_international_names(&tags, name, false);
+        let population = get_population(&tags);
         Some(Self {
             id: index,
             osm_id: osm_id_str,
@@ -100,18 +132,42 @@ impl ZoneExt for Zone {
             loc_name,
             alt_name,
             boundary: None,
+            interned_boundary: None,
             bbox: None,
             parent: None,
-            tags: tags.clone(),
-            center_tags: Tags::new(),
+            parents: vec![],
+            included_in: vec![],
+            neighbors: vec![],
+            center_tags: ZoneTags::new(),
             wikidata,
             center: None,
+            admin_centre: None,
+            admin_centre_name: None,
             international_labels: BTreeMap::default(),
             international_names,
+            alternate_names: AlternateNames::default(),
+            secondary_label: None,
             label: "".to_string(),
             zip_codes,
             is_generated: true,
+            origin: None,
             country_code: None,
+            disputed: false,
+            is_enclave: false,
+            clipped: false,
+            level_inferred: false,
+            start_date: node.tags.get("start_date").map(|s| s.to_string()),
+            end_date: node.tags.get("end_date").map(|s| s.to_string()),
+            historic: is_historic(&node.tags),
+            claimed_by: vec![],
+            overlaps: vec![],
+            population,
+            area: None,
+            perimeter: None,
+            timezone: None,
+            h3_cells: vec![],
+            osm_metadata: None,
+            tags,
         })
     }
 
@@ -120,47 +176,6 @@ impl ZoneExt for Zone {
         objects: &BTreeMap<OsmId, OsmObj>,
         index: ZoneIndex,
     ) -> Option<Self> {
-        use geo::centroid::Centroid;
-
-        // Skip administrative region without name
-        let name = match relation.tags.get("name") {
-            Some(val) => val,
-            None => {
-                debug!(
-                    "relation/{}: administrative region without name, skipped",
-                    relation.id.0
-                );
-                return None;
-            }
-        };
-        let level = relation
-            .tags
-            .get("admin_level")
-            .and_then(|s| s.parse().ok());
-
-        let zip_code = relation
-            .tags
-            .get("addr:postcode")
-            .or_else(|| relation.tags.get("postal_code"))
-            .map_or("", |val| &val[..]);
-        let zip_codes = zip_code
-            .split(';')
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .sorted()
-            .collect();
-        let wikidata = relation.tags.get("wikidata").map(|s| s.to_string());
-        let loc_name = relation
-            .tags
-            .get("loc_name")
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "".to_string());
-        let alt_name = relation
-            .tags
-            .get("alt_name")
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "".to_string());
-
         let osm_id = format!("relation:{}", relation.id.0);
 
         let label_node = relation
@@ -170,18 +185,16 @@ impl ZoneExt for Zone {
             .and_then(|r| objects.get(&r.member))
             .and_then(|o| o.node());
 
-        let mut tags = relation.tags.clone();
+        let mut tags = ZoneTags::from(&relation.tags);
         if let Some(node) = label_node {
             node.tags
                 .iter()
                 .filter(|(k, _)| k.starts_with("name:") || *k == "population")
-                .for_each(|(k, v)| {
-                    tags.entry(k.clone()).or_insert(v.clone());
-                })
+                .for_each(|(k, v)| tags.insert_if_absent(k, v))
         }
+        let mut zone = admin_zone(&relation.tags, tags, osm_id, index)?;
 
         let boundary = build_boundary(relation, objects);
-        let bbox = boundary.as_ref().and_then(|b| b.bounding_rect());
 
         let refs = &relation.refs;
         let osm_center = refs
@@ -190,90 +203,58 @@ impl ZoneExt for Zone {
             .or_else(|| refs.iter().find(|r| &r.role == "label"))
             .and_then(|r| objects.get(&r.member))
             .and_then(|o| o.node());
-        let center_tags = osm_center.map_or(Tags::new(), |n| n.tags.clone());
-
-        let center = osm_center.map_or(
-            boundary.as_ref().and_then(|b| {
-                b.centroid().filter(|p| {
-                    /*
-                        On a broken polygon Geo may return Some(NaN,NaN) centroid.
-                        It should NOT be serialized as [null,null] in the JSON output.
-                    */
-                    if p.x().is_nan() || p.y().is_nan() {
-                        warn!("NaN in centroid {:?} for {}", p, osm_id);
-                        return false;
-                    }
-                    true
-                })
-            }),
-            |node| Some(Coord::new(node.lon(), node.lat())),
-        );
+        zone.center_tags = osm_center.map_or(ZoneTags::new(), |n| ZoneTags::from(&n.tags));
+        zone.admin_centre = osm_center.map(|n| Coord::new(n.lon(), n.lat()));
+        zone.admin_centre_name = osm_center.and_then(|n| n.tags.get("name").map(|n| n.to_string()));
 
-        Some(Zone {
-            id: index,
-            osm_id,
-            admin_level: level,
-            zone_type: None,
-            name: name.to_string(),
-            loc_name,
-            alt_name,
-            label: "".to_string(),
-            international_labels: BTreeMap::default(),
-            international_names: BTreeMap::default(),
-            zip_codes,
-            center,
-            boundary,
-            bbox,
-            parent: None,
-            tags,
-            center_tags,
-            wikidata,
-            is_generated: false,
-            country_code: None,
-        })
+        zone.center = zone
+            .admin_centre
+            .or_else(|| boundary_centroid(boundary.as_ref(), &zone.osm_id));
+        zone.bbox = boundary.as_ref().and_then(|b| b.bounding_rect());
+        zone.origin = boundary.as_ref().map(|_| ZoneOrigin::OsmRelation);
+        zone.boundary = boundary;
+        Some(zone)
     }
 
-    fn contains(&self, other: &Zone) -> bool {
-        match (&self.boundary, &other.boundary) {
-            (&Some(ref mpoly1), &Some(ref mpoly2)) => {
-                let m_self: Result<Geometry, _> = mpoly1.try_into();
-                let m_other: Result<Geometry, _> = mpoly2.try_into();
-
-                match (&m_self, &m_other) {
-                    (&Ok(ref m_self), &Ok(ref m_other)) => {
-                        // In GEOS, "covers" is less strict than "contains".
-                        // eg: a polygon does NOT "contain" its boundary, but "covers" it.
-                        m_self.covers(m_other)
-                        .map_err(|e| info!("impossible to compute geometries coverage for zone {:?}/{:?}: error {}",
-                        &self.osm_id, &other.osm_id, e))
-                        .unwrap_or(false)
-                    }
-                    (&Err(ref e), _) => {
-                        info!(
-                            "impossible to convert to geos for zone {:?}, error {}",
-                            &self.osm_id, e
-                        );
-                        debug!(
-                            "impossible to convert to geos the zone {:?}",
-                            serde_json::to_string(&self)
-                        );
-                        false
-                    }
-                    (_, &Err(ref e)) => {
-                        info!(
-                            "impossible to convert to geos for zone {:?}, error {}",
-                            &other.osm_id, e
-                        );
-                        debug!(
-                            "impossible to convert to geos the zone {:?}",
-                            serde_json::to_string(&other)
-                        );
-                        false
-                    }
-                }
+    fn from_osm_way(
+        way: &Way,
+        objects: &BTreeMap<OsmId, OsmObj>,
+        index: ZoneIndex,
+    ) -> Option<Self> {
+        let osm_id = format!("way:{}", way.id.0);
+        let mut zone = admin_zone(&way.tags, ZoneTags::from(&way.tags), osm_id, index)?;
+
+        let ring: Option<Vec<_>> = way
+            .nodes
+            .iter()
+            .map(|n| {
+                objects
+                    .get(&OsmId::Node(*n))
+                    .and_then(|o| o.node())
+                    .map(|n| (n.lon(), n.lat()))
+            })
+            .collect();
+        let boundary = match ring {
+            Some(ring) if way.is_closed() && ring.len() >= 4 => {
+                Some(MultiPolygon(vec![Polygon::new(
+                    LineString::from(ring),
+                    vec![],
+                )]))
             }
-            _ => false,
-        }
+            _ => {
+                debug!(
+                    "{}: not a closed way or missing nodes, no boundary",
+                    zone.osm_id
+                );
+                None
+            }
+        };
+
+        zone.center = boundary_centroid(boundary.as_ref(), &zone.osm_id);
+        zone.bbox = boundary.as_ref().and_then(|b| b.bounding_rect());
+        zone.origin = boundary.as_ref().map(|_| ZoneOrigin::OsmWay);
+        zone.boundary = boundary;
+        Some(zone)
     }
 
     fn contains_center(&self, other: &Zone) -> bool {
@@ -304,37 +285,54 @@ impl ZoneExt for Zone {
     /// Paris (75000-75116), Île-de-France, France
     ///
     /// We compute a default label, and a label per language
-    /// Note: for the moment we use the same format for every language,
-    /// but in the future we might use opencage's configuration for this
-    fn compute_labels(&mut self, all_zones: &MutableSlice<'_>, filter_langs: &[String]) {
-        let label = create_lbl(self, all_zones, |z: &Zone| z.name.clone());
+    /// Note: we use the same format for every language, the template of the zone's country
+    /// if any (see `label_templates`)
+    fn build_labels(
+        &self,
+        all_zones: &[Zone],
+        lang_filter: &LangFilter,
+        template: Option<&LabelTemplate>,
+    ) -> (String, BTreeMap<String, String>) {
+        let label = create_lbl(self, all_zones, template, |z: &Zone| z.name.clone());
 
         // we compute a label per language
-        let it = self
-            .iter_hierarchy(all_zones)
+        let all_lang: BTreeSet<String> = hierarchy(self, all_zones)
             .flat_map(|z| z.international_names.keys())
-            .map(|n| n.as_str().into());
-        let all_lang: BTreeSet<String> = if !filter_langs.is_empty() {
-            it.filter(|n| filter_langs.iter().any(|x| x == n)).collect()
-        } else {
-            it.collect()
-        };
+            .filter(|n| lang_filter.accepts(n))
+            .cloned()
+            .collect();
 
         let international_labels = all_lang
             .iter()
             .map(|lang| {
-                let lbl = create_lbl(self, all_zones, |z: &Zone| {
+                let lbl = create_lbl(self, all_zones, template, |z: &Zone| {
                     z.international_names.get(lang).unwrap_or(&z.name).clone()
                 });
                 (lang.to_string(), lbl)
             })
             .collect();
 
-        self.international_labels = international_labels;
-        self.label = label;
+        (label, international_labels)
+    }
+
+    fn build_secondary_label(
+        &self,
+        all_zones: &[Zone],
+        lang: &str,
+        format: &SecondaryLabelFormat,
+        template: Option<&LabelTemplate>,
+    ) -> String {
+        let label = create_lbl(self, all_zones, template, |z: &Zone| {
+            z.international_names.get(lang).unwrap_or(&z.name).clone()
+        });
+        if self.international_names.get(lang).unwrap_or(&self.name) == &self.name {
+            return label;
+        }
+        let local_label = create_lbl(self, all_zones, template, |z: &Zone| z.name.clone());
+        format.format(&self.name, &label, &local_label)
     }
 
-    fn compute_names(&mut self) {
+    fn compute_names(&mut self, reject_invalid_langs: bool) {
         let center_wikidata = self.center_tags.get("wikidata").map(|s| s.to_string());
 
         // Names from the center node can be used as additional tags, with some precautions:
@@ -347,11 +345,38 @@ impl ZoneExt for Zone {
             self.center_tags
                 .iter()
                 .filter(|(k, _)| k.starts_with("name:"))
-                .for_each(|(k, v)| {
-                    self.tags.entry(k.clone()).or_insert_with(|| v.clone());
-                })
+                .for_each(|(k, v)| self.tags.insert_if_absent(k, v))
         }
-        self.international_names = get_international_names(&self.tags, &self.name);
+        self.international_names =
+            get_international_names(&self.tags, &self.name, reject_invalid_langs);
+    }
+
+    fn compute_alternate_names(&mut self, reject_invalid_langs: bool) {
+        let names = |key| get_names_by_lang(&self.tags, key, reject_invalid_langs);
+        self.alternate_names = AlternateNames {
+            official: names("official_name"),
+            short: names("short_name"),
+            alt: names("alt_name"),
+            old: names("old_name"),
+        };
+    }
+
+    fn compute_measures(&mut self) {
+        let boundary = match self.boundary {
+            Some(ref b) => b,
+            None => {
+                self.area = None;
+                self.perimeter = None;
+                return;
+            }
+        };
+        self.area = Some(boundary.chamberlain_duquette_unsigned_area() / 1e6);
+        let perimeter: f64 = boundary
+            .iter()
+            .flat_map(|p| std::iter::once(p.exterior()).chain(p.interiors()))
+            .map(|ring| ring.geodesic_length())
+            .sum();
+        self.perimeter = Some(perimeter / 1e3);
     }
 
     /// a zone can be a child of another zone z if:
@@ -362,11 +387,129 @@ impl ZoneExt for Zone {
     }
 }
 
-fn create_lbl<'a, F>(zone: &'a Zone, all_zones: &'a MutableSlice<'_>, f: F) -> String
+/// The zone of an administrative osm object (with its `tags`, possibly completed with
+/// the tags of other objects), without geometry
+fn admin_zone(osm_tags: &Tags, tags: ZoneTags, osm_id: String, index: ZoneIndex) -> Option<Zone> {
+    // Skip administrative region without name
+    let name = match osm_tags.get("name") {
+        Some(val) => val,
+        None => {
+            debug!("{}: administrative region without name, skipped", osm_id);
+            return None;
+        }
+    };
+    let level = osm_tags.get("admin_level").and_then(|s| s.parse().ok());
+
+    let zip_code = osm_tags
+        .get("addr:postcode")
+        .or_else(|| osm_tags.get("postal_code"))
+        .map_or("", |val| &val[..]);
+    let zip_codes = zip_code
+        .split(';')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .sorted()
+        .collect();
+    let wikidata = osm_tags.get("wikidata").map(|s| s.to_string());
+    let loc_name = osm_tags
+        .get("loc_name")
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "".to_string());
+    let alt_name = osm_tags
+        .get("alt_name")
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "".to_string());
+
+    let disputed = osm_tags.contains("boundary", "disputed");
+    let claimed_by = get_claimants(osm_tags);
+    let population = get_population(&tags);
+
+    Some(Zone {
+        id: index,
+        osm_id,
+        admin_level: level,
+        zone_type: None,
+        name: name.to_string(),
+        loc_name,
+        alt_name,
+        label: "".to_string(),
+        international_labels: BTreeMap::default(),
+        international_names: BTreeMap::default(),
+        alternate_names: AlternateNames::default(),
+        secondary_label: None,
+        zip_codes,
+        center: None,
+        admin_centre: None,
+        admin_centre_name: None,
+        boundary: None,
+        interned_boundary: None,
+        bbox: None,
+        parent: None,
+        parents: vec![],
+        included_in: vec![],
+        neighbors: vec![],
+        tags,
+        center_tags: ZoneTags::new(),
+        wikidata,
+        is_generated: false,
+        origin: None,
+        country_code: None,
+        disputed,
+        is_enclave: false,
+        clipped: false,
+        level_inferred: false,
+        start_date: osm_tags.get("start_date").map(|s| s.to_string()),
+        end_date: osm_tags.get("end_date").map(|s| s.to_string()),
+        historic: is_historic(osm_tags),
+        claimed_by,
+        overlaps: vec![],
+        population,
+        area: None,
+        perimeter: None,
+        timezone: None,
+        h3_cells: vec![],
+        osm_metadata: None,
+    })
+}
+
+pub(crate) fn boundary_centroid(
+    boundary: Option<&MultiPolygon<f64>>,
+    osm_id: &str,
+) -> Option<Coord> {
+    use geo::centroid::Centroid;
+
+    boundary.and_then(|b| {
+        b.centroid().filter(|p| {
+            /*
+                On a broken polygon Geo may return Some(NaN,NaN) centroid.
+                It should NOT be serialized as [null,null] in the JSON output.
+            */
+            if p.x().is_nan() || p.y().is_nan() {
+                warn!("NaN in centroid {:?} for {}", p, osm_id);
+                return false;
+            }
+            true
+        })
+    })
+}
+
+/// the zone and its parents, up to the root of the hierarchy
+fn hierarchy<'a>(zone: &'a Zone, all_zones: &'a [Zone]) -> impl Iterator<Item = &'a Zone> {
+    std::iter::successors(Some(zone), move |z| z.parent.map(|p| &all_zones[p.index]))
+}
+
+fn create_lbl<F>(zone: &Zone, all_zones: &[Zone], template: Option<&LabelTemplate>, f: F) -> String
 where
     F: Fn(&Zone) -> String,
 {
-    let mut hierarchy: Vec<String> = zone.iter_hierarchy(all_zones).map(f).dedup().collect();
+    if let Some(template) = template {
+        let hierarchy: Vec<(Option<ZoneType>, String)> = hierarchy(zone, all_zones)
+            .map(|z| (z.zone_type, f(z)))
+            .dedup_by(|(_, n1), (_, n2)| n1 == n2)
+            .collect();
+        return template.format(&hierarchy, &zip_code_range(&zone.zip_codes));
+    }
+    let mut hierarchy: Vec<String> = hierarchy(zone, all_zones).map(f).dedup().collect();
 
     if let Some(ref mut zone_name) = hierarchy.first_mut() {
         zone_name.push_str(&format_zip_code(&zone.zip_codes));
@@ -385,35 +528,126 @@ where
 fn format_zip_code(zip_codes: &[String]) -> String {
     match zip_codes.len() {
         0 => "".to_string(),
-        1 => format!(" ({})", zip_codes.first().unwrap()),
+        _ => format!(" ({})", zip_code_range(zip_codes)),
+    }
+}
+
+/// the zone's zip code, or the range of its zip codes (eg. "75000-75116")
+fn zip_code_range(zip_codes: &[String]) -> String {
+    match zip_codes.len() {
+        0 => "".to_string(),
+        1 => zip_codes.first().unwrap().to_string(),
         _ => format!(
-            " ({}-{})",
+            "{}-{}",
             zip_codes.first().unwrap_or(&"".to_string()),
             zip_codes.last().unwrap_or(&"".to_string())
         ),
     }
 }
 
+/// get the population of a zone from the osm `population` tag
+///
+/// the population can have thousands separators (a space, a comma, a dot, an apostrophe or an
+/// underscore, the same between all the groups of 3 digits), the other values are rejected
+fn get_population(tags: &ZoneTags) -> Option<u64> {
+    let p = tags.get("population")?.trim();
+    let Some(separator) = p.chars().find(|c| !c.is_ascii_digit()) else {
+        return p.parse().ok();
+    };
+    if !matches!(separator, ' ' | ',' | '.' | '\'' | '_') {
+        return None;
+    }
+    let mut groups = p.split(separator);
+    let first = groups.next()?;
+    let is_digits = |g: &str| g.chars().all(|c| c.is_ascii_digit());
+    let valid = (1..=3).contains(&first.len())
+        && is_digits(first)
+        && groups.all(|g| g.len() == 3 && is_digits(g));
+    valid
+        .then(|| p.replace(separator, "").parse().ok())
+        .flatten()
+}
+
+/// get the countries claiming a disputed zone
+///
+/// the claimants are given in osm as a list of ISO3166-1:alpha2 codes
+/// separated by ';' in the `claimed_by` tag (or in the older `disputed_by` tag),
+/// they are deduplicated but keep the order of the tag (the first claimant of the
+/// `DisputedStrategy` being the first one of the tag)
+fn get_claimants(tags: &Tags) -> Vec<String> {
+    tags.get("claimed_by")
+        .or_else(|| tags.get("disputed_by"))
+        .map_or(vec![], |v| {
+            v.split(';')
+                .map(|c| c.trim().to_uppercase())
+                .filter(|c| !c.is_empty())
+                .unique()
+                .collect()
+        })
+}
+
+/// Add a name to the names by language, the language being canonicalized
+///
+/// If there are several names for the same language, the one with the canonical case wins.
+fn insert_lang_name(
+    names: &mut BTreeMap<String, String>,
+    lang: &str,
+    name: &str,
+    reject_invalid_langs: bool,
+) {
+    let lang = match canonical_lang_tag(lang) {
+        Some(canonical) if canonical != lang && names.contains_key(&canonical) => return,
+        Some(canonical) => canonical,
+        None if reject_invalid_langs => return,
+        None => lang.to_string(),
+    };
+    names.insert(lang, name.to_string());
+}
+
 /// get all the international names from the osm tags
 ///
 /// the names in osm are in a tag names `name:<lang>`,
 /// eg `name:fr`, `name:de`, ...
 ///
+/// the languages are canonicalized (eg `name:zh-hant` gives a `zh-Hant` name),
+/// and the ones that are not valid language tags are discarded if `reject_invalid_langs`.
+///
 /// we don't add the international names that are equivalent to the default name
 /// to reduce the size of the map
-fn get_international_names(tags: &Tags, default_name: &str) -> BTreeMap<String, String> {
+pub(crate) fn get_international_names(
+    tags: &ZoneTags,
+    default_name: &str,
+    reject_invalid_langs: bool,
+) -> BTreeMap<String, String> {
     lazy_static::lazy_static! {
         static ref LANG_NAME_REG: Regex = Regex::new("^name:(.+)").unwrap();
     }
 
-    tags.iter()
-        .filter(|&(_, v)| v != default_name)
-        .filter_map(|(k, v)| {
-            let lang = LANG_NAME_REG.captures(k)?.get(1)?;
+    let mut names = BTreeMap::new();
+    for (k, v) in tags.iter().filter(|&(_, v)| v != default_name) {
+        if let Some(lang) = LANG_NAME_REG.captures(k).and_then(|c| c.get(1)) {
+            insert_lang_name(&mut names, lang.as_str(), v, reject_invalid_langs);
+        }
+    }
+    names
+}
 
-            Some((lang.as_str().into(), v.clone().into()))
-        })
-        .collect()
+/// The values of a name tag (like `official_name`) by language, the tag without language being
+/// the `default` one
+fn get_names_by_lang(
+    tags: &ZoneTags,
+    key: &str,
+    reject_invalid_langs: bool,
+) -> BTreeMap<String, String> {
+    let mut names = BTreeMap::new();
+    for (k, v) in tags.iter() {
+        if k == key {
+            names.insert("default".to_string(), v.to_string());
+        } else if let Some(lang) = k.strip_prefix(key).and_then(|k| k.strip_prefix(':')) {
+            insert_lang_name(&mut names, lang, v, reject_invalid_langs);
+        }
+    }
+    names
 }
 
 #[cfg(test)]
@@ -436,45 +670,67 @@ mod test {
             label: "".into(),
             international_labels: BTreeMap::default(),
             international_names: BTreeMap::default(),
+            alternate_names: AlternateNames::default(),
+            secondary_label: None,
             center: None,
+            admin_centre: None,
+            admin_centre_name: None,
             boundary: None,
+            interned_boundary: None,
             bbox: None,
             parent: parent.map(|p| ZoneIndex { index: p }),
-            tags: Tags::new(),
-            center_tags: Tags::new(),
+            parents: vec![],
+            included_in: vec![],
+            neighbors: vec![],
+            tags: ZoneTags::new(),
+            center_tags: ZoneTags::new(),
             wikidata: None,
             zip_codes: zips.iter().map(|s| s.to_string()).collect(),
             is_generated: false,
+            origin: None,
             country_code: None,
+            disputed: false,
+            is_enclave: false,
+            clipped: false,
+            level_inferred: false,
+            start_date: None,
+            end_date: None,
+            historic: false,
+            claimed_by: vec![],
+            overlaps: vec![],
+            population: None,
+            area: None,
+            perimeter: None,
+            timezone: None,
+            h3_cells: vec![],
+            osm_metadata: None,
         }
     }
 
     #[test]
     fn simple_label_test() {
-        let mut zones = vec![make_zone("toto", 0)];
+        let zones = vec![make_zone("toto", 0)];
 
-        let (mslice, z) = MutableSlice::init(&mut zones, 0);
-        z.compute_labels(&mslice, &[]);
-        assert_eq!(z.label, "toto");
+        let (label, _) = zones[0].build_labels(&zones, &LangFilter::default(), None);
+        assert_eq!(label, "toto");
     }
 
     #[test]
     fn label_with_zip_and_parent() {
-        let mut zones = vec![
+        let zones = vec![
             make_zone_and_zip("bob", 0, vec!["75020", "75021", "75022"], Some(1)),
             make_zone_and_zip("bob sur mer", 1, vec!["75"], Some(2)), // it's zip code shouldn't be used
             make_zone("bobette's land", 2),
         ];
 
-        let (mslice, z) = MutableSlice::init(&mut zones, 0);
-        z.compute_labels(&mslice, &[]);
-        assert_eq!(z.label, "bob (75020-75022), bob sur mer, bobette's land");
+        let (label, _) = zones[0].build_labels(&zones, &LangFilter::default(), None);
+        assert_eq!(label, "bob (75020-75022), bob sur mer, bobette's land");
     }
 
     #[test]
     fn label_with_zip_and_double_parent() {
         // we should not have any double in the label
-        let mut zones = vec![
+        let zones = vec![
             make_zone_and_zip("bob", 0, vec!["75020"], Some(1)),
             make_zone_and_zip("bob", 1, vec![], Some(2)),
             make_zone_and_zip("bob", 2, vec![], Some(3)),
@@ -483,24 +739,46 @@ mod test {
             make_zone("bobette's land", 5),
         ];
 
-        let (mslice, z) = MutableSlice::init(&mut zones, 0);
-        z.compute_labels(&mslice, &[]);
-        assert_eq!(z.label, "bob (75020), bob sur mer, bobette's land");
+        let (label, _) = zones[0].build_labels(&zones, &LangFilter::default(), None);
+        assert_eq!(label, "bob (75020), bob sur mer, bobette's land");
     }
 
     #[test]
     fn label_with_zip_and_parent_named_as_zone() {
         // we should not have any consecutive double in the labl
         // but non consecutive double should not be cleaned
-        let mut zones = vec![
+        let zones = vec![
             make_zone_and_zip("bob", 0, vec!["75020"], Some(1)),
             make_zone_and_zip("bob sur mer", 1, vec!["75"], Some(2)),
             make_zone("bob", 2),
         ];
 
-        let (mslice, z) = MutableSlice::init(&mut zones, 0);
-        z.compute_labels(&mslice, &[]);
-        assert_eq!(z.label, "bob (75020), bob sur mer, bob");
+        let (label, _) = zones[0].build_labels(&zones, &LangFilter::default(), None);
+        assert_eq!(label, "bob (75020), bob sur mer, bob");
+    }
+
+    #[test]
+    fn label_with_template() {
+        let mut zones = vec![
+            make_zone_and_zip("Springfield", 0, vec!["62701", "62703"], Some(1)),
+            make_zone_and_zip("Sangamon County", 1, vec![], Some(2)),
+            make_zone_and_zip("Illinois", 2, vec![], Some(3)),
+            make_zone("United States", 3),
+        ];
+        zones[1].zone_type = Some(ZoneType::StateDistrict);
+        zones[2].zone_type = Some(ZoneType::State);
+        zones[2].international_names = [("fr".to_string(), "Illinois".to_string())].into();
+        zones[3].zone_type = Some(ZoneType::Country);
+        zones[3].international_names = [("fr".to_string(), "États-Unis".to_string())].into();
+        let template: LabelTemplate = "{city}, {state} {zip}, {country}".parse().unwrap();
+
+        let (label, international_labels) =
+            zones[0].build_labels(&zones, &LangFilter::default(), Some(&template));
+        assert_eq!(label, "Springfield, Illinois 62701-62703, United States");
+        assert_eq!(
+            international_labels.get("fr").unwrap(),
+            "Springfield, Illinois 62701-62703, États-Unis"
+        );
     }
 
     #[test]
@@ -513,10 +791,9 @@ mod test {
             ("name:a_strange_lang_name", "bibi"),
         ]
         .into_iter()
-        .map(|(k, v)| (k.into(), v.into()))
         .collect();
 
-        let names = get_international_names(&tags, "bob");
+        let names = get_international_names(&tags, "bob", false);
 
         assert_eq!(
             names,
@@ -526,4 +803,249 @@ mod test {
                 .collect()
         );
     }
+
+    #[test]
+    fn test_international_names_lang_tags() {
+        let tags = vec![
+            ("name:ZH-hant", "bob ZH"),
+            ("name:zh-Hant", "bob zh"),
+            ("name:sr-latn", "bob sr"),
+            ("name:fr-x-old", "bob old"),
+            ("name:left", "bob left"),
+        ]
+        .into_iter()
+        .collect();
+
+        let names = get_international_names(&tags, "bob", true);
+        assert_eq!(
+            names,
+            vec![("sr-Latn", "bob sr"), ("zh-Hant", "bob zh")]
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect()
+        );
+
+        let names = get_international_names(&tags, "bob", false);
+        assert_eq!(names.len(), 4);
+        assert_eq!(names.get("fr-x-old").map(String::as_str), Some("bob old"));
+    }
+
+    #[test]
+    fn test_alternate_names() {
+        let mut zone = make_zone("Paris", 0);
+        zone.tags = vec![
+            ("name", "Paris"),
+            ("official_name", "Ville de Paris"),
+            ("official_name:EN", "City of Paris"),
+            ("short_name", "PRS"),
+            ("alt_name", "Lutèce;Paname"),
+            ("old_name:la", "Lutetia"),
+            ("old_name:left", "nope"),
+            ("official_names", "not an official name"),
+        ]
+        .into_iter()
+        .collect();
+        zone.compute_alternate_names(true);
+
+        let names = |names: &[(&str, &str)]| -> BTreeMap<String, String> {
+            names
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            zone.alternate_names,
+            AlternateNames {
+                official: names(&[("default", "Ville de Paris"), ("en", "City of Paris")]),
+                short: names(&[("default", "PRS")]),
+                alt: names(&[("default", "Lutèce;Paname")]),
+                old: names(&[("la", "Lutetia")]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_secondary_label() {
+        let mut country = make_zone("日本", 0);
+        country.international_names = [("en".to_string(), "Japan".to_string())].into();
+        let mut city = make_zone("東京都", 1);
+        city.international_names = [("en".to_string(), "Tokyo".to_string())].into();
+        city.parent = Some(ZoneIndex { index: 0 });
+        let mut osaka = make_zone("Osaka", 2);
+        osaka.parent = Some(ZoneIndex { index: 0 });
+        let zones = vec![country, city, osaka];
+        let format = SecondaryLabelFormat::default();
+
+        assert_eq!(
+            zones[1].build_secondary_label(&zones, "en", &format, None),
+            "東京都, Tokyo, Japan"
+        );
+        // the same name in both languages is not repeated
+        assert_eq!(
+            zones[2].build_secondary_label(&zones, "en", &format, None),
+            "Osaka, Japan"
+        );
+        let format = "{label} ({local_label})".parse().unwrap();
+        assert_eq!(
+            zones[1].build_secondary_label(&zones, "en", &format, None),
+            "Tokyo, Japan (東京都, 日本)"
+        );
+    }
+
+    #[test]
+    fn test_population() {
+        let population = |p: &str| {
+            let tags = vec![("population", p)].into_iter().collect();
+            get_population(&tags)
+        };
+        assert_eq!(population("2187526"), Some(2187526));
+        assert_eq!(population("2 187 526"), Some(2187526));
+        assert_eq!(population("2,187,526"), Some(2187526));
+        assert_eq!(population("2.187.526"), Some(2187526));
+        assert_eq!(population("about 2 millions"), None);
+        assert_eq!(population("1234.5"), None);
+        assert_eq!(population("2,187.526"), None);
+        assert_eq!(population("2 18 526"), None);
+        assert_eq!(population("-1"), None);
+        assert_eq!(get_population(&ZoneTags::new()), None);
+    }
+
+    #[test]
+    fn test_claimants() {
+        let tags: Tags = vec![("claimed_by".into(), "in;CN; in;pk".into())]
+            .into_iter()
+            .collect();
+        assert_eq!(get_claimants(&tags), vec!["IN", "CN", "PK"]);
+        assert!(get_claimants(&Tags::new()).is_empty());
+    }
+
+    #[test]
+    fn test_measures() {
+        let ring =
+            geo_types::LineString::from(vec![(0., 0.), (0., 1.), (1., 1.), (1., 0.), (0., 0.)]);
+        let mut zone = Zone {
+            boundary: Some(geo_types::MultiPolygon(vec![geo_types::Polygon::new(
+                ring,
+                vec![],
+            )])),
+            ..Default::default()
+        };
+        zone.compute_measures();
+
+        // a 1° square at the equator is about 111km x 111km
+        assert!((zone.area.unwrap() - 12_360.).abs() < 50.);
+        assert!((zone.perimeter.unwrap() - 443.8).abs() < 1.);
+
+        zone.boundary = None;
+        zone.compute_measures();
+        assert_eq!(zone.area, None);
+        assert_eq!(zone.perimeter, None);
+    }
+
+    fn admin_closed_way(
+        id: i64,
+        objects: &mut BTreeMap<OsmId, OsmObj>,
+    ) -> osmpbfreader::objects::Way {
+        use osmpbfreader::objects::{Node, NodeId, WayId};
+
+        let coords = [
+            (0, 0),
+            (0, 10_000_000),
+            (10_000_000, 10_000_000),
+            (10_000_000, 0),
+        ];
+        for (i, (lon, lat)) in coords.iter().enumerate() {
+            let node = Node {
+                id: NodeId(id * 10 + i as i64),
+                tags: Tags::new(),
+                decimicro_lat: *lat,
+                decimicro_lon: *lon,
+            };
+            objects.insert(OsmId::Node(node.id), OsmObj::Node(node));
+        }
+        let mut nodes: Vec<_> = (0..4).map(|i| NodeId(id * 10 + i)).collect();
+        nodes.push(nodes[0]);
+        osmpbfreader::objects::Way {
+            id: WayId(id),
+            tags: [
+                ("boundary", "administrative"),
+                ("admin_level", "10"),
+                ("name", "Hameau"),
+            ]
+            .iter()
+            .map(|(k, v)| ((*k).into(), (*v).into()))
+            .collect(),
+            nodes,
+        }
+    }
+
+    #[test]
+    fn test_zone_from_closed_way() {
+        let mut objects = BTreeMap::new();
+        let way = admin_closed_way(1, &mut objects);
+        assert!(crate::is_admin(&OsmObj::Way(way.clone())));
+
+        let zone = Zone::from_osm_way(&way, &objects, ZoneIndex { index: 0 }).unwrap();
+        assert_eq!(zone.osm_id, "way:1");
+        assert_eq!(zone.admin_level, Some(10));
+        assert_eq!(
+            zone.boundary.as_ref().map(|b| b.0[0].exterior().0.len()),
+            Some(5)
+        );
+        assert_eq!(zone.center, Some(Coord::new(0.5, 0.5)));
+
+        let mut open_way = way;
+        open_way.nodes.pop();
+        assert!(!crate::is_admin(&OsmObj::Way(open_way)));
+    }
+
+    #[test]
+    fn test_closed_way_of_a_relation_is_skipped() {
+        use osmpbfreader::objects::{Ref, RelationId};
+
+        let mut objects = BTreeMap::new();
+        let way = admin_closed_way(1, &mut objects);
+        let relation = Relation {
+            id: RelationId(2),
+            tags: way.tags.clone(),
+            refs: vec![Ref {
+                member: OsmId::Way(way.id),
+                role: "outer".into(),
+            }],
+        };
+        objects.insert(OsmId::Way(way.id), OsmObj::Way(way));
+        let other_way = admin_closed_way(3, &mut objects);
+        objects.insert(OsmId::Way(other_way.id), OsmObj::Way(other_way));
+        objects.insert(OsmId::Relation(relation.id), OsmObj::Relation(relation));
+
+        let (zones, _) = crate::get_zones_and_stats(&objects, &Default::default()).unwrap();
+        let osm_ids: Vec<_> = zones.iter().map(|z| z.osm_id.as_str()).collect();
+        assert_eq!(osm_ids, vec!["way:3", "relation:2"]);
+    }
+
+    #[test]
+    fn test_zone_tags() {
+        let mut tags: ZoneTags = vec![("name", "Bob"), ("boundary", "administrative")]
+            .into_iter()
+            .collect();
+        tags.insert_if_absent("name", "Bobby");
+        tags.insert("admin_level", "8");
+        assert_eq!(
+            tags.iter().collect::<Vec<_>>(),
+            vec![
+                ("admin_level", "8"),
+                ("boundary", "administrative"),
+                ("name", "Bob")
+            ]
+        );
+        assert!(tags.contains("boundary", "administrative"));
+
+        // the tags are serialized as a map, like the osm tags
+        let json = serde_json::to_string(&tags).unwrap();
+        assert_eq!(
+            json,
+            r#"{"admin_level":"8","boundary":"administrative","name":"Bob"}"#
+        );
+        assert_eq!(serde_json::from_str::<ZoneTags>(&json).unwrap(), tags);
+    }
 }
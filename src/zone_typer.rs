@@ -4,6 +4,7 @@ use log::warn;
 use serde_derive::*;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::path::{Path, PathBuf};
 
 use include_dir::{include_dir, Dir};
 
@@ -16,6 +17,78 @@ const LIBPOSTAL_RULES_DIR: Dir = include_dir!("./libpostal/resources/boundaries/
 #[derive(Debug)]
 pub struct ZoneTyper {
     countries_rules: BTreeMap<String, CountryAdminTypeRules>,
+    level_overrides: BTreeMap<String, CountryLevelOverrides>,
+    /// the directory the rules have been read from (None for the bundled libpostal rules)
+    rules_dir: Option<PathBuf>,
+    /// the file the admin_level overrides have been read from
+    level_overrides_file: Option<PathBuf>,
+}
+
+/// A problem in a typing rules file (a libpostal rules file or an admin_level overrides file)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulesError {
+    pub file: String,
+    /// the line of the problem, starting at 1, if known
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+impl RulesError {
+    fn new(file: &str, message: impl fmt::Display) -> Self {
+        RulesError {
+            file: file.to_string(),
+            line: None,
+            message: message.to_string(),
+        }
+    }
+
+    fn from_yaml(file: &str, error: &serde_yaml::Error) -> Self {
+        RulesError {
+            line: error.location().map(|l| l.line()),
+            ..RulesError::new(file, error)
+        }
+    }
+}
+
+/// An error listing all the problems of the rules
+fn rules_errors(errors: &[RulesError]) -> Error {
+    let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+    anyhow!("invalid typing rules:\n{}", errors.join("\n"))
+}
+
+/// The admin_levels that are not numbers (no zone would ever match them), with their line
+fn check_levels<'a>(
+    file: &str,
+    contents: &str,
+    levels: impl Iterator<Item = &'a String>,
+) -> Vec<RulesError> {
+    levels
+        .filter(|level| level.parse::<u32>().is_err())
+        .map(|level| RulesError {
+            // the first line defining this level
+            line: contents
+                .lines()
+                .position(|l| {
+                    let l = l.trim_start();
+                    l.starts_with(&format!("\"{}\":", level))
+                        || l.starts_with(&format!("{}:", level))
+                })
+                .map(|l| l + 1),
+            ..RulesError::new(
+                file,
+                format!("invalid admin_level '{}', a number is expected", level),
+            )
+        })
+        .collect()
 }
 
 #[derive(Deserialize, Ord, PartialOrd, Eq, PartialEq, Debug)]
@@ -57,15 +130,132 @@ struct CountryAdminTypeRules {
     // we don't implement libpostal's 'use_admin_center' as we don't need it
 }
 
+/// User defined admin_level mapping of a country, taking precedence over libpostal's rules
+///
+/// A level can be mapped to `null` to explicitly not type the zones of this level.
+#[derive(Deserialize, Debug, Default)]
+struct CountryLevelOverrides {
+    #[serde(rename = "admin_level", default)]
+    type_by_level: BTreeMap<String, Option<ZoneType>>,
+}
+
 pub enum ZoneTyperError {
     InvalidCountry(String),
     UnkownLevel(Option<u32>, String),
 }
 
+/// A rule used to type a zone, see [`ZoneTyper::explain`]
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum TypingRule {
+    /// the admin_level is mapped by the user's overrides (to no type if `zone_type` is None)
+    LevelOverride {
+        admin_level: Option<u32>,
+        zone_type: Option<ZoneType>,
+    },
+    /// there are no libpostal rules for the country
+    NoCountryRules,
+    /// libpostal has a rule for this very zone (to not type it if `zone_type` is None)
+    IdRule { zone_type: Option<ZoneType> },
+    /// the zone is inside a zone with its own libpostal rules, used by the following rules
+    ContainedBy { osm_id: String },
+    /// the admin_level is mapped by libpostal's rules
+    AdminLevel {
+        admin_level: Option<u32>,
+        zone_type: ZoneType,
+    },
+    /// the zone is a neighbourhood, whose level is not in libpostal's rules
+    SubCity {
+        admin_level: Option<u32>,
+        zone_type: ZoneType,
+    },
+    /// the admin_level is not in the rules
+    UnknownLevel { admin_level: Option<u32> },
+    /// the sub-city zone is more precisely typed by its `place` tag (eg. a `place=borough`)
+    PlaceTag { place: String, zone_type: ZoneType },
+}
+
+impl fmt::Display for TypingRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = |l: &Option<u32>| l.map_or("none".to_string(), |l| l.to_string());
+        let zone_type = |t: &Option<ZoneType>| t.map_or("no type", |t| t.as_str());
+        match self {
+            TypingRule::LevelOverride {
+                admin_level,
+                zone_type: t,
+            } => write!(
+                f,
+                "admin_level {} overridden to {}",
+                level(admin_level),
+                zone_type(t)
+            ),
+            TypingRule::NoCountryRules => write!(f, "no libpostal rules for the country"),
+            TypingRule::IdRule { zone_type: t } => {
+                write!(f, "libpostal rule for the zone id: {}", zone_type(t))
+            }
+            TypingRule::ContainedBy { osm_id } => {
+                write!(f, "contained by {}, using its libpostal rules", osm_id)
+            }
+            TypingRule::AdminLevel {
+                admin_level,
+                zone_type: t,
+            } => write!(
+                f,
+                "libpostal rule for admin_level {}: {}",
+                level(admin_level),
+                t.as_str()
+            ),
+            TypingRule::SubCity {
+                admin_level,
+                zone_type: t,
+            } => write!(
+                f,
+                "admin_level {} not in libpostal rules, neighbourhood: {}",
+                level(admin_level),
+                t.as_str()
+            ),
+            TypingRule::UnknownLevel { admin_level } => {
+                write!(f, "no rule for admin_level {}", level(admin_level))
+            }
+            TypingRule::PlaceTag {
+                place,
+                zone_type: t,
+            } => write!(f, "place={}: {}", place, t.as_str()),
+        }
+    }
+}
+
+/// Why a zone got its type: the rules applied by the zone typer, in their order
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TypingExplanation {
+    pub osm_id: String,
+    pub country_code: String,
+    pub rules: Vec<TypingRule>,
+    pub zone_type: Option<ZoneType>,
+}
+
+impl fmt::Display for TypingExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({}): {}",
+            self.osm_id,
+            self.country_code,
+            self.zone_type.map_or("not typed", |t| t.as_str())
+        )?;
+        for rule in &self.rules {
+            writeln!(f, "  - {}", rule)?;
+        }
+        Ok(())
+    }
+}
+
 impl ZoneTyper {
     pub fn new() -> Result<ZoneTyper, Error> {
         let z = ZoneTyper {
             countries_rules: read_libpostal_yaml_folder()?,
+            level_overrides: BTreeMap::new(),
+            rules_dir: None,
+            level_overrides_file: None,
         };
         if z.countries_rules.is_empty() {
             Err(anyhow!(
@@ -77,6 +267,89 @@ impl ZoneTyper {
         }
     }
 
+    /// Override libpostal's admin_level mapping for some countries, with a yaml file like:
+    ///
+    /// ```yaml
+    /// FR:
+    ///   admin_level:
+    ///     "7": null # the level 7 zones are not typed
+    ///     "9": "suburb"
+    /// ```
+    ///
+    /// The overridden levels of a country are not affected by libpostal's specific
+    /// rules (by id or by parent zone), the other levels are still typed with libpostal's rules.
+    pub fn with_level_overrides_file(mut self, path: &Path) -> Result<ZoneTyper, Error> {
+        self.level_overrides = read_level_overrides_file(path).map_err(|e| rules_errors(&e))?;
+        self.level_overrides_file = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// A typer with the rules of a directory of libpostal-like rules files (`<country>.yaml`),
+    /// instead of the bundled libpostal rules, to try some rules without building cosmogony
+    /// again
+    ///
+    /// Unlike the bundled rules, for which the invalid files are skipped, all the files must be
+    /// valid.
+    pub fn from_rules_dir(dir: &Path) -> Result<ZoneTyper, Error> {
+        Ok(ZoneTyper {
+            countries_rules: read_rules_dir(dir)?,
+            level_overrides: BTreeMap::new(),
+            rules_dir: Some(dir.to_path_buf()),
+            level_overrides_file: None,
+        })
+    }
+
+    /// Read again the rules from their directory and the admin_level overrides from their file,
+    /// eg. after editing them (the bundled libpostal rules cannot change)
+    ///
+    /// The rules are unchanged if the new ones are invalid.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let countries_rules = self.rules_dir.as_deref().map(read_rules_dir).transpose()?;
+        let level_overrides = self
+            .level_overrides_file
+            .as_deref()
+            .map(read_level_overrides_file)
+            .transpose()
+            .map_err(|e| rules_errors(&e))?;
+        if let Some(countries_rules) = countries_rules {
+            self.countries_rules = countries_rules;
+        }
+        if let Some(level_overrides) = level_overrides {
+            self.level_overrides = level_overrides;
+        }
+        Ok(())
+    }
+
+    /// Check the typing rules (the bundled libpostal rules, or the rules of a directory) and
+    /// the admin_level overrides file, and give all their problems
+    ///
+    /// ```no_run
+    /// # use cosmogony_builder::zone_typer::ZoneTyper;
+    /// for error in ZoneTyper::validate_rules(None, Some("overrides.yaml".as_ref())) {
+    ///     eprintln!("{}", error);
+    /// }
+    /// ```
+    pub fn validate_rules(
+        rules_dir: Option<&Path>,
+        level_overrides: Option<&Path>,
+    ) -> Vec<RulesError> {
+        let (files, mut errors) = match rules_dir {
+            Some(dir) => dir_rules_files(dir),
+            None => bundled_rules_files(),
+        };
+        let (rules, parse_errors) = parse_rules(&files);
+        errors.extend(parse_errors);
+        if rules.is_empty() && errors.is_empty() {
+            let source =
+                rules_dir.map_or("the libpostal rules".into(), |d| d.display().to_string());
+            errors.push(RulesError::new(&source, "no rules files"));
+        }
+        if let Some(Err(overrides_errors)) = level_overrides.map(read_level_overrides_file) {
+            errors.extend(overrides_errors);
+        }
+        errors
+    }
+
     pub fn get_zone_type(
         &self,
         zone: &Zone,
@@ -84,17 +357,63 @@ impl ZoneTyper {
         zone_inclusions: &[ZoneIndex],
         all_zones: &[Zone],
     ) -> Result<ZoneType, ZoneTyperError> {
-        let country_rules = self
-            .countries_rules
-            .get(country_code)
-            .ok_or_else(|| ZoneTyperError::InvalidCountry(country_code.to_string()))?;
+        self.type_zone(zone, country_code, zone_inclusions, all_zones, &mut vec![])
+    }
+
+    /// Type a zone like [`ZoneTyper::get_zone_type`], returning the rules that led to its type
+    /// (eg. to understand why a zone is typed as a state district)
+    pub fn explain(
+        &self,
+        zone: &Zone,
+        country_code: &str,
+        zone_inclusions: &[ZoneIndex],
+        all_zones: &[Zone],
+    ) -> TypingExplanation {
+        let mut rules = vec![];
+        let zone_type = self
+            .type_zone(zone, country_code, zone_inclusions, all_zones, &mut rules)
+            .ok();
+        TypingExplanation {
+            osm_id: zone.osm_id.clone(),
+            country_code: country_code.to_string(),
+            rules,
+            zone_type,
+        }
+    }
+
+    fn type_zone(
+        &self,
+        zone: &Zone,
+        country_code: &str,
+        zone_inclusions: &[ZoneIndex],
+        all_zones: &[Zone],
+        rules: &mut Vec<TypingRule>,
+    ) -> Result<ZoneType, ZoneTyperError> {
+        let overridden_type = self.level_overrides.get(country_code).and_then(|o| {
+            o.type_by_level
+                .get(&zone.admin_level.unwrap_or(0).to_string())
+        });
+        if let Some(zone_type) = overridden_type {
+            rules.push(TypingRule::LevelOverride {
+                admin_level: zone.admin_level,
+                zone_type: *zone_type,
+            });
+            return zone_type.ok_or_else(|| {
+                ZoneTyperError::UnkownLevel(zone.admin_level, country_code.to_string())
+            });
+        }
+        let Some(country_rules) = self.countries_rules.get(country_code) else {
+            rules.push(TypingRule::NoCountryRules);
+            return Err(ZoneTyperError::InvalidCountry(country_code.to_string()));
+        };
         country_rules
-            .get_zone_type(zone, zone_inclusions, all_zones)
+            .get_zone_type(zone, zone_inclusions, all_zones, rules)
             .ok_or_else(|| ZoneTyperError::UnkownLevel(zone.admin_level, country_code.to_string()))
     }
 
     pub fn contains_rule(&self, country_code: &str) -> bool {
         self.countries_rules.contains_key(country_code)
+            || self.level_overrides.contains_key(country_code)
     }
 }
 
@@ -103,22 +422,101 @@ impl CountryAdminTypeRules {
     ///
     /// First we look if there is a specific rule for the zone,
     /// else we take the default osm's admin_level rule
+    ///
+    /// The applied rules are added to `rules`.
     fn get_zone_type(
         &self,
         zone: &Zone,
         zone_inclusions: &[ZoneIndex],
         all_zones: &[Zone],
+        rules: &mut Vec<TypingRule>,
     ) -> Option<ZoneType> {
         let overrides = self
             .overrides
-            .get_overrided_type(zone, zone_inclusions, all_zones);
-        match overrides {
-            Some(o) => o,
-            None => self
-                .type_by_level
-                .get(&zone.admin_level.unwrap_or(0).to_string())
-                .cloned(),
+            .get_overrided_type(zone, zone_inclusions, all_zones, rules);
+        if let Some(o) = overrides {
+            return o;
+        }
+        let admin_level = zone.admin_level;
+        if let Some(zone_type) = self
+            .type_by_level
+            .get(&admin_level.unwrap_or(0).to_string())
+        {
+            rules.push(TypingRule::AdminLevel {
+                admin_level,
+                zone_type: *zone_type,
+            });
+            return Some(place_zone_type(zone, *zone_type, rules));
+        }
+        let zone_type = sub_city_zone_type(zone);
+        rules.push(match zone_type {
+            Some(zone_type) => TypingRule::SubCity {
+                admin_level,
+                zone_type,
+            },
+            None => TypingRule::UnknownLevel { admin_level },
+        });
+        zone_type.map(|t| place_zone_type(zone, t, rules))
+    }
+}
+
+/// The type of a sub-city zone given by its `place` tag
+///
+/// In the large cities (Berlin, London, New York...), the boroughs and city districts between
+/// the city and its neighbourhoods are often at levels typed as suburbs or city districts, the
+/// `place=borough` and `place=city_district` tags tell them apart.
+fn place_zone_type(zone: &Zone, zone_type: ZoneType, rules: &mut Vec<TypingRule>) -> ZoneType {
+    if !matches!(zone_type, ZoneType::Suburb | ZoneType::CityDistrict) {
+        return zone_type;
+    }
+    let place_type = zone
+        .tags
+        .get("place")
+        .filter(|p| *p == "borough" || *p == "city_district")
+        .and_then(|p| Some((p, ZoneType::parse(p)?)));
+    match place_type {
+        Some((place, place_type)) if place_type != zone_type => {
+            rules.push(TypingRule::PlaceTag {
+                place: place.to_string(),
+                zone_type: place_type,
+            });
+            place_type
         }
+        _ => zone_type,
+    }
+}
+
+/// The type of a zone from its admin_level only, with the most common meaning of the osm levels
+///
+/// It is used when the zones have no country (`--no-country`), so no libpostal's rules. The
+/// sub-city zones are typed like the ones outside of libpostal's rules.
+pub fn generic_zone_type(zone: &Zone) -> Option<ZoneType> {
+    let zone_type = match zone.admin_level {
+        Some(2) => ZoneType::Country,
+        Some(3) => ZoneType::CountryRegion,
+        Some(4) => ZoneType::State,
+        Some(5 | 6) => ZoneType::StateDistrict,
+        Some(7 | 8) => ZoneType::City,
+        _ => sub_city_zone_type(zone)?,
+    };
+    Some(place_zone_type(zone, zone_type, &mut vec![]))
+}
+
+/// The type of the small zones not handled by the country's rules
+///
+/// libpostal's rules rarely cover all the levels from 9 to 11, but those zones
+/// are neighbourhoods in all the countries, like the `place=neighbourhood` or
+/// `place=quarter` relations (that have no admin_level). The `place=borough` and
+/// `place=city_district` relations are typed as boroughs and city districts.
+fn sub_city_zone_type(zone: &Zone) -> Option<ZoneType> {
+    match zone.admin_level {
+        Some(9..=11) => Some(ZoneType::Suburb),
+        Some(_) => None,
+        None => zone
+            .tags
+            .get("place")
+            .filter(|p| ["neighbourhood", "quarter", "city_district", "borough"].contains(p))
+            .and_then(ZoneType::parse),
     }
 }
 
@@ -133,12 +531,18 @@ impl RulesOverrides {
         zone: &Zone,
         zone_inclusions: &[ZoneIndex],
         all_zones: &[Zone],
+        rules: &mut Vec<TypingRule>,
     ) -> Option<Option<ZoneType>> {
         // check id overrides
         let id_overrides = self.id_rules.get(&zone.osm_id);
         // if there is no override for this specific object, we check the contained_by overrides
         match id_overrides {
-            Some(overrides) => Some(*overrides),
+            Some(overrides) => {
+                rules.push(TypingRule::IdRule {
+                    zone_type: *overrides,
+                });
+                Some(*overrides)
+            }
             None => {
                 if self.contained_by.is_empty() {
                     return None;
@@ -148,10 +552,15 @@ impl RulesOverrides {
                     .map(|idx| &all_zones[idx.index].osm_id);
 
                 parents_osm_id
-                    .find_map(|parent_osm_id| self.contained_by.get(parent_osm_id))
-                    .and_then(|country_rules| {
+                    .find_map(|parent_osm_id| {
+                        Some((parent_osm_id, self.contained_by.get(parent_osm_id)?))
+                    })
+                    .and_then(|(parent_osm_id, country_rules)| {
+                        rules.push(TypingRule::ContainedBy {
+                            osm_id: parent_osm_id.clone(),
+                        });
                         country_rules
-                            .get_zone_type(zone, zone_inclusions, all_zones)
+                            .get_zone_type(zone, zone_inclusions, all_zones, rules)
                             .map(Some)
                     })
             }
@@ -159,37 +568,158 @@ impl RulesOverrides {
     }
 }
 
+/// A rules file, named by its path
+struct RulesFile {
+    name: String,
+    country_code: Option<String>,
+    contents: String,
+}
+
+impl RulesFile {
+    fn new(path: &Path, contents: String) -> Self {
+        RulesFile {
+            name: path.display().to_string(),
+            country_code: path
+                .file_stem()
+                .and_then(|f| f.to_str())
+                .map(|f| f.to_uppercase()),
+            contents,
+        }
+    }
+}
+
+fn bundled_rules_files() -> (Vec<RulesFile>, Vec<RulesError>) {
+    let mut files = vec![];
+    let mut errors = vec![];
+    for d in LIBPOSTAL_RULES_DIR.files() {
+        match d.contents_utf8() {
+            Some(contents) => files.push(RulesFile::new(d.path(), contents.to_string())),
+            None => errors.push(RulesError::new(
+                &d.path().display().to_string(),
+                "not utf-8",
+            )),
+        }
+    }
+    (files, errors)
+}
+
+/// The `.yaml` files of a directory
+fn dir_rules_files(dir: &Path) -> (Vec<RulesFile>, Vec<RulesError>) {
+    let dir_name = dir.display().to_string();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return (vec![], vec![RulesError::new(&dir_name, e)]),
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "yaml"))
+        .collect();
+    paths.sort();
+    let mut files = vec![];
+    let mut errors = vec![];
+    for path in paths {
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => files.push(RulesFile::new(&path, contents)),
+            Err(e) => errors.push(RulesError::new(&path.display().to_string(), e)),
+        }
+    }
+    (files, errors)
+}
+
+/// All the levels of some rules, and of their `contained_by` rules
+fn all_levels(rules: &CountryAdminTypeRules) -> Vec<&String> {
+    rules
+        .type_by_level
+        .keys()
+        .chain(rules.overrides.contained_by.values().flat_map(all_levels))
+        .collect()
+}
+
+/// Parse the rules files, the invalid files are not in the rules
+fn parse_rules(files: &[RulesFile]) -> (BTreeMap<String, CountryAdminTypeRules>, Vec<RulesError>) {
+    let mut rules = BTreeMap::new();
+    let mut errors = vec![];
+    for file in files {
+        let Some(ref country_code) = file.country_code else {
+            errors.push(RulesError::new(
+                &file.name,
+                "impossible to deduce the country code",
+            ));
+            continue;
+        };
+        match read_libpostal_yaml(&file.contents) {
+            Ok(country_rules) => {
+                let level_errors = check_levels(
+                    &file.name,
+                    &file.contents,
+                    all_levels(&country_rules).into_iter(),
+                );
+                if level_errors.is_empty() {
+                    rules.insert(country_code.clone(), country_rules);
+                }
+                errors.extend(level_errors);
+            }
+            Err(e) => errors.push(RulesError::from_yaml(&file.name, &e)),
+        }
+    }
+    (rules, errors)
+}
+
 fn read_libpostal_yaml_folder() -> Result<BTreeMap<String, CountryAdminTypeRules>, Error> {
-    Ok(LIBPOSTAL_RULES_DIR.files().filter_map(|d| {
-        let contents = d.contents_utf8()?;
-        let deserialized_level = read_libpostal_yaml(contents)
-            .map_err(|e| {
-                warn!(
-                    "Levels corresponding to file: {:?} have been skipped due to {}",
-                    d.path(),
-                    e
-                )
-            })
-            .ok()?;
-        let country_code = d
-            .path()
-            .file_stem()
-            .and_then(|f| f.to_str())
-            .map(|f| f.to_string())
-            .ok_or_else(|| {
-                warn!(
-                    "Levels corresponding to file: {:?} have been skipped, impossible to deduce country code",
-                    d.path()
-                )
-            })
-            .ok()?;
+    let (files, mut errors) = bundled_rules_files();
+    let (rules, parse_errors) = parse_rules(&files);
+    errors.extend(parse_errors);
+    for e in errors {
+        warn!("Levels of a file have been skipped due to {}", e);
+    }
+    Ok(rules)
+}
 
-        Some((country_code.to_uppercase(), deserialized_level))
-    }).collect())
+/// Read the rules of a directory, all its files must be valid
+fn read_rules_dir(dir: &Path) -> Result<BTreeMap<String, CountryAdminTypeRules>, Error> {
+    let (files, mut errors) = dir_rules_files(dir);
+    let (rules, parse_errors) = parse_rules(&files);
+    errors.extend(parse_errors);
+    if !errors.is_empty() {
+        return Err(rules_errors(&errors));
+    }
+    if rules.is_empty() {
+        return Err(anyhow!("no rules files in {}", dir.display()));
+    }
+    Ok(rules)
+}
+
+fn read_libpostal_yaml(contents: &str) -> Result<CountryAdminTypeRules, serde_yaml::Error> {
+    serde_yaml::from_str(contents)
+}
+
+fn read_level_overrides(
+    file: &str,
+    contents: &str,
+) -> Result<BTreeMap<String, CountryLevelOverrides>, Vec<RulesError>> {
+    let overrides: BTreeMap<String, CountryLevelOverrides> =
+        serde_yaml::from_str(contents).map_err(|e| vec![RulesError::from_yaml(file, &e)])?;
+    let errors = check_levels(
+        file,
+        contents,
+        overrides.values().flat_map(|o| o.type_by_level.keys()),
+    );
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(overrides
+        .into_iter()
+        .map(|(country_code, o)| (country_code.to_uppercase(), o))
+        .collect())
 }
 
-fn read_libpostal_yaml(contents: &str) -> Result<CountryAdminTypeRules, Error> {
-    Ok(serde_yaml::from_str(contents)?)
+fn read_level_overrides_file(
+    path: &Path,
+) -> Result<BTreeMap<String, CountryLevelOverrides>, Vec<RulesError>> {
+    let file = path.display().to_string();
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| vec![RulesError::new(&file, format!("impossible to read: {}", e))])?;
+    read_level_overrides(&file, &contents)
 }
 
 // stuff used for serde
@@ -239,9 +769,10 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::CountryAdminTypeRules;
-    use crate::zone_typer::read_libpostal_yaml;
+    use super::{CountryAdminTypeRules, TypingRule, ZoneTyper, ZoneTyperError};
+    use crate::zone_typer::{generic_zone_type, read_level_overrides, read_libpostal_yaml};
     use cosmogony::{Zone, ZoneIndex, ZoneType};
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_read_libpostal_yaml_basic() {
@@ -391,6 +922,63 @@ mod test {
         assert_eq!(rules.len(), 242);
     }
 
+    #[test]
+    fn rules_validation_and_reload() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let write = |file: &str, contents: &str| std::fs::write(dir.join(file), contents).unwrap();
+        write("fr.yaml", "admin_level:\n  \"4\": state\n  \"8\": city\n");
+        write("lu.yaml", "admin_level:\n  \"2\": country\n  \"6\": town\n");
+        write("ci.yaml", "admin_level:\n  \"2\": country\n  seven: city\n");
+        write(
+            "overrides.yaml",
+            "FR:\n  admin_level:\n    \" 9\": suburb\n",
+        );
+
+        let errors = ZoneTyper::validate_rules(Some(dir), Some(&dir.join("overrides.yaml")));
+        let located: Vec<_> = errors
+            .iter()
+            .map(|e| (e.file.rsplit('/').next().unwrap(), e.line))
+            .collect();
+        assert_eq!(
+            located,
+            vec![
+                ("ci.yaml", Some(3)),
+                ("lu.yaml", Some(3)),
+                ("overrides.yaml", Some(3))
+            ]
+        );
+        assert!(errors[0].message.contains("invalid admin_level 'seven'"));
+        assert!(errors[1].message.contains("unknown variant `town`"));
+        assert!(ZoneTyper::from_rules_dir(dir).is_err());
+
+        std::fs::remove_file(dir.join("ci.yaml")).unwrap();
+        write("lu.yaml", "admin_level:\n  \"2\": country\n");
+        let mut typer = ZoneTyper::from_rules_dir(dir).unwrap();
+        let zone = Zone {
+            admin_level: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(
+            typer.get_zone_type(&zone, "FR", &[], &[]).ok(),
+            Some(ZoneType::City)
+        );
+
+        // the edited rules are used once reloaded, the invalid ones are not
+        write("fr.yaml", "admin_level:\n  \"8\": suburb\n");
+        typer.reload().unwrap();
+        assert_eq!(
+            typer.get_zone_type(&zone, "FR", &[], &[]).ok(),
+            Some(ZoneType::Suburb)
+        );
+        write("fr.yaml", "admin_level:\n  \"8\": village\n");
+        assert!(typer.reload().is_err());
+        assert_eq!(
+            typer.get_zone_type(&zone, "FR", &[], &[]).ok(),
+            Some(ZoneType::Suburb)
+        );
+    }
+
     /// helper method to return a yaml with many corner cases
     fn complex_rules() -> CountryAdminTypeRules {
         let yaml = r#"---
@@ -472,7 +1060,7 @@ mod test {
                 .iter()
                 .find(|z| z.osm_id == format!("relation:{}", osm_id))
                 .unwrap();
-            rules.get_zone_type(z, &inclusions[z.id.index], &zones)
+            rules.get_zone_type(z, &inclusions[z.id.index], &zones, &mut vec![])
         };
 
         // even if z1 has no admin_level it has explicitly been set by libpostal to city_district
@@ -499,4 +1087,259 @@ mod test {
         assert_eq!(get_zone_type("big_zone"), Some(ZoneType::State));
         assert_eq!(get_zone_type("very_big_zone"), Some(ZoneType::Country));
     }
+
+    #[test]
+    fn neighbourhoods_get_zone_type_test() {
+        let rules = complex_rules();
+        let zone = |lvl, place: Option<&str>| Zone {
+            admin_level: lvl,
+            tags: place.into_iter().map(|p| ("place", p)).collect(),
+            ..Default::default()
+        };
+        let get_zone_type = |z: &Zone| rules.get_zone_type(z, &[], &[], &mut vec![]);
+
+        // the levels 10 and 11 are not in the rules, but they are neighbourhoods
+        assert_eq!(get_zone_type(&zone(Some(10), None)), Some(ZoneType::Suburb));
+        assert_eq!(get_zone_type(&zone(Some(11), None)), Some(ZoneType::Suburb));
+        assert_eq!(get_zone_type(&zone(Some(12), None)), None);
+
+        // the place relations have no admin_level
+        assert_eq!(
+            get_zone_type(&zone(None, Some("quarter"))),
+            Some(ZoneType::Suburb)
+        );
+        assert_eq!(get_zone_type(&zone(None, Some("city"))), None);
+        assert_eq!(get_zone_type(&zone(None, None)), None);
+    }
+
+    #[test]
+    fn boroughs_get_zone_type_test() {
+        let rules = complex_rules();
+        let zone = |lvl, place: Option<&str>| Zone {
+            admin_level: lvl,
+            tags: place.into_iter().map(|p| ("place", p)).collect(),
+            ..Default::default()
+        };
+        let get_zone_type = |z: &Zone| rules.get_zone_type(z, &[], &[], &mut vec![]);
+
+        // the level 9 is typed as suburb, but a borough or a city district by its place tag
+        assert_eq!(get_zone_type(&zone(Some(9), None)), Some(ZoneType::Suburb));
+        assert_eq!(
+            get_zone_type(&zone(Some(9), Some("borough"))),
+            Some(ZoneType::Borough)
+        );
+        assert_eq!(
+            get_zone_type(&zone(Some(10), Some("city_district"))),
+            Some(ZoneType::CityDistrict)
+        );
+        assert_eq!(
+            get_zone_type(&zone(None, Some("borough"))),
+            Some(ZoneType::Borough)
+        );
+        // only the sub-city zones are typed by their place tag
+        assert_eq!(
+            get_zone_type(&zone(Some(8), Some("borough"))),
+            Some(ZoneType::City)
+        );
+
+        let mut rules_applied = vec![];
+        rules.get_zone_type(
+            &zone(Some(9), Some("borough")),
+            &[],
+            &[],
+            &mut rules_applied,
+        );
+        assert_eq!(
+            rules_applied.last(),
+            Some(&TypingRule::PlaceTag {
+                place: "borough".into(),
+                zone_type: ZoneType::Borough,
+            })
+        );
+        assert_eq!(ZoneType::parse("borough"), Some(ZoneType::Borough));
+        assert!(ZoneType::CityDistrict < ZoneType::Borough && ZoneType::Borough < ZoneType::City);
+    }
+
+    #[test]
+    fn generic_zone_type_test() {
+        let zone = |lvl, place: Option<&str>| Zone {
+            admin_level: lvl,
+            tags: place.into_iter().map(|p| ("place", p)).collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            generic_zone_type(&zone(Some(2), None)),
+            Some(ZoneType::Country)
+        );
+        assert_eq!(
+            generic_zone_type(&zone(Some(6), None)),
+            Some(ZoneType::StateDistrict)
+        );
+        assert_eq!(
+            generic_zone_type(&zone(Some(8), None)),
+            Some(ZoneType::City)
+        );
+        assert_eq!(
+            generic_zone_type(&zone(Some(10), None)),
+            Some(ZoneType::Suburb)
+        );
+        assert_eq!(
+            generic_zone_type(&zone(Some(9), Some("borough"))),
+            Some(ZoneType::Borough)
+        );
+        assert_eq!(
+            generic_zone_type(&zone(None, Some("quarter"))),
+            Some(ZoneType::Suburb)
+        );
+        assert_eq!(generic_zone_type(&zone(Some(12), None)), None);
+        assert_eq!(generic_zone_type(&zone(None, None)), None);
+    }
+
+    #[test]
+    fn level_overrides_test() {
+        let mut countries_rules = BTreeMap::new();
+        countries_rules.insert("FR".to_string(), complex_rules());
+        let overrides = r#"---
+    fr:
+        admin_level:
+            "7": null
+            "9": "city_district"
+    bob:
+        admin_level:
+            "4": "country"
+            "#;
+        let typer = ZoneTyper {
+            countries_rules,
+            level_overrides: read_level_overrides("overrides.yaml", overrides)
+                .expect("invalid yaml"),
+            rules_dir: None,
+            level_overrides_file: None,
+        };
+        assert!(typer.contains_rule("BOB"));
+
+        let zone = |osm_id: &str, lvl| Zone {
+            osm_id: format!("relation:{}", osm_id),
+            admin_level: Some(lvl),
+            ..Default::default()
+        };
+        let get_zone_type = |country: &str, z: &Zone| typer.get_zone_type(z, country, &[], &[]);
+
+        // the level 7 is explicitly not typed
+        assert!(matches!(
+            get_zone_type("FR", &zone("a", 7)),
+            Err(ZoneTyperError::UnkownLevel(Some(7), _))
+        ));
+        // the override takes precedence over libpostal's rules, even the id ones
+        assert_eq!(
+            get_zone_type("FR", &zone("z4", 9)).ok(),
+            Some(ZoneType::CityDistrict)
+        );
+        // the levels without override are still typed with libpostal's rules
+        assert_eq!(
+            get_zone_type("FR", &zone("a", 4)).ok(),
+            Some(ZoneType::State)
+        );
+        // a country without libpostal's rules can be typed with the overrides only
+        assert_eq!(
+            get_zone_type("BOB", &zone("a", 4)).ok(),
+            Some(ZoneType::Country)
+        );
+        assert!(matches!(
+            get_zone_type("BOB", &zone("a", 8)),
+            Err(ZoneTyperError::InvalidCountry(_))
+        ));
+    }
+
+    #[test]
+    fn explain_zone_type() {
+        let mut countries_rules = BTreeMap::new();
+        countries_rules.insert("FR".to_string(), complex_rules());
+        let typer = ZoneTyper {
+            countries_rules,
+            level_overrides: read_level_overrides(
+                "overrides.yaml",
+                "fr: { admin_level: { \"7\": \"city\" } }",
+            )
+            .expect("invalid yaml"),
+            rules_dir: None,
+            level_overrides_file: None,
+        };
+        let zone = |idx: usize, osm_id: &str, lvl| Zone {
+            id: ZoneIndex { index: idx },
+            osm_id: format!("relation:{}", osm_id),
+            admin_level: Some(lvl),
+            ..Default::default()
+        };
+        let zones = vec![
+            zone(0, "big_zone", 4),
+            zone(1, "z3", 9),
+            zone(2, "z4", 9),
+            zone(3, "z6", 7),
+            zone(4, "z7", 12),
+        ];
+        let big_zone = [ZoneIndex { index: 0 }];
+        let explain = |idx: usize, inclusions: &[ZoneIndex], country: &str| {
+            typer.explain(&zones[idx], country, inclusions, &zones)
+        };
+
+        let explanation = explain(1, &big_zone, "FR");
+        assert_eq!(explanation.zone_type, Some(ZoneType::Suburb));
+        assert_eq!(
+            explanation.rules,
+            vec![
+                TypingRule::ContainedBy {
+                    osm_id: "relation:big_zone".into()
+                },
+                TypingRule::AdminLevel {
+                    admin_level: Some(9),
+                    zone_type: ZoneType::Suburb
+                },
+            ]
+        );
+        assert_eq!(
+            explanation.to_string(),
+            "relation:z3 (FR): suburb\n  \
+             - contained by relation:big_zone, using its libpostal rules\n  \
+             - libpostal rule for admin_level 9: suburb\n"
+        );
+
+        // the explanation always gives the type of the zone typer
+        for (idx, inclusions) in [
+            (0, &[][..]),
+            (1, &big_zone),
+            (2, &big_zone),
+            (3, &[]),
+            (4, &[]),
+        ] {
+            for country in ["FR", "XX"] {
+                assert_eq!(
+                    explain(idx, inclusions, country).zone_type,
+                    typer
+                        .get_zone_type(&zones[idx], country, inclusions, &zones)
+                        .ok()
+                );
+            }
+        }
+        assert_eq!(
+            explain(2, &big_zone, "FR").rules,
+            vec![TypingRule::IdRule { zone_type: None }]
+        );
+        assert_eq!(
+            explain(3, &[], "FR").rules,
+            vec![TypingRule::LevelOverride {
+                admin_level: Some(7),
+                zone_type: Some(ZoneType::City)
+            }]
+        );
+        assert_eq!(
+            explain(4, &[], "FR").rules,
+            vec![TypingRule::UnknownLevel {
+                admin_level: Some(12)
+            }]
+        );
+        assert_eq!(
+            explain(0, &[], "XX").rules,
+            vec![TypingRule::NoCountryRules]
+        );
+    }
 }
@@ -1,8 +1,8 @@
 #[macro_use]
 extern crate approx;
 
-use cosmogony::{Cosmogony, Zone, ZoneIndex, ZoneType};
-use cosmogony_builder::{create_ontology, get_zones_and_stats, is_admin, is_place};
+use cosmogony::{Cosmogony, Zone, ZoneIndex, ZoneOrigin, ZoneType};
+use cosmogony_builder::{create_ontology, get_zones_and_stats, is_admin, is_place, BuildOptions};
 use geo_types::Point;
 use osmpbfreader::OsmPbfReader;
 use std::collections::BTreeMap;
@@ -92,6 +92,156 @@ fn test_cmd_with_json_gz_output() {
     assert_eq!(cosmo.zones.len(), 208);
 }
 
+#[test]
+fn test_cmd_with_zstd_outputs() {
+    let output_file = |name: &str| Path::new(env!("OUT_DIR")).join(name);
+    let generate = |out_file: &Path, extra_args: &[&str]| {
+        let mut args = vec![
+            "-i",
+            "./tests/data/luxembourg_filtered.osm.pbf",
+            "-o",
+            out_file.to_str().unwrap(),
+            "--deterministic",
+        ];
+        args.extend(extra_args);
+        let output = launch_command_line(args);
+        assert!(output.status.success());
+    };
+    let osm_ids = |zones: &[Zone]| zones.iter().map(|z| z.osm_id.clone()).collect::<Vec<_>>();
+    let plain_file = output_file("test_not_compressed.jsonl");
+    generate(&plain_file, &[]);
+    let expected = cosmogony::load_cosmogony_from_file(&plain_file).unwrap();
+    let expected = osm_ids(&expected.zones);
+
+    let zst_file = output_file("test_cosmogony.json.zst");
+    generate(&zst_file, &[]);
+    let cosmo = cosmogony::load_cosmogony_from_file(&zst_file).unwrap();
+    assert_eq!(osm_ids(&cosmo.zones), expected);
+
+    // the zones streamed are written in the same order
+    let zst_file = output_file("test_cosmogony.jsonl.zst");
+    generate(&zst_file, &["--stream"]);
+    let zones: Vec<Zone> = cosmogony::read_zones_from_file(&zst_file)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(osm_ids(&zones), expected);
+}
+
+#[test]
+fn test_cmd_with_interned_rings() {
+    let output_file = |name: &str| Path::new(env!("OUT_DIR")).join(name);
+    let generate = |out_file: &Path, extra_args: &[&str]| {
+        let mut args = vec![
+            "-i",
+            "./tests/data/luxembourg_filtered.osm.pbf",
+            "-o",
+            out_file.to_str().unwrap(),
+            "--deterministic",
+        ];
+        args.extend(extra_args);
+        let output = launch_command_line(args);
+        assert!(output.status.success());
+    };
+    let plain_file = output_file("test_not_interned.json");
+    generate(&plain_file, &[]);
+    let boundaries = |zones: &[Zone]| {
+        zones
+            .iter()
+            .map(|z| (z.osm_id.clone(), z.boundary.clone()))
+            .collect::<Vec<_>>()
+    };
+    let expected = cosmogony::load_cosmogony_from_file(&plain_file).unwrap();
+    let expected = boundaries(&expected.zones);
+
+    let interned_file = output_file("test_interned.json");
+    generate(&interned_file, &["--intern-rings"]);
+    let cosmo = cosmogony::load_cosmogony_from_file(&interned_file).unwrap();
+    assert_eq!(boundaries(&cosmo.zones), expected);
+    assert!(cosmo.zones.iter().all(|z| z.interned_boundary.is_none()));
+
+    let interned_file = output_file("test_interned.jsonl");
+    generate(&interned_file, &["--intern-rings"]);
+    let zones: Vec<Zone> = cosmogony::read_zones_from_file(&interned_file)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(boundaries(&zones), expected);
+}
+
+#[test]
+fn test_cmd_with_stream_on_stdout() {
+    let output = launch_command_line(vec![
+        "-i",
+        "./tests/data/luxembourg_filtered.osm.pbf",
+        "--stream",
+        "-o",
+        "-",
+    ]);
+    assert!(output.status.success());
+
+    let zones: Vec<Zone> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert!(zones
+        .iter()
+        .any(|z| z.zone_type == Some(ZoneType::Country) && z.name == "Lëtzebuerg"));
+    assert!(zones.iter().all(|z| z.zone_type.is_some()));
+}
+
+#[test]
+fn test_cmd_stats_only() {
+    let output = launch_command_line(vec![
+        "-i",
+        "./tests/data/luxembourg_filtered.osm.pbf",
+        "--stats-only",
+        "--disable-voronoi",
+    ]);
+    assert!(output.status.success());
+
+    let stats: cosmogony::CosmogonyStats = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats.zone_type_counts.get("Country"), Some(&1));
+    assert!(stats
+        .country_zone_counts
+        .get("LU")
+        .is_some_and(|c| *c > 100));
+    let lu_stats = &stats.country_stats["LU"];
+    assert_eq!(lu_stats.zone_type_counts.get("Country"), Some(&1));
+    assert_eq!(lu_stats.zones_without_boundary, 0);
+}
+
+#[test]
+fn test_cmd_diff() {
+    let before = concat!(env!("OUT_DIR"), "/test_diff_before.jsonl");
+    let after = concat!(env!("OUT_DIR"), "/test_diff_after.jsonl");
+    let report = concat!(env!("OUT_DIR"), "/test_diff.json");
+    let generate = |out_file, extra_args: Vec<&str>| {
+        let mut args = vec![
+            "-i",
+            "./tests/data/luxembourg_filtered.osm.pbf",
+            "-o",
+            out_file,
+        ];
+        args.extend(extra_args);
+        assert!(launch_command_line(args).status.success());
+    };
+    generate(before, vec!["--disable-voronoi"]);
+    generate(after, vec![]);
+
+    let output = launch_command_line(vec!["diff", before, before]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Added zones: 0"));
+
+    // the zones built from the places are only in the second build
+    let output = launch_command_line(vec!["diff", before, after, "-o", report]);
+    assert!(output.status.success());
+    let diff: cosmogony::diff::CosmogonyDiff =
+        serde_json::from_reader(File::open(report).unwrap()).unwrap();
+    assert!(!diff.added.is_empty());
+    assert!(diff.removed.is_empty());
+}
+
 #[test]
 fn test_cmd_with_unknown_format() {
     let output = launch_command_line(vec![
@@ -110,8 +260,12 @@ fn create_cosmogony_for_lux() -> Cosmogony {
         "/../../../../../tests/data/luxembourg_filtered.osm.pbf"
     );
 
-    cosmogony_builder::build_cosmogony(test_file.into(), Some("lu".into()), true, &[])
-        .expect("invalid cosmogony")
+    let options = BuildOptions {
+        country_code: Some("lu".into()),
+        disable_voronoi: true,
+        ..Default::default()
+    };
+    cosmogony_builder::build_cosmogony(test_file.into(), &options).expect("invalid cosmogony")
 }
 
 fn test_wrapper_for_lux_admin_levels(a_cosmogony: &Cosmogony) {
@@ -145,9 +299,10 @@ fn test_wrapper_for_lux_admin_levels(a_cosmogony: &Cosmogony) {
     assert_count(&wikidata_counts, 7, 0);
     assert_count(&level_counts, 8, 105); // 104 + 1 outside LU
     assert_count(&wikidata_counts, 8, 105);
-    assert_count(&level_counts, 9, 79);
-    // the level 10 is not defined in the libpostal hierarchy, we should not have level 10 admins
-    assert_count(&level_counts, 10, 0);
+    // without the historic commune of Heiderscheid
+    assert_count(&level_counts, 9, 78);
+    // the level 10 is not defined in the libpostal hierarchy, but its zones are neighbourhoods
+    assert_count(&level_counts, 10, 4);
 }
 
 fn test_wrapper_for_lux_zones(a_cosmogony: &Cosmogony) {
@@ -170,10 +325,11 @@ fn test_lux_cosmogony() {
     // from the sample .osm.pbf file,
     let cosmogony = create_cosmogony_for_lux();
     assert_eq!(cosmogony.meta.osm_filename, "luxembourg_filtered.osm.pbf");
-    assert_eq!(cosmogony.zones.len(), 198);
+    assert_eq!(cosmogony.zones.len(), 201);
 
     test_wrapper_for_lux_admin_levels(&cosmogony);
     test_wrapper_for_lux_zones(&cosmogony);
+    test_wrapper_for_lux_hierarchy(&cosmogony);
 }
 
 #[test]
@@ -189,6 +345,102 @@ fn test_lux_cosmogony_with_serialisation() {
     test_wrapper_for_lux_zones(&cosmogony_from_json);
 }
 
+#[test]
+fn test_read_previous_schema_version() {
+    let square = geo_types::MultiPolygon(vec![geo_types::Polygon::new(
+        vec![(0., 0.), (1., 0.), (1., 1.), (0., 0.)].into(),
+        vec![],
+    )]);
+    let cosmogony = Cosmogony {
+        zones: vec![
+            Zone {
+                osm_id: "relation:1".into(),
+                boundary: Some(square.clone()),
+                ..Default::default()
+            },
+            Zone {
+                osm_id: "node:2".into(),
+                boundary: Some(square),
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    assert_eq!(cosmogony.meta.schema_version, cosmogony::SCHEMA_VERSION);
+    // a cosmogony written before the schema versioning, without the zones' origin
+    let mut previous = serde_json::to_value(&cosmogony).unwrap();
+    previous["meta"]
+        .as_object_mut()
+        .unwrap()
+        .remove("schema_version");
+    for zone in previous["zones"].as_array_mut().unwrap() {
+        zone.as_object_mut().unwrap().remove("origin");
+    }
+    let write = |name: &str, content: String| {
+        let path = Path::new(env!("OUT_DIR")).join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    };
+    let zone_lines = |cosmogony: &serde_json::Value| {
+        let zones = cosmogony["zones"].as_array().unwrap();
+        zones.iter().map(|z| format!("{}\n", z)).collect::<String>()
+    };
+
+    let path = write("previous_schema.json", previous.to_string());
+    let read = cosmogony::read_cosmogony_versioned(&path).unwrap();
+    assert_eq!(read.meta.schema_version, cosmogony::SCHEMA_VERSION);
+    let origins: Vec<_> = read.zones.iter().map(|z| z.origin).collect();
+    assert_eq!(
+        origins,
+        vec![Some(ZoneOrigin::OsmRelation), Some(ZoneOrigin::Voronoi)]
+    );
+    let path = write("previous_schema.jsonl", zone_lines(&previous));
+    let read = cosmogony::read_cosmogony_versioned(&path).unwrap();
+    assert_eq!(read.zones[1].origin, Some(ZoneOrigin::Voronoi));
+
+    // the zones of the current version are read as they are
+    let mut current = serde_json::to_value(&cosmogony).unwrap();
+    let path = write("current_schema.jsonl", zone_lines(&current));
+    let read = cosmogony::read_cosmogony_versioned(&path).unwrap();
+    assert!(read.zones.iter().all(|z| z.origin.is_none()));
+
+    // the future versions cannot be read
+    current["meta"]["schema_version"] = (cosmogony::SCHEMA_VERSION + 1).into();
+    let path = write("future_schema.json", current.to_string());
+    assert!(cosmogony::read_cosmogony_versioned(&path).is_err());
+    assert!(cosmogony::load_cosmogony_from_file(&path).is_err());
+}
+
+fn test_wrapper_for_lux_hierarchy(a_cosmogony: &Cosmogony) {
+    let hierarchy = a_cosmogony.hierarchy();
+    assert_eq!(hierarchy.zones_of_type(ZoneType::Country).len(), 1);
+    assert_eq!(hierarchy.zones_of_type(ZoneType::StateDistrict).len(), 13);
+    let country = hierarchy.zones_of_type(ZoneType::Country)[0];
+    assert!(hierarchy.roots().iter().any(|z| z.id == country.id));
+    assert_eq!(hierarchy.children_of(country.id).len(), 13);
+
+    let lux = hierarchy
+        .zones_of_type(ZoneType::City)
+        .iter()
+        .find(|z| z.osm_id == "relation:407489")
+        .unwrap();
+    assert_eq!(
+        hierarchy.parent_of(lux.id).unwrap().name,
+        "Canton Luxembourg"
+    );
+    let ancestors: Vec<_> = hierarchy
+        .parent_chain(lux.id)
+        .iter()
+        .map(|z| z.name.as_str())
+        .collect();
+    assert_eq!(ancestors, vec!["Canton Luxembourg", "Lëtzebuerg"]);
+    assert!(hierarchy
+        .children_of(lux.id)
+        .iter()
+        .all(|z| z.parent == Some(lux.id)));
+    assert!(!hierarchy.children_of(lux.id).is_empty());
+}
+
 fn get_zone<'a>(cosmogony: &'a Cosmogony, idx: &'a ZoneIndex) -> Option<&'a Zone> {
     cosmogony.zones.iter().find(|z| z.id == *idx)
 }
@@ -207,13 +459,20 @@ fn test_lux_zone_types() {
             key
         )
     }
-    assert_count(zone_type_counts, "Suburb", 55);
+    assert_count(zone_type_counts, "Suburb", 58);
     assert_count(zone_type_counts, "City", 105);
     assert_count(zone_type_counts, "StateDistrict", 13);
     assert_count(zone_type_counts, "State", 0);
     assert_count(zone_type_counts, "Country", 1);
     assert_count(zone_type_counts, "None", 0); // all the zones without zone_type should be filtered
 
+    // the boundaries of the places are voronoi cells
+    assert!(cosmogony
+        .zones
+        .iter()
+        .filter(|z| z.osm_id.starts_with("node:"))
+        .all(|z| z.origin == Some(ZoneOrigin::Voronoi)));
+
     // check Luxembourg city
     let lux = cosmogony
         .zones
@@ -221,6 +480,7 @@ fn test_lux_zone_types() {
         .find(|z| z.name == "Luxembourg" && z.zone_type == Some(ZoneType::City))
         .unwrap();
     assert_eq!(lux.osm_id, "relation:407489");
+    assert_eq!(lux.origin, Some(ZoneOrigin::OsmRelation));
     assert_eq!(lux.admin_level, Some(8));
     assert_eq!(lux.label, "Luxembourg, Canton Luxembourg, Lëtzebuerg");
     assert_eq!(lux.country_code, Some("LU".into()));
@@ -248,7 +508,7 @@ fn test_lux_zone_types() {
     );
 
     assert!(!lux.center_tags.is_empty());
-    assert_eq!(lux.center_tags.get("population"), Some(&"103641".into()));
+    assert_eq!(lux.center_tags.get("population"), Some("103641"));
 
     // check the country
     let lux = cosmogony
@@ -281,7 +541,7 @@ fn test_lux_zone_types() {
     );
 
     // Read population from label node
-    assert_eq!(lux.tags.get("population"), Some(&"493500".into()));
+    assert_eq!(lux.tags.get("population"), Some("493500"));
 }
 
 #[test]
@@ -290,9 +550,13 @@ fn test_center_label() {
         env!("OUT_DIR"),
         "/../../../../../tests/data/gatineau.osm.pbf"
     );
-    let cosmogony =
-        cosmogony_builder::build_cosmogony(ottawa_test_file.into(), Some("ca".into()), true, &[])
-            .expect("invalid cosmogony");
+    let options = BuildOptions {
+        country_code: Some("ca".into()),
+        disable_voronoi: true,
+        ..Default::default()
+    };
+    let cosmogony = cosmogony_builder::build_cosmogony(ottawa_test_file.into(), &options)
+        .expect("invalid cosmogony");
 
     let gati = cosmogony
         .zones
@@ -323,11 +587,17 @@ fn test_voronoi() {
         .get_objs_and_deps(|o| is_admin(o) || is_place(o))
         .expect("invalid osm file");
 
-    let (mut zones, mut stats) =
-        get_zones_and_stats(&parsed_pbf).expect("get_zones_and_stats failed");
-
-    assert_eq!(zones.len(), 118);
-    create_ontology(&mut zones, &mut stats, None, false, &parsed_pbf, &[])
-        .expect("create_ontology failed");
+    let (mut zones, mut stats) = get_zones_and_stats(&parsed_pbf, &BuildOptions::default())
+        .expect("get_zones_and_stats failed");
+
+    // 118 relations and 2 closed ways
+    assert_eq!(zones.len(), 120);
+    create_ontology(
+        &mut zones,
+        &mut stats,
+        &parsed_pbf,
+        &BuildOptions::default(),
+    )
+    .expect("create_ontology failed");
     assert_eq!(zones.len(), 4471);
 }
@@ -0,0 +1,107 @@
+//! Regression tests of the zone types, country by country.
+//!
+//! For each fixture, the type given to each zone is compared to a golden file
+//! (`tests/golden/<fixture>.tsv`), so any change in the typing rules shows an explicit diff
+//! of the affected zones.
+//!
+//! When the changes are expected, the golden files can be updated with:
+//! `COSMOGONY_UPDATE_GOLDEN=1 cargo test --test golden_test`
+//! The golden files of the new fixtures are created the same way, they must be reviewed and
+//! committed (a missing golden file fails the test).
+
+use cosmogony::Zone;
+use cosmogony_builder::{build_cosmogony, BuildOptions};
+use std::path::PathBuf;
+
+const UPDATE_ENV_VAR: &str = "COSMOGONY_UPDATE_GOLDEN";
+
+/// One line by zone: osm_id, admin_level, zone type, country and name
+fn zone_types(zones: &[Zone]) -> Vec<String> {
+    let mut lines: Vec<String> = zones
+        .iter()
+        .filter(|z| !z.is_generated)
+        .map(|z| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}",
+                z.osm_id,
+                z.admin_level.map_or("-".to_string(), |l| l.to_string()),
+                z.zone_type.as_ref().map_or("-", |t| t.as_str()),
+                z.country_code.as_deref().unwrap_or("-"),
+                z.name
+            )
+        })
+        .collect();
+    lines.sort();
+    lines
+}
+
+fn check_golden(fixture: &str, pbf: &str, country_code: Option<&str>) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let options = BuildOptions {
+        country_code: country_code.map(str::to_string),
+        disable_voronoi: true,
+        // the zones are checked just after being typed, before the untyped zones are removed
+        stop_after: Some("type".into()),
+        ..Default::default()
+    };
+    let cosmogony = build_cosmogony(
+        root.join("data").join(pbf).to_string_lossy().into_owned(),
+        &options,
+    )
+    .expect("cosmogony build failed");
+    let actual = zone_types(&cosmogony.zones);
+
+    let golden_path = root.join("golden").join(format!("{}.tsv", fixture));
+    if std::env::var_os(UPDATE_ENV_VAR).is_some() {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        std::fs::write(&golden_path, actual.join("\n") + "\n").unwrap();
+        println!("golden file {} written", golden_path.display());
+        return;
+    }
+
+    let golden = std::fs::read_to_string(&golden_path).unwrap_or_else(|e| {
+        panic!(
+            "unable to read the golden file {}: {}\n\
+             run the test with {}=1 to create it",
+            golden_path.display(),
+            e,
+            UPDATE_ENV_VAR
+        )
+    });
+    let expected: Vec<&str> = golden.lines().collect();
+    let removed: Vec<_> = expected
+        .iter()
+        .filter(|l| !actual.iter().any(|a| a == *l))
+        .map(|l| format!("- {}", l))
+        .collect();
+    let added: Vec<_> = actual
+        .iter()
+        .filter(|l| !expected.contains(&l.as_str()))
+        .map(|l| format!("+ {}", l))
+        .collect();
+    assert!(
+        removed.is_empty() && added.is_empty(),
+        "the zone types of {} differ from {}:\n{}\n{}\n\
+         if the changes are expected, rerun the test with {}=1 to update the golden file",
+        fixture,
+        golden_path.display(),
+        removed.join("\n"),
+        added.join("\n"),
+        UPDATE_ENV_VAR,
+    );
+}
+
+#[test]
+fn golden_luxembourg() {
+    check_golden("lu", "luxembourg_filtered.osm.pbf", Some("lu"));
+}
+
+#[test]
+fn golden_canada() {
+    check_golden("ca", "gatineau.osm.pbf", Some("ca"));
+}
+
+#[test]
+fn golden_ivory_coast() {
+    check_golden("ci", "ivory-coast.pbf", None);
+}